@@ -3,7 +3,8 @@ use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt::Debug;
 use std::io::Write;
-use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -22,6 +23,7 @@ use fedimint_core::module::ApiRequestErased;
 use fedimint_core::query::EventuallyConsistent;
 use fedimint_core::task::TaskGroup;
 use fedimint_core::{Amount, OutPoint, TieredMulti, TransactionId};
+use fedimint_encrypted_db::{DbEncryptionKey, EncryptedDatabase};
 use fedimint_ln_client::LightningClientGen;
 use fedimint_logging::TracingSetup;
 use fedimint_mint_client::MintClientGen;
@@ -38,6 +40,9 @@ use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use url::Url;
 
+/// File in the workdir holding the salt for deriving the db encryption key
+const DB_SALT_FILE: &str = "client.db.salt";
+
 #[derive(Serialize)]
 #[serde(rename_all(serialize = "snake_case"))]
 #[serde(untagged)]
@@ -126,6 +131,10 @@ enum CliOutput {
         new_gateway: Value,
     },
 
+    GatewayPreferences {
+        preferences: Value,
+    },
+
     Backup,
 }
 
@@ -196,6 +205,13 @@ struct Cli {
     /// The working directory of the client containing the config and db
     #[arg(long = "workdir")]
     workdir: PathBuf,
+    /// SOCKS5 proxy, e.g. Tor, used to connect to the federation
+    #[arg(long = "socks5-proxy", env = "FM_SOCKS5_PROXY")]
+    socks5_proxy: Option<SocketAddr>,
+    /// Encrypt the db holding the notes and the client secret with a key
+    /// derived from this password, an existing plaintext db is migrated
+    #[arg(long = "db-password", env = "FM_DB_PASSWORD")]
+    db_password: Option<String>,
     #[clap(subcommand)]
     command: Command,
 }
@@ -312,6 +328,26 @@ enum Command {
         pubkey: secp256k1::PublicKey,
     },
 
+    /// Always try the given gateway first when paying invoices
+    PinGateway {
+        /// node public key for a gateway
+        #[clap(value_parser = parse_node_pub_key)]
+        pubkey: secp256k1::PublicKey,
+    },
+
+    /// Stop preferring the pinned gateway
+    UnpinGateway,
+
+    /// Never use the given gateway
+    BlacklistGateway {
+        /// node public key for a gateway
+        #[clap(value_parser = parse_node_pub_key)]
+        pubkey: secp256k1::PublicKey,
+        /// Remove the gateway from the blacklist instead
+        #[clap(long)]
+        remove: bool,
+    },
+
     /// Upload the (encrypted) snapshot of mint notes to federation
     Backup,
 
@@ -335,6 +371,7 @@ enum Command {
 
 trait ErrorHandler<T, E> {
     fn or_terminate(self, err: CliErrorKind, msg: &str) -> T;
+    fn or_cli_error(self, err: CliErrorKind, msg: &str) -> Result<T, CliError>;
     fn transform<F>(self, success: F, err: CliErrorKind, msg: &str) -> CliResult
     where
         F: Fn(T) -> CliOutput;
@@ -351,6 +388,9 @@ impl<T, E: Into<Box<dyn Error>>> ErrorHandler<T, E> for Result<T, E> {
             }
         }
     }
+    fn or_cli_error(self, err: CliErrorKind, msg: &str) -> Result<T, CliError> {
+        self.map_err(|e| CliError::from(err, msg, Some(e.into())))
+    }
     fn transform<F>(self, success: F, err: CliErrorKind, msg: &str) -> CliResult
     where
         F: Fn(T) -> CliOutput,
@@ -398,8 +438,11 @@ async fn main() {
             let connect_obj: WsClientConnectInfo = WsClientConnectInfo::from_str(&connect)
                 .map_err(Box::<dyn Error>::from)
                 .or_terminate(CliErrorKind::InvalidValue, "invalid connect info");
-            let api = Arc::new(WsFederationApi::from_urls(&connect_obj))
-                as Arc<dyn IFederationApi + Send + Sync + 'static>;
+            let mut api = WsFederationApi::from_urls(&connect_obj);
+            if let Some(proxy) = cli.socks5_proxy {
+                api = api.with_socks5_proxy(proxy);
+            }
+            let api = Arc::new(api) as Arc<dyn IFederationApi + Send + Sync + 'static>;
             let cfg: ClientConfig = api
                 .download_client_config(&connect_obj.id, module_gens.to_common())
                 .await
@@ -426,13 +469,27 @@ async fn main() {
         let cfg: UserClientConfig = load_from_file(&cfg_path).expect("Failed to parse config");
         let db = fedimint_rocksdb::RocksDb::open(db_path)
             .or_terminate(CliErrorKind::IOError, "could not open transaction db");
-        let db = Database::new(db, module_decode_stubs());
+        let db = match open_db(db, &cli.workdir, cli.db_password.as_deref()).await {
+            Ok(db) => db,
+            Err(err) => {
+                let _ = writeln!(std::io::stderr(), "{err}");
+                exit(1);
+            }
+        };
 
         let rng = rand::rngs::OsRng;
 
         let decoders = module_decode_stubs();
 
-        let client = Client::new(cfg.clone(), decoders, module_gens, db, Default::default()).await;
+        let client = Client::new_with_socks5_proxy(
+            cfg.clone(),
+            decoders,
+            module_gens,
+            db,
+            Default::default(),
+            cli.socks5_proxy,
+        )
+        .await;
 
         let cli_result = handle_command(cli, client, rng).await;
 
@@ -449,6 +506,45 @@ async fn main() {
     }
 }
 
+/// Wraps the client db in an [`EncryptedDatabase`] if a password is given,
+/// the salt for deriving the key is created in `workdir` on first use
+async fn open_db(
+    db: fedimint_rocksdb::RocksDb,
+    workdir: &Path,
+    password: Option<&str>,
+) -> Result<Database, CliError> {
+    let password = match password {
+        Some(password) => password,
+        None => {
+            let is_encrypted = EncryptedDatabase::is_encrypted(&db)
+                .await
+                .or_cli_error(CliErrorKind::IOError, "could not read transaction db")?;
+            if is_encrypted {
+                return Err(CliError::from(
+                    CliErrorKind::InvalidValue,
+                    "transaction db is encrypted, --db-password is required",
+                    None,
+                ));
+            }
+            return Ok(Database::new(db, module_decode_stubs()));
+        }
+    };
+
+    let salt_path = workdir.join(DB_SALT_FILE);
+    if !salt_path.exists() {
+        std::fs::write(&salt_path, fedimint_aead::random_salt())
+            .or_cli_error(CliErrorKind::IOError, "couldn't write db salt")?;
+    }
+    let salt = std::fs::read_to_string(salt_path)
+        .or_cli_error(CliErrorKind::IOError, "couldn't read db salt")?;
+    let key = DbEncryptionKey::from_password(password, &salt)
+        .or_cli_error(CliErrorKind::GeneralFailure, "couldn't derive db key")?;
+    let db = EncryptedDatabase::migrate(db, key)
+        .await
+        .or_cli_error(CliErrorKind::InvalidValue, "couldn't open encrypted db")?;
+    Ok(Database::new(db, module_decode_stubs()))
+}
+
 async fn handle_command(
     cli: Cli,
     client: Client<UserClientConfig>,
@@ -458,7 +554,11 @@ async fn handle_command(
     match cli.command {
         Command::Api { method, arg } => {
             let arg: Value = serde_json::from_str(&arg).unwrap();
-            let ws_api: Arc<_> = WsFederationApi::from_config(client.config().as_ref()).into();
+            let mut ws_api = WsFederationApi::from_config(client.config().as_ref());
+            if let Some(proxy) = cli.socks5_proxy {
+                ws_api = ws_api.with_socks5_proxy(proxy);
+            }
+            let ws_api: Arc<_> = ws_api.into();
             let response: Value = ws_api
                 .request_with_strategy(
                     EventuallyConsistent::new(ws_api.peers().len()),
@@ -573,34 +673,30 @@ async fn handle_command(
                 )),
             }
         }
-        Command::LnPay { bolt11 } => {
-            match client.fund_outgoing_ln_contract(bolt11, &mut rng).await {
-                Ok((contract_id, outpoint)) => {
-                    match client.await_outgoing_contract_acceptance(outpoint).await {
-                        Ok(_) => client
-                            .await_outgoing_contract_execution(contract_id, &mut rng)
-                            .await
-                            .transform(
-                                |_| CliOutput::LnPay {
-                                    contract_id: (contract_id),
-                                },
-                                CliErrorKind::GeneralFederationError,
-                                "gateway failed to execute contract",
-                            ),
-                        Err(e) => Err(CliError::from(
-                            CliErrorKind::Timeout,
-                            "contract wasn't accepted in time",
-                            Some(Box::new(e)),
-                        )),
-                    }
-                }
-                Err(e) => Err(CliError::from(
+        Command::LnPay { bolt11 } => match client.fund_internal_payment(&bolt11, &mut rng).await {
+            // The invoice was issued by a user of our federation, so we paid them directly
+            Ok(Some((contract_id, outpoint))) => client
+                .await_internal_payment(contract_id, outpoint, &mut rng)
+                .await
+                .transform(
+                    |_| CliOutput::LnPay { contract_id },
                     CliErrorKind::GeneralFederationError,
-                    "Failure creating outgoing LN contract",
-                    Some(Box::new(e)),
-                )),
-            }
-        }
+                    "internal payment failed",
+                ),
+            Ok(None) => client
+                .pay_invoice_with_fallback(bolt11, &mut rng)
+                .await
+                .transform(
+                    |contract_id| CliOutput::LnPay { contract_id },
+                    CliErrorKind::GeneralFederationError,
+                    "no gateway managed to pay the invoice",
+                ),
+            Err(e) => Err(CliError::from(
+                CliErrorKind::GeneralFederationError,
+                "Failure paying invoice internally",
+                Some(Box::new(e)),
+            )),
+        },
         Command::LnInvoice {
             amount,
             description,
@@ -710,6 +806,15 @@ async fn handle_command(
                 )),
             }
         }
+        Command::PinGateway { pubkey } => Ok(CliOutput::GatewayPreferences {
+            preferences: json!(client.pin_gateway(Some(pubkey)).await),
+        }),
+        Command::UnpinGateway => Ok(CliOutput::GatewayPreferences {
+            preferences: json!(client.pin_gateway(None).await),
+        }),
+        Command::BlacklistGateway { pubkey, remove } => Ok(CliOutput::GatewayPreferences {
+            preferences: json!(client.blacklist_gateway(pubkey, !remove).await),
+        }),
         Command::Backup => match client.mint_client().back_up_ecash_to_federation().await {
             Ok(_) => Ok(CliOutput::Backup),
             Err(e) => Err(CliError::from(
@@ -4,7 +4,7 @@ use std::pin::Pin;
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use fedimint_core::api::{IFederationApi, JsonRpcResult};
+use fedimint_core::api::{IFederationApi, JsonRpcResult, JsonRpcSubscription};
 use fedimint_core::module::ApiRequest;
 use fedimint_core::PeerId;
 use futures::Future;
@@ -120,4 +120,16 @@ where
             Err(jsonrpsee_core::Error::MethodNotFound(method.into()))
         }
     }
+
+    /// Subscriptions are answered by the handler of `method`, whose result is
+    /// pushed once
+    async fn subscribe_raw(
+        &self,
+        peer_id: PeerId,
+        method: &str,
+        params: &[Value],
+    ) -> JsonRpcResult<JsonRpcSubscription> {
+        let result = self.request_raw(peer_id, method, params).await?;
+        Ok(Box::pin(futures::stream::once(async { Ok(result) })))
+    }
 }
@@ -1,3 +1,4 @@
+use fedimint_core::config::ApiEndpoint;
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::impl_db_record;
 use serde::Serialize;
@@ -9,6 +10,7 @@ use crate::ClientSecret;
 #[derive(Clone, EnumIter, Debug)]
 pub enum DbKeyPrefix {
     ClientSecret = 0x29,
+    ApiEndpoints = 0x2e,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -25,3 +27,14 @@ impl_db_record!(
     value = ClientSecret,
     db_prefix = DbKeyPrefix::ClientSecret
 );
+
+/// API endpoints of the federation learned after the client config was
+/// created, replacing its `nodes`
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct ApiEndpointsKey;
+
+impl_db_record!(
+    key = ApiEndpointsKey,
+    value = Vec<ApiEndpoint>,
+    db_prefix = DbKeyPrefix::ApiEndpoints
+);
@@ -15,6 +15,7 @@ pub mod modules {
 
 use std::fmt::{Debug, Formatter};
 use std::iter::once;
+use std::net::SocketAddr;
 use std::ops::Add;
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime};
@@ -26,9 +27,9 @@ use bitcoin_hashes::{sha256, Hash};
 use fedimint_client::module::gen::{ClientModuleGenRegistry, ClientModuleGenRegistryExt};
 use fedimint_core::api::{
     DynFederationApi, FederationError, GlobalFederationApi, MemberError, OutputOutcomeError,
-    WsFederationApi,
+    WsClientConnectInfo, WsFederationApi,
 };
-use fedimint_core::config::{ClientConfig, FederationId};
+use fedimint_core::config::{ApiEndpoint, ClientConfig, FederationId};
 use fedimint_core::core::{
     LEGACY_HARDCODED_INSTANCE_ID_LN, LEGACY_HARDCODED_INSTANCE_ID_MINT,
     LEGACY_HARDCODED_INSTANCE_ID_WALLET,
@@ -70,13 +71,16 @@ use threshold_crypto::PublicKey;
 use tracing::{debug, info, instrument, trace};
 use url::Url;
 
-use crate::db::ClientSecretKey;
+use crate::db::{ApiEndpointsKey, ClientSecretKey};
 use crate::ln::db::{
-    OutgoingContractAccountKey, OutgoingContractAccountKeyPrefix, OutgoingPaymentClaimKey,
-    OutgoingPaymentClaimKeyPrefix, OutgoingPaymentKey,
+    GatewayPreferencesKey, InternalPaymentKey, OutgoingContractAccountKey,
+    OutgoingContractAccountKeyPrefix, OutgoingPaymentClaimKey, OutgoingPaymentClaimKeyPrefix,
+    OutgoingPaymentKey,
 };
+use crate::ln::gateway::GatewayPreferences;
 use crate::ln::incoming::ConfirmedInvoice;
-use crate::ln::outgoing::OutgoingContractAccount;
+use crate::ln::incoming::IncomingContractAccount;
+use crate::ln::outgoing::{InternalPaymentData, OutgoingContractAccount};
 use crate::ln::{LnClient, LnClientError};
 use crate::mint::db::{NoteKey, PendingNotesKeyPrefix};
 use crate::mint::{MintClient, MintClientError, SpendableNote};
@@ -132,6 +136,58 @@ pub struct GatewayClientConfig {
     /// `short_channel_id` when creating invoices to be settled by this
     /// gateway.
     pub mint_channel_id: u64,
+    /// Limits on the ecash the gateway holds in this federation
+    #[serde(default)]
+    pub balance_policy: GatewayBalancePolicy,
+}
+
+/// Operator configured bounds of the ecash balance a gateway holds in a
+/// federation
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct GatewayBalancePolicy {
+    /// The gateway can only buy preimages for incoming payments up to its
+    /// ecash balance, so the operator is alerted if it falls below this amount
+    pub min_balance: Option<Amount>,
+    /// Ecash exceeding this amount is pegged out to `withdraw_address` to limit
+    /// custodial exposure
+    pub max_balance: Option<Amount>,
+    pub withdraw_address: Option<Address>,
+}
+
+impl GatewayBalancePolicy {
+    /// Rejects policies whose minimum balance is not below their maximum, they
+    /// would peg out ecash the gateway needs to stay above its minimum
+    pub fn validate(&self) -> Result<()> {
+        match (self.min_balance, self.max_balance) {
+            (Some(min), Some(max)) if max <= min => {
+                Err(ClientError::InvalidBalancePolicy(min, max))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_below_min(&self, balance: Amount) -> bool {
+        self.min_balance.map_or(false, |min| balance < min)
+    }
+
+    /// Amount that has to be pegged out to get `balance` back to the maximum
+    pub fn excess(&self, balance: Amount) -> Option<Amount> {
+        self.max_balance
+            .filter(|max| *max < balance)
+            .map(|max| balance - max)
+    }
+
+    /// The excess in whole sats that can be pegged out, `None` if it is below
+    /// `dust_limit` since the federation rejects such peg-outs
+    pub fn withdrawable_excess(
+        &self,
+        balance: Amount,
+        dust_limit: bitcoin::Amount,
+    ) -> Option<bitcoin::Amount> {
+        self.excess(balance)
+            .map(|excess| bitcoin::Amount::from_sat(excess.msats / 1000))
+            .filter(|excess| dust_limit <= *excess)
+    }
 }
 
 impl GatewayClientConfig {
@@ -264,7 +320,9 @@ impl<T: AsRef<ClientConfig> + Clone + Send> Client<T> {
         self.config.clone()
     }
 
-    /// Verifies the config using the federation id
+    /// Verifies the config using the federation id. If the federation only
+    /// changed its API endpoints the new endpoints are persisted and used by
+    /// clients created afterwards.
     pub async fn verify_config(&self, id: &FederationId) -> Result<()> {
         let config = self
             .context
@@ -283,13 +341,40 @@ impl<T: AsRef<ClientConfig> + Clone + Send> Client<T> {
             .consensus_hash(&self.context.module_gens.to_common())
             .map_err(|_| ClientError::ConfigVerify(ConfigVerifyError::CannotHash))?;
 
-        if api_hash != self_hash {
-            Err(ClientError::ConfigVerify(
+        if api_hash == self_hash {
+            return Ok(());
+        }
+
+        let mut updated = self.config.as_ref().clone();
+        updated.nodes = config.nodes.clone();
+        let updated_hash = updated
+            .consensus_hash(&self.context.module_gens.to_common())
+            .map_err(|_| ClientError::ConfigVerify(ConfigVerifyError::CannotHash))?;
+
+        if api_hash != updated_hash {
+            return Err(ClientError::ConfigVerify(
                 ConfigVerifyError::MismatchingConfigs,
-            ))
-        } else {
-            Ok(())
+            ));
         }
+
+        let mut dbtx = self.context.db.begin_transaction().await;
+        dbtx.insert_entry(&ApiEndpointsKey, &config.nodes).await;
+        dbtx.commit_tx().await;
+        Ok(())
+    }
+
+    /// API endpoints of the federation, which may have changed since our
+    /// config was created
+    pub async fn api_endpoints(&self) -> Vec<ApiEndpoint> {
+        Self::get_api_endpoints(&self.context.db, self.config.as_ref()).await
+    }
+
+    async fn get_api_endpoints(db: &Database, config: &ClientConfig) -> Vec<ApiEndpoint> {
+        db.begin_transaction()
+            .await
+            .get_value(&ApiEndpointsKey)
+            .await
+            .unwrap_or_else(|| config.nodes.clone())
     }
 
     pub async fn new(
@@ -299,7 +384,27 @@ impl<T: AsRef<ClientConfig> + Clone + Send> Client<T> {
         db: Database,
         secp: Secp256k1<All>,
     ) -> Self {
-        let api = WsFederationApi::from_config(config.as_ref());
+        Self::new_with_socks5_proxy(config, decoders, module_gens, db, secp, None).await
+    }
+
+    /// Creates a client that connects to the federation through the given
+    /// SOCKS5 proxy, e.g. Tor
+    pub async fn new_with_socks5_proxy(
+        config: T,
+        decoders: ModuleDecoderRegistry,
+        module_gens: ClientModuleGenRegistry,
+        db: Database,
+        secp: Secp256k1<All>,
+        socks5_proxy: Option<SocketAddr>,
+    ) -> Self {
+        let endpoints = Self::get_api_endpoints(&db, config.as_ref()).await;
+        let mut api = WsFederationApi::from_urls(&WsClientConnectInfo::new(
+            &config.as_ref().federation_id,
+            &endpoints,
+        ));
+        if let Some(proxy) = socks5_proxy {
+            api = api.with_socks5_proxy(proxy);
+        }
         Self::new_with_api(config, decoders, module_gens, db, api.into(), secp).await
     }
 
@@ -763,6 +868,44 @@ impl<T: AsRef<ClientConfig> + Clone + Send> Client<T> {
             .fetch_epoch_history(epoch, epoch_pk, &self.context.decoders)
             .await?)
     }
+
+    /// Wait for a lightning preimage bought by funding an incoming contract to
+    /// be decrypted by the federation
+    pub async fn await_preimage_decryption(&self, outpoint: OutPoint) -> Result<Preimage> {
+        let deadline = Instant::now().add(Duration::from_secs(30));
+
+        let poll = || async {
+            loop {
+                match self
+                    .context
+                    .api
+                    .await_output_outcome::<OutputOutcome>(
+                        outpoint,
+                        deadline.saturating_duration_since(Instant::now()),
+                        &self.context.decoders,
+                    )
+                    .await
+                {
+                    Ok(OutputOutcome::LN(o)) if !o.is_permanent() => {
+                        info!(outcome = %o, "Retrying on temporary outcome");
+                        sleep(Duration::from_secs(1)).await;
+                    }
+                    Ok(t) => return t.try_into_variant(),
+                    Err(e) => {
+                        return Err(e);
+                    }
+                }
+            }
+        };
+        Ok(
+            fedimint_core::task::timeout(
+                deadline.saturating_duration_since(Instant::now()),
+                poll(),
+            )
+            .await
+            .map_err(|_| ClientError::Timeout)??,
+        )
+    }
 }
 
 impl Client<UserClientConfig> {
@@ -773,14 +916,16 @@ impl Client<UserClientConfig> {
     pub async fn fetch_active_gateway(&self) -> Result<LightningGateway> {
         // FIXME: forgetting about old gws might not always be ideal. We assume that the
         // gateway stays the same except for route hints for now.
-        if let Some(gateway) = self
-            .context
-            .db
-            .begin_transaction()
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let preferences = dbtx
+            .get_value(&GatewayPreferencesKey)
             .await
+            .unwrap_or_default();
+        if let Some(gateway) = dbtx
             .get_value(&LightningGatewayKey)
             .await
             .filter(|gw| gw.valid_until > fedimint_core::time::now())
+            .filter(|gw| !preferences.is_blacklisted(gw))
         {
             return Ok(gateway);
         }
@@ -789,37 +934,199 @@ impl Client<UserClientConfig> {
     }
     /// Switches the clients active gateway to a registered gateway with the
     /// given node pubkey. If no pubkey is given (node_pub_key == None) the
-    /// first available registered gateway is activated. This behavior is
-    /// useful for scenarios where we don't know any registered gateways in
-    /// advance.
+    /// best ranked registered gateway is activated (see
+    /// [`ln::gateway::rank_gateways`]). This behavior is useful for scenarios
+    /// where we don't know any registered gateways in advance.
     pub async fn switch_active_gateway(
         &self,
         node_pub_key: Option<secp256k1::PublicKey>,
     ) -> Result<LightningGateway> {
-        let gateways = self.fetch_registered_gateways().await?;
-        if gateways.is_empty() {
-            debug!("Could not find any gateways");
-            return Err(ClientError::NoGateways);
-        };
         let gateway = match node_pub_key {
             // If a pubkey was provided, try to select and activate a gateway with that pubkey.
-            Some(pub_key) => gateways
-                .into_iter()
-                .find(|g| g.node_pub_key == pub_key)
-                .ok_or_else(|| {
-                    debug!("Could not find gateway with public key {:?}", pub_key);
-                    ClientError::GatewayNotFound
-                })?,
-            // Otherwise (no pubkey provided), select and activate the first registered gateway.
+            Some(pub_key) => {
+                let gateways = self.fetch_registered_gateways().await?;
+                if gateways.is_empty() {
+                    debug!("Could not find any gateways");
+                    return Err(ClientError::NoGateways);
+                };
+                gateways
+                    .into_iter()
+                    .find(|g| g.node_pub_key == pub_key)
+                    .ok_or_else(|| {
+                        debug!("Could not find gateway with public key {:?}", pub_key);
+                        ClientError::GatewayNotFound
+                    })?
+            }
+            // Otherwise (no pubkey provided), select and activate the best ranked gateway.
             None => {
-                debug!("No public key for gateway supplied, using first registered one");
-                gateways[0].clone()
+                debug!("No public key for gateway supplied, using best ranked one");
+                self.ranked_gateways(Amount::ZERO)
+                    .await?
+                    .into_iter()
+                    .next()
+                    .ok_or(ClientError::NoGateways)?
             }
         };
+        self.set_active_gateway(&gateway).await;
+        Ok(gateway)
+    }
+
+    async fn set_active_gateway(&self, gateway: &LightningGateway) {
         let mut dbtx = self.context.db.begin_transaction().await;
-        dbtx.insert_entry(&LightningGatewayKey, &gateway).await;
+        dbtx.insert_entry(&LightningGatewayKey, gateway).await;
         dbtx.commit_tx().await;
-        Ok(gateway)
+    }
+
+    /// Registered gateways that are online and not blacklisted, ordered by
+    /// preference for routing a payment of `amount`
+    pub async fn ranked_gateways(&self, amount: Amount) -> Result<Vec<LightningGateway>> {
+        let gateways = self.fetch_registered_gateways().await?;
+        let preferences = self.gateway_preferences().await;
+        let ranked =
+            ln::gateway::rank_gateways(gateways, &preferences, amount, fedimint_core::time::now());
+        Ok(ln::gateway::retain_live_gateways(ranked, ln::gateway::GATEWAY_PROBE_TIMEOUT).await)
+    }
+
+    pub async fn gateway_preferences(&self) -> GatewayPreferences {
+        self.context
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&GatewayPreferencesKey)
+            .await
+            .unwrap_or_default()
+    }
+
+    async fn update_gateway_preferences(
+        &self,
+        update: impl FnOnce(&mut GatewayPreferences),
+    ) -> GatewayPreferences {
+        let mut dbtx = self.context.db.begin_transaction().await;
+        let mut preferences = dbtx
+            .get_value(&GatewayPreferencesKey)
+            .await
+            .unwrap_or_default();
+        update(&mut preferences);
+        dbtx.insert_entry(&GatewayPreferencesKey, &preferences)
+            .await;
+        dbtx.commit_tx().await;
+        preferences
+    }
+
+    /// Always try the gateway with the given node pubkey first when paying, or
+    /// stop doing so if `None` is given
+    pub async fn pin_gateway(
+        &self,
+        node_pub_key: Option<secp256k1::PublicKey>,
+    ) -> GatewayPreferences {
+        self.update_gateway_preferences(|preferences| preferences.pinned = node_pub_key)
+            .await
+    }
+
+    /// Never use the gateway with the given node pubkey, or allow it again if
+    /// `blacklisted` is false
+    pub async fn blacklist_gateway(
+        &self,
+        node_pub_key: secp256k1::PublicKey,
+        blacklisted: bool,
+    ) -> GatewayPreferences {
+        self.update_gateway_preferences(|preferences| {
+            preferences.blacklisted.retain(|pk| pk != &node_pub_key);
+            if blacklisted {
+                preferences.blacklisted.push(node_pub_key);
+            }
+        })
+        .await
+    }
+
+    /// Pays `invoice` through the registered gateways in order of preference.
+    /// If a gateway fails to route the payment and cancels or lets the outgoing
+    /// contract time out, the refund is reclaimed and the next gateway is
+    /// tried.
+    pub async fn pay_invoice_with_fallback<R: RngCore + CryptoRng>(
+        &self,
+        invoice: Invoice,
+        mut rng: R,
+    ) -> Result<ContractId> {
+        let amount = invoice
+            .amount_milli_satoshis()
+            .map(Amount::from_msats)
+            .ok_or(ClientError::InvoiceMissingAmount)?;
+
+        let mut last_error = ClientError::NoGateways;
+        for gateway in self.ranked_gateways(amount).await? {
+            self.set_active_gateway(&gateway).await;
+
+            let (contract_id, outpoint) = self
+                .fund_outgoing_ln_contract(invoice.clone(), &mut rng)
+                .await?;
+            self.await_outgoing_contract_acceptance(outpoint).await?;
+
+            match self
+                .await_outgoing_contract_execution(contract_id, &mut rng)
+                .await
+            {
+                Ok(()) => return Ok(contract_id),
+                Err(ClientError::RefundedFailedPayment) => {
+                    info!(
+                        gateway = %gateway.node_pub_key,
+                        "Gateway failed to route payment, trying next one"
+                    );
+                    last_error = ClientError::RefundedFailedPayment;
+                    // The refund has to be issued before we can fund the next contract
+                    self.fetch_all_notes().await?;
+                }
+                Err(e @ (ClientError::HttpError(_) | ClientError::OutgoingPaymentTimeout)) => {
+                    info!(
+                        gateway = %gateway.node_pub_key,
+                        error = %e,
+                        "Gateway unreachable, reclaiming contract and trying next one"
+                    );
+                    self.reclaim_unexecuted_outgoing_contract(contract_id, &mut rng)
+                        .await?;
+                    last_error = e;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Gets the funds of an outgoing contract back after we failed to reach its
+    /// gateway, so they can be used to pay through another gateway.
+    ///
+    /// A gateway that received our request but failed to route cancels the
+    /// contract, which lets us refund it right away. Otherwise the gateway may
+    /// be offline and the contract only becomes refundable once its timelock
+    /// expires: it stays tracked as an outgoing payment until then and the
+    /// next gateway is funded from the remaining balance.
+    async fn reclaim_unexecuted_outgoing_contract(
+        &self,
+        contract_id: ContractId,
+        rng: impl RngCore + CryptoRng,
+    ) -> Result<()> {
+        let refundable = fedimint_core::task::timeout(
+            Duration::from_secs(10),
+            self.ln_client().await_outgoing_refundable(contract_id),
+        )
+        .await;
+
+        match refundable {
+            Ok(res) => {
+                res?;
+                self.try_refund_outgoing_contract(contract_id, rng).await?;
+                self.fetch_all_notes().await?;
+            }
+            Err(_) => {
+                info!(
+                    %contract_id,
+                    "Outgoing contract not cancelled, it will be refundable after its timelock"
+                );
+            }
+        }
+
+        Ok(())
     }
 
     pub async fn fund_outgoing_ln_contract<R: RngCore + CryptoRng>(
@@ -903,6 +1210,115 @@ impl Client<UserClientConfig> {
         Ok(OutPoint { txid, out_idx: 0 })
     }
 
+    /// Pays an invoice issued by another user of our federation by funding the
+    /// incoming contract of their offer directly. No gateway fee is charged
+    /// and no Lightning node is involved. Returns `None` if the invoice was
+    /// not issued in our federation and has to be paid over Lightning.
+    pub async fn fund_internal_payment<R: RngCore + CryptoRng>(
+        &self,
+        invoice: &Invoice,
+        mut rng: R,
+    ) -> Result<Option<(ContractId, OutPoint)>> {
+        let offer = match self.ln_client().get_internal_offer(invoice).await? {
+            Some(offer) => offer,
+            None => return Ok(None),
+        };
+
+        let refund_key = KeyPair::new(&self.context.secp, &mut rng);
+        let output = self
+            .ln_client()
+            .create_internal_payment_output(&offer, refund_key.x_only_public_key().0);
+        let contract = match &output {
+            LightningOutput::Contract(ContractOutput {
+                contract: Contract::Incoming(contract),
+                ..
+            }) => contract.clone(),
+            _ => unreachable!("Internal payments always fund incoming contracts"),
+        };
+        let contract_id = contract.contract_id();
+
+        // Persist the refund key before funding the contract so we can always
+        // reclaim the funds if decryption fails
+        let mut dbtx = self.context.db.begin_transaction().await;
+        dbtx.insert_new_entry(
+            &InternalPaymentKey(contract_id),
+            &InternalPaymentData {
+                refund_key,
+                contract_account: IncomingContractAccount {
+                    amount: offer.amount,
+                    contract,
+                },
+            },
+        )
+        .await;
+        dbtx.commit_tx().await;
+
+        let mut tx = TransactionBuilder::default();
+        let (mut keys, input) = self.mint_client().select_input(offer.amount).await?;
+        tx.input(&mut keys, input);
+        tx.output(Output::LN(output));
+        let txid = self.submit_tx_with_change(tx, &mut rng).await?;
+        let outpoint = OutPoint { txid, out_idx: 0 };
+
+        debug!("Funded internal payment {} in {}", contract_id, outpoint);
+        Ok(Some((contract_id, outpoint)))
+    }
+
+    /// Waits for the federation to decrypt the preimage of an internal payment
+    /// funded by [`Client::fund_internal_payment`]. If the payee's offer
+    /// contained an invalid preimage the funds are refunded to us.
+    pub async fn await_internal_payment(
+        &self,
+        contract_id: ContractId,
+        outpoint: OutPoint,
+        rng: impl RngCore + CryptoRng,
+    ) -> Result<Preimage> {
+        let result = self.await_preimage_decryption(outpoint).await;
+
+        let contract = self.ln_client().get_incoming_contract(contract_id).await?;
+        if let DecryptedPreimage::Invalid = contract.contract.decrypted_preimage {
+            self.refund_internal_payment(contract_id, rng).await?;
+            return Err(ClientError::InvalidPreimage);
+        }
+
+        let preimage = result?;
+        let mut dbtx = self.context.db.begin_transaction().await;
+        dbtx.remove_entry(&InternalPaymentKey(contract_id)).await;
+        dbtx.commit_tx().await;
+
+        Ok(preimage)
+    }
+
+    /// Reclaims the funds of an internal payment whose preimage failed to
+    /// decrypt
+    pub async fn refund_internal_payment(
+        &self,
+        contract_id: ContractId,
+        rng: impl RngCore + CryptoRng,
+    ) -> Result<TransactionId> {
+        let payment = self
+            .context
+            .db
+            .begin_transaction()
+            .await
+            .get_value(&InternalPaymentKey(contract_id))
+            .await
+            .ok_or(ClientError::RefundUnknownIncomingContract)?;
+
+        let mut tx = TransactionBuilder::default();
+        tx.input(
+            &mut vec![payment.refund_key],
+            Input::LN(payment.contract_account.claim()),
+        );
+        let txid = self.submit_tx_with_change(tx, rng).await?;
+
+        let mut dbtx = self.context.db.begin_transaction().await;
+        dbtx.remove_entry(&InternalPaymentKey(contract_id)).await;
+        dbtx.commit_tx().await;
+
+        Ok(txid)
+    }
+
     pub async fn await_outgoing_contract_acceptance(&self, outpoint: OutPoint) -> Result<()> {
         self.context
             .api
@@ -1169,6 +1585,16 @@ impl Client<UserClientConfig> {
 }
 
 impl Client<GatewayClientConfig> {
+    /// Acts as a regular user of the federation that spends and receives the
+    /// gateway's ecash, e.g. to pay invoices through other gateways
+    pub fn as_user_client(&self) -> UserClient {
+        Client {
+            config: UserClientConfig(self.config.client_config.clone()),
+            context: self.context.clone(),
+            root_secret: self.root_secret.clone(),
+        }
+    }
+
     /// Fetch the specified outgoing payment contract account
     pub async fn fetch_outgoing_contract(
         &self,
@@ -1433,44 +1859,6 @@ impl Client<GatewayClientConfig> {
             .await
     }
 
-    /// Wait for a lightning preimage gateway has purchased to be decrypted by
-    /// the federation
-    pub async fn await_preimage_decryption(&self, outpoint: OutPoint) -> Result<Preimage> {
-        let deadline = Instant::now().add(Duration::from_secs(30));
-
-        let poll = || async {
-            loop {
-                match self
-                    .context
-                    .api
-                    .await_output_outcome::<OutputOutcome>(
-                        outpoint,
-                        deadline.saturating_duration_since(Instant::now()),
-                        &self.context.decoders,
-                    )
-                    .await
-                {
-                    Ok(OutputOutcome::LN(o)) if !o.is_permanent() => {
-                        info!(outcome = %o, "Retrying on temporary outcome");
-                        sleep(Duration::from_secs(1)).await;
-                    }
-                    Ok(t) => return t.try_into_variant(),
-                    Err(e) => {
-                        return Err(e);
-                    }
-                }
-            }
-        };
-        Ok(
-            fedimint_core::task::timeout(
-                deadline.saturating_duration_since(Instant::now()),
-                poll(),
-            )
-            .await
-            .map_err(|_| ClientError::Timeout)??,
-        )
-    }
-
     // TODO: improve error propagation on tx transmission
     /// Waits for a outgoing contract claim transaction to be confirmed and
     /// retransmits it periodically if this does not happen.
@@ -1643,12 +2031,16 @@ pub enum ClientError {
     InvalidAmountTier(Amount),
     #[error("Invalid signature")]
     InvalidSignature,
+    #[error("Minimum balance {0} must be below maximum balance {1}")]
+    InvalidBalancePolicy(Amount, Amount),
     #[error("Violated fee policy")]
     ViolatedFeePolicy,
     #[error("Tried to cancel outgoing contract that we don't know about")]
     CancelUnknownOutgoingContract,
     #[error("Tried to refund outgoing contract that we don't know about")]
     RefundUnknownOutgoingContract,
+    #[error("Tried to refund incoming contract that we don't know about")]
+    RefundUnknownIncomingContract,
     #[error("Routing outgoing payment failed but we got a refund")]
     RefundedFailedPayment,
     #[error("Routing outgoing payment failed, we didn't get a refund (yet)")]
@@ -1682,3 +2074,60 @@ impl From<InvalidAmountTierError> for ClientError {
         ClientError::InvalidAmountTier(e.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::{msats, sats};
+
+    use crate::GatewayBalancePolicy;
+
+    fn policy(min_balance: Option<u64>, max_balance: Option<u64>) -> GatewayBalancePolicy {
+        GatewayBalancePolicy {
+            min_balance: min_balance.map(msats),
+            max_balance: max_balance.map(msats),
+            withdraw_address: None,
+        }
+    }
+
+    #[test]
+    fn validate_requires_min_below_max() {
+        assert!(policy(None, None).validate().is_ok());
+        assert!(policy(Some(1000), None).validate().is_ok());
+        assert!(policy(Some(1000), Some(2000)).validate().is_ok());
+        assert!(policy(Some(2000), Some(2000)).validate().is_err());
+        assert!(policy(Some(3000), Some(2000)).validate().is_err());
+    }
+
+    #[test]
+    fn is_below_min() {
+        assert!(!policy(None, None).is_below_min(msats(0)));
+        assert!(policy(Some(1000), None).is_below_min(msats(999)));
+        assert!(!policy(Some(1000), None).is_below_min(msats(1000)));
+    }
+
+    #[test]
+    fn excess() {
+        assert_eq!(policy(None, None).excess(msats(1_000_000)), None);
+        assert_eq!(policy(None, Some(1000)).excess(msats(1000)), None);
+        assert_eq!(
+            policy(None, Some(1000)).excess(msats(1_500)),
+            Some(msats(500))
+        );
+    }
+
+    #[test]
+    fn withdrawable_excess_skips_dust() {
+        let dust_limit = bitcoin::Amount::from_sat(546);
+        let policy = policy(None, Some(sats(1000).msats));
+
+        // 545.999 sats truncate to 545 which is dust
+        assert_eq!(
+            policy.withdrawable_excess(sats(1000) + msats(545_999), dust_limit),
+            None
+        );
+        assert_eq!(
+            policy.withdrawable_excess(sats(1000) + msats(546_999), dust_limit),
+            Some(bitcoin::Amount::from_sat(546))
+        );
+    }
+}
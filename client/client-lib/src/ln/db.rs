@@ -3,9 +3,10 @@ use fedimint_core::{impl_db_lookup, impl_db_record};
 use serde::Serialize;
 use strum_macros::EnumIter;
 
+use super::gateway::GatewayPreferences;
 use super::incoming::ConfirmedInvoice;
 use super::outgoing::OutgoingContractAccount;
-use crate::ln::outgoing::OutgoingContractData;
+use crate::ln::outgoing::{InternalPaymentData, OutgoingContractData};
 use crate::modules::ln::contracts::ContractId;
 use crate::modules::ln::LightningGateway;
 
@@ -17,6 +18,8 @@ pub enum DbKeyPrefix {
     OutgoingContractAccount = 0x25,
     ConfirmedInvoice = 0x26,
     LightningGateway = 0x28,
+    InternalPayment = 0x2c,
+    GatewayPreferences = 0x2d,
 }
 
 impl std::fmt::Display for DbKeyPrefix {
@@ -104,3 +107,35 @@ impl_db_lookup!(
     key = LightningGatewayKey,
     query_prefix = LightningGatewayKeyPrefix
 );
+
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct InternalPaymentKey(pub ContractId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct InternalPaymentKeyPrefix;
+
+impl_db_record!(
+    key = InternalPaymentKey,
+    value = InternalPaymentData,
+    db_prefix = DbKeyPrefix::InternalPayment,
+);
+impl_db_lookup!(
+    key = InternalPaymentKey,
+    query_prefix = InternalPaymentKeyPrefix
+);
+
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct GatewayPreferencesKey;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct GatewayPreferencesKeyPrefix;
+
+impl_db_record!(
+    key = GatewayPreferencesKey,
+    value = GatewayPreferences,
+    db_prefix = DbKeyPrefix::GatewayPreferences,
+);
+impl_db_lookup!(
+    key = GatewayPreferencesKey,
+    query_prefix = GatewayPreferencesKeyPrefix
+);
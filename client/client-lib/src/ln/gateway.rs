@@ -0,0 +1,236 @@
+use std::time::{Duration, SystemTime};
+
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::Amount;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+
+use crate::modules::ln::LightningGateway;
+
+/// User choices restricting which registered gateways are used for payments
+#[derive(Debug, Clone, Default, PartialEq, Eq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct GatewayPreferences {
+    /// Gateway that is always tried first if it is registered
+    pub pinned: Option<secp256k1::PublicKey>,
+    /// Gateways that are never used
+    pub blacklisted: Vec<secp256k1::PublicKey>,
+}
+
+impl GatewayPreferences {
+    pub fn is_blacklisted(&self, gateway: &LightningGateway) -> bool {
+        self.blacklisted.contains(&gateway.node_pub_key)
+    }
+}
+
+/// Fee a gateway advertises via the cheapest of its route hints for routing
+/// `amount` to its node. Gateways without route hints are reachable directly.
+pub fn advertised_fee(gateway: &LightningGateway, amount: Amount) -> Amount {
+    gateway
+        .route_hints
+        .iter()
+        .map(|route_hint| {
+            route_hint
+                .0
+                .iter()
+                .map(|hop| {
+                    let proportional =
+                        amount.msats * u64::from(hop.proportional_millionths) / 1_000_000;
+                    Amount::from_msats(u64::from(hop.base_msat) + proportional)
+                })
+                .sum::<Amount>()
+        })
+        .min()
+        .unwrap_or(Amount::ZERO)
+}
+
+/// Orders the registered `gateways` by preference for paying `amount`.
+///
+/// Gateways whose announcement expired before `now` are dropped along with
+/// blacklisted ones. The pinned gateway comes first, the rest are sorted by
+/// their advertised fee, preferring gateways with more route hints on ties
+/// since they are easier to reach. Whether the remaining gateways are actually
+/// online is checked separately by [`retain_live_gateways`].
+pub fn rank_gateways(
+    gateways: Vec<LightningGateway>,
+    preferences: &GatewayPreferences,
+    amount: Amount,
+    now: SystemTime,
+) -> Vec<LightningGateway> {
+    let mut gateways = gateways
+        .into_iter()
+        .filter(|gateway| now < gateway.valid_until && !preferences.is_blacklisted(gateway))
+        .collect::<Vec<_>>();
+
+    gateways.sort_by_key(|gateway| {
+        (
+            Some(gateway.node_pub_key) != preferences.pinned,
+            advertised_fee(gateway, amount),
+            std::cmp::Reverse(gateway.route_hints.len()),
+        )
+    });
+
+    gateways
+}
+
+/// How long a gateway may take to answer a liveness probe before it is
+/// considered offline
+pub const GATEWAY_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Probes the gateway's `/health` endpoint, a gateway is live if it answers
+/// with a success status within `timeout`
+pub async fn is_gateway_live(gateway: &LightningGateway, timeout: Duration) -> bool {
+    let url = match gateway.api.join("health") {
+        Ok(url) => url,
+        Err(_) => return false,
+    };
+    let request = reqwest::Client::new().get(url.as_str()).send();
+    match fedimint_core::task::timeout(timeout, request).await {
+        Ok(Ok(response)) => response.status().is_success(),
+        _ => false,
+    }
+}
+
+/// Drops the gateways that don't answer a liveness probe, probing all of them
+/// concurrently and keeping the order of the remaining ones
+pub async fn retain_live_gateways(
+    gateways: Vec<LightningGateway>,
+    timeout: Duration,
+) -> Vec<LightningGateway> {
+    let liveness = join_all(
+        gateways
+            .iter()
+            .map(|gateway| is_gateway_live(gateway, timeout)),
+    )
+    .await;
+
+    gateways
+        .into_iter()
+        .zip(liveness)
+        .filter_map(|(gateway, live)| live.then_some(gateway))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use fedimint_core::{msats, Amount};
+    use url::Url;
+
+    use super::{advertised_fee, rank_gateways, retain_live_gateways, GatewayPreferences};
+    use crate::modules::ln::route_hints::{RouteHint, RouteHintHop};
+    use crate::modules::ln::LightningGateway;
+
+    fn gateway(key_byte: u8, hop_fees: &[(u32, u32)], valid_until: SystemTime) -> LightningGateway {
+        gateway_at(key_byte, hop_fees, valid_until, "http://example.com")
+    }
+
+    fn gateway_at(
+        key_byte: u8,
+        hop_fees: &[(u32, u32)],
+        valid_until: SystemTime,
+        api: &str,
+    ) -> LightningGateway {
+        let node_pub_key = secp256k1::PublicKey::from_secret_key(
+            &secp256k1::Secp256k1::new(),
+            &secp256k1::SecretKey::from_slice(&[key_byte; 32]).unwrap(),
+        );
+        LightningGateway {
+            mint_channel_id: 0,
+            mint_pub_key: node_pub_key.x_only_public_key().0,
+            node_pub_key,
+            api: Url::parse(api).unwrap(),
+            route_hints: hop_fees
+                .iter()
+                .map(|(base_msat, proportional_millionths)| {
+                    RouteHint(vec![RouteHintHop {
+                        src_node_id: node_pub_key,
+                        short_channel_id: 0,
+                        base_msat: *base_msat,
+                        proportional_millionths: *proportional_millionths,
+                        cltv_expiry_delta: 10,
+                        htlc_minimum_msat: None,
+                        htlc_maximum_msat: None,
+                    }])
+                })
+                .collect(),
+            valid_until,
+        }
+    }
+
+    #[test]
+    fn advertised_fee_uses_cheapest_route_hint() {
+        let later = SystemTime::now() + Duration::from_secs(60);
+        let gw = gateway(1, &[(1000, 0), (10, 1000)], later);
+
+        assert_eq!(advertised_fee(&gw, msats(1_000_000)), msats(1000));
+        assert_eq!(advertised_fee(&gw, msats(0)), msats(10));
+        assert_eq!(
+            advertised_fee(&gateway(1, &[], later), msats(1_000_000)),
+            Amount::ZERO
+        );
+    }
+
+    #[test]
+    fn rank_prefers_pinned_then_cheapest() {
+        let now = SystemTime::now();
+        let later = now + Duration::from_secs(60);
+        let cheap = gateway(1, &[(1, 0)], later);
+        let expensive = gateway(2, &[(100, 0)], later);
+        let expired = gateway(3, &[], now - Duration::from_secs(1));
+        let blacklisted = gateway(4, &[], later);
+        let gateways = vec![
+            expensive.clone(),
+            expired,
+            blacklisted.clone(),
+            cheap.clone(),
+        ];
+
+        let mut preferences = GatewayPreferences {
+            pinned: None,
+            blacklisted: vec![blacklisted.node_pub_key],
+        };
+        assert_eq!(
+            rank_gateways(gateways.clone(), &preferences, msats(1000), now),
+            vec![cheap.clone(), expensive.clone()]
+        );
+
+        preferences.pinned = Some(expensive.node_pub_key);
+        assert_eq!(
+            rank_gateways(gateways, &preferences, msats(1000), now),
+            vec![expensive, cheap]
+        );
+    }
+
+    #[tokio::test]
+    async fn unreachable_gateways_are_not_live() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let live_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf).await;
+                let _ = stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                    .await;
+            }
+        });
+
+        // Bind and drop a listener to get a port nobody listens on
+        let dead_addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let later = SystemTime::now() + Duration::from_secs(60);
+        let live = gateway_at(1, &[], later, &format!("http://{live_addr}/"));
+        let dead = gateway_at(2, &[], later, &format!("http://{dead_addr}/"));
+
+        assert_eq!(
+            retain_live_gateways(vec![dead, live.clone()], Duration::from_secs(5)).await,
+            vec![live]
+        );
+    }
+}
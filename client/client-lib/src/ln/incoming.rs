@@ -8,7 +8,7 @@ use crate::modules::ln::contracts::incoming::IncomingContract;
 use crate::modules::ln::contracts::{ContractId, IdentifiableContract};
 use crate::modules::ln::LightningInput;
 
-#[derive(Debug, Clone, Encodable, Decodable)]
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
 pub struct IncomingContractAccount {
     pub amount: Amount,
     pub contract: IncomingContract,
@@ -1,5 +1,6 @@
 // TODO: once user and mint client are merged, make this private again
 pub mod db;
+pub mod gateway;
 pub mod incoming;
 pub mod outgoing;
 
@@ -28,10 +29,11 @@ use crate::ln::db::{OutgoingPaymentKey, OutgoingPaymentKeyPrefix};
 use crate::ln::incoming::IncomingContractAccount;
 use crate::ln::outgoing::{OutgoingContractAccount, OutgoingContractData};
 use crate::modules::ln::config::LightningClientConfig;
-use crate::modules::ln::contracts::incoming::IncomingContractOffer;
+use crate::modules::ln::contracts::incoming::{IncomingContract, IncomingContractOffer};
 use crate::modules::ln::contracts::outgoing::OutgoingContract;
 use crate::modules::ln::contracts::{
-    Contract, ContractId, EncryptedPreimage, FundedContract, IdentifiableContract, Preimage,
+    Contract, ContractId, DecryptedPreimage, EncryptedPreimage, FundedContract,
+    IdentifiableContract, Preimage,
 };
 use crate::modules::ln::{
     ContractAccount, ContractOutput, LightningGateway, LightningInput, LightningModuleTypes,
@@ -261,6 +263,52 @@ impl LnClient {
             .map_err(LnClientError::ApiError)
     }
 
+    /// Looks up the offer of an invoice issued by a user of our own federation.
+    /// Such invoices can be paid by funding the incoming contract directly
+    /// instead of routing the payment through a Lightning gateway. Returns
+    /// `None` if no offer for the invoice's payment hash exists.
+    pub async fn get_internal_offer(
+        &self,
+        invoice: &Invoice,
+    ) -> Result<Option<IncomingContractOffer>> {
+        let payment_hash = *invoice.payment_hash();
+        if !self.offer_exists(payment_hash).await? {
+            return Ok(None);
+        }
+
+        let offer = self.get_offer(payment_hash).await?;
+        let invoice_amount = invoice
+            .amount_milli_satoshis()
+            .ok_or(LnClientError::MissingInvoiceAmount)?;
+        if offer.amount > Amount::from_msats(invoice_amount) {
+            return Err(LnClientError::OfferExceedsInvoice(
+                offer.amount,
+                Amount::from_msats(invoice_amount),
+            ));
+        }
+
+        Ok(Some(offer))
+    }
+
+    /// Create an output funding the incoming contract of `offer`, paying its
+    /// creator directly. If the federation fails to decrypt a valid preimage
+    /// the funds can be reclaimed with `refund_key`.
+    pub fn create_internal_payment_output(
+        &self,
+        offer: &IncomingContractOffer,
+        refund_key: secp256k1_zkp::XOnlyPublicKey,
+    ) -> LightningOutput {
+        LightningOutput::Contract(ContractOutput {
+            amount: offer.amount,
+            contract: Contract::Incoming(IncomingContract {
+                hash: offer.hash,
+                encrypted_preimage: offer.encrypted_preimage.clone(),
+                decrypted_preimage: DecryptedPreimage::Pending,
+                gateway_key: refund_key,
+            }),
+        })
+    }
+
     pub async fn save_confirmed_invoice(&self, invoice: &ConfirmedInvoice) {
         let mut dbtx = self.context.db.begin_transaction().await;
         dbtx.insert_entry(&ConfirmedInvoiceKey(invoice.contract_id()), invoice)
@@ -323,6 +371,8 @@ pub enum LnClientError {
     WrongAccountType,
     #[error("No ConfirmedOffer found for contract ID {0}")]
     NoConfirmedInvoice(ContractId),
+    #[error("Offer asks for {0}, more than the invoice amount {1}")]
+    OfferExceedsInvoice(Amount, Amount),
 }
 
 #[cfg(test)]
@@ -337,7 +387,7 @@ mod tests {
     use fedimint_core::db::mem_impl::MemDatabase;
     use fedimint_core::db::Database;
     use fedimint_core::module::registry::ModuleDecoderRegistry;
-    use fedimint_core::outcome::{SerdeOutputOutcome, TransactionStatus};
+    use fedimint_core::outcome::{OutputStatus, SerdeOutputOutcome, TransactionStatus};
     use fedimint_core::{Amount, OutPoint, ServerModule, TransactionId};
     use fedimint_ln_server::{Lightning, LightningGen};
     use fedimint_testing::FakeFed;
@@ -386,21 +436,15 @@ mod tests {
                 },
             )
             .with(
-                "/wait_transaction",
-                move |mint: Arc<Mutex<FakeFed<Lightning>>>, tx: TransactionId| async move {
+                "/subscribe_output_outcome",
+                move |mint: Arc<Mutex<FakeFed<Lightning>>>, out_point: OutPoint| async move {
                     let mint = mint.lock().await;
-                    Ok(TransactionStatus::Accepted {
-                        epoch: 0,
-                        outputs: vec![SerdeOutputOutcome::from(&DynOutputOutcome::from_typed(
+                    Ok(OutputStatus::Accepted(SerdeOutputOutcome::from(
+                        &DynOutputOutcome::from_typed(
                             module_id,
-                            mint.output_outcome(OutPoint {
-                                txid: tx,
-                                out_idx: 0,
-                            })
-                            .await
-                            .unwrap(),
-                        ))],
-                    })
+                            mint.output_outcome(out_point).await.unwrap(),
+                        ),
+                    )))
                 },
             )
             .with(
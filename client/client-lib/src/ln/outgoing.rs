@@ -2,6 +2,7 @@ use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::Amount;
 use serde::Serialize;
 
+use crate::ln::incoming::IncomingContractAccount;
 use crate::modules::ln::contracts::outgoing::OutgoingContract;
 use crate::modules::ln::contracts::{IdentifiableContract, Preimage};
 use crate::modules::ln::LightningInput;
@@ -35,3 +36,13 @@ impl OutgoingContractAccount {
         }
     }
 }
+
+/// An incoming contract of another user of our federation that we funded to pay
+/// their invoice without routing the payment through a Lightning gateway
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct InternalPaymentData {
+    /// Key that can reclaim the funds if the federation fails to decrypt a
+    /// valid preimage
+    pub refund_key: bitcoin::KeyPair,
+    pub contract_account: IncomingContractAccount,
+}
@@ -11,7 +11,7 @@ use std::cmp::{max, Reverse};
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::ops::Range;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use fedimint_core::api::{FederationError, GlobalFederationApi};
 use fedimint_core::cancellable::{Cancellable, Cancelled};
 use fedimint_core::core::LEGACY_HARDCODED_INSTANCE_ID_MINT;
@@ -256,12 +256,50 @@ impl MintClient {
             .buffered(8)
     }
 
+    /// Recovers the ecash state by replaying the epochs since `backup` was
+    /// taken. If the federation pruned some of these epochs, recovery restarts
+    /// from the latest backup snapshot stored with the federation.
     pub async fn restore_current_state_from_backup(
         &self,
         task_group: &mut TaskGroup,
-        backup: PlaintextEcashBackup,
+        mut backup: PlaintextEcashBackup,
         gap_limit: usize,
     ) -> Result<Cancellable<EcashRecoveryFinalState>> {
+        loop {
+            match self
+                .replay_epochs_since_backup(task_group, backup, gap_limit)
+                .await?
+            {
+                Ok(Ok(state)) => return Ok(Ok(state)),
+                Ok(Err(Cancelled)) => return Ok(Err(Cancelled)),
+                Err(PrunedEpoch { epoch, start_epoch }) => {
+                    backup = match self.download_ecash_backup_from_federation().await? {
+                        Some(latest) if start_epoch < latest.epoch_count.saturating_sub(1) => {
+                            warn!(
+                                target: LOG_ECASH_RECOVERY,
+                                epoch,
+                                latest_epoch_count = latest.epoch_count,
+                                "Epoch history was pruned, restarting recovery from the latest backup"
+                            );
+                            latest
+                        }
+                        _ => bail!(
+                            "Epoch {epoch} needed for recovery was pruned by the federation and no more recent backup snapshot exists"
+                        ),
+                    };
+                }
+            }
+        }
+    }
+
+    /// Replays the epochs since `backup` was taken, returning the first epoch
+    /// the federation pruned if it can't be fetched
+    async fn replay_epochs_since_backup(
+        &self,
+        task_group: &mut TaskGroup,
+        backup: PlaintextEcashBackup,
+        gap_limit: usize,
+    ) -> Result<std::result::Result<Cancellable<EcashRecoveryFinalState>, PrunedEpoch>> {
         let current_epoch_count = match self.context.api.fetch_epoch_count().await {
             Ok(v) => v,
             Err(e) => {
@@ -292,13 +330,17 @@ impl MintClient {
         let mut epoch_stream = self.fetch_epochs_stream(epoch_range);
         while let Some((epoch, epoch_res)) = epoch_stream.next().await {
             if task_handle.is_shutting_down() {
-                return Ok(Err(Cancelled));
+                return Ok(Ok(Err(Cancelled)));
             }
             // if `recv` returned `None` that means fetch_epoch finished prematurelly,
             // withouth sending an `Err` which is supposed to mean `is_shutting_down() ==
             // true`
             info!(target: LOG_ECASH_RECOVERY, epoch, "Awaiting epoch");
-            let epoch_history = epoch_res?;
+            let epoch_history = match epoch_res {
+                Ok(epoch_history) => epoch_history,
+                Err(e) if e.is_pruned() => return Ok(Err(PrunedEpoch { epoch, start_epoch })),
+                Err(e) => return Err(e.into()),
+            };
             assert_eq!(epoch_history.outcome.epoch, epoch);
 
             info!(target: LOG_ECASH_RECOVERY, epoch, "Processing epoch");
@@ -315,10 +357,18 @@ impl MintClient {
             }
         }
 
-        Ok(Ok(tracker.finalize()))
+        Ok(Ok(Ok(tracker.finalize())))
     }
 }
 
+/// Epoch that could not be replayed during recovery since the federation
+/// pruned it
+struct PrunedEpoch {
+    epoch: u64,
+    /// First epoch the failed recovery attempt replayed
+    start_epoch: u64,
+}
+
 /// Snapshot of a ecash state (notes)
 ///
 /// Used to speed up and improve privacy of ecash recovery,
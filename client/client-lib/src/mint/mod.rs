@@ -663,7 +663,7 @@ mod tests {
     use fedimint_core::db::mem_impl::MemDatabase;
     use fedimint_core::db::Database;
     use fedimint_core::module::registry::ModuleDecoderRegistry;
-    use fedimint_core::outcome::{SerdeOutputOutcome, TransactionStatus};
+    use fedimint_core::outcome::{OutputStatus, SerdeOutputOutcome, TransactionStatus};
     use fedimint_core::{Amount, OutPoint, ServerModule, Tiered, TransactionId};
     use fedimint_mint_server::{Mint, MintGen, MintGenParams};
     use fedimint_testing::FakeFed;
@@ -714,21 +714,15 @@ mod tests {
                 },
             )
             .with(
-                "/wait_transaction",
-                move |mint: Arc<Mutex<FakeFed<Mint>>>, tx: TransactionId| async move {
+                "/subscribe_output_outcome",
+                move |mint: Arc<Mutex<FakeFed<Mint>>>, out_point: OutPoint| async move {
                     let mint = mint.lock().await;
-                    Ok(TransactionStatus::Accepted {
-                        epoch: 0,
-                        outputs: vec![SerdeOutputOutcome::from(&DynOutputOutcome::from_typed(
+                    Ok(OutputStatus::Accepted(SerdeOutputOutcome::from(
+                        &DynOutputOutcome::from_typed(
                             module_id,
-                            mint.output_outcome(OutPoint {
-                                txid: tx,
-                                out_idx: 0,
-                            })
-                            .await
-                            .unwrap(),
-                        ))],
-                    })
+                            mint.output_outcome(out_point).await.unwrap(),
+                        ),
+                    )))
                 },
             )
     }
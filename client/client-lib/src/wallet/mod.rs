@@ -202,7 +202,7 @@ mod tests {
     use fedimint_core::db::mem_impl::MemDatabase;
     use fedimint_core::db::Database;
     use fedimint_core::module::registry::ModuleDecoderRegistry;
-    use fedimint_core::outcome::{SerdeOutputOutcome, TransactionStatus};
+    use fedimint_core::outcome::{OutputStatus, SerdeOutputOutcome, TransactionStatus};
     use fedimint_core::task::TaskGroup;
     use fedimint_core::{Feerate, OutPoint, ServerModule, TransactionId};
     use fedimint_testing::btc::bitcoind::{FakeBitcoindRpc, FakeBitcoindRpcController};
@@ -250,15 +250,14 @@ mod tests {
                 },
             )
             .with(
-                "/wait_transaction",
-                move |_mint: Arc<Mutex<FakeFed<Wallet>>>, _tx: TransactionId| async move {
-                    Ok(TransactionStatus::Accepted {
-                        epoch: 0,
-                        outputs: vec![SerdeOutputOutcome::from(&DynOutputOutcome::from_typed(
+                "/subscribe_output_outcome",
+                move |_mint: Arc<Mutex<FakeFed<Wallet>>>, _out_point: OutPoint| async move {
+                    Ok(OutputStatus::Accepted(SerdeOutputOutcome::from(
+                        &DynOutputOutcome::from_typed(
                             module_id,
                             WalletOutputOutcome(Txid::from_slice([0; 32].as_slice()).unwrap()),
-                        ))],
-                    })
+                        ),
+                    )))
                 },
             )
     }
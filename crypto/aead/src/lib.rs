@@ -17,12 +17,20 @@ pub fn get_random_nonce() -> ring::aead::Nonce {
 /// Encrypt `plaintext` using `key`.
 ///
 /// Prefixes the ciphertext with a nonce.
-pub fn encrypt(mut plaintext: Vec<u8>, key: &LessSafeKey) -> Result<Vec<u8>> {
+pub fn encrypt(plaintext: Vec<u8>, key: &LessSafeKey) -> Result<Vec<u8>> {
+    encrypt_with_aad(plaintext, &[], key)
+}
+
+/// Encrypt `plaintext` using `key`, authenticating the additional data `aad`
+/// which has to be passed again to [`decrypt_with_aad`].
+///
+/// Prefixes the ciphertext with a nonce.
+pub fn encrypt_with_aad(mut plaintext: Vec<u8>, aad: &[u8], key: &LessSafeKey) -> Result<Vec<u8>> {
     let nonce = get_random_nonce();
     // prefix ciphertext with nonce
     let mut ciphertext: Vec<u8> = nonce.as_ref().to_vec();
 
-    key.seal_in_place_append_tag(nonce, Aad::empty(), &mut plaintext)
+    key.seal_in_place_append_tag(nonce, Aad::from(aad), &mut plaintext)
         .map_err(|_| anyhow::format_err!("Encryption failed due to unspecified aead error"))?;
 
     ciphertext.append(&mut plaintext);
@@ -34,6 +42,16 @@ pub fn encrypt(mut plaintext: Vec<u8>, key: &LessSafeKey) -> Result<Vec<u8>> {
 ///
 /// Expect nonce in the prefix, like [`encrypt`] produces.
 pub fn decrypt<'c>(ciphertext: &'c mut [u8], key: &LessSafeKey) -> Result<&'c [u8]> {
+    decrypt_with_aad(ciphertext, &[], key)
+}
+
+/// Decrypts a `ciphertext` produced by [`encrypt_with_aad`] using `key`, fails
+/// if `aad` differs from the additional data it was encrypted with.
+pub fn decrypt_with_aad<'c>(
+    ciphertext: &'c mut [u8],
+    aad: &[u8],
+    key: &LessSafeKey,
+) -> Result<&'c [u8]> {
     if ciphertext.len() < NONCE_LEN {
         bail!("Ciphertext too short: {}", ciphertext.len());
     }
@@ -42,7 +60,7 @@ pub fn decrypt<'c>(ciphertext: &'c mut [u8], key: &LessSafeKey) -> Result<&'c [u
 
     key.open_in_place(
         Nonce::assume_unique_for_key(nonce_bytes.try_into().expect("nonce size known")),
-        Aad::empty(),
+        Aad::from(aad),
         encrypted_bytes,
     )
     .map_err(|_| format_err!("Decryption failed due to unspecified aead error"))?;
@@ -86,14 +104,24 @@ pub fn encrypted_read(key: &LessSafeKey, file: PathBuf) -> Result<Vec<u8>> {
 /// * `password` - Strong user-created password
 /// * `salt` - Nonce >8 bytes to discourage rainbow attacks
 pub fn get_encryption_key(password: &str, salt: &str) -> Result<LessSafeKey> {
+    let key = get_encryption_key_raw(password, salt)?;
+    let key = UnboundKey::new(&ring::aead::CHACHA20_POLY1305, &key)
+        .map_err(|_| anyhow::Error::msg("Unable to create key"))?;
+    Ok(LessSafeKey::new(key))
+}
+
+/// Raw bytes of the key returned by [`get_encryption_key`], only use these to
+/// derive further keys from
+pub fn get_encryption_key_raw(
+    password: &str,
+    salt: &str,
+) -> Result<[u8; ring::digest::SHA256_OUTPUT_LEN]> {
     let mut key = [0u8; ring::digest::SHA256_OUTPUT_LEN];
 
     argon2()
         .hash_password_into(password.as_bytes(), salt.as_bytes(), &mut key)
         .map_err(|e| format_err!("could not hash password").context(e))?;
-    let key = UnboundKey::new(&ring::aead::CHACHA20_POLY1305, &key)
-        .map_err(|_| anyhow::Error::msg("Unable to create key"))?;
-    Ok(LessSafeKey::new(key))
+    Ok(key)
 }
 
 /// Memory-hard Argon2 key stretching for password-based authentication
@@ -126,7 +154,10 @@ fn argon2() -> Argon2<'static> {
 
 #[cfg(test)]
 mod tests {
-    use crate::{decrypt, encrypt, get_encryption_key, get_password_hash, random_salt};
+    use crate::{
+        decrypt, decrypt_with_aad, encrypt, encrypt_with_aad, get_encryption_key,
+        get_password_hash, random_salt,
+    };
 
     #[test]
     fn encrypts_and_decrypts() {
@@ -141,6 +172,19 @@ mod tests {
         assert_eq!(decrypted, message.as_bytes());
     }
 
+    #[test]
+    fn decryption_requires_same_aad() {
+        let key = get_encryption_key("test123", "salt1235").unwrap();
+        let cipher_text = encrypt_with_aad(b"hello world".to_vec(), b"key", &key).unwrap();
+
+        assert_eq!(
+            decrypt_with_aad(&mut cipher_text.clone(), b"key", &key).unwrap(),
+            b"hello world"
+        );
+        assert!(decrypt_with_aad(&mut cipher_text.clone(), b"other key", &key).is_err());
+        assert!(decrypt(&mut cipher_text.clone(), &key).is_err());
+    }
+
     #[test]
     fn password_hashing_works() {
         let password = "test1";
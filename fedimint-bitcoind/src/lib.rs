@@ -13,8 +13,12 @@ use fedimint_core::task::TaskHandle;
 use fedimint_core::{dyn_newtype_define, Feerate};
 use tracing::info;
 
+use crate::metrics::{BITCOIND_RPC_DURATION, BITCOIND_RPC_ERRORS};
+
 #[cfg(feature = "bitcoincore-rpc")]
 pub mod bitcoincore_rpc;
+/// Prometheus metrics of the Bitcoin RPC calls
+pub mod metrics;
 
 /// Trait that allows interacting with the Bitcoin blockchain
 ///
@@ -95,18 +99,27 @@ impl<C> RetryClient<C> {
 
     /// Retries with an exponential backoff from `RETRY_SLEEP_MIN_MS` to
     /// `RETRY_SLEEP_MAX_MS`
-    async fn retry_call<T, F, R>(&self, call_fn: F) -> Result<T>
+    ///
+    /// The latency and errors of every attempt are recorded in the metrics of
+    /// `method`.
+    async fn retry_call<T, F, R>(&self, method: &str, call_fn: F) -> Result<T>
     where
         F: Fn() -> R,
         R: Future<Output = Result<T>>,
     {
         let mut retry_time = RETRY_SLEEP_MIN_MS;
         let ret = loop {
-            match call_fn().await {
+            let timer = BITCOIND_RPC_DURATION
+                .with_label_values(&[method])
+                .start_timer();
+            let res = call_fn().await;
+            timer.observe_duration();
+            match res {
                 Ok(ret) => {
                     break ret;
                 }
                 Err(e) => {
+                    BITCOIND_RPC_ERRORS.with_label_values(&[method]).inc();
                     if self.task_handle.is_shutting_down() {
                         return Err(e);
                     }
@@ -131,33 +144,41 @@ where
     }
 
     async fn get_network(&self) -> Result<Network> {
-        self.retry_call(|| async { self.inner.get_network().await })
+        self.retry_call("get_network", || async { self.inner.get_network().await })
             .await
     }
 
     async fn get_block_height(&self) -> Result<u64> {
-        self.retry_call(|| async { self.inner.get_block_height().await })
-            .await
+        self.retry_call("get_block_height", || async {
+            self.inner.get_block_height().await
+        })
+        .await
     }
 
     async fn get_block_hash(&self, height: u64) -> Result<BlockHash> {
-        self.retry_call(|| async { self.inner.get_block_hash(height).await })
-            .await
+        self.retry_call("get_block_hash", || async {
+            self.inner.get_block_hash(height).await
+        })
+        .await
     }
 
     async fn get_block(&self, hash: &BlockHash) -> Result<Block> {
-        self.retry_call(|| async { self.inner.get_block(hash).await })
+        self.retry_call("get_block", || async { self.inner.get_block(hash).await })
             .await
     }
 
     async fn get_fee_rate(&self, confirmation_target: u16) -> Result<Option<Feerate>> {
-        self.retry_call(|| async { self.inner.get_fee_rate(confirmation_target).await })
-            .await
+        self.retry_call("get_fee_rate", || async {
+            self.inner.get_fee_rate(confirmation_target).await
+        })
+        .await
     }
 
     async fn submit_transaction(&self, transaction: Transaction) -> Result<()> {
-        self.retry_call(|| async { self.inner.submit_transaction(transaction.clone()).await })
-            .await
+        self.retry_call("submit_transaction", || async {
+            self.inner.submit_transaction(transaction.clone()).await
+        })
+        .await
     }
 
     async fn was_transaction_confirmed_in(
@@ -165,7 +186,7 @@ where
         transaction: &Transaction,
         height: u64,
     ) -> Result<bool> {
-        self.retry_call(|| async {
+        self.retry_call("was_transaction_confirmed_in", || async {
             self.inner
                 .was_transaction_confirmed_in(transaction, height)
                 .await
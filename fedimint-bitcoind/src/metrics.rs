@@ -0,0 +1,30 @@
+use fedimint_metrics::{
+    histogram_opts, opts, register_histogram_vec_with_registry,
+    register_int_counter_vec_with_registry, HistogramVec, IntCounterVec, NETWORK_LATENCY_BUCKETS,
+    REGISTRY,
+};
+use once_cell::sync::Lazy;
+
+/// Duration of single Bitcoin RPC calls, retries are observed separately
+pub static BITCOIND_RPC_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec_with_registry!(
+        histogram_opts!(
+            "bitcoind_rpc_duration_seconds",
+            "Duration of Bitcoin RPC calls",
+            NETWORK_LATENCY_BUCKETS.to_vec()
+        ),
+        &["method"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Bitcoin RPC calls that returned an error and will be retried
+pub static BITCOIND_RPC_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        opts!("bitcoind_rpc_errors_total", "Failed Bitcoin RPC calls"),
+        &["method"],
+        REGISTRY
+    )
+    .unwrap()
+});
@@ -2,6 +2,7 @@ use std::borrow::Cow;
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{self, Debug, Display, Formatter};
 use std::io::{Cursor, Read};
+use std::net::SocketAddr;
 use std::pin::Pin;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -28,7 +29,7 @@ use futures::stream::FuturesUnordered;
 use futures::{Future, StreamExt};
 #[cfg(not(target_family = "wasm"))]
 use jsonrpsee_core::client::CertificateStore;
-use jsonrpsee_core::client::ClientT;
+use jsonrpsee_core::client::{ClientT, Subscription, SubscriptionClientT};
 use jsonrpsee_core::Error as JsonRpcError;
 #[cfg(target_family = "wasm")]
 use jsonrpsee_wasm_client::{Client as WsClient, WasmClientBuilder as WsClientBuilder};
@@ -42,8 +43,8 @@ use tracing::{debug, error, instrument, trace};
 use url::Url;
 
 use crate::epoch::{SerdeEpochHistory, SignedEpochOutcome};
-use crate::module::ApiRequestErased;
-use crate::outcome::TransactionStatus;
+use crate::module::{ApiError, ApiRequestErased};
+use crate::outcome::{OutputStatus, TransactionStatus};
 use crate::query::{
     CurrentConsensus, EventuallyConsistent, QueryStep, QueryStrategy, UnionResponses,
     VerifiableResponse,
@@ -56,6 +57,18 @@ pub type MemberResult<T> = result::Result<T, MemberError>;
 pub type JsonRpcResult<T> = result::Result<T, jsonrpsee_core::Error>;
 pub type FederationResult<T> = result::Result<T, FederationError>;
 
+/// Results pushed by a peer to a subscription
+#[cfg(not(target_family = "wasm"))]
+pub type JsonRpcSubscription = futures::stream::BoxStream<'static, JsonRpcResult<Value>>;
+/// Results pushed by a peer to a subscription
+#[cfg(target_family = "wasm")]
+pub type JsonRpcSubscription = futures::stream::LocalBoxStream<'static, JsonRpcResult<Value>>;
+
+/// Method that cancels a subscription started by calling `method`
+pub fn unsubscribe_method(method: &str) -> String {
+    format!("{method}/unsubscribe")
+}
+
 /// An API request error when calling a single federation member
 #[derive(Debug, Error)]
 pub enum MemberError {
@@ -77,12 +90,23 @@ impl MemberError {
             MemberError::Rpc(rpc_e) => match rpc_e {
                 JsonRpcError::Transport(_) => true,
                 JsonRpcError::Internal(_) => true,
-                JsonRpcError::Call(jsonrpsee_types::error::CallError::Custom(e)) => e.code() == 404,
+                JsonRpcError::Call(jsonrpsee_types::error::CallError::Custom(e)) => {
+                    e.code() == 404 || e.code() == 429
+                }
                 _ => false,
             },
             MemberError::InvalidResponse(_) => false,
         }
     }
+
+    /// Whether the peer answered that the requested data was pruned
+    pub fn is_pruned(&self) -> bool {
+        matches!(
+            self,
+            MemberError::Rpc(JsonRpcError::Call(jsonrpsee_types::error::CallError::Custom(e)))
+                if e.code() == 410
+        )
+    }
 }
 
 /// An API request error when calling an entire federation
@@ -107,6 +131,11 @@ impl FederationError {
     pub fn is_retryable(&self) -> bool {
         self.0.iter().any(|(_, e)| e.is_retryable())
     }
+
+    /// Whether any peer answered that the requested data was pruned
+    pub fn is_pruned(&self) -> bool {
+        self.0.values().any(MemberError::is_pruned)
+    }
 }
 
 type OutputOutcomeResult<O> = result::Result<O, OutputOutcomeError>;
@@ -145,6 +174,15 @@ pub trait IFederationApi: Debug + MaybeSend + MaybeSync {
         method: &str,
         params: &[Value],
     ) -> result::Result<Value, jsonrpsee_core::Error>;
+
+    /// Subscribe to results pushed by a specific federation member by
+    /// `peer_id`
+    async fn subscribe_raw(
+        &self,
+        peer_id: PeerId,
+        method: &str,
+        params: &[Value],
+    ) -> JsonRpcResult<JsonRpcSubscription>;
 }
 
 pub trait DynTryIntoOutcome: Sized {
@@ -258,6 +296,89 @@ pub trait FederationApiExt: IFederationApi {
         }
     }
 
+    /// Subscribe to `method` on all members, using strategies created by
+    /// `new_strategy` to logically merge the results they push. Only the
+    /// latest result of every member counts, so a member that changed its
+    /// result is no longer counted for the previous one. Members whose
+    /// subscription ended keep their latest result.
+    async fn subscribe_with_strategy<
+        MemberRet: serde::de::DeserializeOwned + Clone + MaybeSend,
+        FedRet: Debug,
+        Strategy: QueryStrategy<MemberRet, FedRet>,
+    >(
+        &self,
+        new_strategy: impl Fn() -> Strategy + MaybeSend,
+        method: String,
+        params: ApiRequestErased,
+    ) -> FederationResult<FedRet> {
+        let subscriptions =
+            futures::future::join_all(self.all_members().iter().map(|peer_id| async {
+                (
+                    *peer_id,
+                    self.subscribe_raw(*peer_id, &method, &[params.to_json()])
+                        .await,
+                )
+            }))
+            .await;
+
+        let mut member_errors = BTreeMap::new();
+        let mut pushed = futures::stream::SelectAll::new();
+        for (peer, subscription) in subscriptions {
+            match subscription {
+                // A `None` marks the end of the subscription
+                Ok(subscription) => pushed.push(
+                    subscription
+                        .map(Some)
+                        .chain(futures::stream::once(futures::future::ready(None)))
+                        .map(move |result| (peer, result)),
+                ),
+                Err(e) => {
+                    member_errors.insert(peer, MemberError::Rpc(e));
+                }
+            }
+        }
+
+        let mut latest_results = BTreeMap::new();
+        while let Some((peer, result)) = pushed.next().await {
+            let Some(result) = result else {
+                debug!(%peer, method, "Member closed subscription");
+                continue;
+            };
+            trace!(?result, method, params = ?AbbreviateDebug(params.to_json()), "Received pushed member result");
+            let result: MemberResult<MemberRet> = result.map_err(MemberError::Rpc).and_then(|o| {
+                serde_json::from_value::<MemberRet>(o)
+                    .map_err(|e| MemberError::ResponseDeserialization(e.into()))
+            });
+            match result {
+                Ok(result) => {
+                    member_errors.remove(&peer);
+                    latest_results.insert(peer, result);
+                }
+                Err(error) => {
+                    latest_results.remove(&peer);
+                    member_errors.insert(peer, error);
+                }
+            }
+
+            let mut strategy = new_strategy();
+            for (peer, result) in &latest_results {
+                match strategy.process(*peer, Ok(result.clone())) {
+                    QueryStep::RetryMembers(_) | QueryStep::Continue => {}
+                    QueryStep::FailMembers(failed) => {
+                        member_errors.extend(failed);
+                    }
+                    QueryStep::Failure(failed) => {
+                        member_errors.extend(failed);
+                        return Err(FederationError(member_errors));
+                    }
+                    QueryStep::Success(response) => return Ok(response),
+                }
+            }
+        }
+
+        Err(FederationError(member_errors))
+    }
+
     async fn request_union<Ret>(
         &self,
         method: String,
@@ -413,8 +534,12 @@ where
 
     /// Await the outcome of an entire transaction
     async fn await_tx_outcome(&self, tx: &TransactionId) -> FederationResult<TransactionStatus> {
-        self.request_current_consensus("/wait_transaction".to_owned(), ApiRequestErased::new(tx))
-            .await
+        self.subscribe_with_strategy(
+            || CurrentConsensus::new(self.all_members().one_honest()),
+            "/subscribe_transaction".to_owned(),
+            ApiRequestErased::new(tx),
+        )
+        .await
     }
 
     async fn fetch_epoch_history(
@@ -491,7 +616,6 @@ where
             .transpose()?)
     }
 
-    // TODO should become part of the API
     async fn await_output_outcome<R: DynTryIntoOutcome + MaybeSend>(
         &self,
         outpoint: OutPoint,
@@ -499,8 +623,22 @@ where
         decoders: &ModuleDecoderRegistry,
     ) -> OutputOutcomeResult<R> {
         fedimint_core::task::timeout(timeout, async move {
-            let tx_outcome = self.await_tx_outcome(&outpoint.txid).await?;
-            map_tx_outcome_outpoint(tx_outcome, outpoint, decoders)
+            let status: OutputStatus = self
+                .subscribe_with_strategy(
+                    || CurrentConsensus::new(self.all_members().one_honest()),
+                    "/subscribe_output_outcome".to_owned(),
+                    ApiRequestErased::new(outpoint),
+                )
+                .await?;
+            match status {
+                OutputStatus::Rejected(e) => Err(OutputOutcomeError::Rejected(e)),
+                OutputStatus::Accepted(outcome) => R::try_into_outcome(
+                    outcome
+                        .try_into_inner(decoders)
+                        .map_err(|e| OutputOutcomeError::ResponseDeserialization(e.into()))?,
+                )
+                .map_err(OutputOutcomeError::Core),
+            }
         })
         .await
         .map_err(|_| OutputOutcomeError::Timeout(timeout))?
@@ -551,6 +689,7 @@ pub struct WsFederationApi<C = WsClient> {
 struct FederationMember<C> {
     url: Url,
     peer_id: PeerId,
+    socks5_proxy: Option<SocketAddr>,
     client: RwLock<Option<C>>,
 }
 
@@ -689,32 +828,93 @@ impl<C: JsonRpcClient + Debug + MaybeSend + MaybeSync> IFederationApi for WsFede
 
         member.request(method, params).await
     }
+
+    async fn subscribe_raw(
+        &self,
+        peer_id: PeerId,
+        method: &str,
+        params: &[Value],
+    ) -> JsonRpcResult<JsonRpcSubscription> {
+        let member = self
+            .members
+            .iter()
+            .find(|m| m.peer_id == peer_id)
+            .ok_or_else(|| JsonRpcError::Custom(format!("Invalid peer_id: {peer_id}")))?;
+
+        member.subscribe(method, params).await
+    }
 }
 
 #[apply(async_trait_maybe_send!)]
 pub trait JsonRpcClient: ClientT + Sized {
-    async fn connect(url: &Url) -> result::Result<Self, JsonRpcError>;
+    /// Connects to `url`, through the SOCKS5 proxy if one is given
+    async fn connect(
+        url: &Url,
+        socks5_proxy: Option<SocketAddr>,
+    ) -> result::Result<Self, JsonRpcError>;
     fn is_connected(&self) -> bool;
+
+    /// Subscribes to `method`, returning the results pushed by the server
+    async fn subscribe_raw(
+        &self,
+        method: &str,
+        params: &[Value],
+    ) -> result::Result<JsonRpcSubscription, JsonRpcError>;
 }
 
 #[apply(async_trait_maybe_send!)]
 impl JsonRpcClient for WsClient {
-    async fn connect(url: &Url) -> result::Result<Self, JsonRpcError> {
+    async fn connect(
+        url: &Url,
+        socks5_proxy: Option<SocketAddr>,
+    ) -> result::Result<Self, JsonRpcError> {
         #[cfg(not(target_family = "wasm"))]
-        return WsClientBuilder::default()
-            .certificate_store(CertificateStore::WebPki)
-            .build(url_to_string_with_default_port(url)) // Hack for default ports, see fn docs
-            .await;
+        return match socks5_proxy {
+            // `jsonrpsee` can only dial urls itself, so we hand it the websocket we
+            // opened through the proxy
+            Some(proxy) => {
+                let (sender, receiver) = crate::net::socks::socks5_ws_transport(url, proxy)
+                    .await
+                    .map_err(|e| JsonRpcError::Transport(e.into()))?;
+                Ok(jsonrpsee_core::client::ClientBuilder::default()
+                    .build_with_tokio(sender, receiver))
+            }
+            None => {
+                WsClientBuilder::default()
+                    .certificate_store(CertificateStore::WebPki)
+                    .build(url_to_string_with_default_port(url)) // Hack for default ports, see fn docs
+                    .await
+            }
+        };
 
         #[cfg(target_family = "wasm")]
-        WsClientBuilder::default()
-            .build(url_to_string_with_default_port(url)) // Hack for default ports, see fn docs
-            .await
+        {
+            if socks5_proxy.is_some() {
+                return Err(JsonRpcError::Custom(
+                    "SOCKS5 proxies are not supported in the browser".to_string(),
+                ));
+            }
+
+            WsClientBuilder::default()
+                .build(url_to_string_with_default_port(url)) // Hack for default ports, see fn docs
+                .await
+        }
     }
 
     fn is_connected(&self) -> bool {
         self.is_connected()
     }
+
+    async fn subscribe_raw(
+        &self,
+        method: &str,
+        params: &[Value],
+    ) -> result::Result<JsonRpcSubscription, JsonRpcError> {
+        let subscription: Subscription<Value> =
+            SubscriptionClientT::subscribe(self, method, params, &unsubscribe_method(method))
+                .await?;
+        Ok(Box::pin(subscription))
+    }
 }
 
 impl WsFederationApi<WsClient> {
@@ -751,6 +951,15 @@ impl<C> WsFederationApi<C> {
         self.members.iter().map(|member| member.peer_id).collect()
     }
 
+    /// Connects to all peers through the given SOCKS5 proxy, e.g. Tor, which
+    /// allows using onion addresses as API urls
+    pub fn with_socks5_proxy(mut self, proxy: SocketAddr) -> Self {
+        for member in &mut self.members {
+            member.socks5_proxy = Some(proxy);
+        }
+        self
+    }
+
     /// Creates a new API client
     pub fn new_with_client(members: Vec<(PeerId, Url)>) -> Self {
         WsFederationApi {
@@ -767,6 +976,7 @@ impl<C> WsFederationApi<C> {
                     FederationMember {
                         peer_id,
                         url,
+                        socks5_proxy: None,
                         client: RwLock::new(None),
                     }
                 })
@@ -809,7 +1019,7 @@ impl<C: JsonRpcClient> FederationMember<C> {
             _ => {
                 // write lock is acquired before creating a new client
                 // so only one task will try to create a new client
-                match C::connect(&self.url).await {
+                match C::connect(&self.url, self.socks5_proxy).await {
                     Ok(client) => {
                         *wclient = Some(client);
                         // drop the write lock before making the request
@@ -830,6 +1040,28 @@ impl<C: JsonRpcClient> FederationMember<C> {
             }
         })
     }
+
+    pub async fn subscribe(
+        &self,
+        method: &str,
+        params: &[Value],
+    ) -> JsonRpcResult<JsonRpcSubscription> {
+        let mut wclient = self.client.write().await;
+        match &*wclient {
+            Some(client) if client.is_connected() => {}
+            _ => {
+                debug!("web socket not connected, reconnecting");
+                *wclient = Some(C::connect(&self.url, self.socks5_proxy).await?);
+            }
+        }
+
+        let rclient = RwLockWriteGuard::downgrade(wclient);
+        rclient
+            .as_ref()
+            .expect("Connected above")
+            .subscribe_raw(method, params)
+            .await
+    }
 }
 
 /// `jsonrpsee` converts the `Url` to a `&str` internally and then parses it as
@@ -850,6 +1082,183 @@ fn url_to_string_with_default_port(url: &Url) -> String {
     )
 }
 
+/// Body of a long-polling request to a subscription endpoint of the HTTP API
+///
+/// The server answers as soon as the status of `request` differs from `last`,
+/// or with `204 No Content` if it didn't change before the request timed out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpSubscriptionRequest {
+    pub request: Value,
+    pub last: Option<Value>,
+}
+
+/// Methods of the HTTP API that are also served as `GET {method}/{param}` so
+/// that CDNs and proxies can cache their immutable results. Their parameter
+/// has to be a number.
+pub const HTTP_GET_METHODS: &[&str] = &["/fetch_epoch_history"];
+
+/// Delay before polling a subscription of the HTTP API again after it answered
+/// with `204 No Content`, so a misbehaving server or proxy that answers
+/// immediately doesn't make us busy-loop
+const HTTP_POLL_DELAY: Duration = Duration::from_millis(500);
+
+/// Path under which the HTTP API serves `request` to `method` as a `GET`
+/// request, `None` if it has to be posted
+pub fn http_get_path(method: &str, request: &Value) -> Option<String> {
+    if !HTTP_GET_METHODS.contains(&method) || !request["auth"].is_null() {
+        return None;
+    }
+
+    match &request["params"] {
+        Value::Number(param) => Some(format!("{method}/{param}")),
+        _ => None,
+    }
+}
+
+/// Federation API client that talks to the HTTP/JSON binding of the API
+/// instead of websockets, subscriptions are emulated by long-polling
+#[derive(Debug, Clone)]
+pub struct HttpFederationApi {
+    peers: BTreeSet<PeerId>,
+    members: BTreeMap<PeerId, Url>,
+    client: reqwest::Client,
+}
+
+impl HttpFederationApi {
+    /// Creates a new API client, `members` are the base urls of the HTTP APIs
+    pub fn new(members: Vec<(PeerId, Url)>) -> Self {
+        HttpFederationApi {
+            peers: members.iter().map(|m| m.0).collect(),
+            members: members.into_iter().collect(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Url of `path` on the HTTP API of `peer_id`
+    fn url(&self, peer_id: PeerId, path: &str) -> JsonRpcResult<String> {
+        let url = self
+            .members
+            .get(&peer_id)
+            .ok_or_else(|| JsonRpcError::Custom(format!("Invalid peer_id: {peer_id}")))?;
+
+        Ok(format!("{}{path}", url.as_str().trim_end_matches('/')))
+    }
+
+    /// Posts `body` to `method`, returns `None` if the server had no content
+    async fn post(
+        &self,
+        peer_id: PeerId,
+        method: &str,
+        body: &(impl Serialize + MaybeSync),
+    ) -> JsonRpcResult<Option<Value>> {
+        Self::send(self.client.post(self.url(peer_id, method)?).json(body)).await
+    }
+
+    /// Sends `request`, returns `None` if the server had no content
+    async fn send(request: reqwest::RequestBuilder) -> JsonRpcResult<Option<Value>> {
+        let response = request
+            .send()
+            .await
+            .map_err(|e| JsonRpcError::Transport(e.into()))?;
+
+        let status = response.status();
+        if status == reqwest::StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+
+        if status.is_success() {
+            return response
+                .json()
+                .await
+                .map(Some)
+                .map_err(|e| JsonRpcError::Transport(e.into()));
+        }
+
+        // Map errors back to the codes returned by the websocket API so that
+        // e.g. retrying on 404s works the same for both transports
+        let error = match response.json::<ApiError>().await {
+            Ok(error) => error,
+            Err(_) => ApiError::new(status.as_u16().into(), status.to_string()),
+        };
+        Err(JsonRpcError::Call(
+            jsonrpsee_types::error::CallError::Custom(jsonrpsee_types::error::ErrorObject::owned(
+                error.code,
+                error.message,
+                None::<()>,
+            )),
+        ))
+    }
+}
+
+/// Every endpoint of the HTTP API takes the single request object as its body
+fn single_param<'a>(method: &str, params: &'a [Value]) -> JsonRpcResult<&'a Value> {
+    match params {
+        [request] => Ok(request),
+        _ => Err(JsonRpcError::Custom(format!(
+            "HTTP request to {method} requires exactly one parameter"
+        ))),
+    }
+}
+
+#[apply(async_trait_maybe_send!)]
+impl IFederationApi for HttpFederationApi {
+    fn all_members(&self) -> &BTreeSet<PeerId> {
+        &self.peers
+    }
+
+    async fn request_raw(
+        &self,
+        peer_id: PeerId,
+        method: &str,
+        params: &[Value],
+    ) -> JsonRpcResult<Value> {
+        let request = single_param(method, params)?;
+        let response = match http_get_path(method, request) {
+            Some(path) => Self::send(self.client.get(self.url(peer_id, &path)?)).await?,
+            None => self.post(peer_id, method, request).await?,
+        };
+        response.ok_or_else(|| JsonRpcError::Custom(format!("Empty response to {method}")))
+    }
+
+    async fn subscribe_raw(
+        &self,
+        peer_id: PeerId,
+        method: &str,
+        params: &[Value],
+    ) -> JsonRpcResult<JsonRpcSubscription> {
+        let request = single_param(method, params)?.clone();
+        let api = self.clone();
+        let method = method.to_owned();
+
+        // The state is the last status we received, or `None` once the
+        // subscription failed and the stream should end
+        let stream = futures::stream::unfold(Some(None), move |last: Option<Option<Value>>| {
+            let api = api.clone();
+            let method = method.clone();
+            let request = request.clone();
+            async move {
+                let mut subscription = HttpSubscriptionRequest {
+                    request,
+                    last: last?,
+                };
+                loop {
+                    match api.post(peer_id, &method, &subscription).await {
+                        Ok(Some(status)) => {
+                            subscription.last = Some(status.clone());
+                            return Some((Ok(status), Some(subscription.last)));
+                        }
+                        // Timed out without a change, poll again
+                        Ok(None) => sleep(HTTP_POLL_DELAY).await,
+                        Err(e) => return Some((Err(e), None)),
+                    }
+                }
+            }
+        });
+
+        Ok(Box::pin(stream))
+    }
+}
+
 impl<C: JsonRpcClient> WsFederationApi<C> {}
 
 #[cfg(test)]
@@ -891,9 +1300,17 @@ mod tests {
             self.0.is_connected()
         }
 
-        async fn connect(_url: &Url) -> Result<Self> {
+        async fn connect(_url: &Url, _socks5_proxy: Option<SocketAddr>) -> Result<Self> {
             Ok(Self(C::connect().await?))
         }
+
+        async fn subscribe_raw(
+            &self,
+            _method: &str,
+            _params: &[Value],
+        ) -> Result<JsonRpcSubscription> {
+            unimplemented!()
+        }
     }
 
     #[apply(async_trait_maybe_send!)]
@@ -929,6 +1346,7 @@ mod tests {
         FederationMember {
             url: Url::from_str("http://127.0.0.1").expect("Could not parse"),
             peer_id: PeerId::from(0),
+            socks5_proxy: None,
             client: RwLock::new(None),
         }
     }
@@ -1096,4 +1514,102 @@ mod tests {
         let connect_parsed_json: WsClientConnectInfo = serde_json::from_str(&json).unwrap();
         assert_eq!(connect_parsed_json, connect_parsed);
     }
+
+    /// Federation whose members push a fixed list of values to subscribers,
+    /// each after waiting for the given number of milliseconds
+    #[derive(Debug)]
+    struct PushingApi {
+        members: BTreeSet<PeerId>,
+        pushed: BTreeMap<PeerId, Vec<(u64, u64)>>,
+    }
+
+    #[apply(async_trait_maybe_send!)]
+    impl IFederationApi for PushingApi {
+        fn all_members(&self) -> &BTreeSet<PeerId> {
+            &self.members
+        }
+
+        async fn request_raw(
+            &self,
+            _peer_id: PeerId,
+            _method: &str,
+            _params: &[Value],
+        ) -> Result<Value> {
+            unimplemented!()
+        }
+
+        async fn subscribe_raw(
+            &self,
+            peer_id: PeerId,
+            _method: &str,
+            _params: &[Value],
+        ) -> Result<JsonRpcSubscription> {
+            let pushed = self.pushed[&peer_id].clone();
+            Ok(Box::pin(futures::stream::iter(pushed).then(
+                |(delay, value)| async move {
+                    sleep(Duration::from_millis(delay)).await;
+                    Ok(Value::from(value))
+                },
+            )))
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_subscribe_with_strategy() {
+        let pushing_api = |pushed: [Vec<(u64, u64)>; 3]| PushingApi {
+            members: (0..3).map(PeerId::from).collect(),
+            pushed: (0..3).map(PeerId::from).zip(pushed).collect(),
+        };
+        let immediately = |pushed: Vec<u64>| pushed.into_iter().map(|value| (0, value)).collect();
+
+        let agreed: u64 = pushing_api([
+            immediately(vec![1, 2]),
+            immediately(vec![2]),
+            immediately(vec![3]),
+        ])
+        .subscribe_with_strategy(
+            || CurrentConsensus::new(2),
+            "/subscribe".to_string(),
+            ApiRequestErased::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(agreed, 2, "should agree on a later pushed result");
+
+        let disagreed: FederationResult<u64> = pushing_api([
+            immediately(vec![1]),
+            immediately(vec![2]),
+            immediately(vec![3]),
+        ])
+        .subscribe_with_strategy(
+            || CurrentConsensus::new(2),
+            "/subscribe".to_string(),
+            ApiRequestErased::default(),
+        )
+        .await;
+        assert!(
+            disagreed.is_err(),
+            "should fail once subscriptions closed without agreement"
+        );
+
+        let latest: u64 = pushing_api([vec![(0, 1), (50, 2)], vec![(100, 1)], vec![(150, 2)]])
+            .subscribe_with_strategy(
+                || CurrentConsensus::new(2),
+                "/subscribe".to_string(),
+                ApiRequestErased::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(latest, 2, "should only count the latest pushed results");
+
+        let closed: u64 = pushing_api([immediately(vec![1]), immediately(vec![]), vec![(50, 1)]])
+            .subscribe_with_strategy(
+                || CurrentConsensus::new(2),
+                "/subscribe".to_string(),
+                ApiRequestErased::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(closed, 1, "should keep results of closed subscriptions");
+    }
 }
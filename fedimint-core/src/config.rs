@@ -15,7 +15,8 @@ use fedimint_core::core::{
     ModuleInstanceId, ModuleKind, LEGACY_HARDCODED_INSTANCE_ID_LN,
     LEGACY_HARDCODED_INSTANCE_ID_MINT, LEGACY_HARDCODED_INSTANCE_ID_WALLET,
 };
-use fedimint_core::encoding::Encodable;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::epoch::SerdeSignatureShare;
 use fedimint_core::{BitcoinHash, ModuleDecoderRegistry};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
@@ -92,7 +93,7 @@ impl JsonWithKind {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize, Encodable, Decodable)]
 pub struct ApiEndpoint {
     /// The peer's API websocket network address and port (e.g.
     /// `ws://10.42.0.10:5000`)
@@ -568,6 +569,13 @@ pub enum DkgPeerMsg {
     DistributedGen((String, SupportedDkgMessage)),
     // Dkg completed on our side
     Done,
+    /// Message of a resumable DKG session, numbered per peer and module
+    Sequenced(u64, Box<DkgPeerMsg>),
+    /// Asks a peer to resend its DKG session messages for a module, starting
+    /// with the given sequence number
+    Resend(ModuleInstanceId, u64),
+    /// Signature share over the hash of the resulting consensus config
+    TranscriptSignature(SerdeSignatureShare),
 }
 
 /// Result of running DKG
@@ -211,6 +211,16 @@ mod tests {
         fedimint_core::db::verify_commit(database()).await;
     }
 
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_subscribe_prefix() {
+        fedimint_core::db::verify_subscribe_prefix(database()).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_module_subscribe_prefix() {
+        fedimint_core::db::verify_subscribe_prefix(module_database(1)).await;
+    }
+
     #[test_log::test(tokio::test)]
     async fn test_dbtx_prevent_nonrepeatable_reads() {
         fedimint_core::db::verify_prevent_nonrepeatable_reads(database()).await;
@@ -0,0 +1,30 @@
+//! Prometheus metrics of database transactions
+
+use fedimint_metrics::{opts, register_int_counter_with_registry, IntCounter, REGISTRY};
+use once_cell::sync::Lazy;
+
+/// Transactions that failed to commit, usually because of a write-write
+/// conflict with a concurrent transaction
+pub static DB_TRANSACTION_CONFLICTS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter_with_registry!(
+        opts!(
+            "db_transaction_conflicts_total",
+            "Database transactions that failed to commit"
+        ),
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Retries of [`Database::autocommit`](super::Database::autocommit) after a
+/// failed commit
+pub static DB_AUTOCOMMIT_RETRIES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter_with_registry!(
+        opts!(
+            "db_autocommit_retries_total",
+            "Database transactions retried after failing to commit"
+        ),
+        REGISTRY
+    )
+    .unwrap()
+});
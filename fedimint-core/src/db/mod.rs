@@ -22,6 +22,8 @@ use crate::task::{MaybeSend, MaybeSync};
 use crate::{async_trait_maybe_send, maybe_add_send};
 
 pub mod mem_impl;
+#[cfg(not(target_family = "wasm"))]
+pub mod metrics;
 pub mod notifications;
 
 pub use test_utils::*;
@@ -214,6 +216,8 @@ impl Database {
                         }
                         Err(_) => {
                             // try again
+                            #[cfg(not(target_family = "wasm"))]
+                            metrics::DB_AUTOCOMMIT_RETRIES.inc();
                         }
                     }
                 }
@@ -244,16 +248,11 @@ impl Database {
     where
         K: DatabaseKey + DatabaseRecord + DatabaseKeyWithNotify,
     {
-        let key_bytes = if let Some(module_id) = self.module_instance_id {
-            let mut prefix_bytes = vec![MODULE_GLOBAL_PREFIX];
-            module_id
-                .consensus_encode(&mut prefix_bytes)
-                .expect("Error encoding module instance id as prefix");
-            prefix_bytes.extend(key.to_bytes());
-            prefix_bytes
-        } else {
-            key.to_bytes()
-        };
+        let key_bytes = self
+            .module_prefix_bytes()
+            .into_iter()
+            .chain(key.to_bytes())
+            .collect::<Vec<_>>();
         loop {
             // register for notification
             let notify = self.inner_db.notifications.register(&key_bytes);
@@ -300,6 +299,67 @@ impl Database {
     {
         self.wait_key_check(key, std::convert::identity).await.0
     }
+
+    /// Subscribes to changes of the records matching `key_prefix`.
+    ///
+    /// The returned stream yields every matching key written by a transaction
+    /// committed after subscribing, together with its new value or `None` if
+    /// it was removed. Keys written several times in one transaction are only
+    /// reported with their final value, and not at all if it equals their value
+    /// before the transaction. Unlike [`Self::wait_key_check`] this does not
+    /// require the record to be `DatabaseKeyWithNotify`.
+    ///
+    /// The stream ends if the subscriber falls too far behind, it then has to
+    /// re-read the records and subscribe again.
+    pub fn subscribe_prefix<KP>(
+        &self,
+        key_prefix: &KP,
+    ) -> impl Stream<
+        Item = (
+            KP::Record,
+            Option<<<KP as DatabaseLookup>::Record as DatabaseRecord>::Value>,
+        ),
+    >
+    where
+        KP: DatabaseLookup,
+        KP::Record: DatabaseKey,
+    {
+        let module_prefix_len = self.module_prefix_bytes().len();
+        let prefix_bytes = self
+            .module_prefix_bytes()
+            .into_iter()
+            .chain(key_prefix.to_bytes())
+            .collect();
+        let decoders = self.inner_db.module_decoders.clone();
+
+        self.inner_db
+            .notifications
+            .subscribe_prefix(prefix_bytes)
+            .map(move |(key_bytes, value_bytes)| {
+                let key = KP::Record::from_bytes(&key_bytes[module_prefix_len..], &decoders)
+                    .expect("Unrecoverable error reading DatabaseKey");
+                let value = value_bytes.map(|value_bytes| {
+                    decode_value(&value_bytes, &decoders)
+                        .expect("Unrecoverable decoding DatabaseValue")
+                });
+                (key, value)
+            })
+    }
+
+    /// Bytes prepended to all keys of the module this database is isolated to,
+    /// empty if it isn't isolated
+    fn module_prefix_bytes(&self) -> Vec<u8> {
+        match self.module_instance_id {
+            Some(module_id) => {
+                let mut prefix_bytes = vec![MODULE_GLOBAL_PREFIX];
+                module_id
+                    .consensus_encode(&mut prefix_bytes)
+                    .expect("Error encoding module instance id as prefix");
+                prefix_bytes
+            }
+            None => vec![],
+        }
+    }
 }
 
 /// Fedimint requires that the database implementation implement Snapshot
@@ -928,7 +988,18 @@ impl<'parent> DatabaseTransaction<'parent> {
 
     pub async fn commit_tx_result(mut self) -> Result<()> {
         self.commit_tracker.is_committed = true;
-        return self.tx.commit_tx().await;
+        let result = self.tx.commit_tx().await;
+        #[cfg(not(target_family = "wasm"))]
+        if result.is_err() {
+            metrics::DB_TRANSACTION_CONFLICTS.inc();
+        }
+        result
+    }
+
+    /// Discards all writes of the transaction, which also happens when it is
+    /// dropped without committing
+    pub fn rollback_tx(mut self) {
+        self.commit_tracker.has_writes = false;
     }
 
     pub async fn commit_tx(mut self) {
@@ -989,6 +1060,27 @@ impl<'parent> DatabaseTransaction<'parent> {
             })
     }
 
+    /// Returns all undecoded key/value pairs whose key starts with
+    /// `key_prefix`, used by tooling that handles entries of all modules like
+    /// backups
+    #[instrument(level = "debug", skip_all, fields(key = %AbbreviateHexBytes(key_prefix)))]
+    pub async fn raw_find_by_prefix(&mut self, key_prefix: &[u8]) -> PrefixStream<'_> {
+        self.tx
+            .raw_find_by_prefix(key_prefix)
+            .await
+            .expect("Error doing prefix search in database")
+    }
+
+    /// Inserts an undecoded value, see [`Self::raw_find_by_prefix`]
+    #[instrument(level = "debug", skip_all, fields(key = %AbbreviateHexBytes(key)))]
+    pub async fn raw_insert_bytes(&mut self, key: &[u8], value: Vec<u8>) -> Option<Vec<u8>> {
+        self.commit_tracker.has_writes = true;
+        self.tx
+            .raw_insert_bytes(key, value)
+            .await
+            .expect("Unrecoverable error while inserting into the database")
+    }
+
     #[instrument(level = "debug", skip_all, fields(?key, ?value), ret)]
     pub async fn insert_entry<K>(&mut self, key: &K, value: &K::Value) -> Option<K::Value>
     where
@@ -1363,39 +1455,119 @@ pub async fn apply_migrations<'a>(
     target_db_version: DatabaseVersion,
     migrations: MigrationMap<'a>,
 ) -> Result<(), anyhow::Error> {
+    let mut dbtx = db.begin_transaction().await;
+    let db_version = run_migrations(&mut dbtx, &kind, &target_db_version, &migrations).await?;
+
+    dbtx.commit_tx_result().await?;
+    info!(target: LOG_DB, "{} module db version: {}", kind, db_version);
+    Ok(())
+}
+
+/// Outcome of running the migrations of a module without committing them, see
+/// [`dry_run_migrations`]
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationReport {
+    pub kind: String,
+    /// Version of the database before migrating, `None` if it was never
+    /// initialized
+    pub disk_version: Option<DatabaseVersion>,
+    pub target_version: DatabaseVersion,
+    /// Keys that the migrations would change, grouped by their first byte
+    pub changes: BTreeMap<u8, PrefixChanges>,
+}
+
+/// Number of keys with a certain prefix a migration adds, removes or modifies
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PrefixChanges {
+    pub added: usize,
+    pub removed: usize,
+    pub modified: usize,
+}
+
+/// Runs the same migrations as [`apply_migrations`] but rolls them back
+/// instead of committing, reporting which keys they would have changed
+pub async fn dry_run_migrations<'a>(
+    db: &'a Database,
+    kind: String,
+    target_db_version: DatabaseVersion,
+    migrations: MigrationMap<'a>,
+) -> Result<MigrationReport, anyhow::Error> {
     let mut dbtx = db.begin_transaction().await;
     let disk_version = dbtx.get_value(&DatabaseVersionKey).await;
-    let db_version = if let Some(disk_version) = disk_version {
-        let mut current_db_version = disk_version;
+    let before = dbtx
+        .raw_find_by_prefix(&[])
+        .await
+        .collect::<BTreeMap<_, _>>()
+        .await;
 
-        if current_db_version > target_db_version {
-            return Err(anyhow::anyhow!(format!(
-                "On disk database version for module {kind} was higher than the code database version."
-            )));
-        }
+    run_migrations(&mut dbtx, &kind, &target_db_version, &migrations).await?;
 
-        while current_db_version < target_db_version {
-            if let Some(migration) = migrations.get(&current_db_version) {
-                migration(&mut dbtx).await?;
-            } else {
-                panic!("Missing migration for version {current_db_version}");
+    let after = dbtx
+        .raw_find_by_prefix(&[])
+        .await
+        .collect::<BTreeMap<_, _>>()
+        .await;
+    dbtx.rollback_tx();
+
+    let mut changes = BTreeMap::<u8, PrefixChanges>::new();
+    for (key, value) in &before {
+        match after.get(key) {
+            None => changes.entry(key[0]).or_default().removed += 1,
+            Some(new_value) if new_value != value => {
+                changes.entry(key[0]).or_default().modified += 1
             }
-
-            current_db_version.increment();
-            dbtx.insert_entry(&DatabaseVersionKey, &current_db_version)
-                .await;
+            Some(_) => {}
         }
+    }
+    for key in after.keys().filter(|key| !before.contains_key(*key)) {
+        changes.entry(key[0]).or_default().added += 1;
+    }
+
+    Ok(MigrationReport {
+        kind,
+        disk_version,
+        target_version: target_db_version,
+        changes,
+    })
+}
 
-        current_db_version
-    } else {
-        dbtx.insert_entry(&DatabaseVersionKey, &target_db_version)
+/// Migrates the database accessed by `dbtx` to `target_db_version`, returning
+/// the resulting version. Uninitialized databases are set to the target
+/// version directly.
+async fn run_migrations<'a>(
+    dbtx: &mut DatabaseTransaction<'a>,
+    kind: &str,
+    target_db_version: &DatabaseVersion,
+    migrations: &MigrationMap<'a>,
+) -> Result<DatabaseVersion, anyhow::Error> {
+    let Some(mut current_db_version) = dbtx.get_value(&DatabaseVersionKey).await else {
+        dbtx.insert_entry(&DatabaseVersionKey, target_db_version)
             .await;
-        target_db_version
+        return Ok(target_db_version.clone());
     };
 
-    dbtx.commit_tx_result().await?;
-    info!(target: LOG_DB, "{} module db version: {}", kind, db_version);
-    Ok(())
+    if current_db_version > *target_db_version {
+        anyhow::bail!(
+            "On disk database version {current_db_version} for module {kind} is higher than the \
+             code database version {target_db_version}, downgrading is not supported"
+        );
+    }
+
+    while current_db_version < *target_db_version {
+        let Some(migration) = migrations.get(&current_db_version) else {
+            anyhow::bail!(
+                "Missing migration from database version {current_db_version} for module {kind}, \
+                 cannot reach the code database version {target_db_version}"
+            );
+        };
+        migration(dbtx).await?;
+
+        current_db_version.increment();
+        dbtx.insert_entry(&DatabaseVersionKey, &current_db_version)
+            .await;
+    }
+
+    Ok(current_db_version)
 }
 
 #[allow(unused_imports)]
@@ -1405,8 +1577,8 @@ mod test_utils {
     use futures::{Future, FutureExt, StreamExt};
 
     use super::{
-        apply_migrations, Database, DatabaseTransaction, DatabaseVersion, DatabaseVersionKey,
-        MigrationMap,
+        apply_migrations, dry_run_migrations, Database, DatabaseTransaction, DatabaseVersion,
+        DatabaseVersionKey, MigrationMap, PrefixChanges,
     };
     use crate::core::ModuleKind;
     use crate::db::mem_impl::MemDatabase;
@@ -1598,6 +1770,43 @@ mod test_utils {
         assert_eq!(returned_keys, expected_keys);
     }
 
+    pub async fn verify_subscribe_prefix(db: Database) {
+        let mut changes = db.subscribe_prefix(&DbPrefixTestPrefix);
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(&TestKey(1), &TestVal(1)).await;
+        dbtx.insert_entry(&TestKey(1), &TestVal(2)).await;
+        dbtx.insert_entry(&AltTestKey(1), &TestVal(3)).await;
+        dbtx.commit_tx().await;
+
+        let (key, value) = changes.next().await.expect("subscription ended");
+        assert_eq!(key.0, 1);
+        assert_eq!(value, Some(TestVal(2)));
+
+        // Changes of uncommitted transactions are not reported
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(&TestKey(2), &TestVal(4)).await;
+        drop(dbtx);
+        assert!(future_returns_shortly(changes.next()).await.is_none());
+
+        // Changes that cancel out within a transaction are not reported
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(&TestKey(3), &TestVal(5)).await;
+        dbtx.remove_entry(&TestKey(3)).await;
+        dbtx.insert_entry(&TestKey(1), &TestVal(6)).await;
+        dbtx.insert_entry(&TestKey(1), &TestVal(2)).await;
+        dbtx.commit_tx().await;
+        assert!(future_returns_shortly(changes.next()).await.is_none());
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.remove_entry(&TestKey(1)).await;
+        dbtx.commit_tx().await;
+
+        let (key, value) = changes.next().await.expect("subscription ended");
+        assert_eq!(key.0, 1);
+        assert_eq!(value, None);
+    }
+
     pub async fn verify_commit(db: Database) {
         let mut dbtx = db.begin_transaction().await;
 
@@ -2009,6 +2218,82 @@ mod test_utils {
         }
     }
 
+    #[cfg(test)]
+    #[tokio::test]
+    pub async fn verify_migration_dry_run() {
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let mut dbtx = db.begin_transaction().await;
+        for i in 0..10 {
+            dbtx.insert_new_entry(&TestKeyV0(i, i + 1), &TestVal(i))
+                .await;
+        }
+        dbtx.insert_new_entry(&DatabaseVersionKey, &DatabaseVersion(0))
+            .await;
+        dbtx.commit_tx().await;
+
+        let mut migrations = MigrationMap::new();
+        migrations.insert(DatabaseVersion(0), move |dbtx| {
+            migrate_test_db_version_0(dbtx).boxed()
+        });
+
+        let report = dry_run_migrations(
+            &db,
+            "TestModule".to_string(),
+            DatabaseVersion(1),
+            migrations.clone(),
+        )
+        .await
+        .expect("Dry run failed");
+
+        assert_eq!(report.disk_version, Some(DatabaseVersion(0)));
+        assert_eq!(report.target_version, DatabaseVersion(1));
+        assert_eq!(
+            report.changes.into_iter().collect::<Vec<_>>(),
+            vec![
+                (
+                    TestDbKeyPrefix::Test as u8,
+                    PrefixChanges {
+                        added: 10,
+                        removed: 10,
+                        modified: 0,
+                    }
+                ),
+                (
+                    0x50,
+                    PrefixChanges {
+                        modified: 1,
+                        ..Default::default()
+                    }
+                ),
+            ]
+        );
+
+        // Nothing was committed
+        let mut dbtx = db.begin_transaction().await;
+        assert_eq!(
+            dbtx.get_value(&DatabaseVersionKey).await,
+            Some(DatabaseVersion(0))
+        );
+        assert_eq!(
+            dbtx.find_by_prefix(&DbPrefixTestPrefixV0)
+                .await
+                .collect::<Vec<_>>()
+                .await
+                .len(),
+            10
+        );
+
+        // Skipping a version is reported as an error instead of panicking
+        assert!(apply_migrations(
+            &db,
+            "TestModule".to_string(),
+            DatabaseVersion(2),
+            migrations
+        )
+        .await
+        .is_err());
+    }
+
     #[allow(dead_code)]
     async fn migrate_test_db_version_0<'a, 'b>(
         dbtx: &'b mut DatabaseTransaction<'a>,
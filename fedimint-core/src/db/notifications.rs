@@ -1,9 +1,13 @@
 use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
 use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
 
 use anyhow::Context;
 use bitvec::vec::BitVec;
 use fedimint_core::{apply, async_trait_maybe_send};
+use futures::channel::mpsc;
+use futures::StreamExt;
 use tokio::sync::futures::Notified;
 use tokio::sync::Notify;
 
@@ -12,6 +16,10 @@ use super::{ISingleUseDatabaseTransaction, PrefixStream, Result};
 /// Number of buckets used for `Notifications`.
 const NOTIFY_BUCKETS: usize = 32;
 
+/// Number of changes a prefix subscriber may fall behind before its
+/// subscription is closed
+const PREFIX_SUBSCRIBER_CAPACITY: usize = 1024;
+
 /// The state of Notification.
 ///
 /// This stores `NOTIFY_BUCKETS` number of `Notifies`.
@@ -20,16 +28,27 @@ const NOTIFY_BUCKETS: usize = 32;
 #[derive(Debug)]
 pub struct Notifications {
     buckets: Vec<Notify>,
+    prefix_subscribers: Mutex<Vec<PrefixSubscriber>>,
 }
 
 impl Default for Notifications {
     fn default() -> Self {
         Self {
             buckets: (0..NOTIFY_BUCKETS).map(|_| Notify::new()).collect(),
+            prefix_subscribers: Mutex::new(vec![]),
         }
     }
 }
 
+/// A committed write of a raw key, the value is `None` if the key was removed
+pub type RawChange = (Vec<u8>, Option<Vec<u8>>);
+
+#[derive(Debug)]
+struct PrefixSubscriber {
+    prefix: Vec<u8>,
+    sender: mpsc::Sender<RawChange>,
+}
+
 fn slot_index_for_hash(hash_value: u64) -> usize {
     (hash_value % (NOTIFY_BUCKETS as u64)) as usize
 }
@@ -74,6 +93,41 @@ impl Notifications {
             self.buckets[bucket].notify_waiters();
         }
     }
+
+    /// Registers for all changes to keys starting with `prefix` that are
+    /// committed from now on.
+    ///
+    /// The subscription ends when the returned receiver is dropped. If the
+    /// receiver lags more than `PREFIX_SUBSCRIBER_CAPACITY` changes behind, the
+    /// subscription is closed so that the receiver sees the end of the stream
+    /// instead of silently missing changes, it then has to re-read the prefix
+    /// and subscribe again.
+    pub fn subscribe_prefix(&self, prefix: Vec<u8>) -> mpsc::Receiver<RawChange> {
+        let (sender, receiver) = mpsc::channel(PREFIX_SUBSCRIBER_CAPACITY);
+        self.prefix_subscribers
+            .lock()
+            .expect("poisoned")
+            .push(PrefixSubscriber { prefix, sender });
+        receiver
+    }
+
+    /// Sends the changes of a committed transaction to the subscribers of
+    /// matching prefixes and drops subscribers that went away or lag behind.
+    pub fn submit_changes(&self, changes: BTreeMap<Vec<u8>, Option<Vec<u8>>>) {
+        let mut subscribers = self.prefix_subscribers.lock().expect("poisoned");
+        subscribers.retain_mut(|subscriber| {
+            changes
+                .iter()
+                .filter(|(key, _)| key.starts_with(&subscriber.prefix))
+                .all(|(key, value)| {
+                    subscriber
+                        .sender
+                        .try_send((key.clone(), value.clone()))
+                        .is_ok()
+                })
+                && !subscriber.sender.is_closed()
+        });
+    }
 }
 
 /// Save notifications to be sent after transaction is complete.
@@ -109,9 +163,19 @@ pub struct NotifyingTransaction<'a> {
     dbtx: Box<dyn ISingleUseDatabaseTransaction<'a>>,
     // notifications to be submitted after commit
     notify_queue: Option<NotifyQueue>,
+    // value before the transaction and final value of every key written, the
+    // keys whose value changed are sent to prefix subscribers after commit
+    changes: BTreeMap<Vec<u8>, KeyChange>,
+    savepoint_changes: Option<BTreeMap<Vec<u8>, KeyChange>>,
     notifications: &'a Notifications,
 }
 
+#[derive(Debug, Clone)]
+struct KeyChange {
+    before: Option<Vec<u8>>,
+    after: Option<Vec<u8>>,
+}
+
 impl<'a> NotifyingTransaction<'a> {
     pub fn new(
         dbtx: Box<dyn ISingleUseDatabaseTransaction<'a>>,
@@ -120,15 +184,41 @@ impl<'a> NotifyingTransaction<'a> {
         Self {
             dbtx,
             notify_queue: Some(NotifyQueue::new()),
+            changes: BTreeMap::new(),
+            savepoint_changes: None,
             notifications,
         }
     }
+
+    /// Records a write of `key`, keeping the value it had before the
+    /// transaction from its first write
+    fn record_change(&mut self, key: &[u8], old_value: Option<Vec<u8>>, value: Option<Vec<u8>>) {
+        self.changes
+            .entry(key.to_vec())
+            .and_modify(|change| change.after = value.clone())
+            .or_insert(KeyChange {
+                before: old_value,
+                after: value,
+            });
+    }
+
+    /// Final values of the keys that differ from before the transaction, so
+    /// e.g. a key inserted and removed again is not reported
+    fn net_changes(&mut self) -> BTreeMap<Vec<u8>, Option<Vec<u8>>> {
+        std::mem::take(&mut self.changes)
+            .into_iter()
+            .filter(|(_, change)| change.before != change.after)
+            .map(|(key, change)| (key, change.after))
+            .collect()
+    }
 }
 
 #[apply(async_trait_maybe_send!)]
 impl<'a> ISingleUseDatabaseTransaction<'a> for NotifyingTransaction<'a> {
     async fn raw_insert_bytes(&mut self, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>> {
-        self.dbtx.raw_insert_bytes(key, value).await
+        let old_value = self.dbtx.raw_insert_bytes(key, value.clone()).await?;
+        self.record_change(key, old_value.clone(), Some(value));
+        Ok(old_value)
     }
 
     async fn raw_get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
@@ -136,7 +226,11 @@ impl<'a> ISingleUseDatabaseTransaction<'a> for NotifyingTransaction<'a> {
     }
 
     async fn raw_remove_entry(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
-        self.dbtx.raw_remove_entry(key).await
+        let old_value = self.dbtx.raw_remove_entry(key).await?;
+        if old_value.is_some() {
+            self.record_change(key, old_value.clone(), None);
+        }
+        Ok(old_value)
     }
 
     async fn raw_find_by_prefix(&mut self, key_prefix: &[u8]) -> Result<PrefixStream<'_>> {
@@ -144,7 +238,17 @@ impl<'a> ISingleUseDatabaseTransaction<'a> for NotifyingTransaction<'a> {
     }
 
     async fn raw_remove_by_prefix(&mut self, key_prefix: &[u8]) -> Result<()> {
-        self.dbtx.raw_remove_by_prefix(key_prefix).await
+        let removed_entries = self
+            .dbtx
+            .raw_find_by_prefix(key_prefix)
+            .await?
+            .collect::<Vec<_>>()
+            .await;
+        self.dbtx.raw_remove_by_prefix(key_prefix).await?;
+        for (key, old_value) in removed_entries {
+            self.record_change(&key, Some(old_value), None);
+        }
+        Ok(())
     }
 
     async fn commit_tx(&mut self) -> Result<()> {
@@ -154,15 +258,23 @@ impl<'a> ISingleUseDatabaseTransaction<'a> for NotifyingTransaction<'a> {
                 .take()
                 .expect("commit must be called only once"),
         );
+        let changes = self.net_changes();
+        self.notifications.submit_changes(changes);
         Ok(())
     }
 
     async fn rollback_tx_to_savepoint(&mut self) -> Result<()> {
-        self.dbtx.rollback_tx_to_savepoint().await
+        self.dbtx.rollback_tx_to_savepoint().await?;
+        if let Some(changes) = &self.savepoint_changes {
+            self.changes = changes.clone();
+        }
+        Ok(())
     }
 
     async fn set_tx_savepoint(&mut self) -> Result<()> {
-        self.dbtx.set_tx_savepoint().await
+        self.dbtx.set_tx_savepoint().await?;
+        self.savepoint_changes = Some(self.changes.clone());
+        Ok(())
     }
 
     fn add_notification_key(&mut self, key: &[u8]) -> Result<()> {
@@ -219,6 +331,48 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_prefix_subscription() {
+        let notifs = Notifications::new();
+        let mut sub = notifs.subscribe_prefix(vec![1]);
+        let changes = BTreeMap::from([
+            (vec![1, 1], Some(vec![42])),
+            (vec![1, 2], None),
+            (vec![2, 1], Some(vec![43])),
+        ]);
+        notifs.submit_changes(changes);
+
+        assert_eq!(sub.next().await, Some((vec![1, 1], Some(vec![42]))));
+        assert_eq!(sub.next().await, Some((vec![1, 2], None)));
+        assert!(
+            future_returns_shortly(sub.next()).await.is_none(),
+            "should only receive changes matching the prefix"
+        );
+
+        drop(sub);
+        notifs.submit_changes(BTreeMap::from([(vec![1, 3], None)]));
+        assert!(notifs.prefix_subscribers.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_lagging_prefix_subscription_is_closed() {
+        let notifs = Notifications::new();
+        let mut sub = notifs.subscribe_prefix(vec![1]);
+        for i in 0..=PREFIX_SUBSCRIBER_CAPACITY as u64 + 1 {
+            let mut key = vec![1];
+            key.extend(i.to_be_bytes());
+            notifs.submit_changes(BTreeMap::from([(key, None)]));
+        }
+        assert!(notifs.prefix_subscribers.lock().unwrap().is_empty());
+
+        // Buffered changes are still delivered before the stream ends
+        let mut received = 0;
+        while sub.next().await.is_some() {
+            received += 1;
+        }
+        assert!(received <= PREFIX_SUBSCRIBER_CAPACITY + 1);
+    }
+
     #[tokio::test]
     async fn test_notify_queue() {
         let notifs = Notifications::new();
@@ -9,6 +9,7 @@ use fedimint_core::{PeerId, TransactionId};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use threshold_crypto::{PublicKey, PublicKeySet, Signature, SignatureShare};
+use url::Url;
 
 use crate::transaction::Transaction;
 
@@ -26,12 +27,76 @@ pub enum ConsensusItem {
     Transaction(Transaction),
     /// Any data that modules require consensus on
     Module(ModuleConsensusItem),
+    /// A peer announces a new TLS certificate for p2p connections
+    TlsCertRotation(TlsCertRotation),
+    /// A peer moves its API and p2p endpoints to new URLs
+    EndpointUpdate(EndpointUpdate),
 }
 
 /// May eventually contains consensus info about the upgrade
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
 pub struct ConsensusUpgrade;
 
+/// New TLS certificate of a peer that all peers accept from
+/// `activation_epoch` on
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
+pub struct TlsCertRotation {
+    /// DER encoded certificate
+    pub cert: Vec<u8>,
+    /// First epoch in which the announcing peer uses the new certificate
+    pub activation_epoch: u64,
+    /// Signature share of the announcing peer's auth key over
+    /// [`TlsCertRotation::signing_hash`]
+    pub signature: SerdeSignatureShare,
+}
+
+impl TlsCertRotation {
+    /// Hash of the certificate and activation epoch that the announcing peer
+    /// signs
+    pub fn signing_hash(cert: &[u8], activation_epoch: u64) -> Sha256 {
+        (cert.to_vec(), activation_epoch)
+            .consensus_hash()
+            .expect("Hashes")
+    }
+
+    /// Checks the rotation was signed by `peer`
+    pub fn verify(&self, peer: PeerId, auth_pks: &PublicKeySet) -> bool {
+        auth_pks.public_key_share(peer.to_usize()).verify(
+            &self.signature.0,
+            Self::signing_hash(&self.cert, self.activation_epoch),
+        )
+    }
+}
+
+/// New URLs of a peer's API and p2p endpoints
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
+pub struct EndpointUpdate {
+    /// URL clients reach the peer's API at
+    pub api_url: Url,
+    /// URL other peers connect to for consensus
+    pub p2p_url: Url,
+    /// Signature share of the announcing peer's auth key over
+    /// [`EndpointUpdate::signing_hash`]
+    pub signature: SerdeSignatureShare,
+}
+
+impl EndpointUpdate {
+    /// Hash of the new URLs that the announcing peer signs
+    pub fn signing_hash(api_url: &Url, p2p_url: &Url) -> Sha256 {
+        (api_url.clone(), p2p_url.clone())
+            .consensus_hash()
+            .expect("Hashes")
+    }
+
+    /// Checks the update was signed by `peer`
+    pub fn verify(&self, peer: PeerId, auth_pks: &PublicKeySet) -> bool {
+        auth_pks.public_key_share(peer.to_usize()).verify(
+            &self.signature.0,
+            Self::signing_hash(&self.api_url, &self.p2p_url),
+        )
+    }
+}
+
 pub type SerdeConsensusItem = SerdeModuleEncoding<ConsensusItem>;
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
@@ -166,6 +231,49 @@ impl SignedEpochOutcome {
     }
 }
 
+/// Compact record of a pruned [`SignedEpochOutcome`] that keeps the hash chain
+/// of the epoch history verifiable without the epoch's consensus items
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
+pub struct EpochCheckpoint {
+    pub epoch: u64,
+    pub last_hash: Option<Sha256>,
+    pub hash: Sha256,
+    pub signature: Option<SerdeSignature>,
+}
+
+impl From<&SignedEpochOutcome> for EpochCheckpoint {
+    fn from(signed: &SignedEpochOutcome) -> Self {
+        EpochCheckpoint {
+            epoch: signed.outcome.epoch,
+            last_hash: signed.outcome.last_hash,
+            hash: signed.hash,
+            signature: signed.signature.clone(),
+        }
+    }
+}
+
+impl EpochCheckpoint {
+    pub fn verify_sig(&self, pk: &PublicKey) -> Result<(), EpochVerifyError> {
+        match &self.signature {
+            Some(sig) if pk.verify(&sig.0, self.hash) => Ok(()),
+            Some(_) => Err(EpochVerifyError::InvalidSignature),
+            None => Err(EpochVerifyError::MissingSignature),
+        }
+    }
+
+    /// Verifies that the checkpoint follows `prev_hash`, the hash of the
+    /// previous epoch
+    pub fn verify_chain(&self, prev_hash: Option<Sha256>) -> Result<(), EpochVerifyError> {
+        if self.epoch > 0 && prev_hash.is_none() {
+            return Err(EpochVerifyError::MissingPreviousEpoch);
+        }
+        if self.last_hash != prev_hash {
+            return Err(EpochVerifyError::InvalidPreviousEpochHash);
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum EpochVerifyError {
     MissingSignature,
@@ -223,8 +331,8 @@ mod tests {
     use threshold_crypto::{SecretKey, SecretKeySet};
 
     use crate::epoch::{
-        ConsensusItem, EpochOutcome, EpochVerifyError, SerdeSignature, SerdeSignatureShare, Sha256,
-        SignedEpochOutcome,
+        ConsensusItem, EpochCheckpoint, EpochOutcome, EpochVerifyError, SerdeSignature,
+        SerdeSignatureShare, Sha256, SignedEpochOutcome, TlsCertRotation,
     };
 
     fn signed_history(
@@ -370,4 +478,55 @@ mod tests {
             Err(EpochVerifyError::InvalidSignature)
         );
     }
+    #[test]
+    fn checkpoints_keep_chain_verifiable() {
+        let sk: SecretKey = SecretKey::random();
+        let pk = sk.public_key();
+
+        let epoch0 = signed_history(0, &None, &sk);
+        let epoch1 = signed_history(1, &Some(epoch0.clone()), &sk);
+        let checkpoint0 = EpochCheckpoint::from(&epoch0);
+        let checkpoint1 = EpochCheckpoint::from(&epoch1);
+
+        assert_eq!(checkpoint0.verify_sig(&pk), Ok(()));
+        assert_eq!(checkpoint0.verify_chain(None), Ok(()));
+        assert_eq!(checkpoint1.verify_chain(Some(checkpoint0.hash)), Ok(()));
+        assert_eq!(
+            checkpoint1.verify_chain(None),
+            Err(EpochVerifyError::MissingPreviousEpoch)
+        );
+        assert_eq!(
+            checkpoint1.verify_chain(Some(checkpoint1.hash)),
+            Err(EpochVerifyError::InvalidPreviousEpochHash)
+        );
+
+        let unsigned = EpochCheckpoint::from(&history(0, &None, None));
+        assert_eq!(
+            unsigned.verify_sig(&pk),
+            Err(EpochVerifyError::MissingSignature)
+        );
+    }
+
+    #[test]
+    fn tls_cert_rotation_verifies_signer() {
+        let sk_set = SecretKeySet::random(2, &mut OsRng);
+        let pk_set = sk_set.public_keys();
+        let peer = PeerId::from(1);
+
+        let cert = vec![42; 32];
+        let hash = TlsCertRotation::signing_hash(&cert, 10);
+        let rotation = TlsCertRotation {
+            cert,
+            activation_epoch: 10,
+            signature: SerdeSignatureShare(sk_set.secret_key_share(peer.to_usize()).sign(hash)),
+        };
+        assert!(rotation.verify(peer, &pk_set));
+        assert!(!rotation.verify(PeerId::from(2), &pk_set));
+
+        let delayed = TlsCertRotation {
+            activation_epoch: 11,
+            ..rotation
+        };
+        assert!(!delayed.verify(peer, &pk_set));
+    }
 }
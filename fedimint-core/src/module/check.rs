@@ -0,0 +1,100 @@
+use serde::Serialize;
+
+/// Result of checking a single invariant over all database entries it applies
+/// to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InvariantCheck {
+    pub invariant: String,
+    /// Number of entries the invariant was checked for
+    pub checked: usize,
+    /// Descriptions of the entries violating the invariant
+    pub violations: Vec<String>,
+}
+
+impl InvariantCheck {
+    /// Records one check of the invariant, `violation` describes the entry if
+    /// the invariant does not hold
+    pub fn verify(&mut self, holds: bool, violation: impl FnOnce() -> String) {
+        self.checked += 1;
+        if !holds {
+            self.violations.push(violation());
+        }
+    }
+}
+
+/// Structured report of a database consistency check
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DatabaseCheckReport {
+    pub invariants: Vec<InvariantCheck>,
+}
+
+impl DatabaseCheckReport {
+    /// Returns the check of `invariant`, adding it to the report if it was not
+    /// checked before
+    pub fn invariant(&mut self, invariant: &str) -> &mut InvariantCheck {
+        let idx = match self
+            .invariants
+            .iter()
+            .position(|check| check.invariant == invariant)
+        {
+            Some(idx) => idx,
+            None => {
+                self.invariants.push(InvariantCheck {
+                    invariant: invariant.to_string(),
+                    checked: 0,
+                    violations: vec![],
+                });
+                self.invariants.len() - 1
+            }
+        };
+        &mut self.invariants[idx]
+    }
+
+    /// Adds all invariants of `other` to the report, prefixing their names
+    /// with `scope`
+    pub fn extend_scoped(&mut self, scope: &str, other: DatabaseCheckReport) {
+        self.invariants
+            .extend(other.invariants.into_iter().map(|check| InvariantCheck {
+                invariant: format!("{scope}: {}", check.invariant),
+                ..check
+            }));
+    }
+
+    pub fn violations(&self) -> usize {
+        self.invariants
+            .iter()
+            .map(|check| check.violations.len())
+            .sum()
+    }
+
+    pub fn is_consistent(&self) -> bool {
+        self.violations() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DatabaseCheckReport;
+
+    #[test]
+    fn report_collects_violations() {
+        let mut report = DatabaseCheckReport::default();
+        report.invariant("positive").verify(true, || unreachable!());
+        assert!(report.is_consistent());
+
+        let mut module_report = DatabaseCheckReport::default();
+        module_report
+            .invariant("positive")
+            .verify(false, || "-1".to_string());
+        module_report
+            .invariant("positive")
+            .verify(true, String::new);
+        report.extend_scoped("mint-1", module_report);
+
+        assert_eq!(report.invariants.len(), 2);
+        assert_eq!(report.invariants[1].invariant, "mint-1: positive");
+        assert_eq!(report.invariants[1].checked, 2);
+        assert_eq!(report.violations(), 1);
+        assert!(!report.is_consistent());
+    }
+}
@@ -1,4 +1,5 @@
 pub mod audit;
+pub mod check;
 pub mod interconnect;
 pub mod registry;
 
@@ -7,12 +8,15 @@ use std::ffi::OsString;
 use std::fmt::Debug;
 use std::marker::PhantomData;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
-use bitcoin_hashes::sha256;
 use bitcoin_hashes::sha256::Hash;
+use bitcoin_hashes::{sha256, Hash as BitcoinHash, HashEngine};
 use futures::Future;
 use jsonrpsee_core::JsonValue;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use secp256k1_zkp::XOnlyPublicKey;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -21,12 +25,13 @@ use tracing::instrument;
 
 use crate::config::{ConfigGenParams, DkgPeerMsg, ServerModuleConfig};
 use crate::core::{
-    Decoder, DecoderBuilder, Input, ModuleConsensusItem, ModuleInstanceId, ModuleKind, Output,
-    OutputOutcome,
+    Decoder, DecoderBuilder, DynOutput, Input, ModuleConsensusItem, ModuleInstanceId, ModuleKind,
+    Output, OutputOutcome,
 };
 use crate::db::{Database, DatabaseVersion, MigrationMap, ModuleDatabaseTransaction};
 use crate::encoding::{Decodable, DecodeError, Encodable};
 use crate::module::audit::Audit;
+use crate::module::check::DatabaseCheckReport;
 use crate::module::interconnect::ModuleInterconect;
 use crate::net::peers::MuxPeerConnections;
 use crate::server::{DynServerModule, VerificationCache};
@@ -97,7 +102,7 @@ impl ApiRequestErased {
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ApiAuth(pub String);
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiError {
     pub code: i32,
     pub message: String,
@@ -115,6 +120,20 @@ impl ApiError {
     pub fn bad_request(message: String) -> Self {
         Self::new(400, message)
     }
+
+    pub fn unauthorized() -> Self {
+        Self::new(401, "Invalid authentication".to_string())
+    }
+
+    /// Requested data existed but was pruned by the guardian
+    pub fn pruned(message: String) -> Self {
+        Self::new(410, message)
+    }
+
+    /// Client exceeded a rate limit or concurrency cap of the guardian
+    pub fn too_many_requests(message: String) -> Self {
+        Self::new(429, message)
+    }
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -153,11 +172,38 @@ pub mod __reexports {
 ///     }
 /// };
 /// ```
+///
+/// Endpoints restricted to the guardian can bind a fourth argument that is
+/// `true` if the request carried valid authentication:
+///
+/// ```rust
+/// # use fedimint_core::module::{api_endpoint, ApiEndpoint, ApiError, registry::ModuleInstanceId};
+/// struct State;
+///
+/// let _: ApiEndpoint<State> = api_endpoint! {
+///     "/admin",
+///     async |state: &State, _dbtx, params: (), has_auth| -> i32 {
+///         if !has_auth {
+///             return Err(ApiError::unauthorized());
+///         }
+///         Ok(0)
+///     }
+/// };
+/// ```
 #[macro_export]
 macro_rules! __api_endpoint {
     (
         $path:expr,
         async |$state:ident: &$state_ty:ty, $dbtx:ident, $param:ident: $param_ty:ty| -> $resp_ty:ty $body:block
+    ) => {
+        $crate::__api_endpoint! {
+            $path,
+            async |$state: &$state_ty, $dbtx, $param: $param_ty, _has_auth| -> $resp_ty $body
+        }
+    };
+    (
+        $path:expr,
+        async |$state:ident: &$state_ty:ty, $dbtx:ident, $param:ident: $param_ty:ty, $has_auth:ident| -> $resp_ty:ty $body:block
     ) => {{
         struct Endpoint;
 
@@ -172,7 +218,7 @@ macro_rules! __api_endpoint {
                 $state: &'a Self::State,
                 $dbtx: &'a mut fedimint_core::db::ModuleDatabaseTransaction<'b, ModuleInstanceId>,
                 $param: Self::Param,
-                has_auth: bool,
+                $has_auth: bool,
             ) -> ::std::result::Result<Self::Response, $crate::module::ApiError> {
                 $body
             }
@@ -405,6 +451,16 @@ pub trait IServerModuleGen: IDynCommonModuleGen {
         dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
         prefix_names: Vec<String>,
     ) -> Box<dyn Iterator<Item = (String, Box<dyn erased_serde::Serialize + Send>)> + '_>;
+
+    /// Audits the module database and verifies its invariants, `outputs` are
+    /// the outputs of all accepted transactions belonging to this module
+    async fn check_database(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        outputs: &[(OutPoint, DynOutput)],
+        audit: &mut Audit,
+        report: &mut DatabaseCheckReport,
+    );
 }
 
 dyn_newtype_define!(
@@ -582,6 +638,18 @@ pub trait ServerModuleGen: ExtendsCommonModuleGen + Sized {
         dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
         prefix_names: Vec<String>,
     ) -> Box<dyn Iterator<Item = (String, Box<dyn erased_serde::Serialize + Send>)> + '_>;
+
+    /// Audits the module database like [`ServerModule::audit`] and verifies
+    /// the module's invariants for the database consistency check. `outputs`
+    /// are the outputs of all accepted transactions belonging to this module.
+    async fn check_database(
+        &self,
+        _dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        _outputs: &[(OutPoint, DynOutput)],
+        _audit: &mut Audit,
+        _report: &mut DatabaseCheckReport,
+    ) {
+    }
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -649,6 +717,16 @@ where
     ) -> Box<dyn Iterator<Item = (String, Box<dyn erased_serde::Serialize + Send>)> + '_> {
         <Self as ServerModuleGen>::dump_database(self, dbtx, prefix_names).await
     }
+
+    async fn check_database(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        outputs: &[(OutPoint, DynOutput)],
+        audit: &mut Audit,
+        report: &mut DatabaseCheckReport,
+    ) {
+        <Self as ServerModuleGen>::check_database(self, dbtx, outputs, audit, report).await
+    }
 }
 
 pub enum ConsensusProposal<CI> {
@@ -931,6 +1009,9 @@ pub struct PeerHandle<'a> {
     pub our_id: PeerId,
     #[doc(hidden)]
     pub peers: Vec<PeerId>,
+    #[doc(hidden)]
+    pub seed: [u8; 32],
+    rng_counter: AtomicU64,
 }
 
 impl<'a> PeerHandle<'a> {
@@ -939,16 +1020,33 @@ impl<'a> PeerHandle<'a> {
         module_instance_id: ModuleInstanceId,
         our_id: PeerId,
         peers: Vec<PeerId>,
+        seed: [u8; 32],
     ) -> Self {
         Self {
             connections,
             module_instance_id,
             our_id,
             peers,
+            seed,
+            rng_counter: AtomicU64::new(0),
         }
     }
 
     pub fn peer_ids(&self) -> &[PeerId] {
         self.peers.as_slice()
     }
+
+    /// Randomness for generating the module's secrets
+    ///
+    /// Every call returns a different rng derived from the secret seed of the
+    /// DKG session, so a restarted session recreates the same secrets as long
+    /// as the module calls this in the same order. Modules must use it instead
+    /// of `OsRng` for anything they exchange with their peers.
+    pub fn rng(&self) -> StdRng {
+        let counter = self.rng_counter.fetch_add(1, Ordering::SeqCst);
+        let mut engine = sha256::HashEngine::default();
+        engine.input(&self.seed);
+        engine.input(&counter.to_be_bytes());
+        StdRng::from_seed(sha256::Hash::from_engine(engine).into_inner())
+    }
 }
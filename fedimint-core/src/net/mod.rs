@@ -1 +1,3 @@
 pub mod peers;
+#[cfg(not(target_family = "wasm"))]
+pub mod socks;
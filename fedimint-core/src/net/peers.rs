@@ -55,9 +55,12 @@ where
     /// Await receipt of a message from any connected peer.
     async fn receive(&mut self) -> Cancellable<(PeerId, Msg)>;
 
-    /// Removes a peer connection in case of misbehavior
+    /// Ignores a peer in case of misbehavior until it is unbanned
     async fn ban_peer(&mut self, peer: PeerId);
 
+    /// Resumes communicating with a previously banned peer
+    async fn unban_peer(&mut self, peer: PeerId);
+
     /// Converts the struct to a `PeerConnection` trait object
     fn into_dyn(self) -> PeerConnections<Msg>
     where
@@ -49,6 +49,10 @@ where
     async fn ban_peer(&mut self, _peer: PeerId) {
         unimplemented!();
     }
+
+    async fn unban_peer(&mut self, _peer: PeerId) {
+        // Fake connections never ban peers, so there is nothing to lift
+    }
 }
 
 /// Create a fake link between `peer1` and `peer2` for test purposes
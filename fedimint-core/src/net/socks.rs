@@ -0,0 +1,367 @@
+//! Minimal SOCKS5 client used to reach peers through a proxy such as Tor
+//!
+//! Host names are always handed to the proxy unresolved so that onion
+//! addresses work and no DNS requests leak around the proxy.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use jsonrpsee_core::client::{ReceivedMessage, TransportReceiverT, TransportSenderT};
+use soketto::handshake::{self, ServerResponse};
+use soketto::Data;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls;
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+use url::Url;
+
+const SOCKS_VERSION: u8 = 0x05;
+const NO_AUTHENTICATION: u8 = 0x00;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+const REPLY_SUCCEEDED: u8 = 0x00;
+
+/// Opens a TCP stream to `host:port` through the SOCKS5 proxy at `proxy`
+pub async fn socks5_connect(proxy: SocketAddr, host: &str, port: u16) -> io::Result<TcpStream> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let host_len = u8::try_from(host.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "SOCKS5 host name too long"))?;
+
+    let mut stream = TcpStream::connect(proxy).await?;
+
+    stream
+        .write_all(&[SOCKS_VERSION, 1, NO_AUTHENTICATION])
+        .await?;
+    let mut method = [0u8; 2];
+    stream.read_exact(&mut method).await?;
+    if method != [SOCKS_VERSION, NO_AUTHENTICATION] {
+        return Err(socks_error("SOCKS5 proxy requires authentication"));
+    }
+
+    let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0x00, ATYP_DOMAIN, host_len];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(socks_error("Invalid SOCKS5 reply"));
+    }
+    if reply[1] != REPLY_SUCCEEDED {
+        return Err(socks_error(&format!(
+            "SOCKS5 proxy failed to connect to {host}:{port} (code {})",
+            reply[1]
+        )));
+    }
+
+    // Skip the bound address, we have no use for it
+    let addr_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => stream.read_u8().await? as usize,
+        _ => return Err(socks_error("Invalid SOCKS5 address type")),
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(stream)
+}
+
+/// Opens a websocket to `url` through the SOCKS5 proxy at `proxy` for the
+/// jsonrpsee client, `wss://` URLs are verified against the web PKI like
+/// direct connections
+pub async fn socks5_ws_transport(
+    url: &Url,
+    proxy: SocketAddr,
+) -> io::Result<(WsTransportSender, WsTransportReceiver)> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "URL has no host"))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "URL has no port"))?;
+    let stream = socks5_connect(proxy, host, port).await?;
+
+    let stream: Box<dyn Stream> = match url.scheme() {
+        "ws" => Box::new(stream),
+        "wss" => Box::new(tls_connect(host, stream).await?),
+        scheme => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unsupported websocket scheme {scheme}"),
+            ))
+        }
+    };
+
+    let host_header = format!("{host}:{port}");
+    let mut client = handshake::Client::new(stream.compat(), &host_header, url.path());
+    match client.handshake().await.map_err(ws_error)? {
+        ServerResponse::Accepted { .. } => {}
+        ServerResponse::Rejected { status_code } => {
+            return Err(socks_error(&format!(
+                "Websocket handshake rejected with status {status_code}"
+            )))
+        }
+        ServerResponse::Redirect { .. } => {
+            return Err(socks_error("Websocket redirects are not supported"))
+        }
+    }
+
+    let (sender, receiver) = client.into_builder().finish();
+    Ok((WsTransportSender(sender), WsTransportReceiver(receiver)))
+}
+
+async fn tls_connect(
+    host: &str,
+    stream: TcpStream,
+) -> io::Result<tokio_rustls::client::TlsStream<TcpStream>> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|anchor| {
+        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject,
+            anchor.spki,
+            anchor.name_constraints,
+        )
+    }));
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let server_name = rustls::ServerName::try_from(host)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    tokio_rustls::TlsConnector::from(Arc::new(config))
+        .connect(server_name, stream)
+        .await
+}
+
+/// Plain or TLS stream a websocket runs over
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+type WsStream = Compat<Box<dyn Stream>>;
+
+/// Sending half of a websocket opened by [`socks5_ws_transport`]
+pub struct WsTransportSender(soketto::connection::Sender<WsStream>);
+
+/// Receiving half of a websocket opened by [`socks5_ws_transport`]
+pub struct WsTransportReceiver(soketto::connection::Receiver<WsStream>);
+
+#[async_trait]
+impl TransportSenderT for WsTransportSender {
+    type Error = soketto::connection::Error;
+
+    async fn send(&mut self, msg: String) -> Result<(), Self::Error> {
+        self.0.send_text(msg).await?;
+        self.0.flush().await
+    }
+
+    async fn close(&mut self) -> Result<(), Self::Error> {
+        self.0.close().await
+    }
+}
+
+#[async_trait]
+impl TransportReceiverT for WsTransportReceiver {
+    type Error = soketto::connection::Error;
+
+    async fn receive(&mut self) -> Result<ReceivedMessage, Self::Error> {
+        let mut message = Vec::new();
+        match self.0.receive_data(&mut message).await? {
+            Data::Text(_) => String::from_utf8(message)
+                .map(ReceivedMessage::Text)
+                .map_err(|e| e.utf8_error().into()),
+            Data::Binary(_) => Ok(ReceivedMessage::Bytes(message)),
+        }
+    }
+}
+
+fn ws_error(e: soketto::handshake::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+fn socks_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, msg.to_string())
+}
+
+/// Local SOCKS5 stand-in server for tests
+#[cfg(any(test, feature = "test-utils"))]
+pub mod mock {
+    use std::collections::BTreeMap;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::{ATYP_DOMAIN, CMD_CONNECT, NO_AUTHENTICATION, REPLY_SUCCEEDED, SOCKS_VERSION};
+
+    /// SOCKS5 proxy that, like Tor for onion services, only knows a fixed set
+    /// of host names and records every destination it was asked for
+    #[derive(Debug, Clone)]
+    pub struct MockSocks5Proxy {
+        pub addr: SocketAddr,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl MockSocks5Proxy {
+        /// Starts the proxy on a random local port, resolving `hosts`
+        /// (`host:port`) to the given addresses
+        pub async fn spawn(hosts: BTreeMap<String, SocketAddr>) -> MockSocks5Proxy {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let proxy = MockSocks5Proxy {
+                addr: listener.local_addr().unwrap(),
+                requests: Default::default(),
+            };
+
+            let requests = proxy.requests.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let hosts = hosts.clone();
+                    let requests = requests.clone();
+                    tokio::spawn(async move {
+                        let _ = handle(stream, &hosts, &requests).await;
+                    });
+                }
+            });
+
+            proxy
+        }
+
+        /// Destinations that clients requested so far
+        pub fn requests(&self) -> Vec<String> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    async fn handle(
+        mut stream: TcpStream,
+        hosts: &BTreeMap<String, SocketAddr>,
+        requests: &Mutex<Vec<String>>,
+    ) -> std::io::Result<()> {
+        let mut greeting = [0u8; 2];
+        stream.read_exact(&mut greeting).await?;
+        let mut methods = vec![0u8; greeting[1] as usize];
+        stream.read_exact(&mut methods).await?;
+        stream
+            .write_all(&[SOCKS_VERSION, NO_AUTHENTICATION])
+            .await?;
+
+        let mut request = [0u8; 4];
+        stream.read_exact(&mut request).await?;
+        assert_eq!(request[1], CMD_CONNECT);
+        assert_eq!(request[3], ATYP_DOMAIN, "Host names must not be resolved");
+        let mut host = vec![0u8; stream.read_u8().await? as usize];
+        stream.read_exact(&mut host).await?;
+        let port = stream.read_u16().await?;
+
+        let destination = format!("{}:{port}", String::from_utf8_lossy(&host));
+        requests.lock().unwrap().push(destination.clone());
+
+        let mut target = match hosts.get(&destination) {
+            Some(addr) => TcpStream::connect(addr).await?,
+            None => {
+                // host unreachable
+                stream
+                    .write_all(&[SOCKS_VERSION, 0x04, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                    .await?;
+                return Ok(());
+            }
+        };
+
+        stream
+            .write_all(&[SOCKS_VERSION, REPLY_SUCCEEDED, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+            .await?;
+        tokio::io::copy_bidirectional(&mut stream, &mut target).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use jsonrpsee_core::client::{ReceivedMessage, TransportReceiverT, TransportSenderT};
+    use soketto::handshake;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio_util::compat::TokioAsyncReadCompatExt;
+
+    use super::mock::MockSocks5Proxy;
+    use super::{socks5_connect, socks5_ws_transport};
+
+    const ONION: &str = "fedimintxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx.onion";
+
+    #[tokio::test]
+    async fn connects_to_onion_host_through_proxy() {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        let proxy =
+            MockSocks5Proxy::spawn(BTreeMap::from([(format!("{ONION}:8173"), target_addr)])).await;
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = target.accept().await.unwrap();
+            let mut buf = [0u8; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+        });
+
+        let mut stream = socks5_connect(proxy.addr, ONION, 8173).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        server.await.unwrap();
+
+        assert!(socks5_connect(proxy.addr, ONION, 8174).await.is_err());
+        assert_eq!(
+            proxy.requests(),
+            vec![format!("{ONION}:8173"), format!("{ONION}:8174")]
+        );
+    }
+
+    #[tokio::test]
+    async fn opens_websocket_through_proxy() {
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap();
+        let proxy =
+            MockSocks5Proxy::spawn(BTreeMap::from([(format!("{ONION}:80"), target_addr)])).await;
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = target.accept().await.unwrap();
+            let mut server = handshake::Server::new(stream.compat());
+            let key = server.receive_request().await.unwrap().key();
+            server
+                .send_response(&handshake::server::Response::Accept {
+                    key,
+                    protocol: None,
+                })
+                .await
+                .unwrap();
+            let (mut sender, mut receiver) = server.into_builder().finish();
+            let mut message = Vec::new();
+            receiver.receive_data(&mut message).await.unwrap();
+            sender
+                .send_text(String::from_utf8(message).unwrap())
+                .await
+                .unwrap();
+            sender.flush().await.unwrap();
+        });
+
+        let url = format!("ws://{ONION}/").parse().unwrap();
+        let (mut sender, mut receiver) = socks5_ws_transport(&url, proxy.addr).await.unwrap();
+        sender.send("ping".to_string()).await.unwrap();
+        match receiver.receive().await.unwrap() {
+            ReceivedMessage::Text(text) => assert_eq!(text, "ping"),
+            _ => panic!("Expected a text message"),
+        }
+        server.await.unwrap();
+        assert_eq!(proxy.requests(), vec![format!("{ONION}:80")]);
+    }
+}
@@ -16,3 +16,14 @@ pub enum TransactionStatus {
 }
 
 pub type SerdeOutputOutcome = SerdeModuleEncoding<fedimint_core::core::DynOutputOutcome>;
+
+/// Status of a single transaction output, pushed to subscribers whenever it
+/// changes
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub enum OutputStatus {
+    /// The transaction was rejected, see [`TransactionStatus::Rejected`]
+    Rejected(String),
+    /// The transaction was accepted and the output has this outcome, which may
+    /// still change while the module is processing it
+    Accepted(SerdeOutputOutcome),
+}
@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+
+use bitcoin_hashes::hex::ToHex;
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::{DbKeyPrefix as CoreRange, MODULE_GLOBAL_PREFIX};
+use fedimint_core::encoding::Decodable;
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_ln_server::common::db::DbKeyPrefix as LightningRange;
+use fedimint_mint_server::common::db::DbKeyPrefix as MintRange;
+use fedimint_server::db::DbKeyPrefix as ConsensusRange;
+use fedimint_wallet_server::common::db::DbKeyPrefix as WalletRange;
+use serde::Serialize;
+
+use crate::dump::{consensus_prefix_name, module_prefix_name};
+
+/// Differences between two guardian databases, grouped by module and
+/// `DbKeyPrefix`. Keys and values are hex encoded.
+#[derive(Debug, Default, Serialize)]
+pub struct DatabaseDiff(BTreeMap<String, BTreeMap<String, PrefixDiff>>);
+
+#[derive(Debug, Default, Serialize)]
+pub struct PrefixDiff {
+    /// Entries only present in the second database
+    added: BTreeMap<String, String>,
+    /// Entries only present in the first database
+    removed: BTreeMap<String, String>,
+    /// Entries whose value differs between the databases
+    changed: BTreeMap<String, ValueChange>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValueChange {
+    before: String,
+    after: String,
+}
+
+impl DatabaseDiff {
+    /// Compares all entries of `before` and `after`, `module_kinds` maps the
+    /// module instance ids to their kinds. Unless `include_local` is set,
+    /// entries of node-local prefixes are ignored since they are expected to
+    /// differ between guardians, as are epochs that only one of the guardians
+    /// has pruned.
+    pub fn new(
+        before: &BTreeMap<Vec<u8>, Vec<u8>>,
+        after: &BTreeMap<Vec<u8>, Vec<u8>>,
+        module_kinds: &BTreeMap<ModuleInstanceId, String>,
+        include_local: bool,
+    ) -> DatabaseDiff {
+        let mut diff = DatabaseDiff::default();
+        for (key, value) in before {
+            let change = match after.get(key) {
+                Some(after_value) if after_value == value => continue,
+                Some(after_value) => Some(after_value),
+                None => None,
+            };
+            let Some(prefix) = diff.prefix(key, module_kinds, include_local, change.is_none())
+            else {
+                continue;
+            };
+            match change {
+                Some(after_value) => {
+                    prefix.changed.insert(
+                        key.to_hex(),
+                        ValueChange {
+                            before: value.to_hex(),
+                            after: after_value.to_hex(),
+                        },
+                    );
+                }
+                None => {
+                    prefix.removed.insert(key.to_hex(), value.to_hex());
+                }
+            }
+        }
+        for (key, value) in after {
+            if before.contains_key(key) {
+                continue;
+            }
+            if let Some(prefix) = diff.prefix(key, module_kinds, include_local, true) {
+                prefix.added.insert(key.to_hex(), value.to_hex());
+            }
+        }
+
+        diff
+    }
+
+    /// Returns the group `key` is reported in, or `None` if it is ignored.
+    /// `only_in_one` is set if the key is missing from one of the databases.
+    fn prefix(
+        &mut self,
+        key: &[u8],
+        module_kinds: &BTreeMap<ModuleInstanceId, String>,
+        include_local: bool,
+        only_in_one: bool,
+    ) -> Option<&mut PrefixDiff> {
+        let (module, prefix) = match key {
+            [MODULE_GLOBAL_PREFIX, module_key @ ..] if module_key.len() >= 2 => {
+                let module_id = ModuleInstanceId::consensus_decode(
+                    &mut &module_key[..2],
+                    &ModuleDecoderRegistry::default(),
+                )
+                .expect("Module instance ids are two bytes");
+                let kind = module_kinds
+                    .get(&module_id)
+                    .cloned()
+                    .unwrap_or_else(|| "unknown".to_string());
+                let prefix = match module_key.get(2) {
+                    Some(byte) if !include_local && is_node_local(&kind, *byte) => return None,
+                    Some(byte) => prefix_name(module_prefix_name(&kind, *byte), *byte),
+                    None => String::new(),
+                };
+                (format!("{kind}-{module_id}"), prefix)
+            }
+            [byte, ..] if !include_local && is_node_local_consensus(*byte) => return None,
+            [byte, ..] if !include_local && only_in_one && is_pruned_with_retention(*byte) => {
+                return None
+            }
+            [byte, ..] => (
+                "consensus".to_string(),
+                prefix_name(consensus_prefix_name(*byte), *byte),
+            ),
+            [] => ("consensus".to_string(), String::new()),
+        };
+
+        Some(self.0.entry(module).or_default().entry(prefix).or_default())
+    }
+}
+
+/// Falls back to the database version key and then to the hex value of `byte`
+/// for prefixes without a `name`
+fn prefix_name(name: Option<String>, byte: u8) -> String {
+    name.unwrap_or_else(|| {
+        if byte == CoreRange::DatabaseVersion as u8 {
+            CoreRange::DatabaseVersion.to_string().to_lowercase()
+        } else {
+            format!("{byte:02x}")
+        }
+    })
+}
+
+/// Returns whether entries with `prefix` in a module of `kind` only concern the
+/// local guardian, like its own signature shares or data submitted to its API
+fn is_node_local(kind: &str, prefix: u8) -> bool {
+    let local_prefixes = match kind {
+        "ln" => vec![
+            LightningRange::ProposeDecryptionShare as u8,
+            LightningRange::LightningGateway as u8,
+        ],
+        "mint" => vec![
+            MintRange::ProposedPartialSig as u8,
+            MintRange::ReceivedPartialSig as u8,
+            MintRange::EcashBackup as u8,
+        ],
+        "wallet" => vec![WalletRange::PegOutTxSigCi as u8],
+        _ => vec![],
+    };
+    local_prefixes.contains(&prefix)
+}
+
+/// Returns whether consensus entries with `prefix` only concern the local
+/// guardian, like its pending config changes or its view of misbehaving peers
+fn is_node_local_consensus(prefix: u8) -> bool {
+    [
+        ConsensusRange::DropPeer as u8,
+        ConsensusRange::PendingEndpointUpdate as u8,
+        ConsensusRange::PeerFault as u8,
+        ConsensusRange::CompletedTlsCertRotation as u8,
+    ]
+    .contains(&prefix)
+}
+
+/// Returns whether consensus entries with `prefix` are pruned depending on the
+/// guardian's `--epoch-retention`, so an epoch may be kept in full by one
+/// guardian and only as a checkpoint by another
+fn is_pruned_with_retention(prefix: u8) -> bool {
+    [
+        ConsensusRange::EpochHistory as u8,
+        ConsensusRange::EpochCheckpoint as u8,
+    ]
+    .contains(&prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use fedimint_core::db::MODULE_GLOBAL_PREFIX;
+    use fedimint_mint_server::common::db::DbKeyPrefix as MintRange;
+    use fedimint_server::db::DbKeyPrefix as ConsensusRange;
+
+    use super::DatabaseDiff;
+
+    fn mint_key(prefix: MintRange, suffix: u8) -> Vec<u8> {
+        vec![MODULE_GLOBAL_PREFIX, 1, 0, prefix as u8, suffix]
+    }
+
+    #[test]
+    fn diff_ignores_node_local_and_pruned_entries() {
+        let accepted = ConsensusRange::AcceptedTransaction as u8;
+        let history = ConsensusRange::EpochHistory as u8;
+        let checkpoint = ConsensusRange::EpochCheckpoint as u8;
+        let fault = ConsensusRange::PeerFault as u8;
+
+        let before = BTreeMap::from([
+            (vec![accepted, 1], vec![1]),
+            (vec![accepted, 2], vec![2]),
+            (vec![history, 1], vec![1]),
+            (vec![history, 2], vec![2]),
+            (vec![fault, 1], vec![1]),
+            (mint_key(MintRange::EcashBackup, 1), vec![1]),
+        ]);
+        let after = BTreeMap::from([
+            (vec![accepted, 1], vec![3]),
+            (vec![accepted, 3], vec![3]),
+            (vec![history, 2], vec![3]),
+            (vec![checkpoint, 1], vec![1]),
+            (mint_key(MintRange::NoteNonce, 1), vec![]),
+        ]);
+        let module_kinds = BTreeMap::from([(1, "mint".to_string())]);
+
+        let diff =
+            serde_json::to_value(DatabaseDiff::new(&before, &after, &module_kinds, false)).unwrap();
+        assert_eq!(
+            diff,
+            serde_json::json!({
+                "consensus": {
+                    "acceptedtransaction": {
+                        "added": { "0203": "03" },
+                        "removed": { "0202": "02" },
+                        "changed": { "0201": { "before": "01", "after": "03" } },
+                    },
+                    "epochhistory": {
+                        "added": {},
+                        "removed": {},
+                        "changed": { "0502": { "before": "02", "after": "03" } },
+                    },
+                },
+                "mint-1": {
+                    "notenonce": {
+                        "added": { "ff01001001": "" },
+                        "removed": {},
+                        "changed": {},
+                    },
+                },
+            })
+        );
+
+        let diff = DatabaseDiff::new(&before, &after, &module_kinds, true);
+        let consensus = &diff.0["consensus"];
+        assert!(consensus.contains_key("peerfault"));
+        assert!(consensus.contains_key("epochcheckpoint"));
+        assert!(diff.0["mint-1"].contains_key("ecashbackup"));
+    }
+}
@@ -1,22 +1,23 @@
 use std::collections::BTreeMap;
 use std::path::PathBuf;
 
+use anyhow::format_err;
 use erased_serde::Serialize;
 use fedimint_core::config::ServerModuleGenRegistry;
+use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::db::notifications::Notifications;
-use fedimint_core::db::{DatabaseTransaction, DatabaseVersionKey, SingleUseDatabaseTransaction};
-use fedimint_core::encoding::Encodable;
-use fedimint_core::module::DynServerModuleGen;
+use fedimint_core::db::{
+    DatabaseTransaction, DatabaseVersionKey, ISingleUseDatabaseTransaction,
+    SingleUseDatabaseTransaction, MODULE_GLOBAL_PREFIX,
+};
+use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::__reexports::serde_json;
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::{push_db_key_items, push_db_pair_items, push_db_pair_items_no_serde};
-use fedimint_ln_server::LightningGen;
-use fedimint_mint_server::MintGen;
 use fedimint_rocksdb::RocksDbReadOnly;
 use fedimint_server::config::io::read_server_config;
 use fedimint_server::config::ServerConfig;
 use fedimint_server::db as ConsensusRange;
-use fedimint_wallet_server::WalletGen;
 use futures::StreamExt;
 use mint_client::db as ClientRange;
 use mint_client::ln::db as ClientLightningRange;
@@ -24,6 +25,8 @@ use mint_client::mint::db as ClientMintRange;
 use mint_client::wallet::db as ClientWalletRange;
 use strum::IntoEnumIterator;
 
+use crate::server_module_gens;
+
 #[derive(Debug, serde::Serialize)]
 struct SerdeWrapper(#[serde(with = "hex::serde")] Vec<u8>);
 
@@ -63,45 +66,126 @@ impl<'a> DatabaseDump<'a> {
         };
         let single_use = SingleUseDatabaseTransaction::new(read_only);
 
-        // leak here is OK, it only happens once.
-        let notifications = Box::leak(Box::new(Notifications::new()));
-        if modules.contains(&"client".to_string()) {
-            let dbtx = DatabaseTransaction::new(
-                Box::new(single_use),
-                ModuleDecoderRegistry::default(),
-                notifications,
-            );
-            return DatabaseDump {
-                serialized: BTreeMap::new(),
-                read_only: dbtx,
-                modules,
-                prefixes,
-                cfg: None,
-                module_inits: Default::default(),
-            };
-        }
+        let cfg = if modules.contains(&"client".to_string()) {
+            None
+        } else {
+            Some(read_server_config(&password, cfg_dir).unwrap())
+        };
 
-        let module_inits = ServerModuleGenRegistry::from(vec![
-            DynServerModuleGen::from(WalletGen),
-            DynServerModuleGen::from(MintGen),
-            DynServerModuleGen::from(LightningGen),
-        ]);
+        Self::from_transaction(Box::new(single_use), cfg, modules, prefixes)
+    }
 
-        let cfg = read_server_config(&password, cfg_dir).unwrap();
-        let decoders = module_inits.decoders(cfg.iter_module_instances()).unwrap();
-        let dbtx = DatabaseTransaction::new(Box::new(single_use), decoders, notifications);
+    /// Like [`Self::new`], but dumps using an already opened database
+    /// transaction. Without a server config the database is treated as a
+    /// client database.
+    pub fn from_transaction(
+        dbtx: Box<dyn ISingleUseDatabaseTransaction<'a>>,
+        cfg: Option<ServerConfig>,
+        modules: Vec<String>,
+        prefixes: Vec<String>,
+    ) -> DatabaseDump<'a> {
+        // leak here is OK, it only happens once.
+        let notifications = Box::leak(Box::new(Notifications::new()));
+        let module_inits = server_module_gens();
+        let decoders = match &cfg {
+            Some(cfg) => module_inits.decoders(cfg.iter_module_instances()).unwrap(),
+            None => ModuleDecoderRegistry::default(),
+        };
+        let dbtx = DatabaseTransaction::new(dbtx, decoders, notifications);
 
         DatabaseDump {
             serialized: BTreeMap::new(),
             read_only: dbtx,
             modules,
             prefixes,
-            cfg: Some(cfg),
+            cfg,
             module_inits,
         }
     }
 }
 
+/// Finds the lowercase name of the variant of the `DbKeyPrefix` enum
+/// `$prefix_enum` with the value `$byte`, as used by `dump` to select prefixes
+macro_rules! prefix_name {
+    ($prefix_enum:ty, $byte:expr) => {
+        <$prefix_enum>::iter()
+            .find(|prefix| prefix.clone() as u8 == $byte)
+            .map(|prefix| prefix.to_string().to_lowercase())
+    };
+}
+
+/// Name of the consensus `DbKeyPrefix` with the value `byte`
+pub fn consensus_prefix_name(byte: u8) -> Option<String> {
+    prefix_name!(ConsensusRange::DbKeyPrefix, byte)
+}
+
+/// Name of the `DbKeyPrefix` with the value `byte` of the module `kind`
+pub fn module_prefix_name(kind: &str, byte: u8) -> Option<String> {
+    match kind {
+        "ln" => prefix_name!(fedimint_ln_server::common::db::DbKeyPrefix, byte),
+        "mint" => prefix_name!(fedimint_mint_server::common::db::DbKeyPrefix, byte),
+        "wallet" => prefix_name!(fedimint_wallet_server::common::db::DbKeyPrefix, byte),
+        _ => None,
+    }
+}
+
+/// Translates a raw key `prefix` into the module and prefix names `dump`
+/// selects, so the entries it points into can be printed decoded. Without a
+/// server config the prefix is looked up in the client ranges.
+pub fn dump_filters(
+    prefix: &[u8],
+    cfg: Option<&ServerConfig>,
+) -> anyhow::Result<(Vec<String>, Vec<String>)> {
+    let Some(cfg) = cfg else {
+        let prefixes = match prefix.first() {
+            Some(byte) => vec![prefix_name!(ClientRange::DbKeyPrefix, *byte)
+                .or_else(|| prefix_name!(ClientLightningRange::DbKeyPrefix, *byte))
+                .or_else(|| prefix_name!(ClientMintRange::DbKeyPrefix, *byte))
+                .or_else(|| prefix_name!(ClientWalletRange::DbKeyPrefix, *byte))
+                .ok_or_else(|| format_err!("Unknown client prefix {byte:02x}"))?],
+            None => vec![],
+        };
+        return Ok((vec!["client".to_string()], prefixes));
+    };
+
+    match prefix {
+        [] => Ok((vec![], vec![])),
+        [MODULE_GLOBAL_PREFIX, module_prefix @ ..] if module_prefix.len() >= 2 => {
+            let module_id = ModuleInstanceId::consensus_decode(
+                &mut &module_prefix[..2],
+                &ModuleDecoderRegistry::default(),
+            )?;
+            let kind = cfg
+                .consensus
+                .modules
+                .get(&module_id)
+                .ok_or_else(|| format_err!("Unknown module instance {module_id}"))?
+                .kind();
+
+            let prefixes = match module_prefix.get(2) {
+                Some(byte) => vec![module_prefix_name(kind.as_str(), *byte)
+                    .ok_or_else(|| format_err!("Unknown prefix {byte:02x} of module {kind}"))?],
+                None => vec![],
+            };
+            Ok((vec![kind.to_string()], prefixes))
+        }
+        // All modules
+        [MODULE_GLOBAL_PREFIX, ..] => Ok((
+            cfg.consensus
+                .modules
+                .values()
+                .map(|module_cfg| module_cfg.kind().to_string())
+                .collect(),
+            vec![],
+        )),
+        [byte, ..] => {
+            let name = consensus_prefix_name(*byte)
+                .ok_or_else(|| format_err!("Unknown consensus prefix {byte:02x}"))?;
+            Ok((vec!["consensus".to_string()], vec![name]))
+        }
+    }
+}
+
 impl<'a> DatabaseDump<'a> {
     /// Prints the contents of the BTreeMap to a pretty JSON string
     fn print_database(&self) {
@@ -183,14 +267,24 @@ impl<'a> DatabaseDump<'a> {
                     );
                 }
                 ConsensusRange::DbKeyPrefix::DropPeer => {
-                    push_db_key_items!(
+                    push_db_pair_items!(
                         dbtx,
                         ConsensusRange::DropPeerKeyPrefix,
                         ConsensusRange::DropPeerKey,
+                        fedimint_server::consensus::misbehavior::PeerBan,
                         consensus,
                         "Dropped Peers"
                     );
                 }
+                ConsensusRange::DbKeyPrefix::PeerFault => {
+                    push_db_key_items!(
+                        dbtx,
+                        ConsensusRange::PeerFaultKeyPrefix,
+                        ConsensusRange::PeerFaultKey,
+                        consensus,
+                        "Peer Faults"
+                    );
+                }
                 ConsensusRange::DbKeyPrefix::RejectedTransaction => {
                     push_db_pair_items!(
                         dbtx,
@@ -233,6 +327,57 @@ impl<'a> DatabaseDump<'a> {
                         consensus.insert("ShutdownSignal".to_string(), Box::new(shutdown));
                     }
                 }
+                ConsensusRange::DbKeyPrefix::EpochCheckpoint => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::EpochCheckpointKeyPrefix,
+                        ConsensusRange::EpochCheckpointKey,
+                        fedimint_core::epoch::EpochCheckpoint,
+                        consensus,
+                        "Epoch Checkpoints"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::TlsCertRotation => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::TlsCertRotationKeyPrefix,
+                        ConsensusRange::TlsCertRotationKey,
+                        fedimint_core::epoch::TlsCertRotation,
+                        consensus,
+                        "TLS Cert Rotations"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::CompletedTlsCertRotation => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::CompletedTlsCertRotationKeyPrefix,
+                        ConsensusRange::CompletedTlsCertRotationKey,
+                        fedimint_core::epoch::TlsCertRotation,
+                        consensus,
+                        "Completed TLS Cert Rotations"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::EndpointUpdate => {
+                    push_db_pair_items_no_serde!(
+                        dbtx,
+                        ConsensusRange::EndpointUpdateKeyPrefix,
+                        ConsensusRange::EndpointUpdateKey,
+                        fedimint_core::epoch::EndpointUpdate,
+                        consensus,
+                        "Endpoint Updates"
+                    );
+                }
+                ConsensusRange::DbKeyPrefix::PendingEndpointUpdate => {
+                    let pending = dbtx
+                        .get_value(&ConsensusRange::PendingEndpointUpdateKey)
+                        .await;
+                    if let Some(pending) = pending {
+                        consensus.insert(
+                            "PendingEndpointUpdate".to_string(),
+                            Box::new(SerdeWrapper::from_encodable(pending)),
+                        );
+                    }
+                }
                 // Module is a global prefix for all module data
                 ConsensusRange::DbKeyPrefix::Module => {}
             }
@@ -302,6 +447,24 @@ impl<'a> DatabaseDump<'a> {
                         "Outgoing Payment Claims"
                     );
                 }
+                ClientLightningRange::DbKeyPrefix::InternalPayment => {
+                    push_db_pair_items!(
+                        dbtx,
+                        ClientLightningRange::InternalPaymentKeyPrefix,
+                        ClientLightningRange::InternalPaymentKey,
+                        mint_client::ln::outgoing::InternalPaymentData,
+                        ln_client,
+                        "Internal Payments"
+                    );
+                }
+                ClientLightningRange::DbKeyPrefix::GatewayPreferences => {
+                    let preferences = dbtx
+                        .get_value(&ClientLightningRange::GatewayPreferencesKey)
+                        .await;
+                    if let Some(preferences) = preferences {
+                        ln_client.insert("GatewayPreferences".to_string(), Box::new(preferences));
+                    }
+                }
             }
         }
 
@@ -423,6 +586,15 @@ impl<'a> DatabaseDump<'a> {
                         client.insert("Client Secret".to_string(), Box::new(secret));
                     }
                 }
+                ClientRange::DbKeyPrefix::ApiEndpoints => {
+                    let endpoints = self
+                        .read_only
+                        .get_value(&ClientRange::ApiEndpointsKey)
+                        .await;
+                    if let Some(endpoints) = endpoints {
+                        client.insert("API Endpoints".to_string(), Box::new(endpoints));
+                    }
+                }
             }
         }
 
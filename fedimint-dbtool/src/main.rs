@@ -1,33 +1,72 @@
 #![allow(where_clauses_object_safety)] // https://github.com/dtolnay/async-trait/issues/228
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
-use bitcoin_hashes::hex::ToHex;
+use aead::{get_encryption_key, LessSafeKey};
+use anyhow::{bail, Result};
+use bitcoin_hashes::hex::{FromHex, ToHex};
 use bytes::Bytes;
-use clap::{Parser, Subcommand};
-use fedimint_core::db::IDatabase;
+use clap::{Parser, Subcommand, ValueEnum};
+use fedimint_core::config::ServerModuleGenRegistry;
+use fedimint_core::db::notifications::Notifications;
+use fedimint_core::db::{
+    apply_migrations, dry_run_migrations, Database, DatabaseTransaction, DatabaseVersion,
+    IDatabase, IDatabaseTransaction, MigrationMap, MigrationReport, SingleUseDatabaseTransaction,
+};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use fedimint_core::module::DynServerModuleGen;
+use fedimint_encrypted_db::{is_encryption_marker, DbEncryptionKey, EncryptedDatabase};
+use fedimint_ln_server::LightningGen;
 use fedimint_logging::TracingSetup;
+use fedimint_mint_server::MintGen;
+use fedimint_rocksdb::RocksDbReadOnly;
+use fedimint_server::backup::DatabaseBackup;
+use fedimint_server::check::check_database;
+use fedimint_server::config::io::{read_server_config, SALT_FILE};
+use fedimint_server::db::{get_global_database_migrations, GLOBAL_DATABASE_VERSION};
+use fedimint_wallet_server::WalletGen;
 use futures::StreamExt;
 
-use crate::dump::DatabaseDump;
+use crate::diff::DatabaseDiff;
+use crate::dump::{dump_filters, DatabaseDump};
 
+mod diff;
 mod dump;
 
 #[derive(Debug, Clone, Parser)]
 struct Options {
     database: String,
+    /// Database backend, detected from `database` if not set: SQLite for
+    /// files and `sqlite:` connection strings, RocksDB for directories
+    #[arg(long, value_enum)]
+    backend: Option<DbBackend>,
     #[command(subcommand)]
     command: DbCommand,
 }
 
-/// Tool to inspect and manipulate rocksdb databases. All binary arguments
-/// (keys, values) have to be hex encoded.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum DbBackend {
+    Rocksdb,
+    Sqlite,
+}
+
+/// Tool to inspect and manipulate rocksdb and sqlite databases. All binary
+/// arguments (keys, values) have to be hex encoded.
 #[derive(Debug, Clone, Subcommand)]
 enum DbCommand {
-    /// List all key-value pairs where the key begins with `prefix`
+    /// List all key-value pairs where the key begins with `prefix`. With
+    /// `--decode` the entries of the table `prefix` points into are printed
+    /// decoded as JSON like `dump` does, which requires `--cfg-dir` and
+    /// `--password` for server databases.
     List {
         #[arg(value_parser = hex_parser)]
         prefix: Bytes,
+        #[arg(long)]
+        decode: bool,
+        #[arg(long)]
+        cfg_dir: Option<PathBuf>,
+        #[arg(long, env = "FM_PASSWORD")]
+        password: Option<String>,
     },
     /// Write a key-value pair to the database, overwriting the previous value
     /// if present
@@ -56,6 +95,66 @@ enum DbCommand {
         #[arg(required = false)]
         prefixes: Option<String>,
     },
+    /// Write a consistent snapshot of the database to `backup_file`, encrypted
+    /// with the key derived from the server's config password. The database is
+    /// opened read-only, so this works while fedimintd is running. Backups of
+    /// databases encrypted with `--encrypt-db` contain the encrypted entries,
+    /// their versions are read by decrypting them with the same password.
+    Backup {
+        cfg_dir: PathBuf,
+        #[arg(env = "FM_PASSWORD")]
+        password: String,
+        backup_file: PathBuf,
+    },
+    /// Restore a backup created by `backup` or the `/backup` API into an empty
+    /// database after checking that its versions can be migrated by this
+    /// version of fedimint
+    Restore {
+        cfg_dir: PathBuf,
+        #[arg(env = "FM_PASSWORD")]
+        password: String,
+        backup_file: PathBuf,
+    },
+    /// Migrate the global and all module databases to the versions expected by
+    /// this version of fedimint. With `--dry-run` the migrations are rolled back
+    /// and only the on-disk and target versions as well as the number of keys
+    /// changed per prefix are reported as JSON.
+    Migrate {
+        cfg_dir: PathBuf,
+        #[arg(env = "FM_PASSWORD")]
+        password: String,
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Verify the invariants of the federation's database, e.g. that the
+    /// balance sheet is non-negative, that every accepted transaction has
+    /// outcomes in its modules and that the epoch history forms an unbroken
+    /// hash chain. Prints a JSON report and fails if any invariant is
+    /// violated.
+    Check {
+        cfg_dir: PathBuf,
+        #[arg(env = "FM_PASSWORD")]
+        password: String,
+    },
+    /// Compare the database with the database of another guardian of the same
+    /// federation and print the added, removed and changed entries grouped by
+    /// module and prefix as JSON. Prefixes holding node-local data like
+    /// signature shares are skipped unless `--include-local` is set.
+    Diff {
+        other_database: String,
+        cfg_dir: PathBuf,
+        #[arg(env = "FM_PASSWORD")]
+        password: String,
+        /// Config directory of the other guardian, needed if its database is
+        /// encrypted. Defaults to `cfg_dir`.
+        #[arg(long)]
+        other_cfg_dir: Option<PathBuf>,
+        /// Password of the other guardian, defaults to `password`
+        #[arg(long, env = "FM_OTHER_PASSWORD")]
+        other_password: Option<String>,
+        #[arg(long)]
+        include_local: bool,
+    },
 }
 
 fn hex_parser(hex: &str) -> Result<Bytes> {
@@ -67,16 +166,146 @@ fn print_kv(key: &[u8], value: &[u8]) {
     println!("{} {}", key.to_hex(), value.to_hex());
 }
 
+async fn open_db(database: &str, backend: Option<DbBackend>) -> Result<Box<dyn IDatabase>> {
+    let backend = backend.unwrap_or_else(|| {
+        if database.starts_with("sqlite:") || Path::new(database).is_file() {
+            DbBackend::Sqlite
+        } else {
+            DbBackend::Rocksdb
+        }
+    });
+
+    Ok(match backend {
+        DbBackend::Rocksdb => Box::new(fedimint_rocksdb::RocksDb::open(database)?),
+        DbBackend::Sqlite => {
+            let connection_string = if database.starts_with("sqlite:") {
+                database.to_string()
+            } else {
+                format!("sqlite://{database}")
+            };
+            Box::new(fedimint_sqlite::SqliteDb::open(&connection_string).await?)
+        }
+    })
+}
+
+/// Opens the RocksDB server database, decrypting it if it was encrypted with
+/// `--encrypt-db`
+async fn open_server_db(
+    database: &str,
+    cfg_dir: &Path,
+    password: &str,
+    decoders: ModuleDecoderRegistry,
+) -> Result<Database> {
+    let rocksdb = fedimint_rocksdb::RocksDb::open(database)?;
+    Ok(if EncryptedDatabase::is_encrypted(&rocksdb).await? {
+        let key = read_db_key(cfg_dir, password)?;
+        Database::new(EncryptedDatabase::open(rocksdb, key).await?, decoders)
+    } else {
+        Database::new(rocksdb, decoders)
+    })
+}
+
+/// Reads all entries of the RocksDB server database without opening it for
+/// writing, decrypting them if it was encrypted with `--encrypt-db`
+async fn read_server_db(
+    database: &str,
+    cfg_dir: &Path,
+    password: &str,
+) -> Result<BTreeMap<Vec<u8>, Vec<u8>>> {
+    let mut read_only = RocksDbReadOnly::open_read_only(database)?;
+    let entries = read_only
+        .raw_find_by_prefix(&[])
+        .await
+        .collect::<Vec<_>>()
+        .await;
+    if !entries.iter().any(|(key, _)| is_encryption_marker(key)) {
+        return Ok(entries.into_iter().collect());
+    }
+
+    let key = read_db_key(cfg_dir, password)?;
+    let mut decrypted = BTreeMap::new();
+    for (raw_key, raw_value) in entries {
+        if let Some((db_key, value)) = key.decrypt_raw_entry(&raw_key, &raw_value)? {
+            decrypted.insert(db_key, value);
+        }
+    }
+    Ok(decrypted)
+}
+
+fn read_db_key(cfg_dir: &Path, password: &str) -> Result<DbEncryptionKey> {
+    let salt = std::fs::read_to_string(cfg_dir.join(SALT_FILE))?;
+    DbEncryptionKey::from_password(password, &salt)
+}
+
+fn read_config_key(cfg_dir: &Path, password: &str) -> Result<LessSafeKey> {
+    let salt = std::fs::read_to_string(cfg_dir.join(SALT_FILE))?;
+    get_encryption_key(password, &salt)
+}
+
+/// Applies `migrations` to `db` or only reports their effect if `dry_run` is
+/// set
+async fn migrate_database<'a>(
+    db: &'a Database,
+    kind: String,
+    target_version: DatabaseVersion,
+    migrations: MigrationMap<'a>,
+    dry_run: bool,
+) -> Result<Option<MigrationReport>> {
+    if dry_run {
+        Ok(Some(
+            dry_run_migrations(db, kind, target_version, migrations).await?,
+        ))
+    } else {
+        apply_migrations(db, kind, target_version, migrations).await?;
+        Ok(None)
+    }
+}
+
+fn server_module_gens() -> ServerModuleGenRegistry {
+    ServerModuleGenRegistry::from(vec![
+        DynServerModuleGen::from(WalletGen),
+        DynServerModuleGen::from(MintGen),
+        DynServerModuleGen::from(LightningGen),
+    ])
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     TracingSetup::default().init()?;
     let options: Options = Options::parse();
 
     match options.command {
-        DbCommand::List { prefix } => {
-            let rocksdb: Box<dyn IDatabase> =
-                Box::new(fedimint_rocksdb::RocksDb::open(&options.database).unwrap());
-            let mut dbtx = rocksdb.begin_transaction().await;
+        DbCommand::List {
+            prefix,
+            decode,
+            cfg_dir,
+            password,
+        } => {
+            let db = open_db(&options.database, options.backend).await?;
+
+            if decode {
+                let cfg = match cfg_dir {
+                    Some(cfg_dir) => {
+                        let Some(password) = password else {
+                            bail!("Decoding a server database requires --password");
+                        };
+                        Some(read_server_config(&password, cfg_dir)?)
+                    }
+                    None => None,
+                };
+                let (modules, prefixes) = dump_filters(&prefix, cfg.as_ref())?;
+
+                let mut dbdump = DatabaseDump::from_transaction(
+                    db.begin_transaction().await,
+                    cfg,
+                    modules,
+                    prefixes,
+                );
+                dbdump.dump_database().await;
+                return Ok(());
+            }
+
+            let mut dbtx = db.begin_transaction().await;
             let prefix_iter = dbtx
                 .raw_find_by_prefix(&prefix)
                 .await?
@@ -85,25 +314,29 @@ async fn main() -> Result<()> {
             for (key, value) in prefix_iter {
                 print_kv(&key, &value);
             }
-            dbtx.commit_tx().await.expect("Error committing to RocksDb");
+            dbtx.commit_tx()
+                .await
+                .expect("Error committing to database");
         }
         DbCommand::Write { key, value } => {
-            let rocksdb: Box<dyn IDatabase> =
-                Box::new(fedimint_rocksdb::RocksDb::open(&options.database).unwrap());
-            let mut dbtx = rocksdb.begin_transaction().await;
+            let db = open_db(&options.database, options.backend).await?;
+            let mut dbtx = db.begin_transaction().await;
             dbtx.raw_insert_bytes(&key, value.into())
                 .await
-                .expect("Error inserting entry into RocksDb");
-            dbtx.commit_tx().await.expect("Error committing to RocksDb");
+                .expect("Error inserting entry into database");
+            dbtx.commit_tx()
+                .await
+                .expect("Error committing to database");
         }
         DbCommand::Delete { key } => {
-            let rocksdb: Box<dyn IDatabase> =
-                Box::new(fedimint_rocksdb::RocksDb::open(&options.database).unwrap());
-            let mut dbtx = rocksdb.begin_transaction().await;
+            let db = open_db(&options.database, options.backend).await?;
+            let mut dbtx = db.begin_transaction().await;
             dbtx.raw_remove_entry(&key)
                 .await
-                .expect("Error removing entry from RocksDb");
-            dbtx.commit_tx().await.expect("Error committing to RocksDb");
+                .expect("Error removing entry from database");
+            dbtx.commit_tx()
+                .await
+                .expect("Error committing to database");
         }
         DbCommand::Dump {
             cfg_dir,
@@ -131,6 +364,139 @@ async fn main() -> Result<()> {
                 DatabaseDump::new(cfg_dir, options.database, password, modules, prefix_names);
             dbdump.dump_database().await;
         }
+        DbCommand::Backup {
+            cfg_dir,
+            password,
+            backup_file,
+        } => {
+            let cfg = read_server_config(&password, cfg_dir.clone())?;
+            let key = read_config_key(&cfg_dir, &password)?;
+
+            let read_only = RocksDbReadOnly::open_read_only(&options.database)?;
+            let notifications = Notifications::new();
+            let mut dbtx = DatabaseTransaction::new(
+                Box::new(SingleUseDatabaseTransaction::new(read_only)),
+                ModuleDecoderRegistry::default(),
+                &notifications,
+            );
+
+            let module_ids = cfg.consensus.modules.keys().copied();
+            let is_encrypted = dbtx
+                .raw_find_by_prefix(&[])
+                .await
+                .any(|(key, _)| futures::future::ready(is_encryption_marker(&key)))
+                .await;
+            let backup = if is_encrypted {
+                let db_key = read_db_key(&cfg_dir, &password)?;
+                DatabaseBackup::export_encrypted_tx(&mut dbtx, module_ids, |key, value| {
+                    db_key.decrypt_raw_entry(key, value)
+                })
+                .await?
+            } else {
+                DatabaseBackup::export_tx(&mut dbtx, module_ids).await
+            };
+            std::fs::write(backup_file, backup.encrypt(&key)?.to_hex())?;
+        }
+        DbCommand::Restore {
+            cfg_dir,
+            password,
+            backup_file,
+        } => {
+            let cfg = read_server_config(&password, cfg_dir.clone())?;
+            let key = read_config_key(&cfg_dir, &password)?;
+
+            let encrypted = FromHex::from_hex(std::fs::read_to_string(backup_file)?.trim())?;
+            let backup = DatabaseBackup::decrypt(encrypted, &key)?;
+            backup.check_versions(&cfg, &server_module_gens())?;
+
+            let db = Database::new(
+                fedimint_rocksdb::RocksDb::open(&options.database)?,
+                ModuleDecoderRegistry::default(),
+            );
+            backup.restore(&db).await?;
+        }
+        DbCommand::Migrate {
+            cfg_dir,
+            password,
+            dry_run,
+        } => {
+            let cfg = read_server_config(&password, cfg_dir.clone())?;
+            let module_gens = server_module_gens();
+            let decoders = module_gens.decoders(cfg.iter_module_instances())?;
+
+            let db = open_server_db(&options.database, &cfg_dir, &password, decoders).await?;
+
+            let mut reports = vec![];
+            reports.extend(
+                migrate_database(
+                    &db,
+                    "Global".to_string(),
+                    GLOBAL_DATABASE_VERSION,
+                    get_global_database_migrations(),
+                    dry_run,
+                )
+                .await?,
+            );
+            for (module_id, module_cfg) in &cfg.consensus.modules {
+                let kind = module_cfg.kind();
+                let Some(init) = module_gens.get(kind) else {
+                    bail!("Detected configuration for unsupported module kind: {kind}")
+                };
+                let isolated_db = db.new_isolated(*module_id);
+                reports.extend(
+                    migrate_database(
+                        &isolated_db,
+                        kind.to_string(),
+                        init.database_version(),
+                        init.get_database_migrations(),
+                        dry_run,
+                    )
+                    .await?,
+                );
+            }
+
+            if dry_run {
+                println!("{}", serde_json::to_string_pretty(&reports)?);
+            }
+        }
+        DbCommand::Check { cfg_dir, password } => {
+            let cfg = read_server_config(&password, cfg_dir.clone())?;
+            let module_gens = server_module_gens();
+            let decoders = module_gens.decoders(cfg.iter_module_instances())?;
+            let db = open_server_db(&options.database, &cfg_dir, &password, decoders).await?;
+
+            let report = check_database(&db, &cfg, &module_gens).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            if !report.is_consistent() {
+                bail!("Database check found {} violations", report.violations());
+            }
+        }
+        DbCommand::Diff {
+            other_database,
+            cfg_dir,
+            password,
+            other_cfg_dir,
+            other_password,
+            include_local,
+        } => {
+            let cfg = read_server_config(&password, cfg_dir.clone())?;
+            let entries = read_server_db(&options.database, &cfg_dir, &password).await?;
+            let other_entries = read_server_db(
+                &other_database,
+                other_cfg_dir.as_ref().unwrap_or(&cfg_dir),
+                other_password.as_ref().unwrap_or(&password),
+            )
+            .await?;
+
+            let module_kinds = cfg
+                .consensus
+                .modules
+                .iter()
+                .map(|(module_id, module_cfg)| (*module_id, module_cfg.kind().to_string()))
+                .collect();
+            let diff = DatabaseDiff::new(&entries, &other_entries, &module_kinds, include_local);
+            println!("{}", serde_json::to_string_pretty(&diff)?);
+        }
     }
 
     Ok(())
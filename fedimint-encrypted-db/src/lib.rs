@@ -0,0 +1,426 @@
+#![allow(where_clauses_object_safety)] // https://github.com/dtolnay/async-trait/issues/228
+//! Encryption at rest for Fedimint databases
+//!
+//! [`EncryptedDatabase`] wraps any [`IDatabase`] implementation and encrypts
+//! all values with ChaCha20-Poly1305 before they reach the underlying storage.
+//!
+//! Keys are stored in plaintext, prefix searches and the key order rely on
+//! them. Someone with access to the storage therefore still learns which
+//! records exist along with everything encoded in their keys, such as
+//! transaction ids, out points, epoch numbers and note amounts, as well as the
+//! length of every value. Only the contents of the values are confidential.
+//!
+//! Every value is authenticated together with its key, so values can't be
+//! swapped between keys by someone with access to the storage.
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use bitcoin_hashes::sha256;
+use fedimint_core::db::{IDatabase, ISingleUseDatabaseTransaction, PrefixStream};
+use fedimint_derive_secret::DerivableSecret;
+use futures::{stream, StreamExt};
+use hkdf::Hkdf;
+
+/// Raw key under which the encryption marker is stored. The marker lets us
+/// detect encrypted databases and verify that the right key is used.
+const MARKER_KEY: &[u8] = b"\xfefedimint-encrypted-db";
+/// Plaintext of the encrypted marker value
+const MARKER_PLAINTEXT: &[u8] = b"fedimint-encrypted-db-v0";
+
+const VALUE_KEY_INFO: &[u8] = b"fedimint-db-value-encryption";
+
+/// Key used to encrypt the values of a database
+pub struct DbEncryptionKey {
+    value_key: aead::LessSafeKey,
+}
+
+impl DbEncryptionKey {
+    /// Derives the database encryption key from the same password and salt
+    /// that protect the server's private config
+    pub fn from_password(password: &str, salt: &str) -> Result<Self> {
+        Ok(Self::from_root_key(&aead::get_encryption_key_raw(
+            password, salt,
+        )?))
+    }
+
+    /// Derives the database encryption key from a secret, callers should pass
+    /// a child secret dedicated to database encryption
+    pub fn from_secret(secret: &DerivableSecret) -> Self {
+        Self::from_root_key(&secret.to_random_bytes::<32>())
+    }
+
+    fn from_root_key(root_key: &[u8]) -> Self {
+        let kdf = Hkdf::<sha256::Hash>::new(root_key, None);
+        let value_key =
+            aead::UnboundKey::new(&aead::CHACHA20_POLY1305, &kdf.derive::<32>(VALUE_KEY_INFO))
+                .expect("created key");
+
+        DbEncryptionKey {
+            value_key: aead::LessSafeKey::new(value_key),
+        }
+    }
+
+    /// Encrypts the `value` stored under the plaintext `key`
+    fn encrypt_value(&self, key: &[u8], value: Vec<u8>) -> Result<Vec<u8>> {
+        aead::encrypt_with_aad(value, key, &self.value_key)
+    }
+
+    /// Decrypts a value, fails if it wasn't stored under the plaintext `key`
+    fn decrypt_value(&self, key: &[u8], mut ciphertext: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(
+            aead::decrypt_with_aad(&mut ciphertext, key, &self.value_key)
+                .context("Failed to decrypt database value")?
+                .to_vec(),
+        )
+    }
+
+    /// Decrypts an entry as read from the underlying storage of an encrypted
+    /// database, `None` for the internal encryption marker
+    pub fn decrypt_raw_entry(
+        &self,
+        raw_key: &[u8],
+        raw_value: &[u8],
+    ) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        if raw_key == MARKER_KEY {
+            return Ok(None);
+        }
+        let value = self.decrypt_value(raw_key, raw_value.to_vec())?;
+        Ok(Some((raw_key.to_vec(), value)))
+    }
+}
+
+/// Whether an entry read from the underlying storage is the marker of an
+/// encrypted database
+pub fn is_encryption_marker(raw_key: &[u8]) -> bool {
+    raw_key == MARKER_KEY
+}
+
+/// Database wrapper encrypting the data of the wrapped database `Db`
+#[derive(Debug)]
+pub struct EncryptedDatabase<Db> {
+    inner: Db,
+    key: DbEncryptionKey,
+}
+
+impl std::fmt::Debug for DbEncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DbEncryptionKey").finish_non_exhaustive()
+    }
+}
+
+impl<Db: IDatabase> EncryptedDatabase<Db> {
+    /// Opens an encrypted database, fails if `key` does not match the key the
+    /// database was encrypted with. An empty database is initialized for
+    /// encryption, a non-empty plaintext database has to be migrated using
+    /// [`Self::migrate`] first.
+    pub async fn open(inner: Db, key: DbEncryptionKey) -> Result<Self> {
+        let mut dbtx = inner.begin_transaction().await;
+        match dbtx.raw_get_bytes(MARKER_KEY).await? {
+            Some(marker) => {
+                let marker = key
+                    .decrypt_value(MARKER_KEY, marker)
+                    .context("Wrong database encryption key")?;
+                if marker != MARKER_PLAINTEXT {
+                    bail!("Unknown database encryption format");
+                }
+            }
+            None => {
+                let is_empty = dbtx.raw_find_by_prefix(&[]).await?.next().await.is_none();
+                if !is_empty {
+                    bail!("Database is not encrypted, it has to be migrated first");
+                }
+                dbtx.raw_insert_bytes(
+                    MARKER_KEY,
+                    key.encrypt_value(MARKER_KEY, MARKER_PLAINTEXT.to_vec())?,
+                )
+                .await?;
+            }
+        }
+        dbtx.commit_tx().await?;
+        drop(dbtx);
+
+        Ok(EncryptedDatabase { inner, key })
+    }
+
+    /// Encrypts all entries of a plaintext database in a single transaction
+    /// and opens it. Databases that are already encrypted are just opened.
+    pub async fn migrate(inner: Db, key: DbEncryptionKey) -> Result<Self> {
+        if Self::is_encrypted(&inner).await? {
+            return Self::open(inner, key).await;
+        }
+
+        let mut dbtx = inner.begin_transaction().await;
+        let entries = dbtx
+            .raw_find_by_prefix(&[])
+            .await?
+            .collect::<Vec<_>>()
+            .await;
+        for (db_key, value) in entries {
+            dbtx.raw_insert_bytes(&db_key, key.encrypt_value(&db_key, value)?)
+                .await?;
+        }
+        dbtx.raw_insert_bytes(
+            MARKER_KEY,
+            key.encrypt_value(MARKER_KEY, MARKER_PLAINTEXT.to_vec())?,
+        )
+        .await?;
+        dbtx.commit_tx().await?;
+        drop(dbtx);
+
+        Self::open(inner, key).await
+    }
+
+    /// Whether `db` contains data encrypted by an [`EncryptedDatabase`]
+    pub async fn is_encrypted(db: &Db) -> Result<bool> {
+        let mut dbtx = db.begin_transaction().await;
+        let is_encrypted = dbtx.raw_get_bytes(MARKER_KEY).await?.is_some();
+        dbtx.commit_tx().await?;
+        Ok(is_encrypted)
+    }
+
+    pub fn inner(&self) -> &Db {
+        &self.inner
+    }
+}
+
+#[async_trait]
+impl<Db: IDatabase> IDatabase for EncryptedDatabase<Db> {
+    async fn begin_transaction<'a>(&'a self) -> Box<dyn ISingleUseDatabaseTransaction<'a>> {
+        Box::new(EncryptedTransaction {
+            dbtx: self.inner.begin_transaction().await,
+            key: &self.key,
+        })
+    }
+}
+
+struct EncryptedTransaction<'a> {
+    dbtx: Box<dyn ISingleUseDatabaseTransaction<'a>>,
+    key: &'a DbEncryptionKey,
+}
+
+#[async_trait]
+impl<'a> ISingleUseDatabaseTransaction<'a> for EncryptedTransaction<'a> {
+    async fn raw_insert_bytes(&mut self, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let value = self.key.encrypt_value(key, value)?;
+        self.dbtx
+            .raw_insert_bytes(key, value)
+            .await?
+            .map(|old_value| self.key.decrypt_value(key, old_value))
+            .transpose()
+    }
+
+    async fn raw_get_bytes(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.dbtx
+            .raw_get_bytes(key)
+            .await?
+            .map(|value| self.key.decrypt_value(key, value))
+            .transpose()
+    }
+
+    async fn raw_remove_entry(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.dbtx
+            .raw_remove_entry(key)
+            .await?
+            .map(|value| self.key.decrypt_value(key, value))
+            .transpose()
+    }
+
+    async fn raw_find_by_prefix(&mut self, key_prefix: &[u8]) -> Result<PrefixStream<'_>> {
+        let key = self.key;
+        let entries = self
+            .dbtx
+            .raw_find_by_prefix(key_prefix)
+            .await?
+            .filter(|(db_key, _)| futures::future::ready(db_key != MARKER_KEY))
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .map(|(db_key, value)| {
+                let value = key.decrypt_value(&db_key, value)?;
+                Ok((db_key, value))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Box::pin(stream::iter(entries)))
+    }
+
+    async fn raw_remove_by_prefix(&mut self, key_prefix: &[u8]) -> Result<()> {
+        let keys = self
+            .dbtx
+            .raw_find_by_prefix(key_prefix)
+            .await?
+            .map(|(db_key, _)| db_key)
+            .filter(|db_key| futures::future::ready(db_key != MARKER_KEY))
+            .collect::<Vec<_>>()
+            .await;
+        for db_key in keys {
+            self.dbtx.raw_remove_entry(&db_key).await?;
+        }
+        Ok(())
+    }
+
+    async fn commit_tx(&mut self) -> Result<()> {
+        self.dbtx.commit_tx().await
+    }
+
+    async fn rollback_tx_to_savepoint(&mut self) -> Result<()> {
+        self.dbtx.rollback_tx_to_savepoint().await
+    }
+
+    async fn set_tx_savepoint(&mut self) -> Result<()> {
+        self.dbtx.set_tx_savepoint().await
+    }
+
+    fn add_notification_key(&mut self, key: &[u8]) -> Result<()> {
+        self.dbtx.add_notification_key(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{Database, IDatabase};
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use fedimint_derive_secret::DerivableSecret;
+    use futures::StreamExt;
+
+    use super::{DbEncryptionKey, EncryptedDatabase};
+
+    fn key(seed: &[u8]) -> DbEncryptionKey {
+        DbEncryptionKey::from_secret(&DerivableSecret::new_root(seed, b"test"))
+    }
+
+    async fn database() -> Database {
+        let db = EncryptedDatabase::open(MemDatabase::new(), key(b"seed"))
+            .await
+            .unwrap();
+        Database::new(db, ModuleDecoderRegistry::default())
+    }
+
+    async fn raw_entries(db: &impl IDatabase) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.raw_find_by_prefix(&[])
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_insert_elements() {
+        fedimint_core::db::verify_insert_elements(database().await).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_remove_existing() {
+        fedimint_core::db::verify_remove_existing(database().await).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_find_by_prefix() {
+        fedimint_core::db::verify_find_by_prefix(database().await).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_rollback_to_savepoint() {
+        fedimint_core::db::verify_rollback_to_savepoint(database().await).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_remove_by_prefix() {
+        fedimint_core::db::verify_remove_by_prefix(database().await).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_module_dbtx() {
+        fedimint_core::db::verify_module_prefix(database().await).await;
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_migration_encrypts_existing_data() {
+        let plaintext = MemDatabase::new();
+        let mut dbtx = plaintext.begin_transaction().await;
+        dbtx.raw_insert_bytes(&[0x42, 1], vec![1, 2, 3])
+            .await
+            .unwrap();
+        dbtx.commit_tx().await.unwrap();
+        drop(dbtx);
+
+        assert!(
+            EncryptedDatabase::open(MemDatabase::new(), key(b"seed"))
+                .await
+                .is_ok(),
+            "empty databases get initialized"
+        );
+
+        let db = EncryptedDatabase::migrate(plaintext, key(b"seed"))
+            .await
+            .unwrap();
+        assert!(raw_entries(db.inner())
+            .await
+            .iter()
+            .all(|(_, value)| value != &[1, 2, 3]));
+
+        let mut dbtx = db.begin_transaction().await;
+        assert_eq!(
+            dbtx.raw_get_bytes(&[0x42, 1]).await.unwrap(),
+            Some(vec![1, 2, 3])
+        );
+        dbtx.commit_tx().await.unwrap();
+        drop(dbtx);
+
+        let inner = db.inner;
+        assert!(EncryptedDatabase::<MemDatabase>::is_encrypted(&inner)
+            .await
+            .unwrap());
+        assert!(
+            EncryptedDatabase::open(inner, key(b"other")).await.is_err(),
+            "opening with the wrong key fails"
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_values_are_bound_to_their_keys() {
+        let db = EncryptedDatabase::open(MemDatabase::new(), key(b"seed"))
+            .await
+            .unwrap();
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.raw_insert_bytes(&[0x42, 1], vec![1]).await.unwrap();
+        dbtx.raw_insert_bytes(&[0x42, 2], vec![2]).await.unwrap();
+        dbtx.commit_tx().await.unwrap();
+        drop(dbtx);
+
+        // Swap the ciphertexts of both keys in the underlying storage
+        let entries = raw_entries(db.inner()).await;
+        let value = |key: &[u8]| {
+            entries
+                .iter()
+                .find(|(db_key, _)| db_key == key)
+                .unwrap()
+                .1
+                .clone()
+        };
+        let mut dbtx = db.inner().begin_transaction().await;
+        dbtx.raw_insert_bytes(&[0x42, 1], value(&[0x42, 2]))
+            .await
+            .unwrap();
+        dbtx.commit_tx().await.unwrap();
+        drop(dbtx);
+
+        let mut dbtx = db.begin_transaction().await;
+        assert!(dbtx.raw_get_bytes(&[0x42, 1]).await.is_err());
+        assert_eq!(dbtx.raw_get_bytes(&[0x42, 2]).await.unwrap(), Some(vec![2]));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_open_plaintext_database_fails() {
+        let plaintext = MemDatabase::new();
+        let mut dbtx = plaintext.begin_transaction().await;
+        dbtx.raw_insert_bytes(&[0x42], vec![1]).await.unwrap();
+        dbtx.commit_tx().await.unwrap();
+        drop(dbtx);
+
+        assert!(EncryptedDatabase::open(plaintext, key(b"seed"))
+            .await
+            .is_err());
+    }
+}
@@ -19,12 +19,19 @@ pub const LOG_WALLET: &str = "wallet";
 pub const LOG_TEST: &str = "test";
 pub const LOG_ECASH_RECOVERY: &str = "ecash-recovery";
 
+/// Service name reported to telemetry backends if none is set
+#[cfg(feature = "telemetry")]
+const DEFAULT_SERVICE_NAME: &str = "fedimint";
+
 /// Consolidates the setup of server tracing into a helper
 #[derive(Default)]
 pub struct TracingSetup {
     tokio_console_bind: Option<SocketAddr>,
     with_jaeger: bool,
     with_chrome: bool,
+    otlp_endpoint: Option<String>,
+    service_name: Option<String>,
+    with_json: bool,
 }
 
 impl TracingSetup {
@@ -49,13 +56,52 @@ impl TracingSetup {
         self
     }
 
+    /// Export spans to an OpenTelemetry collector at `endpoint` via OTLP/gRPC
+    /// <https://docs.rs/opentelemetry-otlp>
+    #[cfg(feature = "telemetry")]
+    pub fn with_otlp(&mut self, endpoint: Option<String>) -> &mut Self {
+        self.otlp_endpoint = endpoint;
+        self
+    }
+
+    /// Name of the service the exported traces are attributed to, defaults to
+    /// `fedimint`
+    #[cfg(feature = "telemetry")]
+    pub fn with_service_name(&mut self, name: &str) -> &mut Self {
+        self.service_name = Some(name.to_string());
+        self
+    }
+
+    /// Log to stderr as JSON lines including the fields of the current spans
+    /// instead of human readable text
+    pub fn with_json(&mut self, enabled: bool) -> &mut Self {
+        self.with_json = enabled;
+        self
+    }
+
     /// Initialize the logging, must be called for tracing to begin
     pub fn init(&self) -> anyhow::Result<()> {
+        #[cfg(feature = "telemetry")]
+        let service_name = self
+            .service_name
+            .clone()
+            .unwrap_or_else(|| DEFAULT_SERVICE_NAME.to_string());
         let filter_layer =
             EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
-        let fmt_layer = tracing_subscriber::fmt::layer()
-            .with_writer(io::stderr)
-            .with_filter(filter_layer);
+        let fmt_layer = if self.with_json {
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .with_writer(io::stderr)
+                .with_filter(filter_layer)
+                .boxed()
+        } else {
+            tracing_subscriber::fmt::layer()
+                .with_writer(io::stderr)
+                .with_filter(filter_layer)
+                .boxed()
+        };
 
         let console_opt = || -> Option<Box<dyn Layer<_> + Send + Sync + 'static>> {
             #[cfg(feature = "telemetry")]
@@ -75,7 +121,7 @@ impl TracingSetup {
             #[cfg(feature = "telemetry")]
             if self.with_jaeger {
                 let tracer = opentelemetry_jaeger::new_agent_pipeline()
-                    .with_service_name("fedimint")
+                    .with_service_name(&service_name)
                     .install_simple()
                     .unwrap();
 
@@ -84,6 +130,32 @@ impl TracingSetup {
             None
         };
 
+        let otlp_layer_opt =
+            || -> anyhow::Result<Option<Box<dyn Layer<_> + Send + Sync + 'static>>> {
+                #[cfg(feature = "telemetry")]
+                if let Some(endpoint) = &self.otlp_endpoint {
+                    let tracer = opentelemetry_otlp::new_pipeline()
+                        .tracing()
+                        .with_exporter(
+                            opentelemetry_otlp::new_exporter()
+                                .tonic()
+                                .with_endpoint(endpoint),
+                        )
+                        .with_trace_config(opentelemetry::sdk::trace::config().with_resource(
+                            opentelemetry::sdk::Resource::new(vec![opentelemetry::KeyValue::new(
+                                "service.name",
+                                service_name.clone(),
+                            )]),
+                        ))
+                        .install_batch(opentelemetry::runtime::Tokio)?;
+
+                    return Ok(Some(
+                        tracing_opentelemetry::layer().with_tracer(tracer).boxed(),
+                    ));
+                }
+                Ok(None)
+            };
+
         let chrome_layer_opt = || -> Option<Box<dyn Layer<_> + Send + Sync + 'static>> {
             #[cfg(feature = "telemetry")]
             if self.with_chrome {
@@ -103,8 +175,16 @@ impl TracingSetup {
             .with(fmt_layer)
             .with(console_opt())
             .with(telemetry_layer_opt())
+            .with(otlp_layer_opt()?)
             .with(chrome_layer_opt())
             .init();
         Ok(())
     }
 }
+
+/// Flushes the spans not exported yet, should be called before exiting if
+/// telemetry was enabled
+pub fn shutdown_telemetry() {
+    #[cfg(feature = "telemetry")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
@@ -0,0 +1,49 @@
+//! Prometheus metrics shared by fedimint daemons
+//!
+//! All metrics are registered in a single [`REGISTRY`] so every daemon can
+//! expose them through one HTTP route rendering [`encode_metrics`].
+
+use once_cell::sync::Lazy;
+pub use prometheus::{
+    self, histogram_opts, opts, register_histogram_vec_with_registry,
+    register_int_counter_vec_with_registry, register_int_counter_with_registry,
+    register_int_gauge_vec_with_registry, register_int_gauge_with_registry, Histogram,
+    HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Registry,
+};
+use prometheus::{Encoder, TextEncoder};
+
+/// Registry holding all fedimint metrics, every metric name is prefixed with
+/// `fedimint_`
+pub static REGISTRY: Lazy<Registry> = Lazy::new(|| {
+    Registry::new_custom(Some("fedimint".into()), None).expect("Valid registry prefix")
+});
+
+/// Buckets in seconds for histograms of operations that involve network round
+/// trips, from a few milliseconds up to several minutes
+pub const NETWORK_LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+/// Render all metrics in [`REGISTRY`] in the Prometheus text exposition format
+pub fn encode_metrics() -> String {
+    let mut buffer = vec![];
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .expect("Encoding metrics into a vec can't fail");
+    String::from_utf8(buffer).expect("Prometheus text format is valid UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_metrics, opts, register_int_counter_with_registry, REGISTRY};
+
+    #[test]
+    fn registered_metrics_are_encoded_with_prefix() {
+        let counter =
+            register_int_counter_with_registry!(opts!("test_counter", "A test counter"), REGISTRY)
+                .unwrap();
+        counter.inc_by(3);
+
+        assert!(encode_metrics().contains("fedimint_test_counter 3"));
+    }
+}
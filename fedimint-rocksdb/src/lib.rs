@@ -244,6 +244,14 @@ mod fedimint_rocksdb_tests {
         fedimint_core::db::verify_commit(open_temp_db("fcb-rocksdb-test-commit")).await;
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_dbtx_subscribe_prefix() {
+        fedimint_core::db::verify_subscribe_prefix(open_temp_db(
+            "fcb-rocksdb-test-subscribe-prefix",
+        ))
+        .await;
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn test_dbtx_prevent_nonrepeatable_reads() {
         fedimint_core::db::verify_prevent_nonrepeatable_reads(open_temp_db(
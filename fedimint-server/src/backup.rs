@@ -0,0 +1,330 @@
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+use aead::{decrypt, encrypt, LessSafeKey};
+use anyhow::{bail, format_err};
+use fedimint_core::config::ServerModuleGenRegistry;
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::mem_impl::MemDatabase;
+use fedimint_core::db::{
+    Database, DatabaseTransaction, DatabaseVersion, DatabaseVersionKey, MigrationMap,
+};
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::module::registry::ModuleDecoderRegistry;
+use futures::StreamExt;
+
+use crate::config::ServerConfig;
+use crate::db::{get_global_database_migrations, GLOBAL_DATABASE_VERSION};
+
+/// Consistent snapshot of all key/value pairs of a guardian database, taken
+/// from a single database transaction so it can be created while the
+/// federation keeps running
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable)]
+pub struct DatabaseBackup {
+    /// Version of the global (non-module) part of the database
+    pub global_version: Option<DatabaseVersion>,
+    /// Versions of the isolated databases of the modules
+    pub module_versions: BTreeMap<ModuleInstanceId, DatabaseVersion>,
+    /// All raw entries of the database including the version keys
+    pub entries: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl DatabaseBackup {
+    /// Exports the whole database `db`, recording the database versions of the
+    /// modules with the given `module_ids`
+    pub async fn export(
+        db: &Database,
+        module_ids: impl IntoIterator<Item = ModuleInstanceId>,
+    ) -> DatabaseBackup {
+        Self::export_tx(&mut db.begin_transaction().await, module_ids).await
+    }
+
+    /// Like [`Self::export`], but reads from an existing transaction, e.g. on
+    /// top of a read-only database
+    pub async fn export_tx(
+        dbtx: &mut DatabaseTransaction<'_>,
+        module_ids: impl IntoIterator<Item = ModuleInstanceId>,
+    ) -> DatabaseBackup {
+        let (global_version, module_versions) = read_versions(dbtx, module_ids).await;
+
+        let entries = dbtx
+            .raw_find_by_prefix(&[])
+            .await
+            .collect::<BTreeMap<_, _>>()
+            .await;
+
+        DatabaseBackup {
+            global_version,
+            module_versions,
+            entries,
+        }
+    }
+
+    /// Like [`Self::export_tx`] for a transaction reading the underlying
+    /// storage of an encrypted database. The entries are backed up as stored,
+    /// so restoring them yields the same encrypted database, while the
+    /// versions are read from the entries decrypted by `decrypt_entry`, which
+    /// returns `None` for internal entries of the encryption.
+    pub async fn export_encrypted_tx(
+        dbtx: &mut DatabaseTransaction<'_>,
+        module_ids: impl IntoIterator<Item = ModuleInstanceId>,
+        decrypt_entry: impl Fn(&[u8], &[u8]) -> anyhow::Result<Option<(Vec<u8>, Vec<u8>)>>,
+    ) -> anyhow::Result<DatabaseBackup> {
+        let entries = dbtx
+            .raw_find_by_prefix(&[])
+            .await
+            .collect::<BTreeMap<_, _>>()
+            .await;
+
+        let plaintext = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let mut plaintext_dbtx = plaintext.begin_transaction().await;
+        for (raw_key, raw_value) in &entries {
+            if let Some((key, value)) = decrypt_entry(raw_key, raw_value)? {
+                plaintext_dbtx.raw_insert_bytes(&key, value).await;
+            }
+        }
+        let (global_version, module_versions) =
+            read_versions(&mut plaintext_dbtx, module_ids).await;
+
+        Ok(DatabaseBackup {
+            global_version,
+            module_versions,
+            entries,
+        })
+    }
+
+    /// Writes all entries of the backup into `db`, which has to be empty
+    pub async fn restore(&self, db: &Database) -> anyhow::Result<()> {
+        let mut dbtx = db.begin_transaction().await;
+
+        if dbtx.raw_find_by_prefix(&[]).await.next().await.is_some() {
+            bail!("Can only restore a backup into an empty database");
+        }
+
+        for (key, value) in &self.entries {
+            dbtx.raw_insert_bytes(key, value.clone()).await;
+        }
+
+        dbtx.commit_tx_result().await
+    }
+
+    /// Checks that the code can migrate all database versions contained in the
+    /// backup to the versions it is expecting
+    pub fn check_versions(
+        &self,
+        cfg: &ServerConfig,
+        module_inits: &ServerModuleGenRegistry,
+    ) -> anyhow::Result<()> {
+        check_version(
+            "Global",
+            self.global_version.as_ref(),
+            &GLOBAL_DATABASE_VERSION,
+            &get_global_database_migrations(),
+        )?;
+
+        for (module_id, version) in &self.module_versions {
+            let kind = cfg
+                .consensus
+                .modules
+                .get(module_id)
+                .ok_or_else(|| format_err!("Backup contains unknown module {module_id}"))?
+                .kind();
+            let init = module_inits
+                .get(kind)
+                .ok_or_else(|| format_err!("Backup contains unsupported module kind {kind}"))?;
+
+            check_version(
+                kind.as_str(),
+                Some(version),
+                &init.database_version(),
+                &init.get_database_migrations(),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Serializes and encrypts the backup with the guardian's config key
+    pub fn encrypt(&self, key: &LessSafeKey) -> anyhow::Result<Vec<u8>> {
+        encrypt(self.consensus_encode_to_vec()?, key)
+    }
+
+    /// Decrypts a backup created by [`Self::encrypt`]
+    pub fn decrypt(mut ciphertext: Vec<u8>, key: &LessSafeKey) -> anyhow::Result<DatabaseBackup> {
+        let plaintext = decrypt(&mut ciphertext, key)?;
+        Ok(DatabaseBackup::consensus_decode(
+            &mut Cursor::new(plaintext),
+            &ModuleDecoderRegistry::default(),
+        )?)
+    }
+}
+
+/// Reads the global database version and the versions of the modules with the
+/// given `module_ids`
+async fn read_versions(
+    dbtx: &mut DatabaseTransaction<'_>,
+    module_ids: impl IntoIterator<Item = ModuleInstanceId>,
+) -> (
+    Option<DatabaseVersion>,
+    BTreeMap<ModuleInstanceId, DatabaseVersion>,
+) {
+    let global_version = dbtx.get_value(&DatabaseVersionKey).await;
+
+    let mut module_versions = BTreeMap::new();
+    for module_id in module_ids {
+        if let Some(version) = dbtx
+            .with_module_prefix(module_id)
+            .get_value(&DatabaseVersionKey)
+            .await
+        {
+            module_versions.insert(module_id, version);
+        }
+    }
+
+    (global_version, module_versions)
+}
+
+/// Checks that a database at `backup_version` can be brought to `code_version`
+/// by applying `migrations`. Databases without a version are initialized at
+/// startup and are always accepted.
+fn check_version(
+    kind: &str,
+    backup_version: Option<&DatabaseVersion>,
+    code_version: &DatabaseVersion,
+    migrations: &MigrationMap,
+) -> anyhow::Result<()> {
+    let Some(backup_version) = backup_version else {
+        return Ok(());
+    };
+
+    if backup_version > code_version {
+        bail!("Backup database version {backup_version} for {kind} is newer than the code version {code_version}");
+    }
+
+    let mut version = backup_version.clone();
+    while &version < code_version {
+        if !migrations.contains_key(&version) {
+            bail!("Missing migration from database version {version} for {kind}");
+        }
+        version.increment();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::{DatabaseVersion, DatabaseVersionKey, MigrationMap};
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use futures::{FutureExt, StreamExt};
+
+    use super::{check_version, DatabaseBackup};
+
+    fn database() -> fedimint_core::db::Database {
+        fedimint_core::db::Database::new(MemDatabase::new(), ModuleDecoderRegistry::default())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn backup_roundtrip() {
+        let db = database();
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_new_entry(&DatabaseVersionKey, &DatabaseVersion(1))
+            .await;
+        dbtx.with_module_prefix(3)
+            .insert_new_entry(&DatabaseVersionKey, &DatabaseVersion(2))
+            .await;
+        dbtx.raw_insert_bytes(&[0x01, 0x02], vec![0x03]).await;
+        dbtx.commit_tx().await;
+
+        let backup = DatabaseBackup::export(&db, [3, 4]).await;
+        assert_eq!(backup.global_version, Some(DatabaseVersion(1)));
+        assert_eq!(
+            backup.module_versions.into_iter().collect::<Vec<_>>(),
+            vec![(3, DatabaseVersion(2))]
+        );
+
+        let key = aead::get_encryption_key("password", &aead::random_salt()).unwrap();
+        let backup = DatabaseBackup::export(&db, [3]).await;
+        let decrypted = DatabaseBackup::decrypt(backup.encrypt(&key).unwrap(), &key).unwrap();
+        assert_eq!(decrypted, backup);
+
+        let restored = database();
+        decrypted.restore(&restored).await.unwrap();
+        let entries = restored
+            .begin_transaction()
+            .await
+            .raw_find_by_prefix(&[])
+            .await
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(entries, backup.entries.into_iter().collect::<Vec<_>>());
+
+        assert!(decrypted.restore(&restored).await.is_err());
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn encrypted_backup_reads_decrypted_versions() {
+        use fedimint_core::db::{DatabaseKeyPrefix, DatabaseValue};
+
+        // "Encrypts" values by flipping their bits, the marker is skipped
+        let decrypt = |key: &[u8], value: &[u8]| {
+            Ok((key != b"marker").then(|| (key.to_vec(), value.iter().map(|b| !b).collect())))
+        };
+
+        let db = database();
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.raw_insert_bytes(
+            &DatabaseVersionKey.to_bytes(),
+            DatabaseVersion(1).to_bytes().iter().map(|b| !b).collect(),
+        )
+        .await;
+        dbtx.raw_insert_bytes(b"marker", vec![0xff]).await;
+        dbtx.commit_tx().await;
+
+        let backup =
+            DatabaseBackup::export_encrypted_tx(&mut db.begin_transaction().await, [], decrypt)
+                .await
+                .unwrap();
+        assert_eq!(backup.global_version, Some(DatabaseVersion(1)));
+        assert_eq!(backup.entries.len(), 2);
+        assert_eq!(backup.entries[b"marker".as_slice()], vec![0xff]);
+    }
+
+    #[test]
+    fn check_version_requires_migrations() {
+        let mut migrations = MigrationMap::new();
+        migrations.insert(DatabaseVersion(0), |_| async { Ok(()) }.boxed());
+
+        let code_version = DatabaseVersion(1);
+        assert!(check_version("test", None, &code_version, &migrations).is_ok());
+        assert!(check_version(
+            "test",
+            Some(&DatabaseVersion(0)),
+            &code_version,
+            &migrations
+        )
+        .is_ok());
+        assert!(check_version(
+            "test",
+            Some(&DatabaseVersion(1)),
+            &code_version,
+            &migrations
+        )
+        .is_ok());
+        assert!(check_version(
+            "test",
+            Some(&DatabaseVersion(2)),
+            &code_version,
+            &migrations
+        )
+        .is_err());
+        assert!(check_version(
+            "test",
+            Some(&DatabaseVersion(0)),
+            &DatabaseVersion(2),
+            &migrations
+        )
+        .is_err());
+    }
+}
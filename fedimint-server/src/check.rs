@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+
+use anyhow::format_err;
+use fedimint_core::config::ServerModuleGenRegistry;
+use fedimint_core::core::{DynOutput, ModuleInstanceId};
+use fedimint_core::db::Database;
+use fedimint_core::encoding::Encodable;
+use fedimint_core::module::audit::Audit;
+use fedimint_core::module::check::DatabaseCheckReport;
+use fedimint_core::OutPoint;
+use futures::StreamExt;
+
+use crate::config::ServerConfig;
+use crate::db::{AcceptedTransactionKeyPrefix, EpochCheckpointKey, EpochHistoryKey, LastEpochKey};
+
+/// Verifies the invariants of the global and all module databases within a
+/// single database transaction, so the check can run while the federation
+/// keeps processing epochs
+pub async fn check_database(
+    db: &Database,
+    cfg: &ServerConfig,
+    module_inits: &ServerModuleGenRegistry,
+) -> anyhow::Result<DatabaseCheckReport> {
+    let mut dbtx = db.begin_transaction().await;
+    let mut report = DatabaseCheckReport::default();
+
+    let accepted_txs = dbtx
+        .find_by_prefix(&AcceptedTransactionKeyPrefix)
+        .await
+        .collect::<Vec<_>>()
+        .await;
+    let mut module_outputs: BTreeMap<ModuleInstanceId, Vec<(OutPoint, DynOutput)>> =
+        BTreeMap::new();
+    for (key, accepted) in accepted_txs {
+        for (out_idx, output) in accepted.transaction.outputs.into_iter().enumerate() {
+            let out_point = OutPoint {
+                txid: key.0,
+                out_idx: out_idx as u64,
+            };
+            let module_id = output.module_instance_id();
+            report
+                .invariant("accepted outputs belong to a configured module")
+                .verify(cfg.consensus.modules.contains_key(&module_id), || {
+                    format!("{out_point} in module {module_id}")
+                });
+            module_outputs
+                .entry(module_id)
+                .or_default()
+                .push((out_point, output));
+        }
+    }
+
+    let mut audit = Audit::default();
+    for (module_id, module_cfg) in &cfg.consensus.modules {
+        let kind = module_cfg.kind();
+        let init = module_inits
+            .get(kind)
+            .ok_or_else(|| format_err!("Detected configuration for unsupported module {kind}"))?;
+
+        let mut module_report = DatabaseCheckReport::default();
+        init.check_database(
+            &mut dbtx.with_module_prefix(*module_id),
+            module_outputs
+                .get(module_id)
+                .map(Vec::as_slice)
+                .unwrap_or_default(),
+            &mut audit,
+            &mut module_report,
+        )
+        .await;
+        report.extend_scoped(&format!("{kind}-{module_id}"), module_report);
+    }
+
+    let sum = audit.sum();
+    report
+        .invariant("audit sum is non-negative")
+        .verify(sum.milli_sat >= 0, || audit.to_string());
+
+    if let Some(EpochHistoryKey(last_epoch)) = dbtx.get_value(&LastEpochKey).await {
+        let mut last_hash = None;
+        for epoch in 0..=last_epoch {
+            let (outcome_epoch, outcome_last_hash, hash) = match dbtx
+                .get_value(&EpochHistoryKey(epoch))
+                .await
+            {
+                Some(signed) => {
+                    report
+                        .invariant("epoch hashes match their outcome")
+                        .verify(signed.outcome.consensus_hash()? == signed.hash, || {
+                            format!("epoch {epoch}")
+                        });
+                    (signed.outcome.epoch, signed.outcome.last_hash, signed.hash)
+                }
+                None => match dbtx.get_value(&EpochCheckpointKey(epoch)).await {
+                    Some(checkpoint) => (checkpoint.epoch, checkpoint.last_hash, checkpoint.hash),
+                    None => {
+                        report
+                            .invariant("epoch history is complete")
+                            .verify(false, || format!("epoch {epoch} is missing"));
+                        last_hash = None;
+                        continue;
+                    }
+                },
+            };
+            report
+                .invariant("epoch history is complete")
+                .verify(outcome_epoch == epoch, || {
+                    format!("epoch {epoch} contains epoch {outcome_epoch}")
+                });
+            if epoch == 0 || last_hash.is_some() {
+                report
+                    .invariant("epoch hashes form an unbroken chain")
+                    .verify(outcome_last_hash == last_hash, || format!("epoch {epoch}"));
+            }
+            last_hash = Some(hash);
+        }
+    }
+
+    Ok(report)
+}
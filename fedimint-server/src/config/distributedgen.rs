@@ -269,6 +269,15 @@ where
         self.dkg_config.insert(key, threshold);
     }
 
+    /// The keys and thresholds ordered by their serialization
+    fn sorted_config(&self) -> Vec<(&T, &usize)> {
+        let mut config: Vec<_> = self.dkg_config.iter().collect();
+        config.sort_by_cached_key(|(key, _)| {
+            serde_json::to_string(key).expect("serialization can't fail")
+        });
+        config
+    }
+
     /// Create keys from G2 (96B keys, 48B messages) used in `tbs`
     pub async fn run_g2(
         &mut self,
@@ -305,8 +314,9 @@ where
         let mut dkgs: HashMap<T, Dkg<G>> = HashMap::new();
         let mut results: HashMap<T, DkgKeys<G>> = HashMap::new();
 
-        // create the dkgs and send our initial messages
-        for (key, threshold) in self.dkg_config.iter() {
+        // create the dkgs and send our initial messages, in a fixed order so a
+        // restarted DKG session draws the same randomness for every key
+        for (key, threshold) in self.sorted_config() {
             let our_id = self.our_id;
             let peers = self.peers.clone();
             let (dkg, step) = Dkg::new(group, our_id, peers, *threshold, rng);
@@ -502,7 +512,7 @@ impl<'a> PeerHandleOps for PeerHandle<'a> {
         T: Serialize + DeserializeOwned + Unpin + Send + Clone + Eq + Hash + Sync,
     {
         let mut dkg = DkgRunner::new(v, self.peers.threshold(), &self.our_id, &self.peers);
-        dkg.run_g1(self.module_instance_id, self.connections, &mut OsRng)
+        dkg.run_g1(self.module_instance_id, self.connections, &mut self.rng())
             .await
     }
 
@@ -512,7 +522,7 @@ impl<'a> PeerHandleOps for PeerHandle<'a> {
     {
         let mut dkg = DkgRunner::multi(v, self.peers.threshold(), &self.our_id, &self.peers);
 
-        dkg.run_g2(self.module_instance_id, self.connections, &mut OsRng)
+        dkg.run_g2(self.module_instance_id, self.connections, &mut self.rng())
             .await
     }
 
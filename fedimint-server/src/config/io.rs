@@ -3,17 +3,24 @@ use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 
-use aead::{encrypted_read, encrypted_write, get_encryption_key, random_salt, LessSafeKey};
+use aead::{
+    encrypt, encrypted_read, encrypted_write, get_encryption_key, random_salt, LessSafeKey,
+};
 use anyhow::{ensure, format_err};
 use bitcoin_hashes::hex::{FromHex, ToHex};
 use fedimint_core::api::WsClientConnectInfo;
 use fedimint_core::config::ServerModuleGenRegistry;
+use fedimint_core::PeerId;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use tokio_rustls::rustls;
 use url::Url;
 
-use crate::config::{gen_cert_and_key, PeerServerParams, ServerConfig};
+use crate::config::transcript::DkgTranscript;
+use crate::config::{
+    gen_cert_and_key, PeerServerParams, PendingTlsCert, ServerConfig, ServerConfigConsensus,
+    ServerConfigLocal, ServerConfigPrivate,
+};
 
 /// Version of the server code (should be the same among peers)
 pub const CODE_VERSION: &str = env!("CODE_VERSION");
@@ -45,8 +52,14 @@ pub const TLS_PK: &str = "tls-pk";
 /// TLS public cert
 pub const TLS_CERT: &str = "tls-cert";
 
+/// Encrypted progress of a distributed key generation
+pub const DKG_SESSION: &str = "dkg-session";
+
+/// Signed transcript of the distributed key generation
+pub const DKG_TRANSCRIPT: &str = "dkg-transcript";
+
 pub const JSON_EXT: &str = "json";
-const ENCRYPTED_EXT: &str = "encrypt";
+pub(crate) const ENCRYPTED_EXT: &str = "encrypt";
 
 pub fn create_cert(
     dir_out_path: PathBuf,
@@ -105,6 +118,78 @@ pub fn read_server_config(password: &str, path: PathBuf) -> anyhow::Result<Serve
     })
 }
 
+/// Reads the consensus config, which is not encrypted
+pub fn read_consensus_config(path: &Path) -> anyhow::Result<ServerConfigConsensus> {
+    plaintext_json_read(path.join(CONSENSUS_CONFIG))
+}
+
+/// Reads the transcript of the distributed key generation
+pub fn read_dkg_transcript(path: &Path) -> anyhow::Result<DkgTranscript> {
+    plaintext_json_read(path.join(DKG_TRANSCRIPT))
+}
+
+/// Writes the transcript of the distributed key generation
+pub fn write_dkg_transcript(transcript: &DkgTranscript, path: &Path) -> anyhow::Result<()> {
+    plaintext_json_write(transcript, path.join(DKG_TRANSCRIPT))
+}
+
+/// Replaces the TLS certificate of `peer` in the local config after it was
+/// rotated, our own rotations also replace the TLS key in the private config
+/// with the key of our pending rotation
+pub fn write_tls_cert_rotation(
+    path: &Path,
+    key: &LessSafeKey,
+    peer: PeerId,
+    cert: &rustls::Certificate,
+    private_key: Option<&rustls::PrivateKey>,
+) -> anyhow::Result<()> {
+    let mut local: ServerConfigLocal = plaintext_json_read(path.join(LOCAL_CONFIG))?;
+    if let Some(endpoint) = local.p2p.get_mut(&peer) {
+        endpoint.tls_cert = cert.clone();
+    }
+    if private_key.is_some() {
+        local.tls_cert = cert.clone();
+    }
+    plaintext_json_write(&local, path.join(LOCAL_CONFIG))?;
+
+    // written last, so if we crash before that our rotation is still pending
+    // and gets completed again
+    if let Some(private_key) = private_key {
+        let mut private: ServerConfigPrivate = encrypted_json_read(key, path.join(PRIVATE_CONFIG))?;
+        private.tls_key = private_key.clone();
+        private.pending_tls_cert = None;
+        encrypted_json_write(&private, key, path.join(PRIVATE_CONFIG))?;
+    }
+
+    Ok(())
+}
+
+/// Stores our `pending` TLS cert rotation in the private config, or removes it
+pub fn write_pending_tls_cert(
+    path: &Path,
+    key: &LessSafeKey,
+    pending: Option<&PendingTlsCert>,
+) -> anyhow::Result<()> {
+    let mut private: ServerConfigPrivate = encrypted_json_read(key, path.join(PRIVATE_CONFIG))?;
+    private.pending_tls_cert = pending.cloned();
+    encrypted_json_write(&private, key, path.join(PRIVATE_CONFIG))
+}
+
+/// Replaces the p2p URL of `peer` in the local config after the federation
+/// agreed on an endpoint update
+///
+/// The API URL is left untouched in the consensus config, rewriting it would
+/// change the consensus hash signed in the DKG transcript. Agreed API URLs
+/// are applied from the database instead.
+pub fn write_endpoint_update(path: &Path, peer: PeerId, p2p_url: &Url) -> anyhow::Result<()> {
+    let mut local: ServerConfigLocal = plaintext_json_read(path.join(LOCAL_CONFIG))?;
+    if let Some(endpoint) = local.p2p.get_mut(&peer) {
+        endpoint.hbbft = p2p_url.clone();
+    }
+
+    plaintext_json_write(&local, path.join(LOCAL_CONFIG))
+}
+
 /// Reads a plaintext json file into a struct
 fn plaintext_json_read<T: Serialize + DeserializeOwned>(path: PathBuf) -> anyhow::Result<T> {
     let string = fs::read_to_string(path.with_extension(JSON_EXT))?;
@@ -112,7 +197,7 @@ fn plaintext_json_read<T: Serialize + DeserializeOwned>(path: PathBuf) -> anyhow
 }
 
 /// Reads an encrypted json file into a struct
-fn encrypted_json_read<T: Serialize + DeserializeOwned>(
+pub(crate) fn encrypted_json_read<T: Serialize + DeserializeOwned>(
     key: &LessSafeKey,
     path: PathBuf,
 ) -> anyhow::Result<T> {
@@ -151,9 +236,28 @@ fn plaintext_json_write<T: Serialize + DeserializeOwned>(
     path: PathBuf,
 ) -> anyhow::Result<()> {
     let filename = path.with_extension(JSON_EXT);
-    let file = fs::File::create(filename.clone())
-        .map_err(|_| format_err!("Unable to create file {:?}", filename))?;
-    serde_json::to_writer_pretty(file, obj)?;
+    atomic_write(&filename, &serde_json::to_vec_pretty(obj)?)
+}
+
+/// Replaces the file at `path` by writing and syncing a temporary file that is
+/// renamed over it, so a crash never leaves a partially written config behind
+fn atomic_write(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let mut file = fs::File::create(&tmp_path)
+        .map_err(|_| format_err!("Unable to create file {:?}", tmp_path))?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+
+    // the rename itself is only durable once the directory is synced
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::File::open(dir)?.sync_all()?;
     Ok(())
 }
 
@@ -165,11 +269,14 @@ fn plaintext_display_write<T: Display>(obj: &T, path: &Path) -> anyhow::Result<(
 }
 
 /// Writes struct into an encrypted json file
-fn encrypted_json_write<T: Serialize + DeserializeOwned>(
+pub(crate) fn encrypted_json_write<T: Serialize + DeserializeOwned>(
     obj: &T,
     key: &LessSafeKey,
     path: PathBuf,
 ) -> anyhow::Result<()> {
-    let bytes = serde_json::to_string(obj)?.into_bytes();
-    encrypted_write(bytes, key, path.with_extension(ENCRYPTED_EXT))
+    let bytes = encrypt(serde_json::to_string(obj)?.into_bytes(), key)?;
+    atomic_write(
+        &path.with_extension(ENCRYPTED_EXT),
+        bytes.to_hex().as_bytes(),
+    )
 }
@@ -16,6 +16,7 @@ use fedimint_core::config::{
     TypedServerModuleConfig,
 };
 use fedimint_core::core::{ModuleInstanceId, ModuleKind, MODULE_INSTANCE_ID_GLOBAL};
+use fedimint_core::epoch::{SerdeSignatureShare, TlsCertRotation};
 use fedimint_core::module::{ApiAuth, PeerHandle};
 use fedimint_core::net::peers::{IMuxPeerConnections, IPeerConnections, PeerConnections};
 use fedimint_core::task::{timeout, Elapsed, TaskGroup};
@@ -24,7 +25,8 @@ use fedimint_logging::{LOG_NET_PEER, LOG_NET_PEER_DKG};
 use hbbft::crypto::serde_impl::SerdeSecret;
 use hbbft::NetworkInfo;
 use itertools::Itertools;
-use rand::rngs::OsRng;
+use rand::rngs::{OsRng, StdRng};
+use rand::SeedableRng;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use tokio_rustls::rustls;
@@ -33,6 +35,8 @@ use url::Url;
 
 use crate::config::distributedgen::{DkgRunner, ThresholdKeys};
 use crate::config::io::{parse_peer_params, CODE_VERSION, SALT_FILE, TLS_CERT, TLS_PK};
+use crate::config::session::DkgSession;
+use crate::config::transcript::{self, DkgCommitments, DkgTranscript};
 use crate::fedimint_core::encoding::Encodable;
 use crate::fedimint_core::{BitcoinHash, NumPeers};
 use crate::multiplexed::PeerConnectionMultiplexer;
@@ -42,10 +46,15 @@ use crate::{ReconnectPeerConnections, TlsTcpConnector};
 
 pub mod distributedgen;
 pub mod io;
+pub mod session;
+pub mod transcript;
 
 /// The maximum open connections the API can handle
 const DEFAULT_MAX_CLIENT_CONNECTIONS: u32 = 1000;
 
+/// Number of epochs misbehaving peers are banned for by default
+pub const DEFAULT_PEER_BAN_EPOCHS: u64 = 500;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 /// All the serializable configuration for the fedimint server
 pub struct ServerConfig {
@@ -84,6 +93,32 @@ pub struct ServerConfigPrivate {
     pub epoch_sks: SerdeSecret<hbbft::crypto::SecretKeyShare>,
     /// Secret material from modules
     pub modules: BTreeMap<ModuleInstanceId, JsonWithKind>,
+    /// Rotation of our TLS certificate that has not been completed yet
+    #[serde(default)]
+    pub pending_tls_cert: Option<PendingTlsCert>,
+}
+
+/// Our own TLS certificate rotation together with the new private key, which
+/// replaces [`ServerConfigPrivate::tls_key`] once the rotation is completed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingTlsCert {
+    #[serde(with = "serde_tls_cert")]
+    pub cert: rustls::Certificate,
+    #[serde(with = "serde_tls_key")]
+    pub key: rustls::PrivateKey,
+    pub activation_epoch: u64,
+    pub signature: SerdeSignatureShare,
+}
+
+impl PendingTlsCert {
+    /// The rotation we announce to our peers
+    pub fn rotation(&self) -> TlsCertRotation {
+        TlsCertRotation {
+            cert: self.cert.0.clone(),
+            activation_epoch: self.activation_epoch,
+            signature: self.signature.clone(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Encodable)]
@@ -125,6 +160,73 @@ pub struct ServerConfigLocal {
     pub max_connections: u32,
     /// Non-consensus, non-private configuration from modules
     pub modules: BTreeMap<ModuleInstanceId, JsonWithKind>,
+    /// SOCKS5 proxy, e.g. Tor, used to connect to peers, which allows onion
+    /// addresses in their urls
+    #[serde(default)]
+    pub socks5_proxy: Option<SocketAddr>,
+    /// Our bind address for the HTTP/JSON binding of the API, which is only
+    /// served if set
+    #[serde(default)]
+    pub api_http_bind: Option<SocketAddr>,
+    /// Limits protecting the API against clients flooding it with requests
+    #[serde(default)]
+    pub api_limits: ApiLimits,
+    /// When misbehaving peers get banned and for how long
+    #[serde(default)]
+    pub peer_bans: PeerBanPolicy,
+}
+
+/// Rate limits and concurrency caps of the API, requests exceeding them are
+/// rejected with error code 429
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ApiLimits {
+    /// Requests a single IP may make per minute over the HTTP and websocket APIs
+    #[serde(default)]
+    pub requests_per_ip_per_minute: Option<u32>,
+    /// Requests a single websocket connection may make per minute, calls in a
+    /// batch count individually
+    #[serde(default)]
+    pub requests_per_connection_per_minute: Option<u32>,
+    /// Subscriptions a single IP may keep open at once over the websocket API
+    /// and as long-polls over the HTTP API
+    #[serde(default)]
+    pub max_subscriptions_per_client: Option<u32>,
+    /// Limits of individual endpoints over all clients, keyed by their path
+    /// (e.g. `/transaction` or `/module/1/backup`)
+    #[serde(default)]
+    pub endpoints: BTreeMap<String, EndpointLimits>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EndpointLimits {
+    /// Requests to the endpoint per minute
+    #[serde(default)]
+    pub requests_per_minute: Option<u32>,
+    /// Requests to the endpoint that are handled at the same time
+    #[serde(default)]
+    pub max_concurrent: Option<u32>,
+}
+
+/// Peers get banned once the scores of their recent faults add up to
+/// `ban_score`, see [`PeerFault::score`](crate::consensus::misbehavior::PeerFault::score)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerBanPolicy {
+    pub ban_score: u64,
+    /// Number of epochs after which a fault no longer counts
+    pub score_window_epochs: u64,
+    /// Number of epochs after which a ban expires, counted in epochs like the
+    /// scoring window so that all peers lift a ban at the same time
+    pub ban_duration_epochs: u64,
+}
+
+impl Default for PeerBanPolicy {
+    fn default() -> Self {
+        PeerBanPolicy {
+            ban_score: 100,
+            score_window_epochs: 100,
+            ban_duration_epochs: DEFAULT_PEER_BAN_EPOCHS,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -161,6 +263,9 @@ pub struct ServerConfigParams {
     /// Params for the modules we wish to configure, can contain custom
     /// parameters
     pub modules: ConfigGenParams,
+    /// SOCKS5 proxy, e.g. Tor, used to connect to peers during DKG and
+    /// afterwards
+    pub socks5_proxy: Option<SocketAddr>,
 }
 
 impl ServerConfigConsensus {
@@ -241,6 +346,7 @@ impl ServerConfig {
             hbbft_sks: hbbft_keys.secret_key_share,
             epoch_sks: epoch_keys.secret_key_share,
             modules: Default::default(),
+            pending_tls_cert: None,
         };
         let local = ServerConfigLocal {
             p2p: params.peers(),
@@ -250,6 +356,10 @@ impl ServerConfig {
             tls_cert: params.tls.our_certificate.clone(),
             max_connections: DEFAULT_MAX_CLIENT_CONNECTIONS,
             modules: Default::default(),
+            socks5_proxy: params.socks5_proxy,
+            api_http_bind: None,
+            api_limits: ApiLimits::default(),
+            peer_bans: PeerBanPolicy::default(),
         };
         let consensus = ServerConfigConsensus {
             code_version: CODE_VERSION.to_string(),
@@ -424,22 +534,55 @@ impl ServerConfig {
     }
 
     /// Runs the distributed key gen algorithm
+    ///
+    /// Progress is recorded in the `session`, if it was resumed we rejoin the
+    /// DKG where we left off and end up with the same config.
     pub async fn distributed_gen(
         params: &ServerConfigParams,
         registry: ServerModuleGenRegistry,
+        mut session: DkgSession,
         task_group: &mut TaskGroup,
-    ) -> DkgResult<Self> {
-        let server_conn = connect(params.fed_network.clone(), params.tls.clone(), task_group).await;
+    ) -> DkgResult<(Self, DkgTranscript)> {
+        let result = Self::run_distributed_gen(params, registry, &mut session, task_group).await;
+        // A message we could not persist cancels the DKG, report why
+        match session.take_failure() {
+            Some(e) => Err(DkgError::Failed(e)),
+            None => result,
+        }
+    }
+
+    async fn run_distributed_gen(
+        params: &ServerConfigParams,
+        registry: ServerModuleGenRegistry,
+        session: &mut DkgSession,
+        task_group: &mut TaskGroup,
+    ) -> DkgResult<(Self, DkgTranscript)> {
+        let server_conn = connect(
+            params.fed_network.clone(),
+            params.tls.clone(),
+            params.socks5_proxy,
+            task_group,
+        )
+        .await;
         let connections = PeerConnectionMultiplexer::new(server_conn).into_dyn();
-        let mut rng = OsRng;
+        let connections = session.connect(connections, task_group).await;
+        let mut rng = StdRng::from_seed(session.seed(MODULE_INSTANCE_ID_GLOBAL));
 
         let peers = &params.peer_ids;
         let our_id = &params.our_id;
         // in case we are running by ourselves, avoid DKG
         if peers.len() == 1 {
-            let server =
-                Self::trusted_dealer_gen(&HashMap::from([(*our_id, params.clone())]), registry);
-            return Ok(server[our_id].clone());
+            let server = Self::trusted_dealer_gen(
+                &HashMap::from([(*our_id, params.clone())]),
+                registry.clone(),
+            );
+            let server = server[our_id].clone();
+            let commitments = DkgCommitments::default();
+            let (consensus_hash, signature) = server.sign_consensus(&registry, &commitments)?;
+            let signatures = BTreeMap::from([(*our_id, signature)]);
+            let transcript =
+                DkgTranscript::new(&server.consensus, commitments, consensus_hash, signatures)?;
+            return Ok((server, transcript));
         }
         info!(
             target: LOG_NET_PEER_DKG,
@@ -469,13 +612,59 @@ impl ServerConfig {
         for (module_instance_id, (_kind, gen)) in registry.legacy_init_order_iter().enumerate() {
             let module_instance_id = u16::try_from(module_instance_id)
                 .expect("64k module instances should be enough for everyone");
-            let dkg = PeerHandle::new(&connections, module_instance_id, *our_id, peers.clone());
+            let dkg = PeerHandle::new(
+                &connections,
+                module_instance_id,
+                *our_id,
+                peers.clone(),
+                session.seed(module_instance_id),
+            );
             module_cfgs.insert(
                 module_instance_id,
                 gen.distributed_gen(&dkg, &params.modules).await?,
             );
         }
 
+        let server = ServerConfig::from(
+            params.clone(),
+            *our_id,
+            auth_keys,
+            epoch_keys,
+            hbbft_keys,
+            module_cfgs,
+        );
+
+        info!(
+            target: LOG_NET_PEER_DKG,
+            "Exchanging signatures over the consensus config."
+        );
+        let commitments = session.commitments();
+        let (consensus_hash, our_signature) = server.sign_consensus(&registry, &commitments)?;
+        let other_peers: Vec<PeerId> = peers.iter().filter(|p| *p != our_id).copied().collect();
+        connections
+            .send(
+                &other_peers,
+                MODULE_INSTANCE_ID_GLOBAL,
+                DkgPeerMsg::TranscriptSignature(our_signature.clone()),
+            )
+            .await?;
+
+        let mut signatures = BTreeMap::from([(*our_id, our_signature)]);
+        while signatures.len() < peers.len() {
+            match connections.receive(MODULE_INSTANCE_ID_GLOBAL).await? {
+                (peer, DkgPeerMsg::TranscriptSignature(signature)) => {
+                    signatures.insert(peer, signature);
+                }
+                (peer, msg) => {
+                    return Err(
+                        format_err!("Invalid message received from: {peer}: {msg:?}").into(),
+                    );
+                }
+            }
+        }
+        let transcript =
+            DkgTranscript::new(&server.consensus, commitments, consensus_hash, signatures)?;
+
         info!(
             target: LOG_NET_PEER_DKG,
             "Sending confirmations to other peers."
@@ -514,21 +703,29 @@ impl ServerConfig {
             error!(target: LOG_NET_PEER_DKG, "Timeout waiting for dkg completion confirmation from other peers");
         };
 
-        let server = ServerConfig::from(
-            params.clone(),
-            *our_id,
-            auth_keys,
-            epoch_keys,
-            hbbft_keys,
-            module_cfgs,
-        );
-
         info!(
             target: LOG_NET_PEER,
+            code = %transcript.verification_code(),
             "Distributed key generation has completed successfully!"
         );
 
-        Ok(server)
+        Ok((server, transcript))
+    }
+
+    /// Signs the hash of our consensus config together with the DKG
+    /// `commitments` with our auth key, returning the consensus hash
+    fn sign_consensus(
+        &self,
+        module_config_gens: &ServerModuleGenRegistry,
+        commitments: &DkgCommitments,
+    ) -> anyhow::Result<(sha256::Hash, SerdeSignatureShare)> {
+        let consensus_hash = self
+            .consensus
+            .try_to_config_response(module_config_gens)?
+            .consensus_hash;
+        let message = transcript::signed_message(consensus_hash, commitments);
+        let signature = self.private.auth_sks.0.sign(message);
+        Ok((consensus_hash, SerdeSignatureShare(signature)))
     }
 }
 
@@ -697,9 +894,17 @@ impl ServerConfigParams {
             api_network: Self::gen_network(&bind_api, &our_id, peers, |params| params.api_url),
             meta: BTreeMap::from([(META_FEDERATION_NAME_KEY.to_owned(), federation_name)]),
             modules,
+            socks5_proxy: None,
         }
     }
 
+    /// Connects to peers through a SOCKS5 `proxy`, which allows onion
+    /// addresses in their urls
+    pub fn with_socks5_proxy(mut self, proxy: Option<SocketAddr>) -> Self {
+        self.socks5_proxy = proxy;
+        self
+    }
+
     fn gen_network(
         bind_address: &SocketAddr,
         our_id: &PeerId,
@@ -776,12 +981,15 @@ impl ServerConfigParams {
 pub async fn connect<T>(
     network: NetworkConfig,
     certs: TlsConfig,
+    socks5_proxy: Option<SocketAddr>,
     task_group: &mut TaskGroup,
 ) -> PeerConnections<T>
 where
     T: std::fmt::Debug + Clone + Serialize + DeserializeOwned + Unpin + Send + Sync + 'static,
 {
-    let connector = TlsTcpConnector::new(certs).into_dyn();
+    let connector = TlsTcpConnector::new(certs)
+        .with_socks5_proxy(socks5_proxy)
+        .into_dyn();
     ReconnectPeerConnections::new(network, connector, task_group)
         .await
         .into_dyn()
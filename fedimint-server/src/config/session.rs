@@ -0,0 +1,696 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use aead::{decrypt, encrypt, get_encryption_key, LessSafeKey};
+use anyhow::{ensure, format_err, Context};
+use async_trait::async_trait;
+use bitcoin_hashes::hex::{FromHex, ToHex};
+use bitcoin_hashes::{sha256, Hash as BitcoinHash, HashEngine};
+use fedimint_core::cancellable::{Cancellable, Cancelled};
+use fedimint_core::config::{DkgMessage, DkgPeerMsg, SupportedDkgMessage};
+use fedimint_core::core::{ModuleInstanceId, MODULE_INSTANCE_ID_GLOBAL};
+use fedimint_core::net::peers::{IMuxPeerConnections, MuxPeerConnections};
+use fedimint_core::task::TaskGroup;
+use fedimint_core::PeerId;
+use fedimint_logging::LOG_NET_PEER_DKG;
+use rand::rngs::OsRng;
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info, warn};
+
+use crate::config::io::{DKG_SESSION, ENCRYPTED_EXT, SALT_FILE};
+use crate::config::transcript::DkgCommitments;
+
+/// Mux key used for requesting resends, module instance ids are assigned
+/// counting up from zero so it never collides with a module
+const SESSION_CONTROL_ID: ModuleInstanceId = MODULE_INSTANCE_ID_GLOBAL - 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DkgSessionHeader {
+    our_id: PeerId,
+    peers: Vec<PeerId>,
+    seed: [u8; 32],
+}
+
+type ReceivedMessage = (ModuleInstanceId, PeerId, DkgPeerMsg);
+
+/// Progress of a distributed key generation that allows a restarted guardian
+/// to rejoin the same session
+///
+/// All randomness of the DKG is derived from a secret seed and every message
+/// received from our peers is logged before it is processed, so replaying the
+/// log recreates exactly the same state and messages. Messages are numbered
+/// per peer and module, which lets peers drop the duplicates we send while
+/// replaying and resend anything we lost.
+pub struct DkgSession {
+    header: DkgSessionHeader,
+    received: Vec<ReceivedMessage>,
+    log: Option<SessionLog>,
+    resumed: bool,
+    state: Option<Arc<Mutex<SessionState>>>,
+}
+
+impl DkgSession {
+    /// Starts a session that is only kept in memory and cannot be resumed
+    pub fn new(our_id: PeerId, peers: &[PeerId]) -> Self {
+        DkgSession {
+            header: DkgSessionHeader {
+                our_id,
+                peers: peers.to_vec(),
+                seed: OsRng.gen(),
+            },
+            received: vec![],
+            log: None,
+            resumed: false,
+            state: None,
+        }
+    }
+
+    /// Resumes the session persisted in `dir` or starts a new one, encrypting
+    /// it with the config `password`
+    pub fn open(
+        dir: &Path,
+        password: &str,
+        our_id: PeerId,
+        peers: &[PeerId],
+    ) -> anyhow::Result<Self> {
+        let salt = fs::read_to_string(dir.join(SALT_FILE))?;
+        let log = SessionLog {
+            path: session_path(dir),
+            key: get_encryption_key(password, &salt)?,
+        };
+
+        if !log.path.exists() {
+            let session = DkgSession::new(our_id, peers);
+            log.append(&session.header)?;
+            return Ok(DkgSession {
+                log: Some(log),
+                ..session
+            });
+        }
+
+        let (header, received) = log.read()?;
+        ensure!(
+            header.our_id == our_id && header.peers == peers,
+            "DKG session in {dir:?} was started with different peers, remove {:?} to start over",
+            log.path
+        );
+        info!(
+            target: LOG_NET_PEER_DKG,
+            messages = received.len(),
+            "Resuming distributed key generation session"
+        );
+
+        Ok(DkgSession {
+            header,
+            received,
+            log: Some(log),
+            resumed: true,
+            state: None,
+        })
+    }
+
+    /// Removes the persisted session once the configs have been written, or
+    /// once the DKG failed and has to start over
+    pub fn remove(dir: &Path) -> anyhow::Result<()> {
+        let path = session_path(dir);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Secret seed for the randomness used by a module, or the global keys
+    pub fn seed(&self, module_id: ModuleInstanceId) -> [u8; 32] {
+        let mut engine = sha256::HashEngine::default();
+        engine.input(&self.header.seed);
+        engine.input(&module_id.to_be_bytes());
+        sha256::Hash::from_engine(engine).into_inner()
+    }
+
+    /// Takes the error that aborted the DKG if a received message could not be
+    /// persisted
+    pub fn take_failure(&self) -> Option<anyhow::Error> {
+        self.state
+            .as_ref()
+            .and_then(|state| state.lock().expect("lock poisoned").failure.take())
+    }
+
+    /// Commitments broadcast by us and our peers so far
+    pub fn commitments(&self) -> DkgCommitments {
+        self.state
+            .as_ref()
+            .map(|state| state.lock().expect("lock poisoned").commitments.clone())
+            .unwrap_or_default()
+    }
+
+    /// Wraps the `connections` to number, log and replay our DKG messages
+    pub async fn connect(
+        &mut self,
+        connections: MuxPeerConnections<ModuleInstanceId, DkgPeerMsg>,
+        task_group: &mut TaskGroup,
+    ) -> MuxPeerConnections<ModuleInstanceId, DkgPeerMsg> {
+        let mut state = SessionState {
+            log: self.log.take(),
+            ..Default::default()
+        };
+        for (module_id, peer, msg) in std::mem::take(&mut self.received) {
+            *state.received.entry((module_id, peer)).or_default() += 1;
+            state
+                .replay
+                .entry(module_id)
+                .or_default()
+                .push_back((peer, msg));
+        }
+
+        let state = Arc::new(Mutex::new(state));
+        self.state = Some(state.clone());
+        let resend_state = state.clone();
+        let resend_connections = connections.clone();
+        task_group
+            .spawn("dkg-session-resend", move |_| async move {
+                while let Ok((peer, msg)) = resend_connections.receive(SESSION_CONTROL_ID).await {
+                    let DkgPeerMsg::Resend(module_id, from) = msg else {
+                        warn!(target: LOG_NET_PEER_DKG, %peer, ?msg, "Unexpected DKG session message");
+                        continue;
+                    };
+                    debug!(target: LOG_NET_PEER_DKG, %peer, module_id, from, "Resending DKG messages");
+
+                    let messages = resend_state
+                        .lock()
+                        .expect("lock poisoned")
+                        .sent_since(module_id, peer, from);
+                    for (seq, msg) in messages {
+                        let msg = DkgPeerMsg::Sequenced(seq, Box::new(msg));
+                        if resend_connections.send(&[peer], module_id, msg).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            })
+            .await;
+
+        SessionPeerConnections {
+            inner: connections,
+            our_id: self.header.our_id,
+            peers: self.header.peers.clone(),
+            resumed: self.resumed,
+            state,
+        }
+        .into_dyn()
+    }
+}
+
+fn session_path(dir: &Path) -> PathBuf {
+    dir.join(DKG_SESSION).with_extension(ENCRYPTED_EXT)
+}
+
+/// Append-only file with one encrypted entry per line, starting with the
+/// session header
+struct SessionLog {
+    path: PathBuf,
+    key: LessSafeKey,
+}
+
+impl SessionLog {
+    fn append<T: Serialize>(&self, entry: &T) -> anyhow::Result<()> {
+        let bytes = encrypt(serde_json::to_vec(entry)?, &self.key)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", bytes.to_hex())?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    fn read(&self) -> anyhow::Result<(DkgSessionHeader, Vec<ReceivedMessage>)> {
+        let contents = fs::read_to_string(&self.path)?;
+        let mut lines = contents.lines();
+        let header: DkgSessionHeader = lines
+            .next()
+            .ok_or_else(|| format_err!("DKG session is empty"))
+            .and_then(|line| self.decrypt(line))
+            .context("Unable to read DKG session, wrong password?")?;
+
+        let mut received = vec![];
+        for line in lines {
+            match self.decrypt(line) {
+                Ok(entry) => received.push(entry),
+                Err(e) => {
+                    // the last entry can be cut short by a crash, peers will
+                    // resend the message
+                    warn!(target: LOG_NET_PEER_DKG, %e, "Dropping incomplete DKG session entry");
+                    break;
+                }
+            }
+        }
+
+        let valid_lines = received.len() + 1;
+        if valid_lines < contents.lines().count() || !contents.ends_with('\n') {
+            let valid: String = contents
+                .lines()
+                .take(valid_lines)
+                .map(|line| format!("{line}\n"))
+                .collect();
+            fs::write(&self.path, valid)?;
+        }
+
+        Ok((header, received))
+    }
+
+    fn decrypt<T: DeserializeOwned>(&self, line: &str) -> anyhow::Result<T> {
+        let mut bytes = Vec::<u8>::from_hex(line)?;
+        Ok(serde_json::from_slice(decrypt(&mut bytes, &self.key)?)?)
+    }
+}
+
+#[derive(Default)]
+struct SessionState {
+    log: Option<SessionLog>,
+    /// Logged messages that have not been processed again yet
+    replay: BTreeMap<ModuleInstanceId, VecDeque<(PeerId, DkgPeerMsg)>>,
+    sent: BTreeMap<(ModuleInstanceId, PeerId), Vec<DkgPeerMsg>>,
+    received: BTreeMap<(ModuleInstanceId, PeerId), u64>,
+    requested: BTreeMap<(ModuleInstanceId, PeerId), u64>,
+    resynced: BTreeSet<ModuleInstanceId>,
+    commitments: DkgCommitments,
+    /// Set once a received message could not be persisted, the DKG is aborted
+    /// since resuming it would not end up with the same keys
+    failure: Option<anyhow::Error>,
+}
+
+impl SessionState {
+    fn received(&self, module_id: ModuleInstanceId, peer: PeerId) -> u64 {
+        self.received
+            .get(&(module_id, peer))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn record(
+        &mut self,
+        module_id: ModuleInstanceId,
+        peer: PeerId,
+        msg: &DkgPeerMsg,
+    ) -> anyhow::Result<()> {
+        if let Some(log) = &self.log {
+            log.append(&(module_id, peer, msg))
+                .context("Failed to persist DKG session")?;
+        }
+        *self.received.entry((module_id, peer)).or_default() += 1;
+        Ok(())
+    }
+
+    fn record_commitment(&mut self, module_id: ModuleInstanceId, peer: PeerId, msg: &DkgPeerMsg) {
+        if let DkgPeerMsg::DistributedGen((key, commit)) = msg {
+            if let SupportedDkgMessage::G1(DkgMessage::Commit(_))
+            | SupportedDkgMessage::G2(DkgMessage::Commit(_)) = commit
+            {
+                let bytes = serde_json::to_vec(commit).expect("serialization can't fail");
+                self.commitments
+                    .entry(module_id)
+                    .or_default()
+                    .entry(key.clone())
+                    .or_default()
+                    .insert(peer, sha256::Hash::hash(&bytes));
+            }
+        }
+    }
+
+    fn sent_since(
+        &self,
+        module_id: ModuleInstanceId,
+        peer: PeerId,
+        from: u64,
+    ) -> Vec<(u64, DkgPeerMsg)> {
+        self.sent
+            .get(&(module_id, peer))
+            .into_iter()
+            .flatten()
+            .cloned()
+            .enumerate()
+            .map(|(seq, msg)| (seq as u64, msg))
+            .skip(from as usize)
+            .collect()
+    }
+}
+
+struct SessionPeerConnections {
+    inner: MuxPeerConnections<ModuleInstanceId, DkgPeerMsg>,
+    our_id: PeerId,
+    peers: Vec<PeerId>,
+    resumed: bool,
+    state: Arc<Mutex<SessionState>>,
+}
+
+#[async_trait]
+impl IMuxPeerConnections<ModuleInstanceId, DkgPeerMsg> for SessionPeerConnections {
+    async fn send(
+        &self,
+        peers: &[PeerId],
+        module_id: ModuleInstanceId,
+        msg: DkgPeerMsg,
+    ) -> Cancellable<()> {
+        for peer in peers {
+            let seq = {
+                let mut state = self.state.lock().expect("lock poisoned");
+                state.record_commitment(module_id, self.our_id, &msg);
+                let sent = state.sent.entry((module_id, *peer)).or_default();
+                sent.push(msg.clone());
+                sent.len() as u64 - 1
+            };
+            let msg = DkgPeerMsg::Sequenced(seq, Box::new(msg.clone()));
+            self.inner.send(&[*peer], module_id, msg).await?;
+        }
+        Ok(())
+    }
+
+    async fn receive(&self, module_id: ModuleInstanceId) -> Cancellable<(PeerId, DkgPeerMsg)> {
+        let resend_requests: Vec<(PeerId, u64)> = {
+            let mut state = self.state.lock().expect("lock poisoned");
+            if state.failure.is_some() {
+                return Err(Cancelled);
+            }
+            if let Some((peer, msg)) = state
+                .replay
+                .get_mut(&module_id)
+                .and_then(VecDeque::pop_front)
+            {
+                state.record_commitment(module_id, peer, &msg);
+                return Ok((peer, msg));
+            }
+
+            // after replaying we ask peers for anything we lost in the crash
+            if self.resumed && state.resynced.insert(module_id) {
+                self.peers
+                    .iter()
+                    .filter(|peer| **peer != self.our_id)
+                    .map(|peer| (*peer, state.received(module_id, *peer)))
+                    .collect()
+            } else {
+                vec![]
+            }
+        };
+        for (peer, from) in resend_requests {
+            let msg = DkgPeerMsg::Resend(module_id, from);
+            self.inner.send(&[peer], SESSION_CONTROL_ID, msg).await?;
+        }
+
+        loop {
+            let (peer, msg) = self.inner.receive(module_id).await?;
+            let (seq, msg) = match msg {
+                DkgPeerMsg::Sequenced(seq, msg) => (seq, *msg),
+                msg => return Ok((peer, msg)),
+            };
+
+            let request = {
+                let mut state = self.state.lock().expect("lock poisoned");
+                let expected = state.received(module_id, peer);
+                if seq == expected {
+                    if let Err(e) = state.record(module_id, peer, &msg) {
+                        error!(target: LOG_NET_PEER_DKG, "{e:#}");
+                        state.failure = Some(e);
+                        return Err(Cancelled);
+                    }
+                    state.record_commitment(module_id, peer, &msg);
+                    return Ok((peer, msg));
+                }
+
+                if seq < expected {
+                    debug!(target: LOG_NET_PEER_DKG, %peer, seq, "Dropping duplicate DKG message");
+                    None
+                } else if state.requested.insert((module_id, peer), expected) != Some(expected) {
+                    Some(expected)
+                } else {
+                    None
+                }
+            };
+
+            if let Some(from) = request {
+                let msg = DkgPeerMsg::Resend(module_id, from);
+                self.inner.send(&[peer], SESSION_CONTROL_ID, msg).await?;
+            }
+        }
+    }
+
+    async fn ban_peer(&self, peer: PeerId) {
+        self.inner.ban_peer(peer).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet, HashMap};
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
+    use fedimint_core::cancellable::{Cancellable, Cancelled};
+    use fedimint_core::config::{DkgPeerMsg, DkgResult};
+    use fedimint_core::core::{ModuleInstanceId, MODULE_INSTANCE_ID_GLOBAL};
+    use fedimint_core::net::peers::{IMuxPeerConnections, MuxPeerConnections};
+    use fedimint_core::task::TaskGroup;
+    use fedimint_core::PeerId;
+    use hbbft::crypto::G1Projective;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+    use crate::config::distributedgen::{DkgKeys, DkgRunner};
+    use crate::config::io::SALT_FILE;
+    use crate::config::session::{session_path, DkgSession};
+    use crate::config::transcript::DkgCommitments;
+
+    #[test]
+    fn session_survives_restart() {
+        std::env::set_var("FM_TEST_FAST_WEAK_CRYPTO", "1");
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(SALT_FILE), aead::random_salt()).unwrap();
+        let peers = [0, 1, 2].map(PeerId::from).to_vec();
+
+        let session = DkgSession::open(dir.path(), "pass", PeerId::from(0), &peers).unwrap();
+        assert!(!session.resumed);
+        let seed = session.seed(MODULE_INSTANCE_ID_GLOBAL);
+        let log = session.log.as_ref().unwrap();
+        log.append(&(0u16, PeerId::from(1), DkgPeerMsg::Done))
+            .unwrap();
+        log.append(&(0u16, PeerId::from(2), DkgPeerMsg::Done))
+            .unwrap();
+        // simulate a crash while writing an entry
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&log.path)
+            .unwrap();
+        std::io::Write::write_all(&mut file, b"00ff").unwrap();
+
+        let session = DkgSession::open(dir.path(), "pass", PeerId::from(0), &peers).unwrap();
+        assert!(session.resumed);
+        assert_eq!(session.seed(MODULE_INSTANCE_ID_GLOBAL), seed);
+        let senders: BTreeSet<PeerId> = session.received.iter().map(|(_, p, _)| *p).collect();
+        assert_eq!(senders, BTreeSet::from([PeerId::from(1), PeerId::from(2)]));
+
+        assert!(DkgSession::open(dir.path(), "wrong", PeerId::from(0), &peers).is_err());
+        assert!(DkgSession::open(dir.path(), "pass", PeerId::from(1), &peers).is_err());
+
+        DkgSession::remove(dir.path()).unwrap();
+        let session = DkgSession::open(dir.path(), "pass", PeerId::from(0), &peers).unwrap();
+        assert!(!session.resumed);
+        assert_ne!(session.seed(MODULE_INSTANCE_ID_GLOBAL), seed);
+    }
+
+    #[tokio::test]
+    async fn restarted_dkg_produces_the_same_keys() {
+        std::env::set_var("FM_TEST_FAST_WEAK_CRYPTO", "1");
+        let peers = [0, 1, 2].map(PeerId::from).to_vec();
+        let uninterrupted = tempfile::tempdir().unwrap();
+        let restarted = tempfile::tempdir().unwrap();
+
+        // both runs start from the same sessions, so they draw the same randomness
+        for peer in &peers {
+            let dir = uninterrupted.path().join(peer.to_string());
+            std::fs::create_dir(&dir).unwrap();
+            std::fs::write(dir.join(SALT_FILE), aead::random_salt()).unwrap();
+            DkgSession::open(&dir, "pass", *peer, &peers).unwrap();
+
+            let copy = restarted.path().join(peer.to_string());
+            std::fs::create_dir(&copy).unwrap();
+            std::fs::copy(dir.join(SALT_FILE), copy.join(SALT_FILE)).unwrap();
+            std::fs::copy(session_path(&dir), session_path(&copy)).unwrap();
+        }
+
+        let network = FakeNetwork::default();
+        let runs = peers.iter().map(|peer| {
+            let dir = uninterrupted.path().join(peer.to_string());
+            run_peer(dir, *peer, peers.clone(), network.connect(*peer, None))
+        });
+        let expected = futures::future::join_all(runs).await;
+
+        let network = FakeNetwork::default();
+        let mut others = vec![];
+        for peer in &peers[1..] {
+            let dir = restarted.path().join(peer.to_string());
+            let connections = network.connect(*peer, None);
+            others.push(tokio::spawn(run_peer(
+                dir,
+                *peer,
+                peers.clone(),
+                connections,
+            )));
+        }
+
+        // peer 0 crashes halfway through the DKG, losing the messages in flight
+        let dir = restarted.path().join("0");
+        let connections = network.connect(PeerId::from(0), Some(7));
+        assert!(
+            run_peer(dir.clone(), PeerId::from(0), peers.clone(), connections)
+                .await
+                .is_err()
+        );
+        let connections = network.connect(PeerId::from(0), None);
+        let mut results = vec![run_peer(dir, PeerId::from(0), peers.clone(), connections).await];
+        for other in others {
+            results.push(other.await.unwrap());
+        }
+
+        for (expected, result) in expected.into_iter().zip(results) {
+            let (expected_keys, expected_commitments) = expected.unwrap();
+            let (keys, commitments) = result.unwrap();
+            assert_eq!(keys, expected_keys);
+            assert_eq!(commitments, expected_commitments);
+        }
+    }
+
+    type PeerKeys = BTreeMap<String, (Vec<G1Projective>, tbs::Scalar)>;
+
+    async fn run_peer(
+        dir: PathBuf,
+        our_id: PeerId,
+        peers: Vec<PeerId>,
+        connections: MuxPeerConnections<ModuleInstanceId, DkgPeerMsg>,
+    ) -> DkgResult<(PeerKeys, DkgCommitments)> {
+        let mut session = DkgSession::open(&dir, "pass", our_id, &peers)?;
+        let mut task_group = TaskGroup::new();
+        let connections = session.connect(connections, &mut task_group).await;
+        let mut rng = StdRng::from_seed(session.seed(MODULE_INSTANCE_ID_GLOBAL));
+
+        let keys = vec!["auth".to_string(), "epoch".to_string()];
+        let keys: HashMap<String, DkgKeys<G1Projective>> =
+            DkgRunner::multi(keys, 2, &our_id, &peers)
+                .run_g1(MODULE_INSTANCE_ID_GLOBAL, &connections, &mut rng)
+                .await?;
+        let keys = keys
+            .into_iter()
+            .map(|(key, keys)| (key, (keys.public_key_set, keys.secret_key_share)))
+            .collect();
+        Ok((keys, session.commitments()))
+    }
+
+    type Envelope = (PeerId, DkgPeerMsg);
+
+    struct Mailbox {
+        tx: UnboundedSender<Envelope>,
+        rx: Arc<tokio::sync::Mutex<UnboundedReceiver<Envelope>>>,
+    }
+
+    /// Delivers messages between peers in memory, reconnecting a peer drops
+    /// the messages that have not been received yet
+    #[derive(Clone, Default)]
+    struct FakeNetwork {
+        mailboxes: Arc<Mutex<BTreeMap<(PeerId, ModuleInstanceId), Mailbox>>>,
+    }
+
+    impl FakeNetwork {
+        /// Connects a peer that crashes after receiving `crash_after` messages
+        fn connect(
+            &self,
+            our_id: PeerId,
+            crash_after: Option<usize>,
+        ) -> MuxPeerConnections<ModuleInstanceId, DkgPeerMsg> {
+            self.mailboxes
+                .lock()
+                .unwrap()
+                .retain(|(peer, _), _| *peer != our_id);
+            FakeConnections {
+                network: self.clone(),
+                our_id,
+                remaining: crash_after.map(AtomicUsize::new),
+            }
+            .into_dyn()
+        }
+
+        fn sender(&self, peer: PeerId, module_id: ModuleInstanceId) -> UnboundedSender<Envelope> {
+            self.with_mailbox(peer, module_id, |mailbox| mailbox.tx.clone())
+        }
+
+        fn receiver(
+            &self,
+            peer: PeerId,
+            module_id: ModuleInstanceId,
+        ) -> Arc<tokio::sync::Mutex<UnboundedReceiver<Envelope>>> {
+            self.with_mailbox(peer, module_id, |mailbox| mailbox.rx.clone())
+        }
+
+        fn with_mailbox<T>(
+            &self,
+            peer: PeerId,
+            module_id: ModuleInstanceId,
+            f: impl FnOnce(&Mailbox) -> T,
+        ) -> T {
+            let mut mailboxes = self.mailboxes.lock().unwrap();
+            let mailbox = mailboxes.entry((peer, module_id)).or_insert_with(|| {
+                let (tx, rx) = unbounded_channel();
+                Mailbox {
+                    tx,
+                    rx: Arc::new(tokio::sync::Mutex::new(rx)),
+                }
+            });
+            f(mailbox)
+        }
+    }
+
+    struct FakeConnections {
+        network: FakeNetwork,
+        our_id: PeerId,
+        remaining: Option<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl IMuxPeerConnections<ModuleInstanceId, DkgPeerMsg> for FakeConnections {
+        async fn send(
+            &self,
+            peers: &[PeerId],
+            module_id: ModuleInstanceId,
+            msg: DkgPeerMsg,
+        ) -> Cancellable<()> {
+            for peer in peers {
+                // like a real connection, messages to crashed peers are lost
+                let _ = self
+                    .network
+                    .sender(*peer, module_id)
+                    .send((self.our_id, msg.clone()));
+            }
+            Ok(())
+        }
+
+        async fn receive(&self, module_id: ModuleInstanceId) -> Cancellable<(PeerId, DkgPeerMsg)> {
+            if let Some(remaining) = &self.remaining {
+                let remaining = remaining
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+                if remaining.is_err() {
+                    return Err(Cancelled);
+                }
+            }
+            let rx = self.network.receiver(self.our_id, module_id);
+            let msg = rx.lock().await.recv().await.ok_or(Cancelled)?;
+            Ok(msg)
+        }
+
+        async fn ban_peer(&self, _peer: PeerId) {}
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+
+use anyhow::{ensure, format_err};
+use bitcoin_hashes::hex::ToHex;
+use bitcoin_hashes::{sha256, Hash as BitcoinHash, HashEngine};
+use fedimint_core::config::ServerModuleGenRegistry;
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::epoch::{SerdeSignature, SerdeSignatureShare};
+use fedimint_core::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ServerConfigConsensus;
+
+/// Hashes of the commitments every peer broadcast, by module and key
+pub type DkgCommitments =
+    BTreeMap<ModuleInstanceId, BTreeMap<String, BTreeMap<PeerId, sha256::Hash>>>;
+
+/// Hash of the `commitments`, which guardians sign together with the
+/// consensus hash so they also agree on how the keys were generated
+pub fn commitments_hash(commitments: &DkgCommitments) -> sha256::Hash {
+    let bytes = serde_json::to_vec(commitments).expect("serialization can't fail");
+    sha256::Hash::hash(&bytes)
+}
+
+/// Message every guardian signs with their auth key at the end of the DKG
+pub fn signed_message(consensus_hash: sha256::Hash, commitments: &DkgCommitments) -> sha256::Hash {
+    let mut engine = sha256::HashEngine::default();
+    engine.input(consensus_hash.as_inner());
+    engine.input(commitments_hash(commitments).as_inner());
+    sha256::Hash::from_engine(engine)
+}
+
+/// Signed record of a distributed key generation that lets guardians
+/// cross-check they ended up with the same consensus config
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DkgTranscript {
+    /// Commitments we received during the DKG
+    pub commitments: DkgCommitments,
+    /// Hash of the resulting [`ServerConfigConsensus`]
+    pub consensus_hash: sha256::Hash,
+    /// Signature shares of every guardian over the consensus hash and the
+    /// commitments, see [`signed_message`], created with their auth keys
+    pub signatures: BTreeMap<PeerId, SerdeSignatureShare>,
+    /// Threshold signature of the federation over the consensus hash and the
+    /// commitments
+    pub signature: SerdeSignature,
+}
+
+impl DkgTranscript {
+    /// Combines the signature shares of all guardians, failing if any of them
+    /// signed a different consensus config or saw different commitments
+    pub fn new(
+        consensus: &ServerConfigConsensus,
+        commitments: DkgCommitments,
+        consensus_hash: sha256::Hash,
+        signatures: BTreeMap<PeerId, SerdeSignatureShare>,
+    ) -> anyhow::Result<Self> {
+        let message = signed_message(consensus_hash, &commitments);
+        for (peer, share) in &signatures {
+            ensure!(
+                consensus
+                    .auth_pk_set
+                    .public_key_share(peer.to_usize())
+                    .verify(&share.0, message),
+                "{peer} signed a different consensus config or commitments"
+            );
+        }
+
+        let signature = consensus
+            .auth_pk_set
+            .combine_signatures(
+                signatures
+                    .iter()
+                    .map(|(peer, share)| (peer.to_usize(), &share.0)),
+            )
+            .map_err(|e| format_err!("Unable to combine signatures: {e:?}"))?;
+
+        Ok(DkgTranscript {
+            commitments,
+            consensus_hash,
+            signatures,
+            signature: SerdeSignature(signature),
+        })
+    }
+
+    /// Short code derived from the consensus hash that guardians compare
+    /// out-of-band
+    pub fn verification_code(&self) -> String {
+        let hex = self.consensus_hash.to_hex();
+        format!("{}-{}", &hex[0..4], &hex[4..8])
+    }
+
+    /// Checks the transcript is signed by every guardian and matches our
+    /// consensus config, and that its commitments are the ones that were
+    /// signed
+    pub fn verify(
+        &self,
+        consensus: &ServerConfigConsensus,
+        module_config_gens: &ServerModuleGenRegistry,
+    ) -> anyhow::Result<()> {
+        let consensus_hash = consensus
+            .try_to_config_response(module_config_gens)?
+            .consensus_hash;
+        ensure!(
+            self.consensus_hash == consensus_hash,
+            "Transcript was created for a different consensus config"
+        );
+
+        let message = signed_message(consensus_hash, &self.commitments);
+        for peer in consensus.api.keys() {
+            let share = self
+                .signatures
+                .get(peer)
+                .ok_or_else(|| format_err!("Transcript is missing the signature of {peer}"))?;
+            ensure!(
+                consensus
+                    .auth_pk_set
+                    .public_key_share(peer.to_usize())
+                    .verify(&share.0, message),
+                "Invalid signature of {peer} in transcript"
+            );
+        }
+
+        ensure!(
+            consensus
+                .auth_pk_set
+                .public_key()
+                .verify(&self.signature.0, message),
+            "Invalid federation signature in transcript"
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use bitcoin_hashes::{sha256, Hash as BitcoinHash};
+    use fedimint_core::config::{ConfigGenParams, ServerModuleGenRegistry};
+    use fedimint_core::PeerId;
+
+    use crate::config::transcript::{DkgCommitments, DkgTranscript};
+    use crate::config::{ServerConfig, ServerConfigParams};
+
+    #[test]
+    fn transcript_verifies_consensus_config() {
+        let peers = (0..4).map(PeerId::from).collect::<Vec<_>>();
+        let params =
+            ServerConfigParams::gen_local(&peers, 18000, "test", ConfigGenParams::new()).unwrap();
+        let registry = ServerModuleGenRegistry::new();
+        let configs = ServerConfig::trusted_dealer_gen(&params, registry.clone());
+
+        let commit_hashes = peers
+            .iter()
+            .map(|peer| (*peer, sha256::Hash::hash(&[peer.to_usize() as u8])))
+            .collect();
+        let commitments: DkgCommitments =
+            BTreeMap::from([(0, BTreeMap::from([("key".to_string(), commit_hashes)]))]);
+
+        let signatures: BTreeMap<_, _> = configs
+            .iter()
+            .map(|(peer, cfg)| {
+                let (_, signature) = cfg.sign_consensus(&registry, &commitments).unwrap();
+                (*peer, signature)
+            })
+            .collect();
+        let (consensus_hash, _) = configs[&peers[0]]
+            .sign_consensus(&registry, &commitments)
+            .unwrap();
+        let consensus = &configs[&peers[0]].consensus;
+
+        let transcript = DkgTranscript::new(
+            consensus,
+            commitments.clone(),
+            consensus_hash,
+            signatures.clone(),
+        )
+        .unwrap();
+        transcript.verify(consensus, &registry).unwrap();
+
+        assert!(DkgTranscript::new(
+            consensus,
+            Default::default(),
+            consensus_hash,
+            signatures.clone()
+        )
+        .is_err());
+
+        let mut other_commitments = transcript.clone();
+        other_commitments
+            .commitments
+            .get_mut(&0)
+            .unwrap()
+            .get_mut("key")
+            .unwrap()
+            .insert(peers[0], sha256::Hash::hash(b"other"));
+        assert!(other_commitments.verify(consensus, &registry).is_err());
+
+        let mut other_consensus = consensus.clone();
+        other_consensus
+            .meta
+            .insert("other".to_string(), "config".to_string());
+        assert!(transcript.verify(&other_consensus, &registry).is_err());
+
+        let mut missing_signature = transcript.clone();
+        missing_signature.signatures.remove(&peers[3]);
+        assert!(missing_signature.verify(consensus, &registry).is_err());
+
+        let mut wrong_signatures = signatures;
+        let wrong_signature = wrong_signatures[&peers[1]].clone();
+        wrong_signatures.insert(peers[2], wrong_signature);
+        assert!(
+            DkgTranscript::new(consensus, commitments, consensus_hash, wrong_signatures).is_err()
+        );
+    }
+}
@@ -40,5 +40,13 @@ fn item_message(item: &ConsensusItem) -> String {
             tx_debug
         }
         ConsensusItem::ConsensusUpgrade(reason) => format!("Shutdown signal for {reason:?}"),
+        ConsensusItem::TlsCertRotation(rotation) => format!(
+            "TLS Cert Rotation activating at epoch {}",
+            rotation.activation_epoch
+        ),
+        ConsensusItem::EndpointUpdate(update) => format!(
+            "Endpoint Update to api={} p2p={}",
+            update.api_url, update.p2p_url
+        ),
     }
 }
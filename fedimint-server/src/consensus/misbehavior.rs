@@ -0,0 +1,250 @@
+//! Scores the faults of peers recorded while processing epochs and bans peers
+//! for a while once their score gets too high
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::db::DatabaseTransaction;
+use fedimint_core::encoding::{Decodable, Encodable};
+use fedimint_core::PeerId;
+use fedimint_logging::LOG_CONSENSUS;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::config::PeerBanPolicy;
+use crate::db::{
+    DropPeerKey, DropPeerKeyPrefix, PeerFaultKey, PeerFaultKeyPrefix, PeerFaultPeerPrefix,
+};
+
+/// Misbehavior of a peer observed while processing an epoch
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Encodable, Decodable, Serialize, Deserialize,
+)]
+pub enum PeerFault {
+    /// Contributed consensus items we couldn't decode
+    UndecodableContribution,
+    /// Didn't contribute a valid share of the client config signature
+    ClientConfigSignature,
+    /// Didn't contribute a valid share of the epoch signature
+    EpochSignature,
+    /// A module rejected or missed the peer's contribution, e.g. invalid blind
+    /// signature shares for the mint or decryption shares for lightning
+    Module(ModuleInstanceId),
+}
+
+impl PeerFault {
+    /// How much the fault counts towards banning the peer
+    pub fn score(&self) -> u64 {
+        match self {
+            // Honest peers never send items we can't decode
+            PeerFault::UndecodableContribution => 100,
+            // Missing contributions can also be caused by a slow peer
+            PeerFault::ClientConfigSignature | PeerFault::EpochSignature | PeerFault::Module(_) => {
+                10
+            }
+        }
+    }
+}
+
+/// Ban of a peer whose messages we ignore until it expires
+#[derive(Debug, Clone, PartialEq, Eq, Encodable, Decodable, Serialize, Deserialize)]
+pub struct PeerBan {
+    /// First epoch in which the peer is no longer banned
+    pub until_epoch: u64,
+    /// Score of the peer when it was banned
+    pub score: u64,
+}
+
+/// Misbehavior of a peer as shown to the guardian
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerMisbehavior {
+    /// Sum of the scores of the recorded faults
+    pub score: u64,
+    /// Faults within the scoring window and the epochs they were committed in
+    pub faults: Vec<(u64, PeerFault)>,
+    pub ban: Option<PeerBan>,
+}
+
+/// Records the `faults` peers committed in `epoch` and bans peers whose score
+/// reached the limit of the `policy`. Faults that left the scoring window and
+/// expired bans are removed.
+///
+/// Faults of banned peers are not recorded since we ignore their messages
+/// anyway, which would otherwise keep them banned forever.
+pub async fn record_peer_faults(
+    dbtx: &mut DatabaseTransaction<'_>,
+    policy: &PeerBanPolicy,
+    epoch: u64,
+    faults: Vec<(PeerId, PeerFault)>,
+) {
+    let next_epoch = epoch + 1;
+
+    let expired_faults = dbtx
+        .find_by_prefix(&PeerFaultKeyPrefix)
+        .await
+        .filter_map(|(key, ())| async move {
+            (key.epoch + policy.score_window_epochs <= epoch).then_some(key)
+        })
+        .collect::<Vec<_>>()
+        .await;
+    for key in expired_faults {
+        dbtx.remove_entry(&key).await;
+    }
+
+    let expired_bans = dbtx
+        .find_by_prefix(&DropPeerKeyPrefix)
+        .await
+        .filter_map(|(key, ban)| async move { (ban.until_epoch <= next_epoch).then_some(key) })
+        .collect::<Vec<_>>()
+        .await;
+    for key in expired_bans {
+        info!(target: LOG_CONSENSUS, peer = %key.0, "Ban of peer expired");
+        dbtx.remove_entry(&key).await;
+    }
+
+    let mut faulty_peers = BTreeSet::new();
+    for (peer, fault) in faults {
+        if dbtx.get_value(&DropPeerKey(peer)).await.is_some() {
+            continue;
+        }
+
+        warn!(target: LOG_CONSENSUS, %peer, ?fault, epoch, "Peer misbehaved");
+        dbtx.insert_entry(&PeerFaultKey { peer, epoch, fault }, &())
+            .await;
+        faulty_peers.insert(peer);
+    }
+
+    for peer in faulty_peers {
+        let score: u64 = peer_faults(dbtx, peer)
+            .await
+            .iter()
+            .map(|(_, fault)| fault.score())
+            .sum();
+
+        if score >= policy.ban_score {
+            warn!(target: LOG_CONSENSUS, %peer, score, "Banning misbehaving peer");
+            let ban = PeerBan {
+                until_epoch: next_epoch + policy.ban_duration_epochs,
+                score,
+            };
+            dbtx.insert_entry(&DropPeerKey(peer), &ban).await;
+        }
+    }
+}
+
+/// Peers that are banned in the next epoch, expired bans are removed when the
+/// last epoch of the ban was recorded
+pub async fn active_peer_bans(dbtx: &mut DatabaseTransaction<'_>) -> BTreeMap<PeerId, PeerBan> {
+    dbtx.find_by_prefix(&DropPeerKeyPrefix)
+        .await
+        .map(|(key, ban)| (key.0, ban))
+        .collect()
+        .await
+}
+
+/// Recorded faults and the ban of `peer`
+pub async fn peer_misbehavior(dbtx: &mut DatabaseTransaction<'_>, peer: PeerId) -> PeerMisbehavior {
+    let faults = peer_faults(dbtx, peer).await;
+    let ban = active_peer_bans(dbtx).await.remove(&peer);
+
+    PeerMisbehavior {
+        score: faults.iter().map(|(_, fault)| fault.score()).sum(),
+        faults,
+        ban,
+    }
+}
+
+/// Lifts the ban of `peer` and forgets its faults so that it starts over with
+/// a clean record
+pub async fn unban_peer(dbtx: &mut DatabaseTransaction<'_>, peer: PeerId) {
+    dbtx.remove_entry(&DropPeerKey(peer)).await;
+    dbtx.remove_by_prefix(&PeerFaultPeerPrefix(peer)).await;
+    info!(target: LOG_CONSENSUS, %peer, "Unbanned peer");
+}
+
+async fn peer_faults(dbtx: &mut DatabaseTransaction<'_>, peer: PeerId) -> Vec<(u64, PeerFault)> {
+    dbtx.find_by_prefix(&PeerFaultPeerPrefix(peer))
+        .await
+        .map(|(key, ())| (key.epoch, key.fault))
+        .collect()
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::Database;
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use fedimint_core::PeerId;
+
+    use super::{active_peer_bans, peer_misbehavior, record_peer_faults, unban_peer, PeerFault};
+    use crate::config::PeerBanPolicy;
+
+    #[test_log::test(tokio::test)]
+    async fn bans_peers_that_keep_misbehaving() {
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let mut dbtx = db.begin_transaction().await;
+        let policy = PeerBanPolicy {
+            ban_score: 30,
+            score_window_epochs: 2,
+            ban_duration_epochs: 10,
+        };
+        let slow = PeerId::from(1);
+        let malicious = PeerId::from(2);
+
+        // Faults of the slow peer leave the window before it gets banned
+        for epoch in 0..6 {
+            let mut faults = vec![(slow, PeerFault::EpochSignature)];
+            if epoch == 2 {
+                faults.push((malicious, PeerFault::UndecodableContribution));
+            }
+            record_peer_faults(&mut dbtx, &policy, epoch, faults).await;
+        }
+
+        let bans = active_peer_bans(&mut dbtx).await;
+        assert_eq!(bans.keys().copied().collect::<Vec<_>>(), vec![malicious]);
+        assert_eq!(bans[&malicious].score, 100);
+        assert_eq!(bans[&malicious].until_epoch, 13);
+
+        let slow_misbehavior = peer_misbehavior(&mut dbtx, slow).await;
+        assert_eq!(slow_misbehavior.score, 20);
+        assert_eq!(
+            slow_misbehavior.faults,
+            vec![
+                (4, PeerFault::EpochSignature),
+                (5, PeerFault::EpochSignature)
+            ]
+        );
+
+        // Faults of banned peers don't extend their bans
+        record_peer_faults(
+            &mut dbtx,
+            &policy,
+            6,
+            vec![(malicious, PeerFault::Module(0))],
+        )
+        .await;
+        let malicious_misbehavior = peer_misbehavior(&mut dbtx, malicious).await;
+        assert!(malicious_misbehavior.faults.is_empty());
+        assert_eq!(malicious_misbehavior.ban.as_ref(), bans.get(&malicious));
+
+        // The ban expires after its last epoch was recorded
+        record_peer_faults(&mut dbtx, &policy, 11, vec![]).await;
+        assert_eq!(active_peer_bans(&mut dbtx).await, bans);
+        record_peer_faults(&mut dbtx, &policy, 12, vec![]).await;
+        assert!(active_peer_bans(&mut dbtx).await.is_empty());
+
+        record_peer_faults(
+            &mut dbtx,
+            &policy,
+            13,
+            vec![(malicious, PeerFault::UndecodableContribution)],
+        )
+        .await;
+        assert!(active_peer_bans(&mut dbtx).await.contains_key(&malicious));
+        unban_peer(&mut dbtx, malicious).await;
+        assert!(active_peer_bans(&mut dbtx).await.is_empty());
+        assert_eq!(peer_misbehavior(&mut dbtx, malicious).await.score, 0);
+    }
+}
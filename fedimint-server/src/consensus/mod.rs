@@ -2,15 +2,18 @@
 
 pub mod debug;
 mod interconnect;
+pub mod misbehavior;
 
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::ffi::OsString;
 use std::iter::FromIterator;
 use std::os::unix::prelude::OsStrExt;
-use std::sync::Mutex;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use anyhow::format_err;
+use aead::LessSafeKey;
+use anyhow::{bail, format_err};
 use fedimint_core::config::{ConfigResponse, ServerModuleGenRegistry};
 use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::db::{
@@ -28,33 +31,56 @@ use fedimint_core::server::{DynServerModule, DynVerificationCache};
 use fedimint_core::task::{sleep, TaskGroup};
 use fedimint_core::{Amount, NumPeers, OutPoint, PeerId, TransactionId};
 use fedimint_logging::{LOG_CONSENSUS, LOG_CORE};
-use futures::future::select_all;
+use futures::future::{self, select_all};
 use futures::StreamExt;
-use hbbft::honey_badger::Batch;
 use itertools::Itertools;
 use thiserror::Error;
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::watch;
+use tokio_rustls::rustls;
 use tracing::{debug, error, info, info_span, instrument, trace, warn, Instrument};
+use url::Url;
 
-use crate::config::ServerConfig;
+use crate::config::io::{write_endpoint_update, write_pending_tls_cert, write_tls_cert_rotation};
+use crate::config::{gen_cert_and_key, PendingTlsCert, ServerConfig, ServerConfigConsensus};
 use crate::consensus::interconnect::FedimintInterconnect;
+use crate::consensus::misbehavior::{PeerFault, PeerMisbehavior};
 use crate::consensus::TransactionSubmissionError::TransactionReplayError;
 use crate::db::{
     get_global_database_migrations, AcceptedTransactionKey, ClientConfigSignatureKey,
-    ConsensusUpgradeKey, DropPeerKey, DropPeerKeyPrefix, EpochHistoryKey, LastEpochKey,
-    RejectedTransactionKey, GLOBAL_DATABASE_VERSION,
+    CompletedTlsCertRotationKey, CompletedTlsCertRotationKeyPrefix, ConsensusUpgradeKey,
+    EndpointUpdateKey, EndpointUpdateKeyPrefix, EpochCheckpointKey, EpochHistoryKey, LastEpochKey,
+    PendingEndpointUpdateKey, RejectedTransactionKey, TlsCertRotationKey, TlsCertRotationKeyPrefix,
+    GLOBAL_DATABASE_VERSION,
 };
+use crate::metrics::{AUDIT_MSATS, CONSENSUS_TRANSACTIONS};
+use crate::net::connect::PeerCertStore;
 use crate::transaction::{Transaction, TransactionError};
 
 pub type HbbftSerdeConsensusOutcome = hbbft::honey_badger::Batch<Vec<SerdeConsensusItem>, PeerId>;
-pub type HbbftConsensusOutcome = hbbft::honey_badger::Batch<Vec<ConsensusItem>, PeerId>;
 pub type HbbftMessage = hbbft::honey_badger::Message<PeerId>;
 
 /// How many txs can be stored in memory before blocking the API
 const TRANSACTION_BUFFER_SIZE: usize = 1000;
 
-// TODO remove HBBFT `Batch` from `ConsensusOutcome`
+/// How many epochs after announcing a new TLS certificate it is activated
+const TLS_CERT_ACTIVATION_DELAY: u64 = 10;
+
+/// How many epochs after activation the previous TLS certificate of a peer is
+/// still accepted
+const TLS_CERT_GRACE_PERIOD: u64 = 100;
+
+/// Outcome of an HBBFT epoch with the contributions of the peers decoded
+#[derive(Debug, Clone)]
+pub struct HbbftConsensusOutcome {
+    pub epoch: u64,
+    pub contributions: BTreeMap<PeerId, Vec<ConsensusItem>>,
+    /// Faults of the peers whose contributions were left out since we couldn't
+    /// decode them, recorded when the outcome is processed
+    pub faults: Vec<(PeerId, PeerFault)>,
+}
+
 #[derive(Debug, Clone)]
 pub struct ConsensusOutcomeConversion(pub HbbftConsensusOutcome);
 
@@ -66,9 +92,10 @@ impl PartialEq<Self> for ConsensusOutcomeConversion {
 
 impl From<EpochOutcome> for ConsensusOutcomeConversion {
     fn from(history: EpochOutcome) -> Self {
-        ConsensusOutcomeConversion(Batch {
+        ConsensusOutcomeConversion(HbbftConsensusOutcome {
             epoch: history.epoch,
             contributions: BTreeMap::from_iter(history.items.into_iter()),
+            faults: vec![],
         })
     }
 }
@@ -104,6 +131,30 @@ pub struct FedimintConsensus {
     /// Cache of transactions to include in a proposal
     // TODO should be able to eventually remove this Mutex
     pub tx_cache: Mutex<HashSet<Transaction>>,
+
+    /// Key used to encrypt database backups requested via the API, backups
+    /// are disabled if not set
+    backup_key: Option<LessSafeKey>,
+
+    /// Number of past epochs whose full outcome is kept, older epochs are
+    /// pruned to an [`EpochCheckpoint`]. Epoch history is never pruned if not
+    /// set.
+    epoch_retention: Option<u64>,
+
+    /// TLS certificates used for p2p connections, updated when certificates
+    /// are rotated
+    pub tls_certs: Arc<PeerCertStore>,
+
+    /// Directory and encryption key of our configs, rotated TLS certificates
+    /// are only kept in memory if not set
+    config_dir: Option<(PathBuf, LessSafeKey)>,
+
+    /// Our TLS cert rotation in progress, persisted in our private config
+    pending_tls_cert: Mutex<Option<PendingTlsCert>>,
+
+    /// Notifies API subscriptions of the epoch count after every processed
+    /// epoch
+    epoch_count: watch::Sender<u64>,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Encodable, Decodable)]
@@ -184,6 +235,8 @@ impl FedimintConsensus {
 
         let (tx_sender, tx_receiver) = mpsc::channel(TRANSACTION_BUFFER_SIZE);
         let client_cfg = cfg.consensus.to_config_response(&module_inits);
+        let tls_certs = Arc::new(PeerCertStore::new(&cfg.tls_config()));
+        let pending_tls_cert = cfg.private.pending_tls_cert.clone();
 
         Ok((
             Self {
@@ -194,6 +247,12 @@ impl FedimintConsensus {
                 db,
                 tx_sender,
                 tx_cache: Default::default(),
+                backup_key: None,
+                epoch_retention: None,
+                tls_certs,
+                config_dir: None,
+                pending_tls_cert: Mutex::new(pending_tls_cert),
+                epoch_count: watch::channel(0).0,
             },
             tx_receiver,
         ))
@@ -208,6 +267,8 @@ impl FedimintConsensus {
     ) -> (Self, Receiver<Transaction>) {
         let (tx_sender, tx_receiver) = mpsc::channel(TRANSACTION_BUFFER_SIZE);
         let client_cfg = cfg.consensus.to_config_response(&module_inits);
+        let tls_certs = Arc::new(PeerCertStore::new(&cfg.tls_config()));
+        let pending_tls_cert = cfg.private.pending_tls_cert.clone();
 
         (
             Self {
@@ -218,12 +279,46 @@ impl FedimintConsensus {
                 db,
                 tx_sender,
                 tx_cache: Default::default(),
+                backup_key: None,
+                epoch_retention: None,
+                tls_certs,
+                config_dir: None,
+                pending_tls_cert: Mutex::new(pending_tls_cert),
+                epoch_count: watch::channel(0).0,
             },
             tx_receiver,
         )
     }
 }
 
+impl FedimintConsensus {
+    /// Enables database backups via the API, encrypting them with `key`
+    /// which should be derived from the guardian's config password
+    pub fn with_backup_key(mut self, key: LessSafeKey) -> Self {
+        self.backup_key = Some(key);
+        self
+    }
+
+    pub fn backup_key(&self) -> Option<&LessSafeKey> {
+        self.backup_key.as_ref()
+    }
+
+    /// Prunes the outcomes of epochs older than the last `retention` epochs,
+    /// keeping only their signed hashes. At least the last epoch is always
+    /// kept since the next epoch adds its signature.
+    pub fn with_epoch_retention(mut self, retention: u64) -> Self {
+        self.epoch_retention = Some(retention.max(1));
+        self
+    }
+
+    /// Persists rotated TLS certificates and our pending rotation to the
+    /// configs in `dir`, encrypting our private config with `key`
+    pub fn with_config_dir(mut self, dir: PathBuf, key: LessSafeKey) -> Self {
+        self.config_dir = Some((dir, key));
+        self
+    }
+}
+
 impl VerificationCaches {
     fn get_cache(&self, module_key: ModuleInstanceId) -> &DynVerificationCache {
         self.caches
@@ -318,7 +413,7 @@ impl FedimintConsensus {
         consensus_outcome: HbbftConsensusOutcome,
         reference_rejected_txs: Option<BTreeSet<TransactionId>>,
     ) -> SignedEpochOutcome {
-        let epoch_history = self
+        let (epoch_history, endpoint_updates) = self
             .db
             .autocommit(
                 |dbtx| {
@@ -335,6 +430,8 @@ impl FedimintConsensus {
                             transaction: transaction_cis,
                             consensus_upgrade: consensus_upgrade_cis,
                             module: module_cis,
+                            tls_cert_rotation: tls_cert_rotation_cis,
+                            endpoint_update: endpoint_update_cis,
                         } = consensus_outcome
                             .contributions
                             .into_iter()
@@ -343,6 +440,9 @@ impl FedimintConsensus {
 
                         self.process_module_consensus_items(dbtx, &module_cis).await;
                         self.process_upgrade_items(dbtx, &consensus_upgrade_cis).await;
+                        self.complete_tls_cert_rotations(dbtx, epoch).await;
+                        self.process_tls_cert_rotations(dbtx, epoch, &tls_cert_rotation_cis)
+                            .await;
 
                         let rejected_txs = self
                             .process_transactions(dbtx, epoch, &transaction_cis)
@@ -362,13 +462,24 @@ impl FedimintConsensus {
                         let epoch_history = self
                             .finalize_process_epoch(dbtx, outcome.clone(), rejected_txs)
                             .await;
-                        Result::<_, ()>::Ok(epoch_history)
+
+                        // Processed after the client config signature shares of this epoch, which
+                        // were still created for the previous config
+                        let endpoint_updates = self
+                            .process_endpoint_updates(dbtx, &endpoint_update_cis)
+                            .await;
+                        Result::<_, ()>::Ok((epoch_history, endpoint_updates))
                     })
                 },
                 Some(100),
             )
             .await
             .expect("Committing consensus epoch failed");
+        self.observe_epoch_transactions(&epoch_history);
+        self.notify_epoch_count(epoch_history.outcome.epoch + 1);
+
+        self.apply_tls_cert_rotations().await;
+        self.persist_endpoint_updates(&endpoint_updates);
 
         let audit = self.audit().await;
         if audit.sum().milli_sat < 0 {
@@ -397,6 +508,10 @@ impl FedimintConsensus {
             self.modules
                 .get_expect(module_key)
                 .begin_consensus_epoch(&mut dbtx.with_module_prefix(module_key), module_cis)
+                .instrument(info_span!(
+                    "Processing module consensus items",
+                    module_instance_id = module_key
+                ))
                 .await;
         }
     }
@@ -423,7 +538,7 @@ impl FedimintConsensus {
                 continue;
             }
 
-            let span = info_span!("Processing transaction");
+            let span = info_span!("Processing transaction", %txid);
             async {
                 trace!(?transaction);
                 self.tx_cache.lock().unwrap().remove(&transaction);
@@ -470,26 +585,29 @@ impl FedimintConsensus {
         rejected_txs: BTreeSet<TransactionId>,
     ) -> SignedEpochOutcome {
         let epoch_peers: HashSet<PeerId> = outcome.contributions.keys().copied().collect();
+        let epoch = outcome.epoch;
 
-        let mut drop_peers = Vec::<PeerId>::new();
+        let mut faults = outcome.faults.clone();
 
-        self.save_client_config_sig(dbtx, &outcome, &mut drop_peers)
+        self.save_client_config_sig(dbtx, &outcome, &mut faults)
             .await;
 
         let epoch_history = self
-            .save_epoch_history(outcome.clone(), dbtx, &mut drop_peers, rejected_txs)
+            .save_epoch_history(outcome.clone(), dbtx, &mut faults, rejected_txs)
             .await;
 
         for (module_key, module) in self.modules.iter_modules() {
             let module_drop_peers = module
                 .end_consensus_epoch(&epoch_peers, &mut dbtx.with_module_prefix(module_key))
                 .await;
-            drop_peers.extend(module_drop_peers);
+            faults.extend(
+                module_drop_peers
+                    .into_iter()
+                    .map(|peer| (peer, PeerFault::Module(module_key))),
+            );
         }
 
-        for peer in drop_peers {
-            dbtx.insert_entry(&DropPeerKey(peer), &()).await;
-        }
+        misbehavior::record_peer_faults(dbtx, &self.cfg.local.peer_bans, epoch, faults).await;
 
         epoch_history
     }
@@ -500,7 +618,7 @@ impl FedimintConsensus {
         &self,
         dbtx: &mut DatabaseTransaction<'_>,
         outcome: &HbbftConsensusOutcome,
-        drop_peers: &mut Vec<PeerId>,
+        faults: &mut Vec<(PeerId, PeerFault)>,
     ) {
         let config = self.get_config_with_sig(&mut dbtx.get_isolated()).await;
 
@@ -545,7 +663,7 @@ impl FedimintConsensus {
 
             for peer in peers {
                 if !contributing_peers.contains(&peer) {
-                    drop_peers.push(peer);
+                    faults.push((peer, PeerFault::ClientConfigSignature));
                 }
             }
         }
@@ -565,6 +683,319 @@ impl FedimintConsensus {
         }
     }
 
+    /// Stores valid TLS certificate rotations announced by peers, a peer can
+    /// only rotate again once its previous rotation completed
+    async fn process_tls_cert_rotations(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        epoch: u64,
+        rotations: &[(PeerId, TlsCertRotation)],
+    ) {
+        for (peer, rotation) in rotations {
+            if dbtx.get_value(&TlsCertRotationKey(*peer)).await.is_some() {
+                continue;
+            }
+
+            if !rotation.verify(*peer, &self.cfg.consensus.auth_pk_set) {
+                warn!(target: LOG_CONSENSUS, %peer, "Invalid TLS cert rotation signature");
+                continue;
+            }
+
+            if rotation.activation_epoch <= epoch {
+                warn!(target: LOG_CONSENSUS, %peer, "TLS cert rotation activates in the past");
+                continue;
+            }
+
+            let cert = rustls::Certificate(rotation.cert.clone());
+            if rustls::RootCertStore::empty().add(&cert).is_err() {
+                warn!(target: LOG_CONSENSUS, %peer, "Invalid certificate in TLS cert rotation");
+                continue;
+            }
+
+            info!(
+                target: LOG_CONSENSUS,
+                %peer,
+                activation_epoch = rotation.activation_epoch,
+                "Agreed on TLS cert rotation"
+            );
+            dbtx.insert_new_entry(&TlsCertRotationKey(*peer), rotation)
+                .await;
+        }
+    }
+
+    /// Ends the agreed TLS cert rotations whose grace period is over by
+    /// `epoch`. They are removed as part of the epoch, so all peers agree on
+    /// when a peer can rotate again, and kept as completed until
+    /// [`Self::apply_tls_cert_rotations`] wrote them to our configs.
+    async fn complete_tls_cert_rotations(&self, dbtx: &mut DatabaseTransaction<'_>, epoch: u64) {
+        let completed = dbtx
+            .find_by_prefix(&TlsCertRotationKeyPrefix)
+            .await
+            .filter(|(_, rotation)| {
+                future::ready(rotation.activation_epoch + TLS_CERT_GRACE_PERIOD <= epoch)
+            })
+            .collect::<Vec<_>>()
+            .await;
+
+        for (TlsCertRotationKey(peer), rotation) in completed {
+            info!(target: LOG_CONSENSUS, %peer, "Completed TLS cert rotation");
+            dbtx.remove_entry(&TlsCertRotationKey(peer)).await;
+            dbtx.insert_entry(&CompletedTlsCertRotationKey(peer), &rotation)
+                .await;
+        }
+    }
+
+    /// Updates our TLS certificates according to the agreed rotations and the
+    /// last processed epoch, and writes the completed rotations to our configs.
+    /// A completed rotation that could not be written is retried after the
+    /// next epoch.
+    pub async fn apply_tls_cert_rotations(&self) {
+        let mut dbtx = self.db.begin_transaction().await;
+        let Some(EpochHistoryKey(epoch)) = dbtx.get_value(&LastEpochKey).await else {
+            return;
+        };
+
+        let rotations = dbtx
+            .find_by_prefix(&TlsCertRotationKeyPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await;
+        let completed = dbtx
+            .find_by_prefix(&CompletedTlsCertRotationKeyPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await;
+        let pending = self.pending_tls_cert();
+        let our_id = self.cfg.local.identity;
+
+        if let Some(pending) = &pending {
+            let agreed = rotations
+                .iter()
+                .map(|(key, rotation)| (key.0, rotation))
+                .chain(completed.iter().map(|(key, rotation)| (key.0, rotation)))
+                .any(|(peer, rotation)| peer == our_id && *rotation == pending.rotation());
+            if !agreed && pending.activation_epoch <= epoch {
+                warn!(
+                    target: LOG_CONSENSUS,
+                    "Our TLS cert rotation was not agreed on before its activation, dropping it"
+                );
+                if let Err(e) = self.set_pending_tls_cert(None) {
+                    error!(target: LOG_CONSENSUS, "Failed to drop our TLS cert rotation: {e:?}");
+                }
+            }
+        }
+
+        for (TlsCertRotationKey(peer), rotation) in rotations {
+            if epoch < rotation.activation_epoch {
+                continue;
+            }
+            self.activate_tls_cert(peer, rotation, pending.as_ref());
+        }
+
+        for (CompletedTlsCertRotationKey(peer), rotation) in completed {
+            let Some((cert, private_key)) =
+                self.activate_tls_cert(peer, rotation, pending.as_ref())
+            else {
+                continue;
+            };
+            self.tls_certs.retain_cert(peer, &cert);
+
+            if let Some((dir, key)) = &self.config_dir {
+                if let Err(e) = write_tls_cert_rotation(dir, key, peer, &cert, private_key.as_ref())
+                {
+                    error!(target: LOG_CONSENSUS, %peer, "Failed to persist TLS cert rotation, retrying after the next epoch: {e:?}");
+                    continue;
+                }
+            }
+
+            dbtx.remove_entry(&CompletedTlsCertRotationKey(peer)).await;
+            if peer == our_id {
+                // the private config was already updated with the new key
+                *self.pending_tls_cert.lock().unwrap() = None;
+            }
+        }
+
+        dbtx.commit_tx().await;
+    }
+
+    /// Uses the cert of an active `rotation` for `peer`, and for our own
+    /// connections if `peer` is us. Returns the cert and our new private key,
+    /// `None` if we lost the key of our own rotation.
+    fn activate_tls_cert(
+        &self,
+        peer: PeerId,
+        rotation: TlsCertRotation,
+        pending: Option<&PendingTlsCert>,
+    ) -> Option<(rustls::Certificate, Option<rustls::PrivateKey>)> {
+        let cert = rustls::Certificate(rotation.cert);
+        self.tls_certs.add_cert(peer, cert.clone());
+
+        if peer != self.cfg.local.identity {
+            return Some((cert, None));
+        }
+
+        let key = match pending {
+            Some(pending) => pending.key.clone(),
+            // we crashed after persisting the completed rotation
+            None if self.cfg.local.tls_cert == cert => self.cfg.private.tls_key.clone(),
+            None => {
+                error!(target: LOG_CONSENSUS, "Missing the key of our rotated TLS cert");
+                return None;
+            }
+        };
+        self.tls_certs.set_identity(cert.clone(), key.clone());
+        Some((cert, Some(key)))
+    }
+
+    /// Generates a new TLS certificate that is announced to our peers with
+    /// our next consensus proposals, returns the epoch it activates at
+    pub async fn rotate_tls_cert(&self) -> anyhow::Result<u64> {
+        let activation_epoch = self.get_epoch_count().await + TLS_CERT_ACTIVATION_DELAY;
+        if self.pending_tls_cert().is_some() {
+            bail!("A rotation of our TLS cert is already in progress");
+        }
+
+        let our_id = self.cfg.local.identity;
+        let (cert, key) = gen_cert_and_key(&self.cfg.consensus.api[&our_id].name)?;
+        let hash = TlsCertRotation::signing_hash(&cert.0, activation_epoch);
+        let signature = SerdeSignatureShare(self.cfg.private.auth_sks.0.sign(hash));
+
+        self.set_pending_tls_cert(Some(PendingTlsCert {
+            cert,
+            key,
+            activation_epoch,
+            signature,
+        }))?;
+
+        Ok(activation_epoch)
+    }
+
+    fn pending_tls_cert(&self) -> Option<PendingTlsCert> {
+        self.pending_tls_cert.lock().unwrap().clone()
+    }
+
+    /// Replaces our pending TLS cert rotation, persisting it in our private
+    /// config first since it contains the new private key
+    fn set_pending_tls_cert(&self, pending: Option<PendingTlsCert>) -> anyhow::Result<()> {
+        let mut current = self.pending_tls_cert.lock().unwrap();
+        if pending.is_some() && current.is_some() {
+            bail!("A rotation of our TLS cert is already in progress");
+        }
+
+        if let Some((dir, key)) = &self.config_dir {
+            write_pending_tls_cert(dir, key, pending.as_ref())?;
+        }
+        *current = pending;
+        Ok(())
+    }
+
+    /// Stores valid endpoint updates announced by peers and removes the client
+    /// config signature, so the federation signs the config with the new URLs.
+    /// Returns the updates that changed a peer's endpoints.
+    async fn process_endpoint_updates(
+        &self,
+        dbtx: &mut DatabaseTransaction<'_>,
+        updates: &[(PeerId, EndpointUpdate)],
+    ) -> Vec<(PeerId, EndpointUpdate)> {
+        let mut changed = vec![];
+
+        for (peer, update) in updates {
+            if !update.verify(*peer, &self.cfg.consensus.auth_pk_set) {
+                warn!(target: LOG_CONSENSUS, %peer, "Invalid endpoint update signature");
+                continue;
+            }
+
+            if dbtx.get_value(&EndpointUpdateKey(*peer)).await.as_ref() == Some(update) {
+                continue;
+            }
+
+            info!(
+                target: LOG_CONSENSUS,
+                %peer,
+                api_url = %update.api_url,
+                p2p_url = %update.p2p_url,
+                "Agreed on endpoint update"
+            );
+            dbtx.insert_entry(&EndpointUpdateKey(*peer), update).await;
+            dbtx.remove_entry(&ClientConfigSignatureKey).await;
+            changed.push((*peer, update.clone()));
+        }
+
+        changed
+    }
+
+    /// Writes the p2p URLs of agreed endpoint updates to our local config, API
+    /// URLs are always applied from the database, see
+    /// [`Self::consensus_config`]
+    fn persist_endpoint_updates(&self, updates: &[(PeerId, EndpointUpdate)]) {
+        let Some((dir, _)) = &self.config_dir else {
+            return;
+        };
+
+        for (peer, update) in updates {
+            if let Err(e) = write_endpoint_update(dir, *peer, &update.p2p_url) {
+                error!(target: LOG_CONSENSUS, %peer, "Failed to persist endpoint update: {e:?}");
+            }
+        }
+    }
+
+    /// Signs new URLs for our API and p2p endpoints that are announced to our
+    /// peers with our next consensus proposals
+    pub async fn update_endpoint(&self, api_url: Url, p2p_url: Url) {
+        let hash = EndpointUpdate::signing_hash(&api_url, &p2p_url);
+        let update = EndpointUpdate {
+            api_url,
+            p2p_url,
+            signature: SerdeSignatureShare(self.cfg.private.auth_sks.0.sign(hash)),
+        };
+
+        let mut dbtx = self.db.begin_transaction().await;
+        dbtx.insert_entry(&PendingEndpointUpdateKey, &update).await;
+        dbtx.commit_tx().await;
+    }
+
+    /// Recorded faults and bans of all our peers
+    pub async fn peer_misbehavior(&self) -> BTreeMap<PeerId, PeerMisbehavior> {
+        let mut dbtx = self.db.begin_transaction().await;
+        let mut peers = BTreeMap::new();
+        for peer in self.cfg.consensus.api.keys() {
+            if *peer != self.cfg.local.identity {
+                peers.insert(*peer, misbehavior::peer_misbehavior(&mut dbtx, *peer).await);
+            }
+        }
+        peers
+    }
+
+    /// Lifts the ban of `peer` before it expires, e.g. after the guardian
+    /// confirmed with its operator that the issue was fixed
+    pub async fn unban_peer(&self, peer: PeerId) {
+        let mut dbtx = self.db.begin_transaction().await;
+        misbehavior::unban_peer(&mut dbtx, peer).await;
+        dbtx.commit_tx().await;
+    }
+
+    /// Our consensus config with the endpoint updates agreed on since the DKG,
+    /// which are kept out of the config files so the consensus hash signed in
+    /// the DKG transcript stays valid
+    pub async fn consensus_config(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+    ) -> ServerConfigConsensus {
+        let updates = dbtx
+            .find_by_prefix(&EndpointUpdateKeyPrefix)
+            .await
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut consensus = self.cfg.consensus.clone();
+        for (EndpointUpdateKey(peer), update) in updates {
+            if let Some(endpoint) = consensus.api.get_mut(&peer) {
+                endpoint.url = update.api_url;
+            }
+        }
+        consensus
+    }
+
     /// Returns true if a threshold of peers have signaled to upgrade
     pub async fn is_at_upgrade_threshold(&self) -> bool {
         self.db
@@ -599,7 +1030,12 @@ impl FedimintConsensus {
         &self,
         dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
     ) -> ConfigResponse {
-        let mut client = self.client_cfg.clone();
+        let consensus = self.consensus_config(dbtx).await;
+        let mut client = if consensus.api == self.cfg.consensus.api {
+            self.client_cfg.clone()
+        } else {
+            consensus.to_config_response(&self.module_inits)
+        };
         let maybe_sig = dbtx.get_value(&ClientConfigSignatureKey).await;
         if let Some(SerdeSignature(sig)) = maybe_sig {
             client.client_hash_signature = Some(sig);
@@ -607,6 +1043,17 @@ impl FedimintConsensus {
         client
     }
 
+    /// Receives the epoch count whenever a new epoch was processed
+    pub fn subscribe_epoch_count(&self) -> watch::Receiver<u64> {
+        self.epoch_count.subscribe()
+    }
+
+    /// Wakes up the receivers of [`Self::subscribe_epoch_count`] after
+    /// `epoch_count` epochs were processed
+    pub(crate) fn notify_epoch_count(&self, epoch_count: u64) {
+        self.epoch_count.send_replace(epoch_count);
+    }
+
     pub async fn get_epoch_count(&self) -> u64 {
         self.db
             .begin_transaction()
@@ -625,11 +1072,20 @@ impl FedimintConsensus {
             .await
     }
 
+    /// Returns the checkpoint of `epoch` if its outcome was pruned
+    pub async fn epoch_checkpoint(&self, epoch: u64) -> Option<EpochCheckpoint> {
+        self.db
+            .begin_transaction()
+            .await
+            .get_value(&EpochCheckpointKey(epoch))
+            .await
+    }
+
     async fn save_epoch_history<'a>(
         &self,
         outcome: HbbftConsensusOutcome,
         dbtx: &mut DatabaseTransaction<'a>,
-        drop_peers: &mut Vec<PeerId>,
+        faults: &mut Vec<(PeerId, PeerFault)>,
         rejected_txs: BTreeSet<TransactionId>,
     ) -> SignedEpochOutcome {
         let prev_epoch_key = EpochHistoryKey(outcome.epoch.saturating_sub(1));
@@ -662,7 +1118,7 @@ impl FedimintConsensus {
                                 target: LOG_CONSENSUS,
                                 "Dropping {} for not contributing valid epoch sigs.", peer
                             );
-                            drop_peers.push(peer);
+                            faults.push((peer, PeerFault::EpochSignature));
                         }
                     }
                 }
@@ -675,9 +1131,27 @@ impl FedimintConsensus {
         dbtx.insert_entry(&EpochHistoryKey(current.outcome.epoch), &current)
             .await;
 
+        if let Some(retention) = self.epoch_retention {
+            if let Some(prune_before) = (current.outcome.epoch + 1).checked_sub(retention) {
+                Self::prune_epoch_history(dbtx, prune_before).await;
+            }
+        }
+
         current
     }
 
+    /// Replaces the outcomes of all epochs before `prune_before` with their
+    /// checkpoints, stopping at the first epoch that was already pruned
+    async fn prune_epoch_history(dbtx: &mut DatabaseTransaction<'_>, prune_before: u64) {
+        for epoch in (0..prune_before).rev() {
+            let Some(signed) = dbtx.remove_entry(&EpochHistoryKey(epoch)).await else {
+                break;
+            };
+            dbtx.insert_new_entry(&EpochCheckpointKey(epoch), &EpochCheckpoint::from(&signed))
+                .await;
+        }
+    }
+
     pub async fn await_consensus_proposal(&self) {
         let proposal_futures = self
             .modules
@@ -697,12 +1171,10 @@ impl FedimintConsensus {
     pub async fn get_consensus_proposal(&self) -> ConsensusProposal {
         let mut dbtx = self.db.begin_transaction().await;
 
-        let drop_peers = dbtx
-            .find_by_prefix(&DropPeerKeyPrefix)
+        let drop_peers = misbehavior::active_peer_bans(&mut dbtx)
             .await
-            .map(|(key, _)| key.0)
-            .collect()
-            .await;
+            .into_keys()
+            .collect();
 
         let mut items: Vec<ConsensusItem> = self
             .tx_cache
@@ -748,6 +1220,24 @@ impl FedimintConsensus {
             items.push(item);
         }
 
+        // Announce our new endpoints until they were agreed on
+        if let Some(pending) = dbtx.get_value(&PendingEndpointUpdateKey).await {
+            let our_id = self.cfg.local.identity;
+            if dbtx.get_value(&EndpointUpdateKey(our_id)).await.as_ref() != Some(&pending) {
+                items.push(ConsensusItem::EndpointUpdate(pending));
+                force_new_epoch = true;
+            }
+        }
+
+        // Announce our new TLS cert until it was agreed on
+        if let Some(pending) = self.pending_tls_cert() {
+            let our_id = self.cfg.local.identity;
+            let rotation = pending.rotation();
+            if dbtx.get_value(&TlsCertRotationKey(our_id)).await.as_ref() != Some(&rotation) {
+                items.push(ConsensusItem::TlsCertRotation(rotation));
+            }
+        }
+
         ConsensusProposal {
             items,
             drop_peers,
@@ -921,13 +1411,69 @@ impl FedimintConsensus {
         let mut dbtx = self.db.begin_transaction().await;
         let mut audit = Audit::default();
         for (module_instance_id, module) in self.modules.iter_modules() {
+            let sum_before = audit.sum().milli_sat;
             module
                 .audit(&mut dbtx.with_module_prefix(module_instance_id), &mut audit)
-                .await
+                .await;
+            AUDIT_MSATS
+                .with_label_values(&[
+                    &module_instance_id.to_string(),
+                    &self.module_kind_label(module_instance_id),
+                ])
+                .set(audit.sum().milli_sat - sum_before);
         }
         audit
     }
 
+    /// Kind of the module `module_instance_id` used to label metrics
+    fn module_kind_label(&self, module_instance_id: ModuleInstanceId) -> String {
+        self.cfg
+            .consensus
+            .modules
+            .get(&module_instance_id)
+            .map(|module_cfg| module_cfg.kind().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Counts the transactions of a committed epoch per module they touch
+    fn observe_epoch_transactions(&self, epoch: &SignedEpochOutcome) {
+        let mut processed_txs = HashSet::new();
+        for item in epoch.outcome.items.iter().flat_map(|(_, items)| items) {
+            let ConsensusItem::Transaction(transaction) = item else {
+                continue;
+            };
+            let txid = transaction.tx_hash();
+            if !processed_txs.insert(txid) {
+                continue;
+            }
+            let outcome = if epoch.outcome.rejected_txs.contains(&txid) {
+                "rejected"
+            } else {
+                "accepted"
+            };
+            let module_ids = transaction
+                .inputs
+                .iter()
+                .map(|input| input.module_instance_id())
+                .chain(
+                    transaction
+                        .outputs
+                        .iter()
+                        .map(|output| output.module_instance_id()),
+                )
+                .collect::<BTreeSet<_>>();
+            for module_instance_id in module_ids {
+                CONSENSUS_TRANSACTIONS
+                    .with_label_values(&[
+                        &module_instance_id.to_string(),
+                        &self.module_kind_label(module_instance_id),
+                        outcome,
+                    ])
+                    .inc();
+            }
+        }
+    }
+
     fn build_interconnect(&self) -> FedimintInterconnect {
         FedimintInterconnect { fedimint: self }
     }
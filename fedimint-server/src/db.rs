@@ -1,16 +1,21 @@
 use std::collections::BTreeSet;
 use std::fmt::Debug;
 
-use fedimint_core::db::{DatabaseVersion, MigrationMap, MODULE_GLOBAL_PREFIX};
+use fedimint_core::db::{DatabaseTransaction, DatabaseVersion, MigrationMap, MODULE_GLOBAL_PREFIX};
 use fedimint_core::encoding::{Decodable, Encodable};
-use fedimint_core::epoch::{SerdeSignature, SignedEpochOutcome};
+use fedimint_core::epoch::{
+    EndpointUpdate, EpochCheckpoint, SerdeSignature, SignedEpochOutcome, TlsCertRotation,
+};
 use fedimint_core::{impl_db_lookup, impl_db_record, PeerId, TransactionId};
+use futures::{FutureExt, StreamExt};
 use serde::Serialize;
 use strum_macros::EnumIter;
 
+use crate::config::DEFAULT_PEER_BAN_EPOCHS;
+use crate::consensus::misbehavior::{PeerBan, PeerFault};
 use crate::consensus::AcceptedTransaction;
 
-pub const GLOBAL_DATABASE_VERSION: DatabaseVersion = DatabaseVersion(0);
+pub const GLOBAL_DATABASE_VERSION: DatabaseVersion = DatabaseVersion(1);
 
 #[repr(u8)]
 #[derive(Clone, EnumIter, Debug)]
@@ -22,6 +27,12 @@ pub enum DbKeyPrefix {
     LastEpoch = 0x06,
     ClientConfigSignature = 0x07,
     ConsensusUpgrade = 0x08,
+    EpochCheckpoint = 0x09,
+    TlsCertRotation = 0x0a,
+    EndpointUpdate = 0x0c,
+    PendingEndpointUpdate = 0x0d,
+    PeerFault = 0x0e,
+    CompletedTlsCertRotation = 0x0f,
     Module = MODULE_GLOBAL_PREFIX,
 }
 
@@ -65,6 +76,7 @@ impl_db_lookup!(
     query_prefix = RejectedTransactionKeyPrefix
 );
 
+/// Active ban of a misbehaving peer
 #[derive(Debug, Encodable, Decodable, Serialize)]
 pub struct DropPeerKey(pub PeerId);
 
@@ -73,11 +85,50 @@ pub struct DropPeerKeyPrefix;
 
 impl_db_record!(
     key = DropPeerKey,
-    value = (),
+    value = PeerBan,
     db_prefix = DbKeyPrefix::DropPeer,
 );
 impl_db_lookup!(key = DropPeerKey, query_prefix = DropPeerKeyPrefix);
 
+/// Before version 1 bans were permanent and had no value
+#[derive(Debug, Encodable, Decodable, Serialize)]
+struct DropPeerKeyV0(PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+struct DropPeerKeyPrefixV0;
+
+impl_db_record!(
+    key = DropPeerKeyV0,
+    value = (),
+    db_prefix = DbKeyPrefix::DropPeer,
+);
+impl_db_lookup!(key = DropPeerKeyV0, query_prefix = DropPeerKeyPrefixV0);
+
+/// Evidence of a fault a peer committed in `epoch`
+#[derive(Debug, Clone, Encodable, Decodable, Serialize)]
+pub struct PeerFaultKey {
+    pub peer: PeerId,
+    pub epoch: u64,
+    pub fault: PeerFault,
+}
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PeerFaultKeyPrefix;
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct PeerFaultPeerPrefix(pub PeerId);
+
+impl_db_record!(
+    key = PeerFaultKey,
+    value = (),
+    db_prefix = DbKeyPrefix::PeerFault,
+);
+impl_db_lookup!(
+    key = PeerFaultKey,
+    query_prefix = PeerFaultKeyPrefix,
+    query_prefix = PeerFaultPeerPrefix
+);
+
 #[derive(Debug, Copy, Clone, Encodable, Decodable, Serialize)]
 pub struct EpochHistoryKey(pub u64);
 
@@ -91,6 +142,23 @@ impl_db_record!(
 );
 impl_db_lookup!(key = EpochHistoryKey, query_prefix = EpochHistoryKeyPrefix);
 
+/// Replaces the [`EpochHistoryKey`] entry of a pruned epoch
+#[derive(Debug, Copy, Clone, Encodable, Decodable, Serialize)]
+pub struct EpochCheckpointKey(pub u64);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct EpochCheckpointKeyPrefix;
+
+impl_db_record!(
+    key = EpochCheckpointKey,
+    value = EpochCheckpoint,
+    db_prefix = DbKeyPrefix::EpochCheckpoint,
+);
+impl_db_lookup!(
+    key = EpochCheckpointKey,
+    query_prefix = EpochCheckpointKeyPrefix
+);
+
 #[derive(Debug, Encodable, Decodable, Serialize)]
 pub struct LastEpochKey;
 
@@ -125,6 +193,246 @@ impl_db_record!(
     db_prefix = DbKeyPrefix::ConsensusUpgrade,
 );
 
+/// TLS certificate rotations agreed on in consensus that have not completed
+/// their grace period yet
+#[derive(Debug, Copy, Clone, Encodable, Decodable, Serialize)]
+pub struct TlsCertRotationKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct TlsCertRotationKeyPrefix;
+
+impl_db_record!(
+    key = TlsCertRotationKey,
+    value = TlsCertRotation,
+    db_prefix = DbKeyPrefix::TlsCertRotation,
+);
+impl_db_lookup!(
+    key = TlsCertRotationKey,
+    query_prefix = TlsCertRotationKeyPrefix
+);
+
+/// TLS certificate rotations that completed their grace period but were not
+/// written to our configs yet
+#[derive(Debug, Copy, Clone, Encodable, Decodable, Serialize)]
+pub struct CompletedTlsCertRotationKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct CompletedTlsCertRotationKeyPrefix;
+
+impl_db_record!(
+    key = CompletedTlsCertRotationKey,
+    value = TlsCertRotation,
+    db_prefix = DbKeyPrefix::CompletedTlsCertRotation,
+);
+impl_db_lookup!(
+    key = CompletedTlsCertRotationKey,
+    query_prefix = CompletedTlsCertRotationKeyPrefix
+);
+
+/// Latest endpoint update of a peer agreed on in consensus, overrides the URLs
+/// in our configs
+#[derive(Debug, Copy, Clone, Encodable, Decodable, Serialize)]
+pub struct EndpointUpdateKey(pub PeerId);
+
+#[derive(Debug, Encodable, Decodable)]
+pub struct EndpointUpdateKeyPrefix;
+
+impl_db_record!(
+    key = EndpointUpdateKey,
+    value = EndpointUpdate,
+    db_prefix = DbKeyPrefix::EndpointUpdate,
+);
+impl_db_lookup!(
+    key = EndpointUpdateKey,
+    query_prefix = EndpointUpdateKeyPrefix
+);
+
+/// Our own endpoint update that we propose until it was agreed on
+#[derive(Debug, Encodable, Decodable, Serialize)]
+pub struct PendingEndpointUpdateKey;
+
+impl_db_record!(
+    key = PendingEndpointUpdateKey,
+    value = EndpointUpdate,
+    db_prefix = DbKeyPrefix::PendingEndpointUpdate,
+);
+
 pub fn get_global_database_migrations<'a>() -> MigrationMap<'a> {
-    MigrationMap::new()
+    let mut migrations = MigrationMap::new();
+
+    migrations.insert(DatabaseVersion(0), move |dbtx| {
+        migrate_global_db_version_0(dbtx).boxed()
+    });
+
+    migrations
+}
+
+/// Turns the permanent bans of version 0 into bans that expire after the
+/// default number of epochs
+async fn migrate_global_db_version_0<'a, 'b>(
+    dbtx: &'b mut DatabaseTransaction<'a>,
+) -> Result<(), anyhow::Error> {
+    let banned_peers = dbtx
+        .find_by_prefix(&DropPeerKeyPrefixV0)
+        .await
+        .map(|(key, ())| key.0)
+        .collect::<Vec<_>>()
+        .await;
+    dbtx.remove_by_prefix(&DropPeerKeyPrefixV0).await;
+
+    let epoch_count = dbtx
+        .get_value(&LastEpochKey)
+        .await
+        .map_or(0, |last_epoch| last_epoch.0 + 1);
+    let ban = PeerBan {
+        until_epoch: epoch_count + DEFAULT_PEER_BAN_EPOCHS,
+        score: 0,
+    };
+    for peer in banned_peers {
+        dbtx.insert_new_entry(&DropPeerKey(peer), &ban).await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod fedimint_migration_tests {
+    use std::collections::BTreeSet;
+
+    use fedimint_core::db::{apply_migrations, DatabaseTransaction};
+    use fedimint_core::module::registry::ModuleDecoderRegistry;
+    use fedimint_core::{BitcoinHash, PeerId, TransactionId};
+    use fedimint_testing::{prepare_snapshot, validate_migrations};
+    use futures::StreamExt;
+    use strum::IntoEnumIterator;
+
+    use super::{
+        get_global_database_migrations, ConsensusUpgradeKey, DbKeyPrefix, DropPeerKeyPrefix,
+        DropPeerKeyV0, EpochHistoryKey, LastEpochKey, RejectedTransactionKey,
+        RejectedTransactionKeyPrefix, GLOBAL_DATABASE_VERSION,
+    };
+    use crate::config::DEFAULT_PEER_BAN_EPOCHS;
+
+    const BYTE_32: [u8; 32] = [
+        0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9,
+        0, 1,
+    ];
+
+    /// Create a database with version 0 data. The database produced is not
+    /// intended to be real data or semantically correct. It is only
+    /// intended to provide coverage when reading the database
+    /// in future code versions. This function should not be updated when
+    /// database keys/values change - instead a new function should be added
+    /// that creates a new database backup that can be tested.
+    async fn create_db_with_v0_data(mut dbtx: DatabaseTransaction<'_>) {
+        dbtx.insert_new_entry(&DropPeerKeyV0(PeerId::from(1)), &())
+            .await;
+        dbtx.insert_new_entry(&DropPeerKeyV0(PeerId::from(2)), &())
+            .await;
+
+        dbtx.insert_new_entry(
+            &RejectedTransactionKey(TransactionId::from_inner(BYTE_32)),
+            &"Invalid transaction".to_string(),
+        )
+        .await;
+
+        dbtx.insert_new_entry(&LastEpochKey, &EpochHistoryKey(4))
+            .await;
+
+        dbtx.insert_new_entry(&ConsensusUpgradeKey, &BTreeSet::from([PeerId::from(0)]))
+            .await;
+
+        dbtx.commit_tx().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn prepare_migration_snapshots() {
+        prepare_snapshot(
+            "global-v0",
+            |dbtx| {
+                Box::pin(async move {
+                    create_db_with_v0_data(dbtx).await;
+                })
+            },
+            ModuleDecoderRegistry::default(),
+        )
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_migrations() {
+        validate_migrations(
+            |db| async move {
+                apply_migrations(
+                    &db,
+                    "Global".to_string(),
+                    GLOBAL_DATABASE_VERSION,
+                    get_global_database_migrations(),
+                )
+                .await
+                .expect("Error applying migrations to temp database");
+
+                // Verify that all of the global data can be read. If a database migration
+                // failed or was not properly supplied, the struct will fail to be read.
+                let mut dbtx = db.begin_transaction().await;
+
+                for prefix in DbKeyPrefix::iter() {
+                    match prefix {
+                        DbKeyPrefix::DropPeer => {
+                            let bans = dbtx
+                                .find_by_prefix(&DropPeerKeyPrefix)
+                                .await
+                                .collect::<Vec<_>>()
+                                .await;
+                            assert_eq!(
+                                bans.len(),
+                                2,
+                                "validate_migrations was not able to read all DropPeers"
+                            );
+                            // The permanent bans expire once the default number of
+                            // epochs after the last one passed
+                            for (_, ban) in bans {
+                                assert_eq!(ban.until_epoch, 5 + DEFAULT_PEER_BAN_EPOCHS);
+                            }
+                        }
+                        DbKeyPrefix::RejectedTransaction => {
+                            let rejected_transactions = dbtx
+                                .find_by_prefix(&RejectedTransactionKeyPrefix)
+                                .await
+                                .collect::<Vec<_>>()
+                                .await;
+                            assert!(
+                                !rejected_transactions.is_empty(),
+                                "validate_migrations was not able to read any RejectedTransactions"
+                            );
+                        }
+                        DbKeyPrefix::LastEpoch => {
+                            assert!(
+                                dbtx.get_value(&LastEpochKey).await.is_some(),
+                                "validate_migrations was not able to read the LastEpoch"
+                            );
+                        }
+                        DbKeyPrefix::ConsensusUpgrade => {
+                            assert!(
+                                dbtx.get_value(&ConsensusUpgradeKey).await.is_some(),
+                                "validate_migrations was not able to read the ConsensusUpgrade"
+                            );
+                        }
+                        // Not covered by the version 0 snapshot
+                        DbKeyPrefix::AcceptedTransaction
+                        | DbKeyPrefix::EpochHistory
+                        | DbKeyPrefix::ClientConfigSignature
+                        | DbKeyPrefix::EpochCheckpoint
+                        | DbKeyPrefix::TlsCertRotation
+                        | DbKeyPrefix::EndpointUpdate
+                        | DbKeyPrefix::PendingEndpointUpdate
+                        | DbKeyPrefix::PeerFault
+                        | DbKeyPrefix::CompletedTlsCertRotation
+                        | DbKeyPrefix::Module => {}
+                    }
+                }
+            },
+            ModuleDecoderRegistry::default(),
+        )
+        .await;
+    }
 }
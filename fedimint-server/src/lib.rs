@@ -24,7 +24,7 @@ use fedimint_core::{NumPeers, PeerId};
 use fedimint_logging::{LOG_CONSENSUS, LOG_CORE};
 use futures::stream::Peekable;
 use futures::{FutureExt, StreamExt};
-use hbbft::honey_badger::{Batch, HoneyBadger, Message, Step};
+use hbbft::honey_badger::{HoneyBadger, Message, Step};
 use hbbft::{Epoched, NetworkInfo, Target};
 use itertools::Itertools;
 use rand::rngs::OsRng;
@@ -32,15 +32,18 @@ use rand::{CryptoRng, RngCore};
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc::Receiver;
 use tokio_stream::wrappers::ReceiverStream;
-use tracing::{error, info, warn};
+use tracing::{error, info, instrument, warn};
 
+use crate::consensus::misbehavior::PeerFault;
 use crate::consensus::{
     ConsensusProposal, FedimintConsensus, HbbftConsensusOutcome, HbbftSerdeConsensusOutcome,
 };
 use crate::db::LastEpochKey;
 use crate::fedimint_core::encoding::Encodable;
 use crate::fedimint_core::net::peers::IPeerConnections;
+use crate::metrics::{CONSENSUS_EPOCH, CONSENSUS_EPOCH_DURATION};
 use crate::net::connect::{Connector, TlsTcpConnector};
+use crate::net::limits::ApiLimiter;
 use crate::net::peers::{PeerConnector, PeerSlice, ReconnectPeerConnections};
 
 /// The actual implementation of the federated mint
@@ -58,6 +61,15 @@ pub mod config;
 /// Implementation of multiplexed peer connections
 pub mod multiplexed;
 
+/// Consistent encrypted backups of the guardian database
+pub mod backup;
+
+/// Consistency checks of the guardian database
+pub mod check;
+
+/// Prometheus metrics of the guardian
+pub mod metrics;
+
 type PeerMessage = (PeerId, EpochMessage);
 
 /// how many epochs ahead of consensus to rejoin
@@ -109,6 +121,8 @@ pub struct FedimintServer {
     pub last_processed_epoch: Option<SignedEpochOutcome>,
     /// Used for decoding module specific-values
     pub decoders: ModuleDecoderRegistry,
+    /// Peers whose messages our connections currently ignore
+    pub banned_peers: BTreeSet<PeerId>,
 }
 
 impl FedimintServer {
@@ -123,14 +137,31 @@ impl FedimintServer {
         let server =
             FedimintServer::new(cfg.clone(), consensus, tx_receiver, decoders, task_group).await;
         let server_consensus = server.consensus.clone();
-        let consensus = server
-            .cfg
-            .consensus
-            .to_config_response(&server_consensus.module_inits);
+        // Includes endpoint updates that were agreed on but not persisted yet
+        let consensus = {
+            let mut dbtx = server_consensus.db.begin_transaction().await;
+            server_consensus
+                .get_config_with_sig(&mut dbtx.get_isolated())
+                .await
+        };
+
+        // Shared by both servers so that limits apply to the API as a whole
+        let limiter = Arc::new(ApiLimiter::new(&cfg.local.api_limits));
+
+        if let Some(bind) = cfg.local.api_http_bind {
+            let server_consensus = server_consensus.clone();
+            let limiter = limiter.clone();
+            task_group
+                .spawn("api-http-server", move |handle| {
+                    net::http::run_http_server(bind, server_consensus, limiter, handle)
+                })
+                .await;
+        }
 
+        let api_task_group = task_group.clone();
         task_group
             .spawn("api-server", |handle| {
-                net::api::run_server(cfg, server_consensus, handle)
+                net::api::run_server(cfg, server_consensus, limiter, api_task_group, handle)
             })
             .await;
 
@@ -162,8 +193,12 @@ impl FedimintServer {
         decoders: ModuleDecoderRegistry,
         task_group: &mut TaskGroup,
     ) -> Self {
-        let connector: PeerConnector<EpochMessage> =
-            TlsTcpConnector::new(cfg.tls_config()).into_dyn();
+        let connector: PeerConnector<EpochMessage> = TlsTcpConnector::with_cert_store(
+            consensus.tls_certs.clone(),
+            cfg.tls_config().peer_names,
+        )
+        .with_socks5_proxy(cfg.local.socks5_proxy)
+        .into_dyn();
 
         Self::new_with(
             cfg.clone(),
@@ -186,6 +221,8 @@ impl FedimintServer {
     ) -> Self {
         cfg.validate_config(&cfg.local.identity, &consensus.module_inits)
             .expect("invalid config");
+        // Restore TLS cert rotations that are in progress
+        consensus.apply_tls_cert_rotations().await;
 
         let connections =
             ReconnectPeerConnections::new(cfg.network_config(), connector, task_group)
@@ -208,7 +245,10 @@ impl FedimintServer {
             .clone()
             .into_iter()
             .map(|(id, node)| (id, node.url));
-        let api = WsFederationApi::new(api_endpoints.collect());
+        let mut api = WsFederationApi::new(api_endpoints.collect());
+        if let Some(proxy) = cfg.local.socks5_proxy {
+            api = api.with_socks5_proxy(proxy);
+        }
 
         FedimintServer {
             task_group: task_group.clone(),
@@ -223,6 +263,7 @@ impl FedimintServer {
             run_empty_epochs: 0,
             last_processed_epoch: None,
             decoders,
+            banned_peers: BTreeSet::new(),
         }
     }
 
@@ -243,6 +284,7 @@ impl FedimintServer {
         self.start_consensus().await;
 
         while !task_handle.is_shutting_down() {
+            let epoch_timer = CONSENSUS_EPOCH_DURATION.start_timer();
             let outcomes = if let Ok(v) = self
                 .run_consensus_epoch(consensus.get_consensus_proposal(), &mut rng)
                 .await
@@ -251,6 +293,7 @@ impl FedimintServer {
             } else {
                 // `None` is supposed to mean the proccess is shutting down
                 debug_assert!(task_handle.is_shutting_down());
+                epoch_timer.stop_and_discard();
                 break;
             };
 
@@ -264,6 +307,7 @@ impl FedimintServer {
                     .await
                     .expect("failed to process epoch");
             }
+            epoch_timer.observe_duration();
 
             if self.consensus.is_at_upgrade_threshold().await {
                 info!(
@@ -311,6 +355,7 @@ impl FedimintServer {
     ///
     /// `last_outcome` - The consensus outcome (unprocessed), we're trying to
     /// process.
+    #[instrument(skip_all, fields(epoch = last_outcome.epoch))]
     pub async fn process_outcome(
         &mut self,
         last_outcome: HbbftConsensusOutcome,
@@ -323,7 +368,8 @@ impl FedimintServer {
 
         let next_epoch_to_process = self.next_epoch_to_process();
         for epoch_num in next_epoch_to_process..=last_outcome.epoch {
-            let (items, epoch, prev_epoch_hash, rejected_txs, at_know_trusted_checkpoint) =
+            // We only know about faults in the epochs we ran ourselves
+            let (items, epoch, prev_epoch_hash, rejected_txs, faults, at_know_trusted_checkpoint) =
                 if epoch_num == last_outcome.epoch {
                     (
                         last_outcome
@@ -337,6 +383,7 @@ impl FedimintServer {
                             .as_ref()
                             .and_then(|epoch| epoch.outcome.consensus_hash().ok()),
                         None,
+                        last_outcome.faults.clone(),
                         true,
                     )
                 } else {
@@ -349,7 +396,12 @@ impl FedimintServer {
                         .api
                         .fetch_epoch_history(epoch_num, epoch_pk, &self.decoders)
                         .await
-                        .expect("fetches history");
+                        .unwrap_or_else(|e| {
+                            if e.is_pruned() {
+                                panic!("Epoch {epoch_num} was pruned by the federation, restore a recent database backup to rejoin");
+                            }
+                            panic!("Failed to fetch epoch {epoch_num}: {e}");
+                        });
 
                     epoch.verify_hash(&prev_epoch)?;
                     prev_epoch = Some(epoch.clone());
@@ -361,24 +413,27 @@ impl FedimintServer {
                         epoch.outcome.epoch,
                         epoch.outcome.last_hash,
                         Some(epoch.outcome.rejected_txs),
+                        vec![],
                         sig_valid,
                     )
                 };
 
-            epochs.push((items, epoch, prev_epoch_hash, rejected_txs));
+            epochs.push((items, epoch, prev_epoch_hash, rejected_txs, faults));
 
             if at_know_trusted_checkpoint {
-                for (items, epoch, _prev_epoch_hash, rejected_txs) in epochs.drain(..) {
+                for (items, epoch, _prev_epoch_hash, rejected_txs, faults) in epochs.drain(..) {
                     let epoch = self
                         .consensus
                         .process_consensus_outcome(
-                            Batch {
+                            HbbftConsensusOutcome {
                                 epoch,
                                 contributions: BTreeMap::from_iter(items.into_iter()),
+                                faults,
                             },
                             rejected_txs.clone(),
                         )
                         .await;
+                    CONSENSUS_EPOCH.set(epoch.outcome.epoch as i64);
                     self.last_processed_epoch = Some(epoch);
                 }
             }
@@ -409,6 +464,7 @@ impl FedimintServer {
             return Ok(vec![HbbftConsensusOutcome {
                 epoch,
                 contributions: BTreeMap::from([(self.cfg.local.identity, proposal.items)]),
+                faults: vec![],
             }]);
         }
 
@@ -425,9 +481,7 @@ impl FedimintServer {
         self.save_txs_to_consensus_cache();
 
         let proposal = proposal.await;
-        for peer in proposal.drop_peers.iter() {
-            self.connections.ban_peer(*peer).await;
-        }
+        self.update_peer_bans(&proposal.drop_peers).await;
         let step = self.propose_epoch(proposal, rng).await?;
         outcomes.append(&mut self.handle_step(step).await?);
 
@@ -464,17 +518,28 @@ impl FedimintServer {
 
         let mut outcomes: Vec<HbbftConsensusOutcome> = vec![];
         for outcome in step.output {
-            let (outcome, ban_peers) =
-                module_parse_outcome(outcome, &self.consensus.modules.decoder_registry());
-            for peer in ban_peers {
-                self.connections.ban_peer(peer).await;
-            }
-            outcomes.push(outcome);
+            outcomes.push(module_parse_outcome(
+                outcome,
+                &self.consensus.modules.decoder_registry(),
+            ));
         }
 
         Ok(outcomes)
     }
 
+    /// Bans the peers in `drop_peers` on our connections and unbans the ones
+    /// whose bans expired or were lifted
+    async fn update_peer_bans(&mut self, drop_peers: &[PeerId]) {
+        let drop_peers: BTreeSet<PeerId> = drop_peers.iter().copied().collect();
+        for peer in drop_peers.difference(&self.banned_peers) {
+            self.connections.ban_peer(*peer).await;
+        }
+        for peer in self.banned_peers.difference(&drop_peers) {
+            self.connections.unban_peer(*peer).await;
+        }
+        self.banned_peers = drop_peers;
+    }
+
     async fn propose_epoch(
         &mut self,
         proposal: ConsensusProposal,
@@ -573,8 +638,8 @@ impl FedimintServer {
 fn module_parse_outcome(
     outcome: HbbftSerdeConsensusOutcome,
     module_registry: &ModuleDecoderRegistry,
-) -> (HbbftConsensusOutcome, Vec<PeerId>) {
-    let mut ban_peers = vec![];
+) -> HbbftConsensusOutcome {
+    let mut faults = vec![];
     let contributions = outcome
         .contributions
         .into_iter()
@@ -591,17 +656,16 @@ fn module_parse_outcome(
                         target: LOG_CONSENSUS,
                         "Received invalid message from peer {}: {}", peer, e
                     );
-                    ban_peers.push(peer);
+                    faults.push((peer, PeerFault::UndecodableContribution));
                     None
                 }
             }
         })
         .collect::<BTreeMap<PeerId, Vec<ConsensusItem>>>();
 
-    let outcome = Batch {
+    HbbftConsensusOutcome {
         epoch: outcome.epoch,
         contributions,
-    };
-
-    (outcome, ban_peers)
+        faults,
+    }
 }
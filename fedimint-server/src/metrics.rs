@@ -0,0 +1,115 @@
+//! Prometheus metrics of the guardian, exposed by `fedimintd` if a metrics
+//! listener is configured
+
+use fedimint_metrics::{
+    histogram_opts, opts, register_histogram_vec_with_registry, register_histogram_with_registry,
+    register_int_counter_vec_with_registry, register_int_gauge_vec_with_registry,
+    register_int_gauge_with_registry, Histogram, HistogramVec, IntCounterVec, IntGauge,
+    IntGaugeVec, NETWORK_LATENCY_BUCKETS, REGISTRY,
+};
+use once_cell::sync::Lazy;
+
+/// Last consensus epoch processed by the guardian
+pub static CONSENSUS_EPOCH: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge_with_registry!(
+        opts!("consensus_epoch", "Last processed consensus epoch"),
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Time from proposing to consensus until the resulting epochs are processed
+pub static CONSENSUS_EPOCH_DURATION: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram_with_registry!(
+        histogram_opts!(
+            "consensus_epoch_duration_seconds",
+            "Duration of running and processing consensus epochs",
+            NETWORK_LATENCY_BUCKETS.to_vec()
+        ),
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Transactions processed by consensus, counted once for every module whose
+/// inputs or outputs they contain. `outcome` is either `accepted` or
+/// `rejected`.
+pub static CONSENSUS_TRANSACTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        opts!(
+            "consensus_transactions_total",
+            "Transactions processed by consensus per module"
+        ),
+        &["module_instance_id", "module_kind", "outcome"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Requests handled by the API, `code` is the JSON-RPC error code or `ok`
+pub static API_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        opts!("api_requests_total", "Requests handled by the API"),
+        &["endpoint", "code"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Requests rejected by the API for exceeding a limit, `limit` is one of
+/// `ip_rate`, `endpoint_rate`, `endpoint_concurrency` or `subscriptions`
+pub static API_REQUESTS_LIMITED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        opts!(
+            "api_requests_limited_total",
+            "Requests rejected by the API for exceeding a limit"
+        ),
+        &["endpoint", "limit"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Duration of handling API requests
+pub static API_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec_with_registry!(
+        histogram_opts!(
+            "api_request_duration_seconds",
+            "Duration of handling API requests",
+            NETWORK_LATENCY_BUCKETS.to_vec()
+        ),
+        &["endpoint"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Whether the connection to a peer is established (`1`) or not (`0`)
+pub static PEER_CONNECTED: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec_with_registry!(
+        opts!("peer_connected", "Connection state of peers"),
+        &["peer_id"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Attempts to reconnect to a peer, `outcome` is either `success` or `failure`
+pub static PEER_RECONNECTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        opts!("peer_reconnects_total", "Attempts to reconnect to peers"),
+        &["peer_id", "outcome"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Balance of each module according to the audit after the last epoch
+pub static AUDIT_MSATS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec_with_registry!(
+        opts!("audit_msats", "Audited balance of each module"),
+        &["module_instance_id", "module_kind"],
+        REGISTRY
+    )
+    .unwrap()
+});
@@ -1,34 +1,52 @@
 //! Implements the client API through which users interact with the federation
+use std::collections::BTreeMap;
 use std::fmt::Formatter;
+use std::net::SocketAddr;
 use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::Context;
+use bitcoin_hashes::hex::ToHex;
+use fedimint_core::api::unsubscribe_method;
 use fedimint_core::config::ConfigResponse;
 use fedimint_core::core::ModuleInstanceId;
 use fedimint_core::epoch::SerdeEpochHistory;
-use fedimint_core::module::{api_endpoint, ApiEndpoint, ApiError};
-use fedimint_core::outcome::TransactionStatus;
+use fedimint_core::module::{api_endpoint, ApiEndpoint, ApiError, ApiRequest};
+use fedimint_core::outcome::{OutputStatus, TransactionStatus};
 use fedimint_core::server::DynServerModule;
-use fedimint_core::task::TaskHandle;
-use fedimint_core::TransactionId;
+use fedimint_core::task::{TaskGroup, TaskHandle};
+use fedimint_core::{OutPoint, PeerId, TransactionId};
 use fedimint_logging::LOG_NET_API;
-use futures::FutureExt;
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
+use futures::{Future, FutureExt, StreamExt};
 use jsonrpsee::server::ServerBuilder;
 use jsonrpsee::types::error::CallError;
 use jsonrpsee::types::ErrorObject;
 use jsonrpsee::RpcModule;
-use tracing::{debug, error};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tracing::{debug, error, info_span, Instrument, Span};
+use url::Url;
 
+use crate::backup::DatabaseBackup;
 use crate::config::ServerConfig;
+use crate::consensus::misbehavior::PeerMisbehavior;
 use crate::consensus::FedimintConsensus;
+use crate::metrics::{API_REQUESTS, API_REQUEST_DURATION};
+use crate::net::limits::ApiLimiter;
+use crate::net::ws_proxy::{client_ip, run_ws_proxy};
 use crate::transaction::SerdeTransaction;
 
 /// A state of fedimint server passed to each rpc handler callback
 #[derive(Clone)]
 pub struct RpcHandlerCtx {
     fedimint: Arc<FedimintConsensus>,
+    limiter: Arc<ApiLimiter>,
+    subscriptions: mpsc::UnboundedSender<BoxFuture<'static, ()>>,
 }
 
 impl std::fmt::Debug for RpcHandlerCtx {
@@ -40,28 +58,71 @@ impl std::fmt::Debug for RpcHandlerCtx {
 pub async fn run_server(
     cfg: ServerConfig,
     fedimint: Arc<FedimintConsensus>,
+    limiter: Arc<ApiLimiter>,
+    mut task_group: TaskGroup,
     task_handle: TaskHandle,
 ) {
+    let (subscriptions, new_subscriptions) = mpsc::unbounded_channel();
+    task_group
+        .spawn("api-subscriptions", |handle| {
+            run_subscriptions(new_subscriptions, handle)
+        })
+        .await;
+
     let state = RpcHandlerCtx {
         fedimint: fedimint.clone(),
+        limiter,
+        subscriptions,
     };
     let mut rpc_module = RpcModule::new(state);
 
     attach_endpoints(&mut rpc_module, server_endpoints(), None);
+    for (path, status) in subscriptions() {
+        attach_subscription(&mut rpc_module, path, status);
+    }
 
     for (id, module) in fedimint.modules.iter_modules() {
         attach_endpoints_erased(&mut rpc_module, id, module);
     }
 
     debug!(addr = cfg.local.api_bind.to_string(), "Starting WSServer");
-    let server = ServerBuilder::new()
+    let mut server = ServerBuilder::new()
         .max_connections(cfg.local.max_connections)
-        .ping_interval(Duration::from_secs(10))
-        .build(&cfg.local.api_bind.to_string())
+        .ping_interval(Duration::from_secs(10));
+    if let Some(max) = cfg.local.api_limits.max_subscriptions_per_client {
+        server = server.max_subscriptions_per_connection(max);
+    }
+    // Clients connect to the proxy instead if there are limits that depend on
+    // their connection or IP, which only it knows
+    let limits = &cfg.local.api_limits;
+    let use_proxy = limits.requests_per_connection_per_minute.is_some()
+        || limits.requests_per_ip_per_minute.is_some()
+        || limits.max_subscriptions_per_client.is_some();
+    let bind = if use_proxy {
+        SocketAddr::from(([127, 0, 0, 1], 0))
+    } else {
+        cfg.local.api_bind
+    };
+    let server = server
+        .build(&bind.to_string())
         .await
-        .context(format!("Bind address: {}", cfg.local.api_bind))
+        .context(format!("Bind address: {bind}"))
         .expect("Could not start API server");
 
+    if use_proxy {
+        let listener = TcpListener::bind(cfg.local.api_bind)
+            .await
+            .context(format!("Bind address: {}", cfg.local.api_bind))
+            .expect("Could not start API server");
+        let upstream = server.local_addr().expect("API server is bound");
+        let limits = Arc::new(cfg.local.api_limits.clone());
+        task_group
+            .spawn("api-ws-proxy", move |handle| {
+                run_ws_proxy(listener, upstream, limits, handle)
+            })
+            .await;
+    }
+
     let server_handle = server
         .start(rpc_module)
         .expect("Could not start API server");
@@ -80,7 +141,27 @@ pub async fn run_server(
     server_handle.stopped().await
 }
 
-const API_ENDPOINT_TIMEOUT: Duration = Duration::from_secs(60);
+/// Drives the subscriptions of all clients until the server shuts down,
+/// dropping their sinks disconnects the remaining subscribers
+async fn run_subscriptions(
+    mut new_subscriptions: mpsc::UnboundedReceiver<BoxFuture<'static, ()>>,
+    task_handle: TaskHandle,
+) {
+    let mut shutdown_rx = task_handle.make_shutdown_rx().await;
+    let mut subscriptions = FuturesUnordered::new();
+    loop {
+        tokio::select! {
+            subscription = new_subscriptions.recv() => match subscription {
+                Some(subscription) => subscriptions.push(subscription),
+                None => return,
+            },
+            Some(()) = subscriptions.next(), if !subscriptions.is_empty() => {}
+            _ = &mut shutdown_rx => return,
+        }
+    }
+}
+
+pub(crate) const API_ENDPOINT_TIMEOUT: Duration = Duration::from_secs(60);
 
 // TODO: remove once modularized
 fn attach_endpoints(
@@ -103,15 +184,21 @@ fn attach_endpoints(
 
         rpc_module
             .register_async_method(path, move |params, state| async move {
+                let client = client_ip(&params);
                 let params = params.one::<serde_json::Value>()?;
                 let fedimint = &state.fedimint;
+                let _permit = state
+                    .limiter
+                    .admit(path, client, false)
+                    .map_err(api_error)?;
 
+                let start = Instant::now();
                 let dbtx = fedimint.db.begin_transaction().await;
                 // Using AssertUnwindSafe here is far from ideal. In theory this means we could
                 // end up with an inconsistent state in theory. In practice most API functions
                 // are only reading and the few that do write anything are atomic. Lastly, this
                 // is only the last line of defense
-                AssertUnwindSafe(tokio::time::timeout(
+                let result = AssertUnwindSafe(tokio::time::timeout(
                     API_ENDPOINT_TIMEOUT,
                     (handler)(
                         fedimint,
@@ -119,7 +206,8 @@ fn attach_endpoints(
                         params,
                         module_instance_id,
                         fedimint.cfg.private.api_auth.clone(),
-                    ),
+                    )
+                    .instrument(api_request_span(path, module_instance_id)),
                 ))
                 .catch_unwind()
                 .await
@@ -133,15 +221,15 @@ fn attach_endpoints(
                         "API handler panicked",
                         None::<()>,
                     )))
-                })?
-                .map_err(|tokio::time::error::Elapsed { .. }| {
-                    jsonrpsee::core::Error::RequestTimeout
-                })?
-                .map_err(|e| {
-                    jsonrpsee::core::Error::Call(CallError::Custom(ErrorObject::owned(
-                        e.code, e.message, None::<()>,
-                    )))
                 })
+                .and_then(|res| {
+                    res.map_err(|tokio::time::error::Elapsed { .. }| {
+                        jsonrpsee::core::Error::RequestTimeout
+                    })
+                })
+                .and_then(|res| res.map_err(api_error));
+                observe_api_request(path, start, &result);
+                result
             })
             .expect("Failed to register async method");
     }
@@ -164,14 +252,20 @@ fn attach_endpoints_erased(
         rpc_module
             .register_async_method(path, move |params, state| async move {
                 // Hack to avoid Sync/Send issues
+                let client = client_ip(&params);
                 let params = params.one::<serde_json::Value>()?;
                 let fedimint = &state.fedimint;
+                let _permit = state
+                    .limiter
+                    .admit(path, client, false)
+                    .map_err(api_error)?;
+                let start = Instant::now();
                 let dbtx = fedimint.db.begin_transaction().await;
                 // Using AssertUnwindSafe here is far from ideal. In theory this means we could
                 // end up with an inconsistent state in theory. In practice most API functions
                 // are only reading and the few that do write anything are atomic. Lastly, this
                 // is only the last line of defense
-                AssertUnwindSafe(tokio::time::timeout(
+                let result = AssertUnwindSafe(tokio::time::timeout(
                     API_ENDPOINT_TIMEOUT,
                     (handler)(
                         fedimint.modules.get_expect(module_instance),
@@ -179,7 +273,8 @@ fn attach_endpoints_erased(
                         params,
                         Some(module_instance),
                         fedimint.cfg.private.api_auth.clone(),
-                    ),
+                    )
+                    .instrument(api_request_span(path, Some(module_instance))),
                 ))
                 .catch_unwind()
                 .await
@@ -193,21 +288,245 @@ fn attach_endpoints_erased(
                         "API handler panicked",
                         None::<()>,
                     )))
-                })?
-                .map_err(|tokio::time::error::Elapsed { .. }| {
-                    jsonrpsee::core::Error::RequestTimeout
-                })?
-                .map_err(|e| {
-                    jsonrpsee::core::Error::Call(CallError::Custom(ErrorObject::owned(
-                        e.code, e.message, None::<()>,
-                    )))
                 })
+                .and_then(|res| {
+                    res.map_err(|tokio::time::error::Elapsed { .. }| {
+                        jsonrpsee::core::Error::RequestTimeout
+                    })
+                })
+                .and_then(|res| res.map_err(api_error));
+                observe_api_request(path, start, &result);
+                result
             })
             .expect("Failed to register async method");
     }
 }
 
-fn server_endpoints() -> Vec<ApiEndpoint<FedimintConsensus>> {
+/// Computes the result pushed to subscribers from the request of the
+/// subscriber, `None` if there is nothing to push yet
+pub(crate) type SubscriptionStatus = Arc<
+    dyn Fn(
+            Arc<FedimintConsensus>,
+            serde_json::Value,
+        ) -> BoxFuture<'static, Result<Option<serde_json::Value>, ApiError>>
+        + Send
+        + Sync,
+>;
+
+/// Subscriptions through which clients get results pushed instead of polling
+/// for them
+pub(crate) fn subscriptions() -> Vec<(&'static str, SubscriptionStatus)> {
+    vec![
+        subscription(
+            "/subscribe_transaction",
+            |fedimint, txid: TransactionId| async move { Ok(fedimint.transaction_status(txid).await) },
+        ),
+        subscription(
+            "/subscribe_output_outcome",
+            |fedimint, out_point: OutPoint| async move {
+                Ok(match fedimint.transaction_status(out_point.txid).await {
+                    None => None,
+                    Some(TransactionStatus::Rejected(e)) => Some(OutputStatus::Rejected(e)),
+                    Some(TransactionStatus::Accepted { outputs, .. }) => {
+                        let outcome = outputs
+                            .into_iter()
+                            .nth(out_point.out_idx as usize)
+                            .ok_or_else(|| {
+                                ApiError::bad_request("Invalid output index".to_string())
+                            })?;
+                        Some(OutputStatus::Accepted(outcome))
+                    }
+                })
+            },
+        ),
+        subscription("/subscribe_epoch_count", |fedimint, _v: ()| async move {
+            Ok(Some(fedimint.get_epoch_count().await))
+        }),
+    ]
+}
+
+fn subscription<P, R, F, Fut>(path: &'static str, status: F) -> (&'static str, SubscriptionStatus)
+where
+    P: DeserializeOwned,
+    R: Serialize,
+    F: Fn(Arc<FedimintConsensus>, P) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Option<R>, ApiError>> + Send + 'static,
+{
+    let status: SubscriptionStatus = Arc::new(
+        move |fedimint, request| match serde_json::from_value::<ApiRequest<P>>(request) {
+            Ok(request) => {
+                let status = status(fedimint, request.params);
+                Box::pin(async move {
+                    Ok(status.await?.map(|result| {
+                        serde_json::to_value(result)
+                            .expect("Serialization of the result must not fail")
+                    }))
+                })
+            }
+            Err(e) => Box::pin(futures::future::ready(Err(ApiError::bad_request(
+                e.to_string(),
+            )))),
+        },
+    );
+    (path, status)
+}
+
+/// Waits until `status` returns a result that differs from `last`, checking
+/// again after every processed epoch
+pub(crate) async fn next_status(
+    fedimint: &Arc<FedimintConsensus>,
+    status: &SubscriptionStatus,
+    request: &serde_json::Value,
+    last: Option<&serde_json::Value>,
+) -> Result<serde_json::Value, ApiError> {
+    let mut epochs = fedimint.subscribe_epoch_count();
+    loop {
+        if let Some(result) = status(fedimint.clone(), request.clone()).await? {
+            if last != Some(&result) {
+                return Ok(result);
+            }
+        }
+
+        if epochs.changed().await.is_err() {
+            return Err(ApiError::new(503, "Server is shutting down".to_string()));
+        }
+    }
+}
+
+/// Registers a subscription at `path` that pushes the result of `status`
+/// whenever it changed. Subscribers are disconnected once it fails.
+fn attach_subscription(
+    rpc_module: &mut RpcModule<RpcHandlerCtx>,
+    path: &'static str,
+    status: SubscriptionStatus,
+) {
+    // These memory leaks are fine because they only happen on server startup
+    let notification: &'static _ = Box::leak(format!("{path}/notification").into_boxed_str());
+    let unsubscribe: &'static _ = Box::leak(unsubscribe_method(path).into_boxed_str());
+
+    rpc_module
+        .register_subscription(
+            path,
+            notification,
+            unsubscribe,
+            move |params, mut sink, state| {
+                let request = match params.one::<serde_json::Value>() {
+                    Ok(request) => request,
+                    Err(e) => {
+                        let _ = sink.reject(ErrorObject::owned(400, e.to_string(), None::<()>));
+                        return Ok(());
+                    }
+                };
+                let permit = match state.limiter.admit(path, client_ip(&params), true) {
+                    Ok(permit) => permit,
+                    Err(e) => {
+                        let _ = sink.reject(ErrorObject::owned(e.code, e.message, None::<()>));
+                        return Ok(());
+                    }
+                };
+                if state.subscriptions.is_closed() {
+                    let _ = sink.reject(ErrorObject::owned(
+                        503,
+                        "Server is shutting down",
+                        None::<()>,
+                    ));
+                    return Ok(());
+                }
+                let fedimint = state.fedimint.clone();
+                let status = status.clone();
+
+                let subscription = async move {
+                    let _permit = permit;
+                    if sink.accept().is_err() {
+                        return;
+                    }
+
+                    let mut last = None;
+                    loop {
+                        let next = tokio::select! {
+                            next = next_status(&fedimint, &status, &request, last.as_ref()) => next,
+                            _ = sink.closed() => return,
+                        };
+                        match next {
+                            Ok(result) => {
+                                if !matches!(sink.send(&result), Ok(true)) {
+                                    return;
+                                }
+                                last = Some(result);
+                            }
+                            Err(e) => {
+                                sink.close(ErrorObject::owned(e.code, e.message, None::<()>));
+                                return;
+                            }
+                        }
+                    }
+                }
+                .instrument(api_request_span(path, None));
+                // Only fails if the server is shutting down, dropping the sink then
+                // disconnects the subscriber
+                let _ = state.subscriptions.send(subscription.boxed());
+                Ok(())
+            },
+        )
+        .expect("Failed to register subscription");
+}
+
+fn api_error(e: ApiError) -> jsonrpsee::core::Error {
+    jsonrpsee::core::Error::Call(CallError::Custom(ErrorObject::owned(
+        e.code, e.message, None::<()>,
+    )))
+}
+
+/// Span of handling a request to the API endpoint `path`
+pub(crate) fn api_request_span(path: &str, module_instance_id: Option<ModuleInstanceId>) -> Span {
+    info_span!(
+        target: LOG_NET_API,
+        "api_request",
+        endpoint = path,
+        module_instance_id
+    )
+}
+
+/// Records the outcome and duration of a request to the API endpoint `path`
+fn observe_api_request(
+    path: &str,
+    start: Instant,
+    result: &Result<serde_json::Value, jsonrpsee::core::Error>,
+) {
+    let code = match result {
+        Ok(_) => "ok".to_string(),
+        Err(jsonrpsee::core::Error::Call(CallError::Custom(e))) => e.code().to_string(),
+        Err(jsonrpsee::core::Error::RequestTimeout) => "timeout".to_string(),
+        Err(_) => "error".to_string(),
+    };
+    observe_api_request_code(path, start, &code);
+}
+
+/// Records the outcome `code` and duration of a request to the API endpoint
+/// `path`
+pub(crate) fn observe_api_request_code(path: &str, start: Instant, code: &str) {
+    API_REQUESTS.with_label_values(&[path, code]).inc();
+    API_REQUEST_DURATION
+        .with_label_values(&[path])
+        .observe(start.elapsed().as_secs_f64());
+}
+
+/// Takes a backup of the database encrypted with the backup key, hex encoded
+/// like the backup files written by dbtool
+pub(crate) async fn encrypted_backup(fedimint: &FedimintConsensus) -> Result<String, ApiError> {
+    let key = fedimint
+        .backup_key()
+        .ok_or_else(|| ApiError::not_found("Backups are not enabled".to_string()))?;
+
+    let backup =
+        DatabaseBackup::export(&fedimint.db, fedimint.cfg.consensus.modules.keys().copied()).await;
+    let encrypted = backup
+        .encrypt(key)
+        .map_err(|e| ApiError::new(500, e.to_string()))?;
+    Ok(encrypted.to_hex())
+}
+
+pub(crate) fn server_endpoints() -> Vec<ApiEndpoint<FedimintConsensus>> {
     vec![
         api_endpoint! {
             "/transaction",
@@ -250,8 +569,13 @@ fn server_endpoints() -> Vec<ApiEndpoint<FedimintConsensus>> {
         api_endpoint! {
             "/fetch_epoch_history",
             async |fedimint: &FedimintConsensus, _dbtx, epoch: u64| -> SerdeEpochHistory {
-                let epoch = fedimint.epoch_history(epoch).await.ok_or_else(|| ApiError::not_found(String::from("epoch not found")))?;
-                Ok((&epoch).into())
+                if let Some(epoch) = fedimint.epoch_history(epoch).await {
+                    return Ok((&epoch).into());
+                }
+                if fedimint.epoch_checkpoint(epoch).await.is_some() {
+                    return Err(ApiError::pruned(format!("epoch {epoch} was pruned")));
+                }
+                Err(ApiError::not_found(String::from("epoch not found")))
             }
         },
         api_endpoint! {
@@ -266,5 +590,177 @@ fn server_endpoints() -> Vec<ApiEndpoint<FedimintConsensus>> {
                 Ok(fedimint.get_config_with_sig(dbtx).await)
             }
         },
+        api_endpoint! {
+            "/backup",
+            async |fedimint: &FedimintConsensus, _dbtx, _v: (), has_auth| -> String {
+                if !has_auth {
+                    return Err(ApiError::unauthorized());
+                }
+
+                encrypted_backup(fedimint).await
+            }
+        },
+        api_endpoint! {
+            "/rotate_tls_cert",
+            async |fedimint: &FedimintConsensus, _dbtx, _v: (), has_auth| -> u64 {
+                if !has_auth {
+                    return Err(ApiError::unauthorized());
+                }
+
+                fedimint.rotate_tls_cert().await.map_err(|e| ApiError::bad_request(e.to_string()))
+            }
+        },
+        api_endpoint! {
+            "/update_endpoint",
+            async |fedimint: &FedimintConsensus, _dbtx, urls: (Url, Url), has_auth| -> () {
+                if !has_auth {
+                    return Err(ApiError::unauthorized());
+                }
+
+                let (api_url, p2p_url) = urls;
+                fedimint.update_endpoint(api_url, p2p_url).await;
+                Ok(())
+            }
+        },
+        api_endpoint! {
+            "/peer_misbehavior",
+            async |fedimint: &FedimintConsensus, _dbtx, _v: (), has_auth| -> BTreeMap<PeerId, PeerMisbehavior> {
+                if !has_auth {
+                    return Err(ApiError::unauthorized());
+                }
+
+                Ok(fedimint.peer_misbehavior().await)
+            }
+        },
+        api_endpoint! {
+            "/unban_peer",
+            async |fedimint: &FedimintConsensus, _dbtx, peer: PeerId, has_auth| -> () {
+                if !has_auth {
+                    return Err(ApiError::unauthorized());
+                }
+
+                fedimint.unban_peer(peer).await;
+                Ok(())
+            }
+        },
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use fedimint_core::api::{IFederationApi, WsFederationApi};
+    use fedimint_core::config::{ConfigGenParams, ServerModuleGenRegistry};
+    use fedimint_core::db::mem_impl::MemDatabase;
+    use fedimint_core::db::Database;
+    use fedimint_core::module::registry::{ModuleDecoderRegistry, ModuleRegistry};
+    use fedimint_core::module::ApiRequestErased;
+    use fedimint_core::outcome::{OutputStatus, TransactionStatus};
+    use fedimint_core::task::{sleep, TaskGroup};
+    use fedimint_core::{BitcoinHash, OutPoint, PeerId, TransactionId};
+    use futures::StreamExt;
+    use serde_json::{json, Value};
+
+    use super::run_server;
+    use crate::config::{ServerConfig, ServerConfigParams};
+    use crate::consensus::FedimintConsensus;
+    use crate::db::{EpochHistoryKey, LastEpochKey, RejectedTransactionKey};
+    use crate::net::limits::ApiLimiter;
+
+    #[test_log::test(tokio::test)]
+    async fn subscriptions_push_changed_results() {
+        let peer = PeerId::from(0);
+        let params =
+            ServerConfigParams::gen_local(&[peer], 18200, "test", ConfigGenParams::new()).unwrap();
+        let mut cfg = ServerConfig::trusted_dealer_gen(&params, ServerModuleGenRegistry::new())
+            .remove(&peer)
+            .unwrap();
+        cfg.local.api_bind = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let url = format!("ws://{}", cfg.local.api_bind).parse().unwrap();
+
+        let db = Database::new(MemDatabase::new(), ModuleDecoderRegistry::default());
+        let (fedimint, _tx_receiver) = FedimintConsensus::new_with_modules(
+            cfg.clone(),
+            db.clone(),
+            ServerModuleGenRegistry::new(),
+            ModuleRegistry::default(),
+        );
+        let fedimint = Arc::new(fedimint);
+        let limiter = Arc::new(ApiLimiter::new(&cfg.local.api_limits));
+
+        let mut task_group = TaskGroup::new();
+        let server_task_group = task_group.clone();
+        let server_fedimint = fedimint.clone();
+        task_group
+            .spawn("api-server", |handle| {
+                run_server(cfg, server_fedimint, limiter, server_task_group, handle)
+            })
+            .await;
+
+        let api = WsFederationApi::new(vec![(peer, url)]);
+        let subscribe = |method: &'static str, params: Value| {
+            let api = &api;
+            async move {
+                let request = [ApiRequestErased::new(params).to_json()];
+                // The server might still be starting up
+                loop {
+                    match api.subscribe_raw(peer, method, &request).await {
+                        Ok(subscription) => return subscription,
+                        Err(_) => sleep(Duration::from_millis(10)).await,
+                    }
+                }
+            }
+        };
+        let txid = TransactionId::from_inner([1; 32]);
+        let mut transaction = subscribe("/subscribe_transaction", json!(txid)).await;
+        let mut output = subscribe(
+            "/subscribe_output_outcome",
+            json!(OutPoint { txid, out_idx: 0 }),
+        )
+        .await;
+        let mut epoch_count = subscribe("/subscribe_epoch_count", json!(null)).await;
+
+        // The epoch count is pushed right away, the transaction is still unknown
+        assert_eq!(epoch_count.next().await.unwrap().unwrap(), json!(0));
+
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_new_entry(&RejectedTransactionKey(txid), &"invalid".to_string())
+            .await;
+        dbtx.insert_new_entry(&LastEpochKey, &EpochHistoryKey(0))
+            .await;
+        dbtx.commit_tx().await;
+        fedimint.notify_epoch_count(1);
+
+        assert_eq!(
+            transaction.next().await.unwrap().unwrap(),
+            json!(TransactionStatus::Rejected("invalid".to_string()))
+        );
+        assert_eq!(
+            output.next().await.unwrap().unwrap(),
+            json!(OutputStatus::Rejected("invalid".to_string()))
+        );
+        assert_eq!(epoch_count.next().await.unwrap().unwrap(), json!(1));
+
+        // Results that did not change are not pushed again
+        let mut dbtx = db.begin_transaction().await;
+        dbtx.insert_entry(&LastEpochKey, &EpochHistoryKey(1)).await;
+        dbtx.commit_tx().await;
+        fedimint.notify_epoch_count(2);
+
+        assert_eq!(epoch_count.next().await.unwrap().unwrap(), json!(2));
+        assert!(futures::poll!(transaction.next()).is_pending());
+
+        // Shutting down disconnects the subscribers
+        task_group
+            .shutdown_join_all(None)
+            .await
+            .expect("API server shuts down cleanly");
+        assert!(!matches!(transaction.next().await, Some(Ok(_))));
+    }
+}
@@ -6,10 +6,11 @@ use std::convert::TryFrom;
 use std::fmt::Debug;
 use std::net::SocketAddr;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 use anyhow::format_err;
 use async_trait::async_trait;
+use fedimint_core::net::socks::socks5_connect;
 use fedimint_core::PeerId;
 use futures::Stream;
 use tokio::io::{ReadHalf, WriteHalf};
@@ -59,13 +60,10 @@ pub trait Connector<M> {
 /// TCP connector with encryption and authentication
 #[derive(Debug)]
 pub struct TlsTcpConnector {
-    our_certificate: rustls::Certificate,
-    our_private_key: rustls::PrivateKey,
     peer_certs: Arc<PeerCertStore>,
-    /// Copy of the certs from `peer_certs`, but in a format that `tokio_rustls`
-    /// understands
-    cert_store: RootCertStore,
     peer_names: HashMap<PeerId, String>,
+    /// Dial peers through this SOCKS5 proxy, e.g. Tor
+    socks5_proxy: Option<SocketAddr>,
 }
 
 #[derive(Debug, Clone)]
@@ -76,39 +74,124 @@ pub struct TlsConfig {
     pub peer_names: HashMap<PeerId, String>,
 }
 
-#[derive(Debug, Clone)]
+/// Our TLS identity and the certificates we accept from peers, which can be
+/// rotated while the connector is running
+#[derive(Debug)]
 pub struct PeerCertStore {
-    peer_certificates: Vec<(PeerId, rustls::Certificate)>,
+    identity: RwLock<(rustls::Certificate, rustls::PrivateKey)>,
+    /// A peer may have several certificates while it rotates to a new one
+    peer_certificates: RwLock<Vec<(PeerId, rustls::Certificate)>>,
 }
 
 impl TlsTcpConnector {
     pub fn new(cfg: TlsConfig) -> TlsTcpConnector {
-        let mut cert_store = RootCertStore::empty();
-        for (_, cert) in cfg.peer_certs.iter() {
-            cert_store
-                .add(cert)
-                .expect("Could not add peer certificate");
-        }
+        Self::with_cert_store(Arc::new(PeerCertStore::new(&cfg)), cfg.peer_names)
+    }
 
+    /// Creates a connector that uses the certificates of a shared store
+    pub fn with_cert_store(
+        peer_certs: Arc<PeerCertStore>,
+        peer_names: HashMap<PeerId, String>,
+    ) -> TlsTcpConnector {
         TlsTcpConnector {
-            our_certificate: cfg.our_certificate,
-            our_private_key: cfg.our_private_key,
-            peer_certs: Arc::new(PeerCertStore::new(cfg.peer_certs)),
-            cert_store,
-            peer_names: cfg.peer_names,
+            peer_certs,
+            peer_names,
+            socks5_proxy: None,
         }
     }
+
+    /// Dials peers through the given SOCKS5 proxy, which allows using onion
+    /// addresses as peer urls
+    pub fn with_socks5_proxy(mut self, proxy: Option<SocketAddr>) -> TlsTcpConnector {
+        self.socks5_proxy = proxy;
+        self
+    }
+
+    async fn dial(&self, destination: Url) -> anyhow::Result<TcpStream> {
+        Ok(match self.socks5_proxy {
+            Some(proxy) => {
+                let host = destination
+                    .host_str()
+                    .ok_or_else(|| format_err!("Missing host in {destination}"))?;
+                let port = destination
+                    .port()
+                    .ok_or_else(|| format_err!("Missing port in {destination}"))?;
+                socks5_connect(proxy, host, port).await?
+            }
+            None => TcpStream::connect(parse_host_port(destination)?).await?,
+        })
+    }
 }
 
 impl PeerCertStore {
-    fn new(certs: impl IntoIterator<Item = (PeerId, rustls::Certificate)>) -> PeerCertStore {
+    pub fn new(cfg: &TlsConfig) -> PeerCertStore {
         PeerCertStore {
-            peer_certificates: certs.into_iter().collect(),
+            identity: RwLock::new((cfg.our_certificate.clone(), cfg.our_private_key.clone())),
+            peer_certificates: RwLock::new(
+                cfg.peer_certs
+                    .iter()
+                    .map(|(peer, cert)| (*peer, cert.clone()))
+                    .collect(),
+            ),
         }
     }
 
+    /// Replaces the certificate and key we authenticate ourselves with
+    pub fn set_identity(&self, cert: rustls::Certificate, key: rustls::PrivateKey) {
+        *self.identity.write().expect("lock poisoned") = (cert, key);
+    }
+
+    /// Accepts `cert` from `peer` in addition to its other certificates
+    pub fn add_cert(&self, peer: PeerId, cert: rustls::Certificate) {
+        let mut peer_certificates = self.peer_certificates.write().expect("lock poisoned");
+        if !peer_certificates.contains(&(peer, cert.clone())) {
+            peer_certificates.push((peer, cert));
+        }
+    }
+
+    /// Stops accepting any certificate of `peer` other than `cert`
+    pub fn retain_cert(&self, peer: PeerId, cert: &rustls::Certificate) {
+        self.peer_certificates
+            .write()
+            .expect("lock poisoned")
+            .retain(|(other_peer, other_cert)| *other_peer != peer || other_cert == cert);
+    }
+
+    /// Certificates of all peers in a format that `tokio_rustls` understands
+    fn root_cert_store(&self) -> RootCertStore {
+        let mut cert_store = RootCertStore::empty();
+        for (_, cert) in self.peer_certificates.read().expect("lock poisoned").iter() {
+            cert_store
+                .add(cert)
+                .expect("Could not add peer certificate");
+        }
+        cert_store
+    }
+
+    fn client_config(&self) -> rustls::ClientConfig {
+        let (our_certificate, our_private_key) =
+            self.identity.read().expect("lock poisoned").clone();
+        rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(self.root_cert_store())
+            .with_single_cert(vec![our_certificate], our_private_key)
+            .expect("Failed to create TLS config")
+    }
+
+    fn server_config(&self) -> Result<rustls::ServerConfig, anyhow::Error> {
+        let (our_certificate, our_private_key) =
+            self.identity.read().expect("lock poisoned").clone();
+        let verifier = AllowAnyAuthenticatedClient::new(self.root_cert_store());
+        Ok(rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(vec![our_certificate], our_private_key)?)
+    }
+
     fn get_peer_by_cert(&self, cert: &rustls::Certificate) -> Option<PeerId> {
         self.peer_certificates
+            .read()
+            .expect("lock poisoned")
             .iter()
             .find_map(|(peer, peer_cert)| if peer_cert == cert { Some(*peer) } else { None })
     }
@@ -136,12 +219,13 @@ impl PeerCertStore {
     async fn accept_connection<M>(
         &self,
         listener: &mut TcpListener,
-        acceptor: &TlsAcceptor,
     ) -> Result<(PeerId, AnyFramedTransport<M>), anyhow::Error>
     where
         M: Debug + serde::Serialize + serde::de::DeserializeOwned + Send + Unpin + 'static,
     {
         let (connection, _) = listener.accept().await?;
+        // Certificates may have been rotated since the last connection
+        let acceptor = TlsAcceptor::from(Arc::new(self.server_config()?));
         let tls_conn = acceptor.accept(connection).await?;
 
         let (_, tls_session) = tls_conn.get_ref();
@@ -162,24 +246,14 @@ where
     M: Debug + serde::Serialize + serde::de::DeserializeOwned + Send + Unpin + 'static,
 {
     async fn connect_framed(&self, destination: Url, peer: PeerId) -> ConnectResult<M> {
-        let cfg = rustls::ClientConfig::builder()
-            .with_safe_defaults()
-            .with_root_certificates(self.cert_store.clone())
-            .with_single_cert(
-                vec![self.our_certificate.clone()],
-                self.our_private_key.clone(),
-            )
-            .expect("Failed to create TLS config");
+        let cfg = self.peer_certs.client_config();
 
         let fake_domain = rustls::ServerName::try_from(self.peer_names[&peer].as_str())
             .expect("Always a valid DNS name");
 
         let connector = TlsConnector::from(Arc::new(cfg));
         let tls_conn = connector
-            .connect(
-                fake_domain,
-                TcpStream::connect(parse_host_port(destination)?).await?,
-            )
+            .connect(fake_domain, self.dial(destination).await?)
             .await?;
 
         let (_, tls_session) = tls_conn.get_ref();
@@ -201,24 +275,16 @@ where
     }
 
     async fn listen(&self, bind_addr: SocketAddr) -> Result<ConnectionListener<M>, anyhow::Error> {
-        let verifier = AllowAnyAuthenticatedClient::new(self.cert_store.clone());
-        let config = rustls::ServerConfig::builder()
-            .with_safe_defaults()
-            .with_client_cert_verifier(verifier)
-            .with_single_cert(
-                vec![self.our_certificate.clone()],
-                self.our_private_key.clone(),
-            )
-            .unwrap();
+        // Fail early if our identity is unusable
+        self.peer_certs.server_config()?;
         let listener = TcpListener::bind(bind_addr).await?;
         let peer_certs = self.peer_certs.clone();
 
         let stream = futures::stream::unfold(listener, move |mut listener| {
-            let acceptor = TlsAcceptor::from(Arc::new(config.clone()));
             let peer_certs = peer_certs.clone();
 
             Box::pin(async move {
-                let res = peer_certs.accept_connection(&mut listener, &acceptor).await;
+                let res = peer_certs.accept_connection(&mut listener).await;
                 Some((res, listener))
             })
         });
@@ -436,14 +502,17 @@ pub mod mock {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
     use std::net::SocketAddr;
+    use std::sync::Arc;
 
+    use fedimint_core::net::socks::mock::MockSocks5Proxy;
     use fedimint_core::PeerId;
     use futures::{SinkExt, StreamExt};
     use url::Url;
 
     use crate::config::gen_cert_and_key;
-    use crate::net::connect::{ConnectionListener, TlsConfig};
+    use crate::net::connect::{ConnectionListener, PeerCertStore, TlsConfig};
     use crate::net::framed::AnyFramedTransport;
     use crate::{Connector, TlsTcpConnector};
 
@@ -622,4 +691,75 @@ mod tests {
             server_task.await.unwrap();
         }
     }
+
+    #[tokio::test]
+    async fn connect_rotated_cert() {
+        let bind_addr: SocketAddr = "127.0.0.1:7002".parse().unwrap();
+        let url: Url = "ws://127.0.0.1:7002".parse().unwrap();
+        let cfg = gen_connector_config(3);
+        let rotating_peer = PeerId::from(2);
+
+        let server_certs = Arc::new(PeerCertStore::new(&cfg[0]));
+        let server =
+            TlsTcpConnector::with_cert_store(server_certs.clone(), cfg[0].peer_names.clone());
+        let client_certs = Arc::new(PeerCertStore::new(&cfg[2]));
+        let client =
+            TlsTcpConnector::with_cert_store(client_certs.clone(), cfg[2].peer_names.clone());
+
+        let (new_cert, new_key) = gen_cert_and_key("peer-2").unwrap();
+        server_certs.add_cert(rotating_peer, new_cert.clone());
+        let mut server: ConnectionListener<u64> = server.listen(bind_addr).await.unwrap();
+
+        // Both certificates are accepted during the grace period
+        for rotate in [false, true] {
+            if rotate {
+                client_certs.set_identity(new_cert.clone(), new_key.clone());
+            }
+            let (accepted, connected) = futures::join!(
+                server.next(),
+                Connector::<u64>::connect_framed(&client, url.clone(), PeerId::from(0))
+            );
+            assert_eq!(accepted.unwrap().unwrap().0, rotating_peer);
+            assert_eq!(connected.unwrap().0, PeerId::from(0));
+        }
+
+        // The old certificate is rejected once the grace period ended
+        server_certs.retain_cert(rotating_peer, &new_cert);
+        client_certs.set_identity(
+            cfg[2].our_certificate.clone(),
+            cfg[2].our_private_key.clone(),
+        );
+        let (accepted, _) = futures::join!(
+            server.next(),
+            Connector::<u64>::connect_framed(&client, url.clone(), PeerId::from(0))
+        );
+        assert!(accepted.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn connect_through_socks5_proxy() {
+        let bind_addr: SocketAddr = "127.0.0.1:7003".parse().unwrap();
+        let onion = "fedimintxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx.onion:7003";
+        let url: Url = format!("ws://{onion}").parse().unwrap();
+        let proxy = MockSocks5Proxy::spawn(BTreeMap::from([(onion.to_string(), bind_addr)])).await;
+
+        let cfg = gen_connector_config(2);
+        let mut server: ConnectionListener<u64> = TlsTcpConnector::new(cfg[0].clone())
+            .listen(bind_addr)
+            .await
+            .unwrap();
+        let client = TlsTcpConnector::new(cfg[1].clone()).with_socks5_proxy(Some(proxy.addr));
+
+        let (accepted, connected) = futures::join!(
+            server.next(),
+            Connector::<u64>::connect_framed(&client, url, PeerId::from(0))
+        );
+        let (_, mut server_conn) = accepted.unwrap().unwrap();
+        let (peer, mut client_conn) = connected.unwrap();
+        assert_eq!(peer, PeerId::from(0));
+
+        client_conn.send(42).await.unwrap();
+        assert_eq!(server_conn.next().await.unwrap().unwrap(), 42);
+        assert_eq!(proxy.requests(), vec![onion.to_string()]);
+    }
 }
@@ -0,0 +1,540 @@
+//! Serves the client API as JSON over HTTP for clients that can't or don't want
+//! to use websockets
+//!
+//! Every endpoint of the websocket API is mapped to a `POST` route of the same
+//! path which takes the request object as its body. Subscriptions are emulated
+//! by long-polling, see [`HttpSubscriptionRequest`]. Endpoints returning
+//! immutable data are also served as `GET` routes that can be cached, see
+//! [`HTTP_GET_METHODS`]. Database backups are streamed as a plain body instead
+//! since they may be too large for a single JSON response.
+use std::cmp;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::body::StreamBody;
+use axum::extract::{ConnectInfo, Path};
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use bytes::Bytes;
+use fedimint_core::api::{HttpSubscriptionRequest, HTTP_GET_METHODS};
+use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::module::{ApiEndpoint, ApiError, ApiRequest, ApiRequestErased};
+use fedimint_core::server::DynServerModule;
+use fedimint_core::task::TaskHandle;
+use fedimint_logging::LOG_NET_API;
+use futures::future::BoxFuture;
+use futures::{Future, FutureExt};
+use serde_json::Value;
+use tokio::select;
+use tracing::{debug, error, Instrument};
+
+use crate::consensus::FedimintConsensus;
+use crate::net::api::{
+    api_request_span, encrypted_backup, next_status, observe_api_request_code, server_endpoints,
+    subscriptions, SubscriptionStatus, API_ENDPOINT_TIMEOUT,
+};
+use crate::net::limits::ApiLimiter;
+
+/// How long a subscription request waits for a change before it is answered
+/// with `204 No Content`, short enough to not be cut off by common proxies
+const LONG_POLL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Size of the chunks binary responses are streamed in
+const BINARY_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Successful response to a request
+enum RouteResponse {
+    /// Result that may still change, e.g. an epoch that wasn't signed yet
+    Current(Value),
+    /// Result that will never change and can be cached by clients and proxies
+    Immutable(Value),
+    /// A subscription didn't change before the long-poll timed out
+    NoContent,
+    /// Raw bytes that are streamed in chunks, e.g. a database backup
+    Binary(Vec<u8>),
+}
+
+/// Handles the JSON body posted to a route
+type RouteHandler =
+    Arc<dyn Fn(Value) -> BoxFuture<'static, Result<RouteResponse, ApiError>> + Send + Sync>;
+
+#[derive(Clone)]
+struct Route {
+    path: &'static str,
+    handler: RouteHandler,
+    /// Counts towards the subscriptions of the client while it is handled
+    long_poll: bool,
+}
+
+impl Route {
+    fn new((path, handler): (&'static str, RouteHandler)) -> Self {
+        Route {
+            path,
+            handler,
+            long_poll: false,
+        }
+    }
+}
+
+/// Serves the HTTP API on `bind` until the task group shuts down
+pub async fn run_http_server(
+    bind: SocketAddr,
+    fedimint: Arc<FedimintConsensus>,
+    limiter: Arc<ApiLimiter>,
+    task_handle: TaskHandle,
+) {
+    let mut routes = vec![];
+    for endpoint in server_endpoints() {
+        if endpoint.path == "/backup" {
+            continue;
+        }
+        routes.push(Route::new(core_route(fedimint.clone(), endpoint)));
+    }
+    routes.push(Route::new((
+        "/backup",
+        guarded("/backup", None, backup_route(fedimint.clone())),
+    )));
+    for (path, status) in subscriptions() {
+        routes.push(Route {
+            path,
+            handler: guarded(path, None, subscription_route(fedimint.clone(), status)),
+            long_poll: true,
+        });
+    }
+    for (id, module) in fedimint.modules.iter_modules() {
+        for endpoint in module.api_endpoints() {
+            routes.push(Route::new(module_route(fedimint.clone(), id, endpoint)));
+        }
+    }
+
+    let shutdown_future = task_handle.make_shutdown_rx().await;
+    let server_future = axum::Server::bind(&bind)
+        .serve(router(routes, limiter).into_make_service_with_connect_info::<SocketAddr>());
+
+    debug!(%bind, "Starting HTTP API server");
+    select! {
+        _ = shutdown_future => {
+            debug!("HTTP API server shutting down");
+        },
+        Err(err) = server_future => {
+            error!(target: LOG_NET_API, ?err, "HTTP API server encountered an error");
+        }
+    }
+}
+
+fn router(routes: Vec<Route>, limiter: Arc<ApiLimiter>) -> Router {
+    routes.into_iter().fold(Router::new(), |router, route| {
+        let router = router.route(
+            route.path,
+            post({
+                let route = route.clone();
+                let limiter = limiter.clone();
+                move |ConnectInfo(client): ConnectInfo<SocketAddr>, Json(body): Json<Value>| {
+                    handle(&route, &limiter, client, body)
+                }
+            }),
+        );
+        if !HTTP_GET_METHODS.contains(&route.path) {
+            return router;
+        }
+
+        let limiter = limiter.clone();
+        router.route(
+            &format!("{}/:param", route.path),
+            get(
+                move |ConnectInfo(client): ConnectInfo<SocketAddr>, Path(param): Path<u64>| {
+                    let body = ApiRequestErased::new(param).to_json();
+                    handle(&route, &limiter, client, body)
+                },
+            ),
+        )
+    })
+}
+
+/// Answers `body` with the handler of `route` once the limiter admitted
+/// `client`
+fn handle(
+    route: &Route,
+    limiter: &ApiLimiter,
+    client: SocketAddr,
+    body: Value,
+) -> impl Future<Output = Response> {
+    let handler = route.handler.clone();
+    let permit = limiter.admit(route.path, Some(client.ip()), route.long_poll);
+    async move {
+        let _permit = match permit {
+            Ok(permit) => permit,
+            Err(e) => return into_response(Err(e)),
+        };
+        into_response(handler(body).await)
+    }
+}
+
+fn into_response(result: Result<RouteResponse, ApiError>) -> Response {
+    match result {
+        Ok(RouteResponse::Current(value)) => {
+            ([(CACHE_CONTROL, "no-store")], Json(value)).into_response()
+        }
+        Ok(RouteResponse::Immutable(value)) => (
+            [(CACHE_CONTROL, "public, max-age=31536000, immutable")],
+            Json(value),
+        )
+            .into_response(),
+        Ok(RouteResponse::NoContent) => StatusCode::NO_CONTENT.into_response(),
+        Ok(RouteResponse::Binary(bytes)) => {
+            let bytes = Bytes::from(bytes);
+            let chunks = (0..bytes.len())
+                .step_by(BINARY_CHUNK_SIZE)
+                .map(move |start| {
+                    let end = cmp::min(start + BINARY_CHUNK_SIZE, bytes.len());
+                    Ok::<_, Infallible>(bytes.slice(start..end))
+                });
+            (
+                [
+                    (CACHE_CONTROL, "no-store"),
+                    (CONTENT_TYPE, "application/octet-stream"),
+                ],
+                StreamBody::new(futures::stream::iter(chunks)),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            // The body keeps the original code in case it is no valid HTTP status
+            let status = u16::try_from(e.code)
+                .ok()
+                .and_then(|code| StatusCode::from_u16(code).ok())
+                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            (status, Json(e)).into_response()
+        }
+    }
+}
+
+fn core_route(
+    fedimint: Arc<FedimintConsensus>,
+    endpoint: ApiEndpoint<FedimintConsensus>,
+) -> (&'static str, RouteHandler) {
+    let path = endpoint.path;
+    // This memory leak is fine because it only happens on server startup
+    let handler: &'static _ = Box::leak(endpoint.handler);
+
+    let route: RouteHandler = Arc::new(move |request| {
+        let fedimint = fedimint.clone();
+        Box::pin(async move {
+            let dbtx = fedimint.db.begin_transaction().await;
+            let result = with_timeout((handler)(
+                &fedimint,
+                dbtx,
+                request.clone(),
+                None,
+                fedimint.cfg.private.api_auth.clone(),
+            ))
+            .await?;
+
+            Ok(if is_immutable(&fedimint, path, &request).await {
+                RouteResponse::Immutable(result)
+            } else {
+                RouteResponse::Current(result)
+            })
+        })
+    });
+    (path, guarded(path, None, route))
+}
+
+fn module_route(
+    fedimint: Arc<FedimintConsensus>,
+    module_instance: ModuleInstanceId,
+    endpoint: ApiEndpoint<DynServerModule>,
+) -> (&'static str, RouteHandler) {
+    // These memory leaks are fine because they only happen on server startup
+    let path: &'static _ =
+        Box::leak(format!("/module/{}{}", module_instance, endpoint.path).into_boxed_str());
+    let handler: &'static _ = Box::leak(endpoint.handler);
+
+    let route: RouteHandler = Arc::new(move |request| {
+        let fedimint = fedimint.clone();
+        Box::pin(async move {
+            let dbtx = fedimint.db.begin_transaction().await;
+            with_timeout((handler)(
+                fedimint.modules.get_expect(module_instance),
+                dbtx,
+                request,
+                Some(module_instance),
+                fedimint.cfg.private.api_auth.clone(),
+            ))
+            .await
+            .map(RouteResponse::Current)
+        })
+    });
+    (path, guarded(path, Some(module_instance), route))
+}
+
+/// Answers requests with an encrypted backup of the database if they are
+/// authenticated, without the usual timeout since large databases take a while
+fn backup_route(fedimint: Arc<FedimintConsensus>) -> RouteHandler {
+    Arc::new(move |body| {
+        let fedimint = fedimint.clone();
+        Box::pin(async move {
+            let request: ApiRequest<()> =
+                serde_json::from_value(body).map_err(|e| ApiError::bad_request(e.to_string()))?;
+            if request.auth.as_ref() != Some(&fedimint.cfg.private.api_auth) {
+                return Err(ApiError::unauthorized());
+            }
+
+            let backup = encrypted_backup(&fedimint).await?;
+            Ok(RouteResponse::Binary(backup.into_bytes()))
+        })
+    })
+}
+
+fn subscription_route(
+    fedimint: Arc<FedimintConsensus>,
+    status: SubscriptionStatus,
+) -> RouteHandler {
+    Arc::new(move |body| {
+        let fedimint = fedimint.clone();
+        let status = status.clone();
+        Box::pin(async move {
+            let subscription: HttpSubscriptionRequest =
+                serde_json::from_value(body).map_err(|e| ApiError::bad_request(e.to_string()))?;
+            let next = next_status(
+                &fedimint,
+                &status,
+                &subscription.request,
+                subscription.last.as_ref(),
+            );
+            match tokio::time::timeout(LONG_POLL_TIMEOUT, next).await {
+                Ok(result) => result.map(RouteResponse::Current),
+                Err(_) => Ok(RouteResponse::NoContent),
+            }
+        })
+    })
+}
+
+/// Whether the successful response to `request` can never change
+async fn is_immutable(fedimint: &FedimintConsensus, path: &str, request: &Value) -> bool {
+    match path {
+        // The history of an epoch is final once the next epoch signed it
+        "/fetch_epoch_history" => {
+            match serde_json::from_value::<ApiRequest<u64>>(request.clone()) {
+                Ok(request) => request.params.saturating_add(2) <= fedimint.get_epoch_count().await,
+                Err(_) => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+async fn with_timeout(
+    handler: impl futures::Future<Output = Result<Value, ApiError>>,
+) -> Result<Value, ApiError> {
+    tokio::time::timeout(API_ENDPOINT_TIMEOUT, handler)
+        .await
+        .unwrap_or_else(|_| Err(ApiError::new(504, "API request timed out".to_string())))
+}
+
+/// Instruments `route`, records metrics and turns panics into errors
+fn guarded(
+    path: &'static str,
+    module_instance_id: Option<ModuleInstanceId>,
+    route: RouteHandler,
+) -> RouteHandler {
+    Arc::new(move |body| {
+        let route = route.clone();
+        Box::pin(
+            async move {
+                let start = Instant::now();
+                // See the websocket API on why AssertUnwindSafe is acceptable here
+                let result = AssertUnwindSafe(route(body))
+                    .catch_unwind()
+                    .await
+                    .unwrap_or_else(|_| {
+                        error!(
+                            target: LOG_NET_API,
+                            path, "API handler panicked, DO NOT IGNORE, FIX IT!!!"
+                        );
+                        Err(ApiError::new(500, "API handler panicked".to_string()))
+                    });
+                let code = match &result {
+                    Ok(_) => "ok".to_string(),
+                    Err(e) => e.code.to_string(),
+                };
+                observe_api_request_code(path, start, &code);
+                result
+            }
+            .instrument(api_request_span(path, module_instance_id)),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use fedimint_core::api::{HttpFederationApi, HttpSubscriptionRequest, IFederationApi};
+    use fedimint_core::module::{ApiError, ApiRequestErased};
+    use fedimint_core::PeerId;
+    use futures::StreamExt;
+    use jsonrpsee::core::Error as JsonRpcError;
+    use jsonrpsee::types::error::CallError;
+    use serde_json::{json, Value};
+
+    use super::{router, Route, RouteHandler, RouteResponse, BINARY_CHUNK_SIZE};
+    use crate::config::{ApiLimits, EndpointLimits};
+    use crate::net::limits::ApiLimiter;
+
+    fn route(
+        path: &'static str,
+        handler: impl Fn(Value) -> Result<RouteResponse, ApiError> + Send + Sync + 'static,
+    ) -> Route {
+        let handler = Arc::new(handler);
+        let handler: RouteHandler = Arc::new(move |body| {
+            let result = handler(body);
+            Box::pin(async move { result })
+        });
+        Route::new((path, handler))
+    }
+
+    /// Spans multiple chunks when streamed
+    fn binary() -> Vec<u8> {
+        (0..3 * BINARY_CHUNK_SIZE / 2).map(|i| i as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn http_client_talks_to_http_server() {
+        let polls = AtomicU64::new(0);
+        let routes = vec![
+            route("/echo", |body| {
+                Ok(RouteResponse::Current(body["params"].clone()))
+            }),
+            route("/final", |_| Ok(RouteResponse::Immutable(json!("final")))),
+            route("/binary", |_| Ok(RouteResponse::Binary(binary()))),
+            route("/fetch_epoch_history", |body| {
+                Ok(RouteResponse::Immutable(body["params"].clone()))
+            }),
+            route("/missing", |_| {
+                Err(ApiError::not_found("no such thing".to_string()))
+            }),
+            route("/closed", |_| unreachable!("Rejected by the limiter")),
+            route("/subscribe", move |body| {
+                let request: HttpSubscriptionRequest = serde_json::from_value(body).unwrap();
+                assert_eq!(request.request["params"], json!("sub"));
+                Ok(match polls.fetch_add(1, Ordering::SeqCst) {
+                    0 => RouteResponse::Current(json!(1)),
+                    1 => RouteResponse::NoContent,
+                    _ => {
+                        assert_eq!(request.last, Some(json!(1)));
+                        RouteResponse::Current(json!(2))
+                    }
+                })
+            }),
+        ];
+        let limiter = ApiLimiter::new(&ApiLimits {
+            endpoints: [(
+                "/closed".to_string(),
+                EndpointLimits {
+                    requests_per_minute: None,
+                    max_concurrent: Some(0),
+                },
+            )]
+            .into(),
+            ..Default::default()
+        });
+        let server = axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(
+            router(routes, Arc::new(limiter)).into_make_service_with_connect_info::<SocketAddr>(),
+        );
+        let url = format!("http://{}/", server.local_addr());
+        tokio::spawn(server);
+
+        let peer = PeerId::from(0);
+        let api = HttpFederationApi::new(vec![(peer, url.parse().unwrap())]);
+        let request = |params: &str| [ApiRequestErased::new(params).to_json()];
+
+        assert_eq!(
+            api.request_raw(peer, "/echo", &request("hello"))
+                .await
+                .unwrap(),
+            json!("hello")
+        );
+
+        match api.request_raw(peer, "/missing", &request("")).await {
+            Err(JsonRpcError::Call(CallError::Custom(e))) => assert_eq!(e.code(), 404),
+            other => panic!("Unexpected response {other:?}"),
+        }
+
+        match api.request_raw(peer, "/closed", &request("")).await {
+            Err(JsonRpcError::Call(CallError::Custom(e))) => assert_eq!(e.code(), 429),
+            other => panic!("Unexpected response {other:?}"),
+        }
+
+        let statuses: Vec<_> = api
+            .subscribe_raw(peer, "/subscribe", &request("sub"))
+            .await
+            .unwrap()
+            .take(2)
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        assert_eq!(statuses, vec![json!(1), json!(2)]);
+
+        let cache_control = |path: &'static str| {
+            let url = format!("{url}{}", path.trim_start_matches('/'));
+            async move {
+                reqwest::Client::new()
+                    .post(url)
+                    .json(&request("")[0])
+                    .send()
+                    .await
+                    .unwrap()
+                    .headers()[reqwest::header::CACHE_CONTROL]
+                    .to_str()
+                    .unwrap()
+                    .to_string()
+            }
+        };
+        assert_eq!(cache_control("/echo").await, "no-store");
+        assert_eq!(cache_control("/binary").await, "no-store");
+
+        let streamed = reqwest::Client::new()
+            .post(format!("{url}binary"))
+            .json(&request("")[0])
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(streamed, binary());
+        assert_eq!(
+            cache_control("/final").await,
+            "public, max-age=31536000, immutable"
+        );
+
+        // Immutable data can also be fetched with cacheable GET requests
+        assert_eq!(
+            api.request_raw(
+                peer,
+                "/fetch_epoch_history",
+                &[ApiRequestErased::new(7).to_json()]
+            )
+            .await
+            .unwrap(),
+            json!(7)
+        );
+        let response = reqwest::get(format!("{url}fetch_epoch_history/7"))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[reqwest::header::CACHE_CONTROL],
+            "public, max-age=31536000, immutable"
+        );
+        assert_eq!(response.json::<Value>().await.unwrap(), json!(7));
+    }
+}
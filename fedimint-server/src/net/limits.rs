@@ -0,0 +1,290 @@
+//! Enforces the [`ApiLimits`] of the guardian on requests to the API
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use fedimint_core::module::ApiError;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config::ApiLimits;
+use crate::metrics::API_REQUESTS_LIMITED;
+
+/// Above this many tracked IPs the ones that were idle for a minute, and
+/// thus have their full quota again, are forgotten
+const MAX_TRACKED_IPS: usize = 10_000;
+
+/// Admits requests to the API as long as they stay within the configured
+/// limits, shared by the websocket and HTTP servers
+#[derive(Debug)]
+pub struct ApiLimiter {
+    requests_per_ip_per_minute: Option<u32>,
+    max_subscriptions_per_client: Option<u32>,
+    ip_buckets: Mutex<HashMap<IpAddr, TokenBucket>>,
+    endpoints: HashMap<String, EndpointLimiter>,
+    subscriptions: Arc<Mutex<HashMap<IpAddr, u32>>>,
+}
+
+#[derive(Debug)]
+struct EndpointLimiter {
+    rate: Option<(u32, Mutex<TokenBucket>)>,
+    slots: Option<Arc<Semaphore>>,
+}
+
+/// Held while a request is handled, frees its concurrency slots when dropped
+#[derive(Debug)]
+pub struct ApiPermit {
+    _slot: Option<OwnedSemaphorePermit>,
+    _subscription: Option<SubscriptionSlot>,
+}
+
+#[derive(Debug)]
+struct SubscriptionSlot {
+    ip: IpAddr,
+    subscriptions: Arc<Mutex<HashMap<IpAddr, u32>>>,
+}
+
+impl Drop for SubscriptionSlot {
+    fn drop(&mut self) {
+        let mut subscriptions = self.subscriptions.lock().expect("Locking failed");
+        if let Some(count) = subscriptions.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                subscriptions.remove(&self.ip);
+            }
+        }
+    }
+}
+
+impl ApiLimiter {
+    pub fn new(limits: &ApiLimits) -> Self {
+        let now = Instant::now();
+        ApiLimiter {
+            requests_per_ip_per_minute: limits.requests_per_ip_per_minute,
+            max_subscriptions_per_client: limits.max_subscriptions_per_client,
+            ip_buckets: Default::default(),
+            endpoints: limits
+                .endpoints
+                .iter()
+                .map(|(path, limits)| {
+                    let limiter = EndpointLimiter {
+                        rate: limits
+                            .requests_per_minute
+                            .map(|rate| (rate, Mutex::new(TokenBucket::full(rate, now)))),
+                        slots: limits
+                            .max_concurrent
+                            .map(|slots| Arc::new(Semaphore::new(slots as usize))),
+                    };
+                    (path.clone(), limiter)
+                })
+                .collect(),
+            subscriptions: Default::default(),
+        }
+    }
+
+    /// Admits a request to `path` from `client`, if known, which will be
+    /// counted towards the client's subscriptions if `subscription` is set
+    pub fn admit(
+        &self,
+        path: &str,
+        client: Option<IpAddr>,
+        subscription: bool,
+    ) -> Result<ApiPermit, ApiError> {
+        let now = Instant::now();
+
+        if let (Some(rate), Some(ip)) = (self.requests_per_ip_per_minute, client) {
+            let mut buckets = self.ip_buckets.lock().expect("Locking failed");
+            if buckets.len() > MAX_TRACKED_IPS {
+                buckets
+                    .retain(|_, bucket| now.duration_since(bucket.last) < Duration::from_secs(60));
+            }
+            let bucket = buckets
+                .entry(ip)
+                .or_insert_with(|| TokenBucket::full(rate, now));
+            if !bucket.try_take(rate, now) {
+                return Err(limited(path, "ip_rate", "Too many requests from your IP"));
+            }
+        }
+
+        let mut slot = None;
+        if let Some(endpoint) = self.endpoints.get(path) {
+            if let Some((rate, bucket)) = &endpoint.rate {
+                if !bucket.lock().expect("Locking failed").try_take(*rate, now) {
+                    return Err(limited(
+                        path,
+                        "endpoint_rate",
+                        "Too many requests to this endpoint",
+                    ));
+                }
+            }
+
+            if let Some(slots) = &endpoint.slots {
+                slot = Some(slots.clone().try_acquire_owned().map_err(|_| {
+                    limited(
+                        path,
+                        "endpoint_concurrency",
+                        "Too many concurrent requests to this endpoint",
+                    )
+                })?);
+            }
+        }
+
+        let subscription = match (self.max_subscriptions_per_client, client) {
+            (Some(max), Some(ip)) if subscription => {
+                let mut subscriptions = self.subscriptions.lock().expect("Locking failed");
+                let count = subscriptions.entry(ip).or_insert(0);
+                if *count >= max {
+                    return Err(limited(
+                        path,
+                        "subscriptions",
+                        "Too many open subscriptions",
+                    ));
+                }
+                *count += 1;
+                Some(SubscriptionSlot {
+                    ip,
+                    subscriptions: self.subscriptions.clone(),
+                })
+            }
+            _ => None,
+        };
+
+        Ok(ApiPermit {
+            _slot: slot,
+            _subscription: subscription,
+        })
+    }
+}
+
+/// Admits the requests made over a single websocket connection as long as
+/// they stay within [`ApiLimits::requests_per_connection_per_minute`]
+#[derive(Debug)]
+pub struct ConnectionLimiter {
+    rate: Option<(u32, TokenBucket)>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(limits: &ApiLimits) -> Self {
+        ConnectionLimiter {
+            rate: limits
+                .requests_per_connection_per_minute
+                .map(|rate| (rate, TokenBucket::full(rate, Instant::now()))),
+        }
+    }
+
+    /// Admits a message calling `path` that contains `calls` requests
+    pub fn admit(&mut self, path: &str, calls: usize) -> Result<(), ApiError> {
+        let now = Instant::now();
+        if let Some((rate, bucket)) = &mut self.rate {
+            if !(0..calls).all(|_| bucket.try_take(*rate, now)) {
+                return Err(limited(
+                    path,
+                    "connection_rate",
+                    "Too many requests over this connection",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn limited(path: &str, limit: &str, message: &str) -> ApiError {
+    API_REQUESTS_LIMITED.with_label_values(&[path, limit]).inc();
+    ApiError::too_many_requests(message.to_string())
+}
+
+/// Allows bursts of up to a minute's worth of requests, refilling evenly
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn full(per_minute: u32, now: Instant) -> Self {
+        TokenBucket {
+            tokens: per_minute as f64,
+            last: now,
+        }
+    }
+
+    fn try_take(&mut self, per_minute: u32, now: Instant) -> bool {
+        let capacity = per_minute as f64;
+        let refill = now.duration_since(self.last).as_secs_f64() * capacity / 60.0;
+        self.tokens = (self.tokens + refill).min(capacity);
+        self.last = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::net::IpAddr;
+
+    use super::{ApiLimiter, ConnectionLimiter};
+    use crate::config::{ApiLimits, EndpointLimits};
+
+    #[test]
+    fn rejects_requests_over_the_limits() {
+        let limiter = ApiLimiter::new(&ApiLimits {
+            requests_per_ip_per_minute: Some(3),
+            max_subscriptions_per_client: Some(1),
+            requests_per_connection_per_minute: None,
+            endpoints: BTreeMap::from([(
+                "/transaction".to_string(),
+                EndpointLimits {
+                    requests_per_minute: None,
+                    max_concurrent: Some(1),
+                },
+            )]),
+        });
+        let alice: IpAddr = "10.0.0.1".parse().unwrap();
+        let bob: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let tx = limiter.admit("/transaction", Some(alice), false).unwrap();
+        let busy = limiter.admit("/transaction", Some(bob), false).unwrap_err();
+        assert_eq!(busy.code, 429);
+        drop(tx);
+
+        let sub = limiter
+            .admit("/subscribe_transaction", Some(alice), true)
+            .unwrap();
+        assert!(limiter
+            .admit("/subscribe_transaction", Some(bob), true)
+            .is_ok());
+        assert!(limiter
+            .admit("/subscribe_transaction", Some(alice), true)
+            .is_err());
+        drop(sub);
+
+        // Alice used up her requests, including the rejected ones
+        assert!(limiter.admit("/config", Some(alice), false).is_err());
+        assert!(limiter.admit("/config", Some(bob), false).is_ok());
+        assert!(limiter.admit("/config", None, false).is_ok());
+    }
+
+    #[test]
+    fn limits_requests_per_connection() {
+        let limits = ApiLimits {
+            requests_per_connection_per_minute: Some(3),
+            ..Default::default()
+        };
+        let mut alice = ConnectionLimiter::new(&limits);
+        let mut bob = ConnectionLimiter::new(&limits);
+
+        assert!(alice.admit("/config", 1).is_ok());
+        assert!(alice.admit("batch", 2).is_ok());
+        assert_eq!(alice.admit("/config", 1).unwrap_err().code, 429);
+        assert!(bob.admit("batch", 3).is_ok());
+        assert!(ConnectionLimiter::new(&ApiLimits::default())
+            .admit("batch", 100)
+            .is_ok());
+    }
+}
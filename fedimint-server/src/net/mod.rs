@@ -1,5 +1,8 @@
 pub mod api;
 pub mod connect;
 pub mod framed;
+pub mod http;
+pub mod limits;
 pub mod peers;
 mod queue;
+pub mod ws_proxy;
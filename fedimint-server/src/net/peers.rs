@@ -29,6 +29,7 @@ use tokio::time::Instant;
 use tracing::{debug, info, instrument, trace, warn};
 use url::Url;
 
+use crate::metrics::{PEER_CONNECTED, PEER_RECONNECTS};
 use crate::net::connect::{AnyConnector, SharedAnyConnector};
 use crate::net::framed::AnyFramedTransport;
 use crate::net::queue::{MessageId, MessageQueue, UniqueMessage};
@@ -49,6 +50,9 @@ pub type PeerConnector<M> = AnyConnector<PeerMessage<M>>;
 /// authenticated and encrypted.
 pub struct ReconnectPeerConnections<T> {
     connections: HashMap<PeerId, PeerConnection<T>>,
+    /// Peers whose messages are dropped, their connections are kept so that
+    /// they can be unbanned
+    banned: BTreeSet<PeerId>,
 }
 
 struct PeerConnection<T> {
@@ -151,7 +155,10 @@ where
             })
             .await;
 
-        ReconnectPeerConnections { connections }
+        ReconnectPeerConnections {
+            connections,
+            banned: BTreeSet::new(),
+        }
     }
 
     async fn run_listen_task(
@@ -223,7 +230,9 @@ where
     async fn send(&mut self, peers: &[PeerId], msg: T) -> Cancellable<()> {
         for peer_id in peers {
             trace!(target: LOG_NET_PEER, ?peer_id, "Sending message to");
-            if let Some(peer) = self.connections.get_mut(peer_id) {
+            if self.banned.contains(peer_id) {
+                trace!(target: LOG_NET_PEER, peer = ?peer_id, "Not sending message to banned peer");
+            } else if let Some(peer) = self.connections.get_mut(peer_id) {
                 peer.send(msg.clone()).await?;
             } else {
                 trace!(target: LOG_NET_PEER,peer = ?peer_id, "Not sending message to unknown peer (maybe banned)");
@@ -234,24 +243,37 @@ where
 
     async fn receive(&mut self) -> Cancellable<(PeerId, T)> {
         // TODO: optimize, don't throw away remaining futures
+        loop {
+            let futures = self.connections.iter_mut().map(|(&peer, connection)| {
+                let receive_future = async move {
+                    let msg = connection.receive().await;
+                    (peer, msg)
+                };
+                Box::pin(receive_future)
+            });
 
-        let futures_non_banned = self.connections.iter_mut().map(|(&peer, connection)| {
-            let receive_future = async move {
-                let msg = connection.receive().await;
-                (peer, msg)
-            };
-            Box::pin(receive_future)
-        });
+            let ((peer, msg), _, _) = select_all(futures).await;
 
-        let first_response = select_all(futures_non_banned).await;
+            // Messages of banned peers are still received so that their
+            // connections don't stall
+            if msg.is_ok() && self.banned.contains(&peer) {
+                trace!(target: LOG_NET_PEER, ?peer, "Dropping message from banned peer");
+                continue;
+            }
 
-        first_response.0 .1.map(|v| (first_response.0 .0, v))
+            return msg.map(|v| (peer, v));
+        }
     }
 
     async fn ban_peer(&mut self, peer: PeerId) {
-        self.connections.remove(&peer);
+        self.banned.insert(peer);
         warn!(target: LOG_NET_PEER, "Peer {} banned.", peer);
     }
+
+    async fn unban_peer(&mut self, peer: PeerId) {
+        self.banned.remove(&peer);
+        info!(target: LOG_NET_PEER, "Peer {} unbanned.", peer);
+    }
 }
 
 impl<M> PeerConnectionStateMachine<M>
@@ -352,9 +374,14 @@ where
         debug!(target: LOG_NET_PEER,
             peer = ?self.peer, "Received incoming connection");
         match self.resend_buffer_contents(&mut new_connection).await {
-            Ok(()) => PeerConnectionState::Connected(ConnectedPeerConnectionState {
-                connection: new_connection,
-            }),
+            Ok(()) => {
+                PEER_CONNECTED
+                    .with_label_values(&[&self.peer.to_string()])
+                    .set(1);
+                PeerConnectionState::Connected(ConnectedPeerConnectionState {
+                    connection: new_connection,
+                })
+            }
             Err(e) => self.disconnect_err(e, disconnect_count),
         }
     }
@@ -377,6 +404,9 @@ where
 
     fn disconnect(&self, mut disconnect_count: u64) -> PeerConnectionState<M> {
         disconnect_count += 1;
+        PEER_CONNECTED
+            .with_label_values(&[&self.peer.to_string()])
+            .set(0);
 
         let reconnect_at = {
             let scaling_factor = disconnect_count as f64;
@@ -536,12 +566,17 @@ where
         &mut self,
         disconnected: DisconnectedPeerConnectionState,
     ) -> PeerConnectionState<M> {
+        let peer = self.peer.to_string();
         match self.try_reconnect().await {
             Ok(conn) => {
+                PEER_RECONNECTS.with_label_values(&[&peer, "success"]).inc();
                 self.connect(conn, disconnected.failed_reconnect_counter)
                     .await
             }
-            Err(e) => self.disconnect_err(e, disconnected.failed_reconnect_counter),
+            Err(e) => {
+                PEER_RECONNECTS.with_label_values(&[&peer, "failure"]).inc();
+                self.disconnect_err(e, disconnected.failed_reconnect_counter)
+            }
         }
     }
 
@@ -605,7 +640,7 @@ where
         self.incoming.recv().await.ok_or(Cancelled)
     }
 
-    #[instrument(skip_all, fields(peer))]
+    #[instrument(skip_all, fields(peer = %peer))]
     async fn run_io_thread(
         incoming: Sender<M>,
         outgoing: Receiver<M>,
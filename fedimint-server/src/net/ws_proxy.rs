@@ -0,0 +1,325 @@
+//! Fronts the websocket API to limit the requests of every connection
+//!
+//! jsonrpsee doesn't tell its handlers which connection a request came in on,
+//! so connections are accepted here instead and relayed to the API server on
+//! the loopback interface. Requests over the limits of their connection are
+//! answered with an error right away and never reach the API server, the
+//! others get the IP of the client appended to their parameters so the API
+//! server can apply its per-IP limits, see [`client_ip`].
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use anyhow::bail;
+use fedimint_core::module::ApiError;
+use fedimint_core::task::TaskHandle;
+use fedimint_logging::LOG_NET_API;
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, StreamExt};
+use jsonrpsee::types::Params;
+use serde_json::{json, Value};
+use soketto::connection::{Receiver, Sender};
+use soketto::handshake::{self, ServerResponse};
+use soketto::Data;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+use tracing::{debug, error};
+
+use crate::config::ApiLimits;
+use crate::net::limits::ConnectionLimiter;
+
+type Socket = Compat<TcpStream>;
+
+/// Responses that may be waiting to be sent to a client before we stop reading
+/// its requests
+const MAX_PENDING_RESPONSES: usize = 32;
+
+/// IP of the client that made the call with `params`, if it was relayed by the
+/// proxy
+pub fn client_ip(params: &Params) -> Option<IpAddr> {
+    let mut params = params.sequence();
+    params.next::<Value>().ok()?;
+    params.optional_next::<IpAddr>().ok().flatten()
+}
+
+/// Relays the websocket connections accepted on `listener` to the API server
+/// listening on `upstream` until the task group shuts down
+pub async fn run_ws_proxy(
+    listener: TcpListener,
+    upstream: SocketAddr,
+    limits: Arc<ApiLimits>,
+    task_handle: TaskHandle,
+) {
+    let mut shutdown_rx = task_handle.make_shutdown_rx().await;
+    let mut connections = FuturesUnordered::<BoxFuture<'static, ()>>::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((client, addr)) => {
+                    let limiter = ConnectionLimiter::new(&limits);
+                    connections.push(
+                        async move {
+                            if let Err(e) = relay(client, addr.ip(), upstream, limiter).await {
+                                debug!(target: LOG_NET_API, %addr, "API connection closed: {e}");
+                            }
+                        }
+                        .boxed(),
+                    );
+                }
+                Err(e) => error!(target: LOG_NET_API, "Accepting API connection failed: {e}"),
+            },
+            Some(()) = connections.next(), if !connections.is_empty() => {}
+            _ = &mut shutdown_rx => return,
+        }
+    }
+}
+
+/// Relays the messages of `client` to the API server on `upstream` and back
+async fn relay(
+    client: TcpStream,
+    client_ip: IpAddr,
+    upstream: SocketAddr,
+    limiter: ConnectionLimiter,
+) -> anyhow::Result<()> {
+    let mut server = handshake::Server::new(client.compat());
+    let key = server.receive_request().await?.key();
+
+    let upstream_host = upstream.to_string();
+    let mut upstream = handshake::Client::new(
+        TcpStream::connect(upstream).await?.compat(),
+        &upstream_host,
+        "/",
+    );
+    if !matches!(upstream.handshake().await?, ServerResponse::Accepted { .. }) {
+        bail!("API server rejected the connection");
+    }
+    server
+        .send_response(&handshake::server::Response::Accept {
+            key,
+            protocol: None,
+        })
+        .await?;
+
+    let (to_client, from_client) = server.into_builder().finish();
+    let (to_upstream, from_upstream) = upstream.into_builder().finish();
+    // Both the API server and the limiter answer the client
+    let (responses, pending_responses) = mpsc::channel(MAX_PENDING_RESPONSES);
+
+    tokio::select! {
+        result = relay_requests(from_client, client_ip, to_upstream, limiter, responses.clone()) => result,
+        result = relay_responses(from_upstream, responses) => result,
+        result = send_responses(pending_responses, to_client) => result,
+    }
+}
+
+/// Forwards the requests of the client that are within its limits, answering
+/// the others right away. Stops reading requests while the client doesn't
+/// read the responses.
+async fn relay_requests(
+    mut from_client: Receiver<Socket>,
+    client_ip: IpAddr,
+    mut to_upstream: Sender<Socket>,
+    mut limiter: ConnectionLimiter,
+    responses: mpsc::Sender<String>,
+) -> anyhow::Result<()> {
+    let mut message = Vec::new();
+    loop {
+        message.clear();
+        let data = from_client.receive_data(&mut message).await?;
+        let mut request = serde_json::from_slice::<Value>(&message).unwrap_or(Value::Null);
+        let (path, calls) = match &request {
+            Value::Array(calls) => ("batch", calls.len()),
+            call => (call["method"].as_str().unwrap_or("unknown"), 1),
+        };
+
+        if let Err(e) = limiter.admit(path, calls) {
+            if responses
+                .send(limited_response(&request, &e).to_string())
+                .await
+                .is_err()
+            {
+                return Ok(());
+            }
+            continue;
+        }
+        // Invalid requests are relayed as they are for the API server to answer
+        if !request.is_null() {
+            match &mut request {
+                Value::Array(calls) => calls
+                    .iter_mut()
+                    .for_each(|call| add_client_ip(call, client_ip)),
+                call => add_client_ip(call, client_ip),
+            }
+            message = request.to_string().into_bytes();
+        }
+        match data {
+            Data::Text(_) => {
+                to_upstream
+                    .send_text(std::str::from_utf8(&message)?)
+                    .await?
+            }
+            Data::Binary(_) => to_upstream.send_binary(&message).await?,
+        }
+        to_upstream.flush().await?;
+    }
+}
+
+/// Replaces all but the first parameter of `call` with `client_ip`, so clients
+/// can't pass another IP themselves. Unsubscribing takes the subscription id as
+/// its only parameter and is left as is.
+fn add_client_ip(call: &mut Value, client_ip: IpAddr) {
+    let unsubscribe = call["method"]
+        .as_str()
+        .map_or(false, |method| method.ends_with("/unsubscribe"));
+    if let Some(params) = call.get_mut("params").and_then(Value::as_array_mut) {
+        if !unsubscribe && !params.is_empty() {
+            params.truncate(1);
+            params.push(json!(client_ip));
+        }
+    }
+}
+
+/// Forwards the responses and notifications of the API server
+async fn relay_responses(
+    mut from_upstream: Receiver<Socket>,
+    responses: mpsc::Sender<String>,
+) -> anyhow::Result<()> {
+    loop {
+        let mut message = Vec::new();
+        from_upstream.receive_data(&mut message).await?;
+        if responses.send(String::from_utf8(message)?).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Sends the responses to the client in the order they were answered
+async fn send_responses(
+    mut responses: mpsc::Receiver<String>,
+    mut to_client: Sender<Socket>,
+) -> anyhow::Result<()> {
+    while let Some(response) = responses.recv().await {
+        to_client.send_text(response).await?;
+        to_client.flush().await?;
+    }
+    Ok(())
+}
+
+/// Answers every call in `request` with `error` in place of the API server
+fn limited_response(request: &Value, error: &ApiError) -> Value {
+    let response = |call: &Value| {
+        json!({
+            "jsonrpc": "2.0",
+            "id": call["id"],
+            "error": { "code": error.code, "message": error.message },
+        })
+    };
+    match request {
+        Value::Array(calls) => calls.iter().map(response).collect(),
+        call => response(call),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use fedimint_core::api::{IFederationApi, WsFederationApi};
+    use fedimint_core::task::TaskGroup;
+    use fedimint_core::PeerId;
+    use jsonrpsee::core::Error as JsonRpcError;
+    use jsonrpsee::server::ServerBuilder;
+    use jsonrpsee::types::error::CallError;
+    use jsonrpsee::RpcModule;
+    use serde_json::{json, Value};
+    use tokio::net::TcpListener;
+
+    use super::{client_ip, run_ws_proxy};
+    use crate::config::ApiLimits;
+
+    #[test_log::test(tokio::test)]
+    async fn limits_requests_per_connection() {
+        let mut rpc_module = RpcModule::new(());
+        rpc_module
+            .register_method("/echo", |params, _| params.one::<Value>())
+            .unwrap();
+        let server = ServerBuilder::new().build("127.0.0.1:0").await.unwrap();
+        let upstream = server.local_addr().unwrap();
+        let _server_handle = server.start(rpc_module).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let limits = Arc::new(ApiLimits {
+            requests_per_connection_per_minute: Some(2),
+            ..Default::default()
+        });
+        let mut task_group = TaskGroup::new();
+        task_group
+            .spawn("api-ws-proxy", move |handle| {
+                run_ws_proxy(listener, upstream, limits, handle)
+            })
+            .await;
+
+        let peer = PeerId::from(0);
+        let connect = || WsFederationApi::new(vec![(peer, url.parse().unwrap())]);
+        let alice = connect();
+        for i in 0..2 {
+            assert_eq!(
+                alice.request_raw(peer, "/echo", &[json!(i)]).await.unwrap(),
+                json!(i)
+            );
+        }
+        match alice.request_raw(peer, "/echo", &[json!(2)]).await {
+            Err(JsonRpcError::Call(CallError::Custom(e))) => assert_eq!(e.code(), 429),
+            other => panic!("Unexpected response {other:?}"),
+        }
+
+        // Other connections have their own limits
+        let bob = connect();
+        assert_eq!(
+            bob.request_raw(peer, "/echo", &[json!(3)]).await.unwrap(),
+            json!(3)
+        );
+
+        task_group.shutdown_join_all(None).await.unwrap();
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn relays_client_ip() {
+        let mut rpc_module = RpcModule::new(());
+        rpc_module
+            .register_method("/client_ip", |params, _| Ok(client_ip(&params)))
+            .unwrap();
+        let server = ServerBuilder::new().build("127.0.0.1:0").await.unwrap();
+        let upstream = server.local_addr().unwrap();
+        let _server_handle = server.start(rpc_module).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let mut task_group = TaskGroup::new();
+        task_group
+            .spawn("api-ws-proxy", move |handle| {
+                run_ws_proxy(listener, upstream, Arc::new(ApiLimits::default()), handle)
+            })
+            .await;
+
+        let peer = PeerId::from(0);
+        let api = WsFederationApi::new(vec![(peer, url.parse().unwrap())]);
+        assert_eq!(
+            api.request_raw(peer, "/client_ip", &[json!(null)])
+                .await
+                .unwrap(),
+            json!("127.0.0.1")
+        );
+        // Clients can't pass another IP themselves
+        assert_eq!(
+            api.request_raw(peer, "/client_ip", &[json!(null), json!("10.0.0.1")])
+                .await
+                .unwrap(),
+            json!("127.0.0.1")
+        );
+
+        task_group.shutdown_join_all(None).await.unwrap();
+    }
+}
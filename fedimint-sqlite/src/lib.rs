@@ -236,6 +236,11 @@ mod fedimint_sqlite_tests {
         fedimint_core::db::verify_commit(open_temp_db("commit").await).await;
     }
 
+    #[test_log::test(tokio::test)]
+    async fn test_dbtx_subscribe_prefix() {
+        fedimint_core::db::verify_subscribe_prefix(open_temp_db("subscribe_prefix").await).await;
+    }
+
     #[test_log::test(tokio::test)]
     async fn test_dbtx_prevent_nonrepeatable_reads() {
         fedimint_core::db::verify_prevent_nonrepeatable_reads(
@@ -12,10 +12,14 @@ use fedimint_core::Amount;
 use fedimint_ln_server::LightningGen;
 use fedimint_logging::TracingSetup;
 use fedimint_mint_server::MintGen;
-use fedimint_server::config::io::{create_cert, write_server_config, CODE_VERSION, SALT_FILE};
+use fedimint_server::config::io::{
+    create_cert, read_consensus_config, read_dkg_transcript, write_dkg_transcript,
+    write_server_config, CODE_VERSION, SALT_FILE,
+};
+use fedimint_server::config::session::DkgSession;
 use fedimint_server::config::{ServerConfig, ServerConfigParams};
 use fedimint_wallet_server::WalletGen;
-use tracing::info;
+use tracing::{info, warn};
 use url::Url;
 
 use crate::configure_modules;
@@ -54,7 +58,7 @@ enum Command {
         password: String,
     },
     /// All peers must run distributed key gen at the same time to create
-    /// configs
+    /// configs, a restarted peer resumes the session stored in the out dir
     Run {
         /// Directory to output all the generated config files
         #[arg(long = "out-dir")]
@@ -91,11 +95,27 @@ enum Command {
         #[arg(long = "finalty", default_value = "10")]
         finality_delay: u32,
 
+        /// SOCKS5 proxy, e.g. Tor, used to connect to peers, which allows
+        /// onion addresses in their urls
+        #[arg(long = "socks5-proxy", env = "FM_SOCKS5_PROXY")]
+        socks5_proxy: Option<SocketAddr>,
+
         /// The password that encrypts the configs
         #[arg(env = "FM_PASSWORD")]
         password: String,
     },
 
+    /// Checks a DKG transcript against our consensus config and prints the
+    /// verification code to compare with the other guardians
+    Verify {
+        /// Directory containing our config files
+        #[arg(long = "data-dir", env = "FM_DATA_DIR")]
+        data_dir: PathBuf,
+        /// Transcript to check, defaults to our own transcript
+        #[arg(long = "transcript")]
+        transcript: Option<PathBuf>,
+    },
+
     ConfigDecrypt {
         /// Encrypted config file
         #[arg(long = "in-file")]
@@ -187,6 +207,7 @@ impl DistributedGen {
                 max_denomination,
                 network,
                 finality_delay,
+                socks5_proxy,
                 password,
             } => {
                 let params = ServerConfigParams::parse_from_connect_strings(
@@ -197,22 +218,51 @@ impl DistributedGen {
                     certs,
                     &password,
                     configure_modules(max_denomination, network, finality_delay),
-                )?;
-                let server = match ServerConfig::distributed_gen(
+                )?
+                .with_socks5_proxy(socks5_proxy);
+                let session =
+                    DkgSession::open(&dir_out_path, &password, params.our_id, &params.peer_ids)?;
+                let (server, transcript) = match ServerConfig::distributed_gen(
                     &params,
                     self.module_gens.clone(),
+                    session,
                     &mut task_group,
                 )
                 .await
                 {
                     Ok(server) => server,
                     Err(DkgError::Cancelled(_)) => return Ok(info!("DKG cancelled")),
-                    Err(DkgError::Failed(err)) => return Err(err),
+                    Err(DkgError::Failed(err)) => {
+                        // a failed DKG can't be resumed, the next attempt starts over
+                        if let Err(e) = DkgSession::remove(&dir_out_path) {
+                            warn!("Unable to remove the failed DKG session: {e}");
+                        }
+                        return Err(err);
+                    }
                 };
 
-                write_server_config(&server, dir_out_path, &password, &self.module_gens)
+                write_server_config(&server, dir_out_path.clone(), &password, &self.module_gens)?;
+                write_dkg_transcript(&transcript, &dir_out_path)?;
+                DkgSession::remove(&dir_out_path)?;
+                println!("Verification code: {}", transcript.verification_code());
+                Ok(())
             }
             Command::VersionHash => Ok(println!("{CODE_VERSION}")),
+            Command::Verify {
+                data_dir,
+                transcript,
+            } => {
+                let consensus = read_consensus_config(&data_dir)?;
+                let transcript = match transcript {
+                    Some(path) => serde_json::from_str(&fs::read_to_string(path)?)?,
+                    None => read_dkg_transcript(&data_dir)?,
+                };
+                transcript.verify(&consensus, &self.module_gens)?;
+                Ok(println!(
+                    "Transcript is valid, verification code: {}",
+                    transcript.verification_code()
+                ))
+            }
             Command::ConfigDecrypt {
                 in_file,
                 out_file,
@@ -2,16 +2,20 @@ use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::time::Duration;
 
+use aead::get_encryption_key;
+use anyhow::bail;
 use clap::Parser;
 use fedimint_core::config::ServerModuleGenRegistry;
 use fedimint_core::db::Database;
 use fedimint_core::module::ServerModuleGen;
 use fedimint_core::task::{sleep, TaskGroup};
+use fedimint_encrypted_db::{DbEncryptionKey, EncryptedDatabase};
 use fedimint_ln_server::LightningGen;
 use fedimint_logging::TracingSetup;
 use fedimint_mint_server::MintGen;
+use fedimint_server::check::check_database;
 use fedimint_server::config::io::{
-    read_server_config, CODE_VERSION, DB_FILE, JSON_EXT, LOCAL_CONFIG,
+    read_server_config, CODE_VERSION, DB_FILE, JSON_EXT, LOCAL_CONFIG, SALT_FILE,
 };
 use fedimint_server::consensus::FedimintConsensus;
 use fedimint_server::FedimintServer;
@@ -20,6 +24,7 @@ use futures::FutureExt;
 use tokio::select;
 use tracing::{debug, error, info, warn};
 
+use crate::metrics::run_metrics_server;
 use crate::ui::{run_ui, UiMessage};
 
 /// Time we will wait before forcefully shutting down tasks
@@ -40,12 +45,42 @@ pub struct ServerOpts {
     /// After an upgrade the epoch must be passed in
     #[arg(long = "upgrade-epoch")]
     pub upgrade_epoch: Option<u64>,
+    /// Address to serve Prometheus metrics on, metrics are not served if not
+    /// set
+    #[arg(long = "bind-metrics", env = "FM_BIND_METRICS")]
+    pub bind_metrics: Option<SocketAddr>,
     /// Enable tokio console logging
     #[arg(long = "tokio-console-bind", env = "FM_TOKIO_CONSOLE_BIND")]
     pub tokio_console_bind: Option<SocketAddr>,
     /// Enable telemetry logging
     #[arg(long, default_value = "false")]
     pub with_telemetry: bool,
+    /// Export traces to an OpenTelemetry collector at this OTLP/gRPC endpoint
+    #[arg(long = "otlp-endpoint", env = "FM_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+    /// Log as JSON lines instead of human readable text
+    #[arg(long = "log-json", env = "FM_LOG_JSON", default_value = "false")]
+    pub log_json: bool,
+    /// Encrypt the database with a key derived from the password, an existing
+    /// plaintext database is migrated on startup
+    #[arg(long = "encrypt-db", env = "FM_ENCRYPT_DB", default_value = "false")]
+    pub encrypt_db: bool,
+    /// Interval in seconds between consistency checks of the database. Every
+    /// check scans the whole database, so they are disabled if not set.
+    #[arg(long = "db-check-interval", env = "FM_DB_CHECK_INTERVAL")]
+    pub db_check_interval: Option<u64>,
+    /// Number of recent epochs whose full history is kept, older epochs are
+    /// pruned to their signed hashes. History is never pruned if not set.
+    #[arg(long = "epoch-retention", env = "FM_EPOCH_RETENTION")]
+    pub epoch_retention: Option<u64>,
+    /// SOCKS5 proxy, e.g. Tor, used to connect to peers, overriding the one
+    /// in the local config. Also used for the DKG run from the setup UI.
+    #[arg(long = "socks5-proxy", env = "FM_SOCKS5_PROXY")]
+    pub socks5_proxy: Option<SocketAddr>,
+    /// Serve the API as JSON over HTTP on this address, overriding the one in
+    /// the local config
+    #[arg(long = "bind-api-http", env = "FM_BIND_API_HTTP")]
+    pub bind_api_http: Option<SocketAddr>,
 }
 
 /// `fedimintd` builder
@@ -99,6 +134,8 @@ impl Fedimintd {
         TracingSetup::default()
             .tokio_console_bind(opts.tokio_console_bind)
             .with_jaeger(opts.with_telemetry)
+            .with_otlp(opts.otlp_endpoint.clone())
+            .with_json(opts.log_json)
             .init()?;
 
         Ok(Self {
@@ -168,8 +205,7 @@ impl Fedimintd {
 
         info!("Shutdown complete");
 
-        #[cfg(feature = "telemetry")]
-        opentelemetry::global::shutdown_tracer_provider();
+        fedimint_logging::shutdown_telemetry();
 
         // Should we ever shut down without an error code?
         std::process::exit(-1);
@@ -185,6 +221,15 @@ async fn run(
 
     info!("Starting pre-check");
 
+    if let Some(bind_metrics) = opts.bind_metrics {
+        let metrics_task_group = task_group.make_subgroup().await;
+        task_group
+            .spawn("metrics-server", move |_| async move {
+                run_metrics_server(bind_metrics, metrics_task_group).await;
+            })
+            .await;
+    }
+
     // Run admin UI if a socket address was given for it
     if let Some(listen_ui) = opts.listen_ui {
         let module_gens = module_gens.clone();
@@ -192,6 +237,7 @@ async fn run(
         let data_dir = opts.data_dir.clone();
         let ui_task_group = task_group.make_subgroup().await;
         let password = opts.password.clone();
+        let socks5_proxy = opts.socks5_proxy;
         task_group
             .spawn("admin-ui", move |_| async move {
                 run_ui(
@@ -199,6 +245,7 @@ async fn run(
                     ui_sender,
                     listen_ui,
                     password,
+                    socks5_proxy,
                     ui_task_group,
                     module_gens,
                 )
@@ -224,17 +271,71 @@ async fn run(
 
     info!("Starting consensus");
 
-    let cfg = read_server_config(&opts.password, opts.data_dir.clone())?;
+    let mut cfg = read_server_config(&opts.password, opts.data_dir.clone())?;
+    if opts.socks5_proxy.is_some() {
+        cfg.local.socks5_proxy = opts.socks5_proxy;
+    }
+    if opts.bind_api_http.is_some() {
+        cfg.local.api_http_bind = opts.bind_api_http;
+    }
 
     let decoders = module_gens.decoders(cfg.iter_module_instances())?;
 
-    let db = Database::new(
-        fedimint_rocksdb::RocksDb::open(opts.data_dir.join(DB_FILE))?,
-        decoders.clone(),
-    );
+    let salt = std::fs::read_to_string(opts.data_dir.join(SALT_FILE))?;
+    let rocksdb = fedimint_rocksdb::RocksDb::open(opts.data_dir.join(DB_FILE))?;
+    let db = if opts.encrypt_db {
+        let key = DbEncryptionKey::from_password(&opts.password, &salt)?;
+        Database::new(
+            EncryptedDatabase::migrate(rocksdb, key).await?,
+            decoders.clone(),
+        )
+    } else {
+        if EncryptedDatabase::is_encrypted(&rocksdb).await? {
+            bail!("Database is encrypted, start fedimintd with --encrypt-db");
+        }
+        Database::new(rocksdb, decoders.clone())
+    };
+
+    if let Some(interval) = opts.db_check_interval.filter(|interval| *interval != 0) {
+        let db = db.clone();
+        let cfg = cfg.clone();
+        let module_gens = module_gens.clone();
+        let interval = Duration::from_secs(interval);
+        task_group
+            .spawn("db-check", move |handle| async move {
+                while !handle.is_shutting_down() {
+                    sleep(interval).await;
+                    match check_database(&db, &cfg, &module_gens).await {
+                        Ok(report) if report.is_consistent() => {
+                            debug!("Database consistency check passed");
+                        }
+                        Ok(report) => {
+                            error!(
+                                violations = report.violations(),
+                                report = %serde_json::to_string(&report).unwrap_or_default(),
+                                "Database consistency check failed"
+                            );
+                        }
+                        Err(e) => {
+                            warn!(?e, "Could not run database consistency check");
+                        }
+                    }
+                }
+            })
+            .await;
+    }
 
     let (consensus, tx_receiver) =
         FedimintConsensus::new(cfg.clone(), db, module_gens, &mut task_group).await?;
+    let mut consensus = consensus
+        .with_backup_key(get_encryption_key(&opts.password, &salt)?)
+        .with_config_dir(
+            opts.data_dir.clone(),
+            get_encryption_key(&opts.password, &salt)?,
+        );
+    if let Some(retention) = opts.epoch_retention {
+        consensus = consensus.with_epoch_retention(retention);
+    }
 
     if let Some(epoch) = opts.upgrade_epoch {
         consensus.remove_upgrade_items(epoch).await?;
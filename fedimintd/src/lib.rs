@@ -4,6 +4,7 @@ use fedimint_core::{Amount, Tiered};
 use fedimint_mint_server::MintGenParams;
 use fedimint_wallet_server::WalletGenParams;
 
+mod metrics;
 mod ui;
 
 /// Module for creating `distributetgen` binary with custom modules
@@ -0,0 +1,31 @@
+use std::net::SocketAddr;
+
+use axum::routing::get;
+use axum::Router;
+use fedimint_core::task::TaskGroup;
+use tokio::select;
+use tracing::{debug, error};
+
+/// Serves the Prometheus metrics of the guardian on `/metrics` until the
+/// `task_group` shuts down
+pub async fn run_metrics_server(bind_addr: SocketAddr, task_group: TaskGroup) {
+    let app = Router::new().route("/metrics", get(metrics));
+
+    let shutdown_future = task_group.make_handle().make_shutdown_rx().await;
+    let server_future = axum::Server::bind(&bind_addr).serve(app.into_make_service());
+
+    debug!(%bind_addr, "Starting metrics server");
+    select! {
+        _ = shutdown_future => {
+            debug!("Metrics server shutting down");
+        },
+        Err(err) = server_future => {
+            error!(?err, "Metrics server encountered an error");
+        }
+    }
+}
+
+/// Prometheus metrics in the text exposition format
+async fn metrics() -> String {
+    fedimint_metrics::encode_metrics()
+}
@@ -13,13 +13,15 @@ use axum_macros::debug_handler;
 use bitcoin::Network;
 use fedimint_core::api::WsClientConnectInfo;
 use fedimint_core::bitcoin_rpc::BitcoindRpcBackend;
-use fedimint_core::config::{ClientConfig, ServerModuleGenRegistry};
+use fedimint_core::config::{ClientConfig, DkgError, ServerModuleGenRegistry};
 use fedimint_core::task::TaskGroup;
 use fedimint_core::util::SanitizedUrl;
 use fedimint_core::Amount;
 use fedimint_server::config::io::{
-    create_cert, parse_peer_params, write_server_config, CONSENSUS_CONFIG, JSON_EXT,
+    create_cert, parse_peer_params, read_dkg_transcript, write_dkg_transcript, write_server_config,
+    CONSENSUS_CONFIG, JSON_EXT,
 };
+use fedimint_server::config::session::DkgSession;
 use fedimint_server::config::{ServerConfig, ServerConfigConsensus, ServerConfigParams};
 use http::StatusCode;
 use qrcode_generator::QrCodeEcc;
@@ -56,8 +58,8 @@ struct RunTemplate {
 enum RunTemplateState {
     DkgNotStarted,
     DkgInProgress,
-    DkgDone(String),   // connnection string
-    DkgFailed(String), // error
+    DkgDone(String, String), // connnection string, verification code
+    DkgFailed(String),       // error
     LocalIoError(String),
 }
 
@@ -80,7 +82,13 @@ async fn run_page(axum::extract::State(state): axum::extract::State<MutableState
                                 .client;
                             let connect_info = WsClientConnectInfo::from_honest_peers(&cfg);
 
-                            RunTemplateState::DkgDone(connect_info.to_string())
+                            match read_dkg_transcript(&state.data_dir) {
+                                Ok(transcript) => RunTemplateState::DkgDone(
+                                    connect_info.to_string(),
+                                    transcript.verification_code(),
+                                ),
+                                Err(e) => RunTemplateState::LocalIoError(e.to_string()),
+                            }
                         }
                         Err(e) => RunTemplateState::LocalIoError(e.to_string()),
                     },
@@ -168,6 +176,7 @@ async fn post_guardians(
     state.dkg_task_group = Some(dkg_task_group.clone());
     let module_gens = state.module_gens.clone();
     let password = state.password.clone();
+    let socks5_proxy = state.socks5_proxy;
     state
         .task_group
         .spawn("admin UI running DKG", move |_| async move {
@@ -182,17 +191,40 @@ async fn post_guardians(
                 connection_strings,
                 &password,
                 configure_modules(max_denomination, params.network, params.finality_delay),
-            ) {
-                Ok(params) => {
-                    ServerConfig::distributed_gen(&params, module_gens.clone(), &mut dkg_task_group)
-                        .await
-                        .map_err(|e| format_err!("Failed {}", e))
-                }
+            )
+            .map(|params| params.with_socks5_proxy(socks5_proxy))
+            {
+                Ok(params) => match DkgSession::open(
+                    &dir_out_path,
+                    &password,
+                    params.our_id,
+                    &params.peer_ids,
+                ) {
+                    Ok(session) => {
+                        let result = ServerConfig::distributed_gen(
+                            &params,
+                            module_gens.clone(),
+                            session,
+                            &mut dkg_task_group,
+                        )
+                        .await;
+                        // a failed DKG can't be resumed, the next attempt starts over
+                        if let Err(DkgError::Failed(_)) = result {
+                            if let Err(e) = DkgSession::remove(&dir_out_path) {
+                                tracing::warn!("Unable to remove the failed DKG session: {e}");
+                            }
+                        }
+                        result.map_err(|e| format_err!("Failed {}", e))
+                    }
+                    Err(err) => Err(err),
+                },
                 Err(err) => Err(err),
             };
 
-            let write_result = maybe_config.and_then(|server| {
-                write_server_config(&server, dir_out_path, &password, &module_gens)
+            let write_result = maybe_config.and_then(|(server, transcript)| {
+                write_server_config(&server, dir_out_path.clone(), &password, &module_gens)?;
+                write_dkg_transcript(&transcript, &dir_out_path)?;
+                DkgSession::remove(&dir_out_path)
             });
 
             match write_result {
@@ -372,6 +404,7 @@ struct State {
     data_dir: PathBuf,
     sender: Sender<UiMessage>,
     password: String,
+    socks5_proxy: Option<SocketAddr>,
     task_group: TaskGroup,
     dkg_task_group: Option<TaskGroup>,
     module_gens: ServerModuleGenRegistry,
@@ -398,6 +431,7 @@ pub async fn run_ui(
     sender: Sender<UiMessage>,
     bind_addr: SocketAddr,
     password: String,
+    socks5_proxy: Option<SocketAddr>,
     task_group: TaskGroup,
     module_gens: ServerModuleGenRegistry,
 ) {
@@ -406,6 +440,7 @@ pub async fn run_ui(
         data_dir,
         sender,
         password,
+        socks5_proxy,
         task_group: task_group.clone(),
         dkg_task_group: None,
         module_gens,
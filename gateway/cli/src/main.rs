@@ -4,13 +4,16 @@ use bitcoin::{Address, Amount, Transaction};
 use clap::{Parser, Subcommand};
 use fedimint_core::config::FederationId;
 use fedimint_logging::TracingSetup;
+use ln_gateway::rebalance::{RebalanceConfig, RebalanceTarget};
 use ln_gateway::rpc::rpc_client::RpcClient;
 use ln_gateway::rpc::{
     BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, DepositPayload,
-    RestorePayload, WithdrawPayload,
+    RebalancePayload, RestorePayload, SetBalancePolicyPayload, SetRebalanceConfigPayload,
+    WithdrawPayload,
 };
 use mint_client::modules::wallet::txoproof::TxOutProof;
 use mint_client::utils::from_hex;
+use mint_client::GatewayBalancePolicy;
 use url::Url;
 
 #[derive(Parser)]
@@ -63,6 +66,42 @@ pub enum Commands {
     Backup { federation_id: FederationId },
     /// Restore ecash from last available snapshot or from scratch
     Restore { federation_id: FederationId },
+    /// Move ecash between federations according to the rebalance config
+    Rebalance {
+        /// Only print the planned transfers without executing them
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Configure rebalancing of ecash between federations
+    SetRebalanceConfig {
+        /// Target weight of a federation, given as `<federation_id>=<weight>`
+        #[clap(long = "target", value_parser = parse_rebalance_target)]
+        targets: Vec<RebalanceTarget>,
+        /// Tolerated deviation from the target balance in percent
+        #[clap(long, default_value = "10")]
+        threshold_percent: u64,
+        /// Smallest transfer worth executing
+        #[clap(long, default_value = "0")]
+        min_transfer: fedimint_core::Amount,
+        /// Rebalance automatically every given number of seconds
+        #[clap(long)]
+        interval_secs: Option<u64>,
+    },
+    /// Configure the bounds of the ecash balance held in a federation, omitted
+    /// bounds are removed
+    SetBalancePolicy {
+        federation_id: FederationId,
+        /// Balance below which incoming payments may fail and the operator is
+        /// warned
+        #[clap(long)]
+        min_balance: Option<fedimint_core::Amount>,
+        /// Balance above which the excess ecash is pegged out
+        #[clap(long)]
+        max_balance: Option<fedimint_core::Amount>,
+        /// Address receiving the pegged out excess ecash
+        #[clap(long)]
+        withdraw_address: Option<Address>,
+    },
 }
 
 #[tokio::main]
@@ -177,11 +216,79 @@ async fn main() -> anyhow::Result<()> {
 
             print_response(response).await;
         }
+        Commands::Rebalance { dry_run } => {
+            let response = client
+                .rebalance(
+                    source_password(cli.rpcpassword),
+                    RebalancePayload { dry_run },
+                )
+                .await
+                .expect("Failed to rebalance");
+
+            print_response(response).await;
+        }
+        Commands::SetRebalanceConfig {
+            targets,
+            threshold_percent,
+            min_transfer,
+            interval_secs,
+        } => {
+            let response = client
+                .set_rebalance_config(
+                    source_password(cli.rpcpassword),
+                    SetRebalanceConfigPayload {
+                        config: RebalanceConfig {
+                            targets,
+                            threshold_percent,
+                            min_transfer,
+                            interval_secs,
+                        },
+                    },
+                )
+                .await
+                .expect("Failed to set rebalance config");
+
+            print_response(response).await;
+        }
+        Commands::SetBalancePolicy {
+            federation_id,
+            min_balance,
+            max_balance,
+            withdraw_address,
+        } => {
+            let response = client
+                .set_balance_policy(
+                    source_password(cli.rpcpassword),
+                    SetBalancePolicyPayload {
+                        federation_id,
+                        policy: GatewayBalancePolicy {
+                            min_balance,
+                            max_balance,
+                            withdraw_address,
+                        },
+                    },
+                )
+                .await
+                .expect("Failed to set balance policy");
+
+            print_response(response).await;
+        }
     }
 
     Ok(())
 }
 
+fn parse_rebalance_target(s: &str) -> anyhow::Result<RebalanceTarget> {
+    let (federation_id, weight) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow::anyhow!("Expected <federation_id>=<weight>"))?;
+
+    Ok(RebalanceTarget {
+        federation_id: federation_id.parse()?,
+        weight: weight.parse()?,
+    })
+}
+
 pub async fn print_response(response: reqwest::Response) {
     match response.status() {
         reqwest::StatusCode::OK => {
@@ -1,15 +1,19 @@
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use bitcoin::{Address, Transaction};
 use bitcoin_hashes::{sha256, Hash};
 use fedimint_core::task::TaskGroup;
 use fedimint_core::{Amount, OutPoint, TransactionId};
 use futures::stream::StreamExt;
+use lightning_invoice::Invoice;
+use mint_client::api::WalletFederationApi;
+use mint_client::ln::incoming::ConfirmedInvoice;
 use mint_client::modules::ln::contracts::{ContractId, Preimage};
 use mint_client::modules::ln::route_hints::RouteHint;
 use mint_client::modules::wallet::txoproof::TxOutProof;
-use mint_client::{GatewayClient, PaymentParameters};
+use mint_client::{GatewayBalancePolicy, GatewayClient, PaymentParameters, UserClient};
 use rand::{CryptoRng, RngCore};
 use tracing::{debug, error, info, instrument, warn};
 
@@ -19,18 +23,31 @@ use crate::gatewaylnrpc::{
     SubscribeInterceptHtlcsResponse,
 };
 use crate::lnrpc_client::DynLnRpcClient;
-use crate::rpc::FederationInfo;
+use crate::metrics::{
+    ECASH_BALANCE_MSATS, HTLCS_CANCELLED, HTLCS_INTERCEPTED, HTLCS_SETTLED, PAYMENT_DURATION,
+};
+use crate::rpc::{FederationHealth, FederationInfo};
 use crate::utils::retry;
 use crate::{GatewayError, Result};
 
 /// How long a gateway announcement stays valid
 const GW_ANNOUNCEMENT_TTL: Duration = Duration::from_secs(600);
+/// How long the federation API may take to answer a health check
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Clone)]
 pub struct GatewayActor {
     client: Arc<GatewayClient>,
     lnrpc: DynLnRpcClient,
     task_group: TaskGroup,
+    /// Whether the HTLC interception stream for our channel is still open
+    htlc_subscription_active: Arc<AtomicBool>,
+    /// Whether the last balance check found less ecash than the configured
+    /// minimum balance
+    below_min_balance: Arc<AtomicBool>,
+    /// Limits on the ecash held in the federation, starting out as the one in
+    /// the client config and replaceable by the operator
+    balance_policy: Arc<Mutex<GatewayBalancePolicy>>,
 }
 
 #[derive(Debug, Clone)]
@@ -78,10 +95,14 @@ impl GatewayActor {
             }
         });
 
+        let balance_policy = Arc::new(Mutex::new(client.config().balance_policy));
         let actor = Self {
             client,
             lnrpc,
             task_group,
+            htlc_subscription_active: Arc::new(AtomicBool::new(false)),
+            below_min_balance: Arc::new(AtomicBool::new(false)),
+            balance_policy,
         };
 
         actor.subscribe_htlcs().await?;
@@ -99,9 +120,11 @@ impl GatewayActor {
             .subscribe_htlcs(SubscribeInterceptHtlcsRequest { short_channel_id })
             .await?;
         info!("Subscribed to HTLCs with {:?}", short_channel_id);
+        self.htlc_subscription_active.store(true, Ordering::SeqCst);
 
         let actor = self.to_owned();
         let lnrpc_copy = self.lnrpc.to_owned();
+        let federation_id = self.federation_id_label();
         tg.spawn(
             "Subscribe to intercepted HTLCs in stream",
             move |subscription| async move {
@@ -128,6 +151,9 @@ impl GatewayActor {
                         break;
                     }
 
+                    HTLCS_INTERCEPTED.with_label_values(&[&federation_id]).inc();
+                    let intercepted_at = Instant::now();
+
                     // TODO: Assert short channel id matches the one we subscribed to, or cancel
                     // processing of intercepted HTLC TODO: Assert the offered
                     // fee derived from invoice amount and outgoing amount is acceptable or cancel
@@ -140,6 +166,7 @@ impl GatewayActor {
                             let fail = "Failed to parse payment hash";
 
                             error!("{}: {:?}", fail, e);
+                            actor.observe_htlc_cancelled(&federation_id, intercepted_at);
                             let _ = lnrpc_copy
                                 .complete_htlc(CompleteHtlcsRequest {
                                     intercepted_htlc_id,
@@ -161,6 +188,7 @@ impl GatewayActor {
                         Ok((outpoint, contract_id)) => (outpoint, contract_id),
                         Err(e) => {
                             error!("Failed to buy preimage: {:?}", e);
+                            actor.observe_htlc_cancelled(&federation_id, intercepted_at);
                             // Note: this specific complete htlc requires no futher action.
                             // If we fail to send the complete htlc message, or get an error
                             // result, lightning node will still
@@ -188,6 +216,11 @@ impl GatewayActor {
                     {
                         Ok(preimage) => {
                             info!("Successfully processed intercepted HTLC");
+                            HTLCS_SETTLED.with_label_values(&[&federation_id]).inc();
+                            PAYMENT_DURATION
+                                .with_label_values(&[&federation_id, "incoming", "success"])
+                                .observe(intercepted_at.elapsed().as_secs_f64());
+                            actor.update_balance_metric().await;
                             if let Err(e) = lnrpc_copy
                                 .complete_htlc(CompleteHtlcsRequest {
                                     intercepted_htlc_id,
@@ -206,6 +239,7 @@ impl GatewayActor {
                         }
                         Err(e) => {
                             error!("Failed to process intercepted HTLC: {:?}", e);
+                            actor.observe_htlc_cancelled(&federation_id, intercepted_at);
                             // Note: this specific complete htlc requires no futher action.
                             // If we fail to send the complete htlc message, or get an error result,
                             // lightning node will still cancel HTCL after expiry period lapses.
@@ -221,6 +255,10 @@ impl GatewayActor {
                         }
                     };
                 }
+
+                actor
+                    .htlc_subscription_active
+                    .store(false, Ordering::SeqCst);
             },
         )
         .await;
@@ -228,6 +266,26 @@ impl GatewayActor {
         Ok(())
     }
 
+    fn federation_id_label(&self) -> String {
+        self.client.config().client_config.federation_id.to_string()
+    }
+
+    fn observe_htlc_cancelled(&self, federation_id: &str, intercepted_at: Instant) {
+        HTLCS_CANCELLED.with_label_values(&[federation_id]).inc();
+        PAYMENT_DURATION
+            .with_label_values(&[federation_id, "incoming", "failure"])
+            .observe(intercepted_at.elapsed().as_secs_f64());
+    }
+
+    /// Updates the balance gauge from the notes in our database without
+    /// fetching pending notes from the federation
+    async fn update_balance_metric(&self) {
+        let balance = self.client.notes().await.total_amount();
+        ECASH_BALANCE_MSATS
+            .with_label_values(&[&self.federation_id_label()])
+            .set(balance.msats as i64);
+    }
+
     async fn fetch_all_notes(&self) {
         if let Err(e) = self.client.fetch_all_notes().await {
             debug!(error = %e, "Fetching notes failed");
@@ -255,11 +313,21 @@ impl GatewayActor {
 
     #[instrument(skip_all, fields(%contract_id))]
     pub async fn pay_invoice(&self, contract_id: ContractId) -> Result<OutPoint> {
-        self.pay_invoice_buy_preimage_finalize_and_claim(
-            contract_id,
-            self.pay_invoice_buy_preimage(contract_id).await?,
-        )
-        .await
+        let started_at = Instant::now();
+        let result = match self.pay_invoice_buy_preimage(contract_id).await {
+            Ok(buy_preimage) => {
+                self.pay_invoice_buy_preimage_finalize_and_claim(contract_id, buy_preimage)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+
+        let outcome = if result.is_ok() { "success" } else { "failure" };
+        PAYMENT_DURATION
+            .with_label_values(&[&self.federation_id_label(), "outgoing", outcome])
+            .observe(started_at.elapsed().as_secs_f64());
+
+        result
     }
 
     #[instrument(skip_all, fields(%contract_id), err)]
@@ -492,17 +560,151 @@ impl GatewayActor {
         Ok(())
     }
 
+    /// Creates an invoice that pays into our ecash in this federation when it
+    /// is paid over Lightning. The invoice routes through another gateway of
+    /// the federation, since paying it through ourselves would move nothing.
+    pub async fn create_rebalance_invoice(&self, amount: Amount) -> Result<ConfirmedInvoice> {
+        let user = self.rebalance_user_client().await;
+        let rng = rand::rngs::OsRng;
+
+        Ok(user
+            .generate_confirmed_invoice(amount, "Gateway rebalance".to_string(), rng, None)
+            .await?)
+    }
+
+    /// Pays `invoice` with our ecash in this federation through another
+    /// gateway of the federation, falling back to the next one on failures
+    pub async fn pay_rebalance_invoice(&self, invoice: Invoice) -> Result<ContractId> {
+        let user = self.rebalance_user_client().await;
+        let rng = rand::rngs::OsRng;
+
+        self.fetch_all_notes().await;
+        Ok(user.pay_invoice_with_fallback(invoice, rng).await?)
+    }
+
+    /// Claims the ecash received for a paid rebalance `invoice`
+    pub async fn claim_rebalance_invoice(&self, invoice: &ConfirmedInvoice) -> Result<()> {
+        let user = self.client.as_user_client();
+        let rng = rand::rngs::OsRng;
+
+        let outpoint = user
+            .claim_incoming_contract(invoice.contract_id(), rng)
+            .await?;
+        user.await_outpoint_outcome(outpoint).await?;
+        self.client.fetch_notes(outpoint).await?;
+        self.update_balance_metric().await;
+        Ok(())
+    }
+
+    /// Our ecash as a user of the federation that never routes through our
+    /// own gateway
+    async fn rebalance_user_client(&self) -> UserClient {
+        let user = self.client.as_user_client();
+        user.blacklist_gateway(self.client.config().node_pub_key, true)
+            .await;
+        user
+    }
+
     pub async fn get_balance(&self) -> Result<Amount> {
         self.fetch_all_notes().await;
+        self.update_balance_metric().await;
 
         Ok(self.client.notes().await.total_amount())
     }
 
+    /// Applies the balance policy of the federation: ecash above the maximum
+    /// balance is pegged out, a balance below the minimum is reported.
+    ///
+    /// Returns the peg-out transaction if excess ecash was withdrawn.
+    pub async fn enforce_balance_policy(&self) -> Result<Option<TransactionId>> {
+        let policy = self.balance_policy();
+        if policy.min_balance.is_none() && policy.max_balance.is_none() {
+            return Ok(None);
+        }
+        policy.validate()?;
+
+        let federation_id = self.client.config().client_config.federation_id;
+        let balance = self.get_balance().await?;
+
+        let below_min_balance = policy.is_below_min(balance);
+        self.below_min_balance
+            .store(below_min_balance, Ordering::SeqCst);
+        if below_min_balance {
+            warn!(
+                %federation_id,
+                %balance,
+                "Ecash balance is below the configured minimum, incoming payments may fail"
+            );
+        }
+
+        if policy.excess(balance).is_none() {
+            return Ok(None);
+        }
+        let address = match &policy.withdraw_address {
+            Some(address) => address.clone(),
+            None => {
+                warn!(
+                    %federation_id,
+                    "Ecash balance exceeds the configured maximum but no withdraw address is set"
+                );
+                return Ok(None);
+            }
+        };
+        let dust_limit = address.script_pubkey().dust_value();
+        let excess = match policy.withdrawable_excess(balance, dust_limit) {
+            Some(excess) => excess,
+            None => {
+                debug!(%federation_id, %balance, "Excess ecash is below the dust limit");
+                return Ok(None);
+            }
+        };
+
+        let txid = self.withdraw(excess, address.clone()).await?;
+        info!(%federation_id, %excess, %address, %txid, "Withdrew excess ecash");
+        Ok(Some(txid))
+    }
+
+    pub fn balance_policy(&self) -> GatewayBalancePolicy {
+        self.balance_policy
+            .lock()
+            .expect("Balance policy lock poisoned")
+            .clone()
+    }
+
+    /// Replaces the balance policy, which is applied from the next balance
+    /// check on
+    pub fn set_balance_policy(&self, policy: GatewayBalancePolicy) {
+        *self
+            .balance_policy
+            .lock()
+            .expect("Balance policy lock poisoned") = policy;
+    }
+
+    /// Checks whether the federation API answers and whether we still
+    /// intercept HTLCs for the federation
+    pub async fn get_health(&self) -> FederationHealth {
+        let api_reachable = fedimint_core::task::timeout(
+            HEALTH_CHECK_TIMEOUT,
+            self.client.context().api.fetch_consensus_block_height(),
+        )
+        .await
+        .map(|res| res.is_ok())
+        .unwrap_or(false);
+
+        FederationHealth {
+            federation_id: self.client.config().client_config.federation_id,
+            api_reachable,
+            htlc_subscription_active: self.htlc_subscription_active.load(Ordering::SeqCst),
+        }
+    }
+
     pub fn get_info(&self) -> Result<FederationInfo> {
         let cfg = self.client.config();
         Ok(FederationInfo {
             federation_id: cfg.client_config.federation_id.clone(),
             mint_pubkey: cfg.redeem_key.x_only_public_key().0,
+            balance_policy: self.balance_policy(),
+            below_min_balance: self.below_min_balance.load(Ordering::SeqCst),
         })
     }
 }
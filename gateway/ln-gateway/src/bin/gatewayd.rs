@@ -41,6 +41,19 @@ pub struct GatewayOpts {
     /// Public URL to a Gateway Lightning rpc service
     #[arg(long = "lnrpc-addr", env = "FM_GATEWAY_LIGHTNING_ADDR")]
     pub lnrpc_addr: Url,
+
+    /// Export traces to an OpenTelemetry collector at this OTLP/gRPC endpoint,
+    /// requires the `telemetry` feature
+    #[arg(long = "otlp-endpoint", env = "FM_GATEWAY_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+
+    /// Log as JSON lines instead of human readable text
+    #[arg(
+        long = "log-json",
+        env = "FM_GATEWAY_LOG_JSON",
+        default_value = "false"
+    )]
+    pub log_json: bool,
 }
 
 // Fedimint Gateway Binary
@@ -51,7 +64,6 @@ pub struct GatewayOpts {
 /// remote Lightning node accessible through a `GatewayLightningServer`.
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    TracingSetup::default().init()?;
     let mut args = std::env::args();
 
     if let Some(ref arg) = args.nth(1) {
@@ -68,8 +80,23 @@ async fn main() -> Result<(), anyhow::Error> {
         api_addr,
         lnrpc_addr,
         password,
+        otlp_endpoint,
+        log_json,
     } = GatewayOpts::parse();
 
+    let mut tracing_setup = TracingSetup::default();
+    #[cfg(feature = "telemetry")]
+    tracing_setup
+        .with_otlp(otlp_endpoint)
+        .with_service_name("gatewayd");
+    #[cfg(not(feature = "telemetry"))]
+    if otlp_endpoint.is_some() {
+        anyhow::bail!(
+            "gatewayd was built without the telemetry feature, --otlp-endpoint is unavailable"
+        );
+    }
+    tracing_setup.with_json(log_json).init()?;
+
     info!(
         "Starting gateway with these configs \n data directory: {:?},\n listen: {},\n api address: {},\n lnrpc address: {} ",
         data_dir, listen, api_addr, lnrpc_addr
@@ -118,12 +145,13 @@ async fn main() -> Result<(), anyhow::Error> {
     )
     .await;
 
-    if let Err(e) = gateway.run(listen, password).await {
+    let result = gateway.run(listen, password).await;
+    if let Err(e) = &result {
         task_group.shutdown_join_all(None).await?;
 
         error!("Gateway stopped with error: {}", e);
-        return Err(e.into());
     }
 
-    Ok(())
+    fedimint_logging::shutdown_telemetry();
+    Ok(result?)
 }
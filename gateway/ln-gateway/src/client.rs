@@ -13,13 +13,19 @@ use fedimint_core::db::mem_impl::MemDatabase;
 use fedimint_core::db::Database;
 use fedimint_core::dyn_newtype_define;
 use fedimint_core::module::registry::ModuleDecoderRegistry;
-use mint_client::{module_decode_stubs, Client, GatewayClientConfig};
+use mint_client::{module_decode_stubs, Client, GatewayBalancePolicy, GatewayClientConfig};
 use secp256k1::{KeyPair, PublicKey};
+use serde::Serialize;
 use tracing::{debug, warn};
 use url::Url;
 
+use crate::rebalance::RebalanceConfig;
 use crate::{GatewayError, Result};
 
+/// File in the work directory holding the rebalancing policy, not ending in
+/// `.json` so it isn't mistaken for a federation client config
+const REBALANCE_CONFIG_FILE: &str = "rebalance.config";
+
 pub trait IDbFactory: Debug {
     fn create_database(
         &self,
@@ -92,6 +98,19 @@ pub trait IGatewayClientBuilder: Debug {
 
     /// Load all gateway client configs from the work directory
     fn load_configs(&self) -> Result<Vec<GatewayClientConfig>>;
+
+    /// Replace the balance policy in the saved client config of a federation
+    fn save_balance_policy(
+        &self,
+        federation_id: FederationId,
+        policy: &GatewayBalancePolicy,
+    ) -> Result<()>;
+
+    /// Save and persist the rebalancing policy of the gateway
+    fn save_rebalance_config(&self, config: &RebalanceConfig) -> Result<()>;
+
+    /// Load the rebalancing policy from the work directory, if one was saved
+    fn load_rebalance_config(&self) -> Result<Option<RebalanceConfig>>;
 }
 
 dyn_newtype_define! {
@@ -161,6 +180,7 @@ impl IGatewayClientBuilder for StandardGatewayClientBuilder {
             timelock_delta: 10,
             node_pub_key: node_pubkey,
             api: self.gateway_api.clone(),
+            balance_policy: Default::default(),
         })
     }
 
@@ -219,4 +239,45 @@ impl IGatewayClientBuilder for StandardGatewayClientBuilder {
             })
             .collect())
     }
+
+    fn save_balance_policy(
+        &self,
+        federation_id: FederationId,
+        policy: &GatewayBalancePolicy,
+    ) -> Result<()> {
+        let path = self.work_dir.join(format!("{federation_id}.json"));
+        let mut config = load_from_file::<GatewayClientConfig>(&path)?;
+        config.balance_policy = policy.clone();
+
+        debug!("Saving balance policy in {}", path.display());
+        write_atomically(&path, &config)
+    }
+
+    fn save_rebalance_config(&self, config: &RebalanceConfig) -> Result<()> {
+        let path = self.work_dir.join(REBALANCE_CONFIG_FILE);
+
+        debug!("Saving rebalance config in {}", path.display());
+        write_atomically(&path, config)
+    }
+
+    fn load_rebalance_config(&self) -> Result<Option<RebalanceConfig>> {
+        let path = self.work_dir.join(REBALANCE_CONFIG_FILE);
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        Ok(Some(load_from_file(&path)?))
+    }
+}
+
+/// Writes `value` as JSON to a temporary file that replaces `path` once it was
+/// synced, so a crash never leaves a partially written file behind
+fn write_atomically<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let file = File::create(&tmp_path).map_err(anyhow::Error::new)?;
+    serde_json::to_writer_pretty(&file, value).map_err(anyhow::Error::new)?;
+    file.sync_all().map_err(anyhow::Error::new)?;
+    std::fs::rename(&tmp_path, path).map_err(anyhow::Error::new)?;
+
+    Ok(())
 }
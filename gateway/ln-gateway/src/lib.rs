@@ -1,6 +1,8 @@
 pub mod actor;
 pub mod client;
 pub mod lnrpc_client;
+pub mod metrics;
+pub mod rebalance;
 pub mod rpc;
 pub mod types;
 pub mod utils;
@@ -25,8 +27,9 @@ use fedimint_client::module::gen::ClientModuleGenRegistry;
 use fedimint_core::api::{FederationError, WsClientConnectInfo};
 use fedimint_core::config::FederationId;
 use fedimint_core::module::registry::ModuleDecoderRegistry;
-use fedimint_core::task::TaskGroup;
+use fedimint_core::task::{sleep, TaskGroup, TaskHandle};
 use fedimint_core::{Amount, TransactionId};
+use futures::future::{join, join_all};
 use mint_client::ln::PayInvoicePayload;
 use mint_client::modules::ln::route_hints::RouteHint;
 use mint_client::{ClientError, GatewayClient};
@@ -39,14 +42,20 @@ use crate::actor::GatewayActor;
 use crate::client::DynGatewayClientBuilder;
 use crate::gatewaylnrpc::GetPubKeyResponse;
 use crate::lnrpc_client::DynLnRpcClient;
+use crate::rebalance::Rebalancer;
 use crate::rpc::rpc_server::run_webserver;
 use crate::rpc::{
     BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, DepositPayload,
-    GatewayInfo, GatewayRequest, GatewayRpcSender, InfoPayload, RestorePayload, WithdrawPayload,
+    GatewayHealth, GatewayInfo, GatewayRequest, GatewayRpcSender, HealthPayload, InfoPayload,
+    RebalancePayload, RestorePayload, SetBalancePolicyPayload, SetRebalanceConfigPayload,
+    WithdrawPayload,
 };
 
 const ROUTE_HINT_RETRIES: usize = 10;
 const ROUTE_HINT_RETRY_SLEEP: Duration = Duration::from_secs(2);
+/// How often the balance policies of the federations are enforced
+const BALANCE_POLICY_INTERVAL: Duration = Duration::from_secs(60);
+const BALANCE_POLICY_POLL_INTERVAL: Duration = Duration::from_secs(1);
 
 pub type Result<T> = std::result::Result<T, GatewayError>;
 
@@ -73,12 +82,13 @@ pub struct Gateway {
     decoders: ModuleDecoderRegistry,
     module_gens: ClientModuleGenRegistry,
     lnrpc: DynLnRpcClient,
-    actors: Mutex<HashMap<String, Arc<GatewayActor>>>,
+    actors: Arc<Mutex<HashMap<String, Arc<GatewayActor>>>>,
     client_builder: DynGatewayClientBuilder,
     sender: mpsc::Sender<GatewayRequest>,
     receiver: mpsc::Receiver<GatewayRequest>,
     task_group: TaskGroup,
     channel_id_generator: AtomicU64,
+    rebalancer: Rebalancer,
 }
 
 impl Gateway {
@@ -116,11 +126,13 @@ impl Gateway {
             tokio::time::sleep(ROUTE_HINT_RETRY_SLEEP).await;
         };
 
+        let actors = Arc::new(Mutex::new(HashMap::new()));
         let gw = Self {
             lnrpc,
-            actors: Mutex::new(HashMap::new()),
+            actors: actors.clone(),
             sender,
             receiver,
+            rebalancer: Rebalancer::new(actors, client_builder.clone()),
             client_builder,
             task_group,
             channel_id_generator: AtomicU64::new(0),
@@ -265,6 +277,33 @@ impl Gateway {
         })
     }
 
+    /// Checks the Lightning node and all federations at once, so that an
+    /// unreachable federation only delays the answer by a single timeout
+    async fn handle_health_msg(
+        lnrpc: DynLnRpcClient,
+        actors: Arc<Mutex<HashMap<String, Arc<GatewayActor>>>>,
+        _payload: HealthPayload,
+    ) -> Result<GatewayHealth> {
+        let lightning_connected = async {
+            match lnrpc.pubkey().await {
+                Ok(_) => true,
+                Err(e) => {
+                    warn!("Lightning node health check failed: {}", e);
+                    false
+                }
+            }
+        };
+
+        let actors = actors.lock().await.clone();
+        let federations = join_all(actors.values().map(|actor| actor.get_health()));
+
+        let (lightning_connected, federations) = join(lightning_connected, federations).await;
+        Ok(GatewayHealth {
+            lightning_connected,
+            federations,
+        })
+    }
+
     async fn handle_pay_invoice_msg(&self, payload: PayInvoicePayload) -> Result<()> {
         let PayInvoicePayload {
             federation_id,
@@ -333,6 +372,51 @@ impl Gateway {
         self.select_actor(federation_id).await?.restore().await
     }
 
+    async fn handle_set_rebalance_config_msg(
+        &self,
+        SetRebalanceConfigPayload { config }: SetRebalanceConfigPayload,
+    ) -> Result<()> {
+        self.rebalancer.set_config(config).await
+    }
+
+    async fn handle_set_balance_policy_msg(
+        &self,
+        SetBalancePolicyPayload {
+            federation_id,
+            policy,
+        }: SetBalancePolicyPayload,
+    ) -> Result<()> {
+        policy.validate()?;
+        let actor = self.select_actor(federation_id.clone()).await?;
+        self.client_builder
+            .save_balance_policy(federation_id, &policy)?;
+        actor.set_balance_policy(policy);
+        Ok(())
+    }
+
+    /// Enforces the balance policies of all federations every
+    /// `BALANCE_POLICY_INTERVAL` until shut down
+    async fn run_balance_policies(
+        actors: Arc<Mutex<HashMap<String, Arc<GatewayActor>>>>,
+        task_handle: TaskHandle,
+    ) {
+        let mut last_check = Instant::now();
+        while !task_handle.is_shutting_down() {
+            sleep(BALANCE_POLICY_POLL_INTERVAL).await;
+            if last_check.elapsed() < BALANCE_POLICY_INTERVAL {
+                continue;
+            }
+            last_check = Instant::now();
+
+            let actors = actors.lock().await.clone();
+            for (federation_id, actor) in actors {
+                if let Err(e) = actor.enforce_balance_policy().await {
+                    warn!(%federation_id, "Enforcing balance policy failed: {}", e);
+                }
+            }
+        }
+    }
+
     pub async fn run(mut self, listen: SocketAddr, password: String) -> Result<()> {
         let mut tg = self.task_group.clone();
 
@@ -348,6 +432,18 @@ impl Gateway {
         })
         .await;
 
+        let rebalancer = self.rebalancer.clone();
+        tg.spawn("Gateway auto rebalance", move |handle| {
+            rebalancer.run_auto_rebalance(handle)
+        })
+        .await;
+
+        let actors = self.actors.clone();
+        tg.spawn("Gateway balance policies", move |handle| {
+            Self::run_balance_policies(actors, handle)
+        })
+        .await;
+
         // TODO: try to drive forward outgoing and incoming payments that were
         // interrupted
         let loop_ctrl = tg.make_handle();
@@ -366,6 +462,19 @@ impl Gateway {
                     GatewayRequest::Info(inner) => {
                         inner.handle(|payload| self.handle_get_info(payload)).await;
                     }
+                    GatewayRequest::Health(inner) => {
+                        // Unreachable federations must not block the request loop
+                        let lnrpc = self.lnrpc.clone();
+                        let actors = self.actors.clone();
+                        tg.spawn("Gateway health check", move |_| async move {
+                            inner
+                                .handle(|payload| {
+                                    Self::handle_health_msg(lnrpc.clone(), actors.clone(), payload)
+                                })
+                                .await;
+                        })
+                        .await;
+                    }
                     GatewayRequest::ConnectFederation(inner) => {
                         let route_hints: Vec<RouteHint> =
                             self.lnrpc.routehints().await?.try_into()?;
@@ -410,6 +519,29 @@ impl Gateway {
                             .handle(|payload| self.handle_restore_msg(payload))
                             .await;
                     }
+                    GatewayRequest::Rebalance(inner) => {
+                        // Transfers wait for Lightning payments, so they must not block
+                        // the request loop
+                        let rebalancer = self.rebalancer.clone();
+                        tg.spawn("Gateway rebalance", move |_| async move {
+                            inner
+                                .handle(|RebalancePayload { dry_run }| {
+                                    rebalancer.rebalance(dry_run)
+                                })
+                                .await;
+                        })
+                        .await;
+                    }
+                    GatewayRequest::SetRebalanceConfig(inner) => {
+                        inner
+                            .handle(|payload| self.handle_set_rebalance_config_msg(payload))
+                            .await;
+                    }
+                    GatewayRequest::SetBalancePolicy(inner) => {
+                        inner
+                            .handle(|payload| self.handle_set_balance_policy_msg(payload))
+                            .await;
+                    }
                 }
             }
 
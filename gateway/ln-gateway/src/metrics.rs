@@ -0,0 +1,75 @@
+//! Prometheus metrics of the gateway, exposed on the `/metrics` route
+
+use fedimint_metrics::{
+    histogram_opts, opts, register_histogram_vec_with_registry,
+    register_int_counter_vec_with_registry, register_int_gauge_vec_with_registry, HistogramVec,
+    IntCounterVec, IntGaugeVec, NETWORK_LATENCY_BUCKETS, REGISTRY,
+};
+use once_cell::sync::Lazy;
+
+/// HTLCs intercepted for a federation's virtual channel
+pub static HTLCS_INTERCEPTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        opts!(
+            "gateway_htlcs_intercepted_total",
+            "HTLCs intercepted for a federation"
+        ),
+        &["federation_id"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// HTLCs settled with a preimage bought from the federation
+pub static HTLCS_SETTLED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        opts!(
+            "gateway_htlcs_settled_total",
+            "Intercepted HTLCs settled with a preimage"
+        ),
+        &["federation_id"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// HTLCs cancelled because buying the preimage failed
+pub static HTLCS_CANCELLED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec_with_registry!(
+        opts!(
+            "gateway_htlcs_cancelled_total",
+            "Intercepted HTLCs cancelled"
+        ),
+        &["federation_id"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Duration of payments routed by the gateway, `direction` is either
+/// `incoming` (Lightning to federation) or `outgoing` (federation to Lightning)
+pub static PAYMENT_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec_with_registry!(
+        histogram_opts!(
+            "gateway_payment_duration_seconds",
+            "Duration of routed payments",
+            NETWORK_LATENCY_BUCKETS.to_vec()
+        ),
+        &["federation_id", "direction", "outcome"],
+        REGISTRY
+    )
+    .unwrap()
+});
+
+/// Ecash held by the gateway in each federation
+pub static ECASH_BALANCE_MSATS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec_with_registry!(
+        opts!(
+            "gateway_ecash_balance_msats",
+            "Ecash held by the gateway per federation"
+        ),
+        &["federation_id"],
+        REGISTRY
+    )
+    .unwrap()
+});
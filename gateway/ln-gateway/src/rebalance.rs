@@ -0,0 +1,363 @@
+//! Rebalancing of gateway ecash between connected federations
+//!
+//! A gateway that serves several federations tends to accumulate ecash in the
+//! federations whose users mostly pay out over Lightning, while it runs dry in
+//! the federations whose users mostly receive. Rebalancing moves the surplus of
+//! the former to the latter according to operator configured target ratios.
+//!
+//! Every transfer is a Lightning payment: the receiving federation's actor
+//! creates an invoice as a user of its federation and the sending federation's
+//! actor pays it as a user of its own. Both payments are routed through other
+//! gateways of the federations, so the ecash actually changes hands.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use fedimint_core::config::FederationId;
+use fedimint_core::task::{sleep, TaskHandle};
+use fedimint_core::Amount;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::actor::GatewayActor;
+use crate::client::DynGatewayClientBuilder;
+use crate::rpc::RebalanceResult;
+use crate::{GatewayError, Result};
+
+/// How often the automatic rebalancing task checks whether its interval
+/// elapsed
+const AUTO_REBALANCE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Operator configured policy used to plan rebalancing transfers
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RebalanceConfig {
+    /// Relative weights of the federations taking part in rebalancing. The
+    /// target balance of a federation is its share of the sum of all weights
+    /// applied to the total ecash held in these federations. Federations
+    /// without a target are never rebalanced.
+    pub targets: Vec<RebalanceTarget>,
+    /// How far (in percent of its target) a federation balance may deviate
+    /// before it is considered out of balance
+    pub threshold_percent: u64,
+    /// Transfers smaller than this amount are not worth their fees and are
+    /// skipped
+    pub min_transfer: Amount,
+    /// If set, the gateway plans and executes a rebalance automatically every
+    /// `interval_secs` seconds
+    pub interval_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RebalanceTarget {
+    pub federation_id: FederationId,
+    pub weight: u64,
+}
+
+/// A single planned movement of ecash from one federation to another
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RebalanceTransfer {
+    pub from: FederationId,
+    pub to: FederationId,
+    pub amount: Amount,
+}
+
+/// Plan the transfers needed to bring `balances` within the thresholds of the
+/// targets configured in `config`.
+///
+/// Federations with the largest surplus are paired with the federations with
+/// the largest deficit first, so the plan uses as few transfers as possible.
+pub fn plan_rebalance(
+    balances: &[(FederationId, Amount)],
+    config: &RebalanceConfig,
+) -> Vec<RebalanceTransfer> {
+    let weights = config
+        .targets
+        .iter()
+        .filter(|target| target.weight > 0)
+        .map(|target| (target.federation_id.clone(), target.weight))
+        .collect::<HashMap<_, _>>();
+
+    let balances = balances
+        .iter()
+        .filter(|(federation_id, _)| weights.contains_key(federation_id))
+        .collect::<Vec<_>>();
+
+    let total_weight = balances
+        .iter()
+        .map(|(federation_id, _)| weights[federation_id] as u128)
+        .sum::<u128>();
+    if total_weight == 0 {
+        return vec![];
+    }
+    let total_balance = balances
+        .iter()
+        .map(|(_, amount)| amount.msats as u128)
+        .sum::<u128>();
+
+    let mut surpluses = vec![];
+    let mut deficits = vec![];
+    for (federation_id, balance) in balances {
+        let target = total_balance * weights[federation_id] as u128 / total_weight;
+        let tolerance = target * config.threshold_percent as u128 / 100;
+        let balance = balance.msats as u128;
+
+        if balance > target + tolerance {
+            surpluses.push((federation_id.clone(), balance - target));
+        } else if balance + tolerance < target {
+            deficits.push((federation_id.clone(), target - balance));
+        }
+    }
+
+    surpluses.sort_by(|(_, a), (_, b)| b.cmp(a));
+    deficits.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+    let mut transfers = vec![];
+    let mut surpluses = surpluses.into_iter().peekable();
+    let mut deficits = deficits.into_iter().peekable();
+    while let (Some((from, surplus)), Some((to, deficit))) =
+        (surpluses.peek_mut(), deficits.peek_mut())
+    {
+        let amount = (*surplus).min(*deficit);
+        *surplus -= amount;
+        *deficit -= amount;
+
+        let amount = Amount::from_msats(amount as u64);
+        if config.min_transfer <= amount {
+            transfers.push(RebalanceTransfer {
+                from: from.clone(),
+                to: to.clone(),
+                amount,
+            });
+        }
+
+        if *surplus == 0 {
+            surpluses.next();
+        }
+        if *deficit == 0 {
+            deficits.next();
+        }
+    }
+
+    transfers
+}
+
+/// Plans and executes rebalancing transfers, shared by the gateway's request
+/// loop and the task that rebalances automatically
+#[derive(Clone)]
+pub struct Rebalancer {
+    actors: Arc<Mutex<HashMap<String, Arc<GatewayActor>>>>,
+    config: Arc<Mutex<RebalanceConfig>>,
+    client_builder: DynGatewayClientBuilder,
+    /// Held while transfers are executed so concurrent rebalances don't move
+    /// the same surplus twice
+    running: Arc<Mutex<()>>,
+}
+
+impl Rebalancer {
+    /// Creates a rebalancer for `actors` with the config saved by
+    /// `client_builder`, if any
+    pub fn new(
+        actors: Arc<Mutex<HashMap<String, Arc<GatewayActor>>>>,
+        client_builder: DynGatewayClientBuilder,
+    ) -> Self {
+        let config = client_builder
+            .load_rebalance_config()
+            .unwrap_or_else(|e| {
+                warn!("Could not load rebalance config: {}", e);
+                None
+            })
+            .unwrap_or_default();
+
+        Rebalancer {
+            actors,
+            config: Arc::new(Mutex::new(config)),
+            client_builder,
+            running: Default::default(),
+        }
+    }
+
+    /// Validates and persists a new rebalancing policy
+    pub async fn set_config(&self, config: RebalanceConfig) -> Result<()> {
+        {
+            let actors = self.actors.lock().await;
+            if let Some(target) = config
+                .targets
+                .iter()
+                .find(|target| !actors.contains_key(&target.federation_id.to_string()))
+            {
+                return Err(GatewayError::Other(anyhow!(
+                    "No federation with id {}",
+                    target.federation_id
+                )));
+            }
+        }
+
+        self.client_builder.save_rebalance_config(&config)?;
+        *self.config.lock().await = config;
+        Ok(())
+    }
+
+    /// Plans the transfers needed to rebalance the federations and executes
+    /// them unless `dry_run` is set
+    pub async fn rebalance(&self, dry_run: bool) -> Result<RebalanceResult> {
+        let _running = self.running.lock().await;
+        let config = self.config.lock().await.clone();
+        let actors = self.actors.lock().await.clone();
+
+        let mut balances = vec![];
+        for actor in actors.values() {
+            balances.push((actor.get_info()?.federation_id, actor.get_balance().await?));
+        }
+
+        let transfers = plan_rebalance(&balances, &config);
+        if dry_run {
+            return Ok(RebalanceResult {
+                transfers,
+                contracts: vec![],
+            });
+        }
+
+        let mut contracts = vec![];
+        for transfer in &transfers {
+            let from = select_actor(&actors, &transfer.from)?;
+            let to = select_actor(&actors, &transfer.to)?;
+
+            let invoice = to.create_rebalance_invoice(transfer.amount).await?;
+            from.pay_rebalance_invoice(invoice.invoice.clone()).await?;
+            to.claim_rebalance_invoice(&invoice).await?;
+
+            let contract_id = invoice.contract_id();
+            info!(
+                from = %transfer.from,
+                to = %transfer.to,
+                amount = %transfer.amount,
+                %contract_id,
+                "Rebalanced ecash between federations"
+            );
+            contracts.push(contract_id);
+        }
+
+        Ok(RebalanceResult {
+            transfers,
+            contracts,
+        })
+    }
+
+    /// Rebalances every `interval_secs` of the config until shut down
+    pub async fn run_auto_rebalance(self, task_handle: TaskHandle) {
+        let mut last_rebalance = Instant::now();
+        while !task_handle.is_shutting_down() {
+            sleep(AUTO_REBALANCE_POLL_INTERVAL).await;
+
+            let interval = match self.config.lock().await.interval_secs {
+                Some(interval) => Duration::from_secs(interval),
+                None => continue,
+            };
+            if last_rebalance.elapsed() < interval {
+                continue;
+            }
+
+            if let Err(e) = self.rebalance(false).await {
+                warn!("Automatic rebalance failed: {}", e);
+            }
+            last_rebalance = Instant::now();
+        }
+    }
+}
+
+fn select_actor(
+    actors: &HashMap<String, Arc<GatewayActor>>,
+    federation_id: &FederationId,
+) -> Result<Arc<GatewayActor>> {
+    actors
+        .get(&federation_id.to_string())
+        .cloned()
+        .ok_or_else(|| GatewayError::Other(anyhow!("No federation with id {}", federation_id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use fedimint_core::config::FederationId;
+    use fedimint_core::{msats, Amount};
+
+    use super::{plan_rebalance, RebalanceConfig, RebalanceTarget, RebalanceTransfer};
+
+    fn federation_id() -> FederationId {
+        FederationId(threshold_crypto::SecretKey::random().public_key())
+    }
+
+    fn config(targets: &[(FederationId, u64)], threshold_percent: u64) -> RebalanceConfig {
+        RebalanceConfig {
+            targets: targets
+                .iter()
+                .map(|(federation_id, weight)| RebalanceTarget {
+                    federation_id: federation_id.clone(),
+                    weight: *weight,
+                })
+                .collect(),
+            threshold_percent,
+            min_transfer: Amount::ZERO,
+            interval_secs: None,
+        }
+    }
+
+    #[test]
+    fn plan_moves_surplus_to_deficit() {
+        let (a, b) = (federation_id(), federation_id());
+        let config = config(&[(a.clone(), 1), (b.clone(), 1)], 10);
+
+        let transfers =
+            plan_rebalance(&[(a.clone(), msats(900)), (b.clone(), msats(100))], &config);
+
+        assert_eq!(
+            transfers,
+            vec![RebalanceTransfer {
+                from: a,
+                to: b,
+                amount: msats(400),
+            }]
+        );
+    }
+
+    #[test]
+    fn plan_respects_threshold_and_min_transfer() {
+        let (a, b) = (federation_id(), federation_id());
+        let mut config = config(&[(a.clone(), 1), (b.clone(), 1)], 20);
+        let balances = [(a, msats(550)), (b, msats(450))];
+
+        assert!(plan_rebalance(&balances, &config).is_empty());
+
+        config.threshold_percent = 0;
+        assert_eq!(plan_rebalance(&balances, &config).len(), 1);
+
+        config.min_transfer = msats(51);
+        assert!(plan_rebalance(&balances, &config).is_empty());
+    }
+
+    #[test]
+    fn plan_ignores_federations_without_target() {
+        let (a, b, c) = (federation_id(), federation_id(), federation_id());
+        let config = config(&[(a.clone(), 3), (b.clone(), 1)], 0);
+
+        let transfers = plan_rebalance(
+            &[
+                (a.clone(), msats(0)),
+                (b.clone(), msats(400)),
+                (c, msats(1_000_000)),
+            ],
+            &config,
+        );
+
+        assert_eq!(
+            transfers,
+            vec![RebalanceTransfer {
+                from: b,
+                to: a,
+                amount: msats(300),
+            }]
+        );
+    }
+}
@@ -11,11 +11,14 @@ use fedimint_core::config::FederationId;
 use fedimint_core::{Amount, TransactionId};
 use futures::Future;
 use mint_client::ln::PayInvoicePayload;
+use mint_client::modules::ln::contracts::ContractId;
 use mint_client::modules::wallet::txoproof::TxOutProof;
+use mint_client::GatewayBalancePolicy;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use tokio::sync::{mpsc, oneshot};
 use tracing::error;
 
+use crate::rebalance::{RebalanceConfig, RebalanceTransfer};
 use crate::{GatewayError, Result};
 
 #[derive(Debug, Clone)]
@@ -103,10 +106,68 @@ pub struct WithdrawPayload {
     pub address: Address,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RebalancePayload {
+    /// Only plan the transfers without executing them
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetRebalanceConfigPayload {
+    pub config: RebalanceConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetBalancePolicyPayload {
+    pub federation_id: FederationId,
+    pub policy: GatewayBalancePolicy,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RebalanceResult {
+    pub transfers: Vec<RebalanceTransfer>,
+    /// Incoming contracts that received the executed transfers, empty on a dry
+    /// run
+    pub contracts: Vec<ContractId>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FederationInfo {
     pub federation_id: FederationId,
     pub mint_pubkey: XOnlyPublicKey,
+    pub balance_policy: GatewayBalancePolicy,
+    /// Whether the last balance check found less ecash than the policy's
+    /// minimum balance
+    pub below_min_balance: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HealthPayload;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FederationHealth {
+    pub federation_id: FederationId,
+    /// Whether the federation API answered a request in time
+    pub api_reachable: bool,
+    /// Whether we still intercept HTLCs for the federation's virtual channel
+    pub htlc_subscription_active: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GatewayHealth {
+    /// Whether the Lightning node answered a request
+    pub lightning_connected: bool,
+    pub federations: Vec<FederationHealth>,
+}
+
+impl GatewayHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.lightning_connected
+            && self
+                .federations
+                .iter()
+                .all(|fed| fed.api_reachable && fed.htlc_subscription_active)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -118,6 +179,7 @@ pub struct GatewayInfo {
 #[derive(Debug)]
 pub enum GatewayRequest {
     Info(GatewayRequestInner<InfoPayload>),
+    Health(GatewayRequestInner<HealthPayload>),
     ConnectFederation(GatewayRequestInner<ConnectFedPayload>),
     PayInvoice(GatewayRequestInner<PayInvoicePayload>),
     Balance(GatewayRequestInner<BalancePayload>),
@@ -126,6 +188,9 @@ pub enum GatewayRequest {
     Withdraw(GatewayRequestInner<WithdrawPayload>),
     Backup(GatewayRequestInner<BackupPayload>),
     Restore(GatewayRequestInner<RestorePayload>),
+    Rebalance(GatewayRequestInner<RebalancePayload>),
+    SetRebalanceConfig(GatewayRequestInner<SetRebalanceConfigPayload>),
+    SetBalancePolicy(GatewayRequestInner<SetBalancePolicyPayload>),
 }
 
 #[derive(Debug)]
@@ -155,6 +220,7 @@ macro_rules! impl_gateway_request_trait {
 }
 
 impl_gateway_request_trait!(InfoPayload, GatewayInfo, GatewayRequest::Info);
+impl_gateway_request_trait!(HealthPayload, GatewayHealth, GatewayRequest::Health);
 impl_gateway_request_trait!(ConnectFedPayload, (), GatewayRequest::ConnectFederation);
 impl_gateway_request_trait!(PayInvoicePayload, (), GatewayRequest::PayInvoice);
 impl_gateway_request_trait!(BalancePayload, Amount, GatewayRequest::Balance);
@@ -167,6 +233,17 @@ impl_gateway_request_trait!(DepositPayload, TransactionId, GatewayRequest::Depos
 impl_gateway_request_trait!(WithdrawPayload, TransactionId, GatewayRequest::Withdraw);
 impl_gateway_request_trait!(BackupPayload, (), GatewayRequest::Backup);
 impl_gateway_request_trait!(RestorePayload, (), GatewayRequest::Restore);
+impl_gateway_request_trait!(RebalancePayload, RebalanceResult, GatewayRequest::Rebalance);
+impl_gateway_request_trait!(
+    SetRebalanceConfigPayload,
+    (),
+    GatewayRequest::SetRebalanceConfig
+);
+impl_gateway_request_trait!(
+    SetBalancePolicyPayload,
+    (),
+    GatewayRequest::SetBalancePolicy
+);
 
 impl<T> GatewayRequestInner<T>
 where
@@ -6,7 +6,8 @@ use url::Url;
 
 use super::{
     BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, DepositPayload,
-    RestorePayload, WithdrawPayload,
+    RebalancePayload, RestorePayload, SetBalancePolicyPayload, SetRebalanceConfigPayload,
+    WithdrawPayload,
 };
 
 pub struct RpcClient {
@@ -92,6 +93,39 @@ impl RpcClient {
         self.call(url, password, payload).await
     }
 
+    pub async fn rebalance(
+        &self,
+        password: String,
+        payload: RebalancePayload,
+    ) -> Result<Response, Error> {
+        let url = self.base_url.join("/rebalance").expect("invalid base url");
+        self.call(url, password, payload).await
+    }
+
+    pub async fn set_rebalance_config(
+        &self,
+        password: String,
+        payload: SetRebalanceConfigPayload,
+    ) -> Result<Response, Error> {
+        let url = self
+            .base_url
+            .join("/set_rebalance_config")
+            .expect("invalid base url");
+        self.call(url, password, payload).await
+    }
+
+    pub async fn set_balance_policy(
+        &self,
+        password: String,
+        payload: SetBalancePolicyPayload,
+    ) -> Result<Response, Error> {
+        let url = self
+            .base_url
+            .join("/set_balance_policy")
+            .expect("invalid base url");
+        self.call(url, password, payload).await
+    }
+
     async fn call<P>(
         &self,
         url: Url,
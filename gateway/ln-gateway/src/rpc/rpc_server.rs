@@ -1,7 +1,8 @@
 use std::net::SocketAddr;
 
+use axum::http::StatusCode;
 use axum::response::IntoResponse;
-use axum::routing::post;
+use axum::routing::{get, post};
 use axum::{Extension, Json, Router};
 use axum_macros::debug_handler;
 use mint_client::ln::PayInvoicePayload;
@@ -12,7 +13,8 @@ use tracing::instrument;
 
 use super::{
     BackupPayload, BalancePayload, ConnectFedPayload, DepositAddressPayload, DepositPayload,
-    GatewayRpcSender, InfoPayload, RestorePayload, WithdrawPayload,
+    GatewayRpcSender, HealthPayload, InfoPayload, RebalancePayload, RestorePayload,
+    SetBalancePolicyPayload, SetRebalanceConfigPayload, WithdrawPayload,
 };
 use crate::GatewayError;
 
@@ -22,7 +24,10 @@ pub async fn run_webserver(
     sender: GatewayRpcSender,
 ) -> axum::response::Result<()> {
     // Public routes on gateway webserver
-    let routes = Router::new().route("/pay_invoice", post(pay_invoice));
+    let routes = Router::new()
+        .route("/pay_invoice", post(pay_invoice))
+        .route("/health", get(health))
+        .route("/metrics", get(metrics));
 
     // Authenticated, public routes used for gateway administration
     let admin_routes = Router::new()
@@ -34,6 +39,9 @@ pub async fn run_webserver(
         .route("/connect", post(connect))
         .route("/backup", post(backup))
         .route("/restore", post(restore))
+        .route("/rebalance", post(rebalance))
+        .route("/set_rebalance_config", post(set_rebalance_config))
+        .route("/set_balance_policy", post(set_balance_policy))
         .layer(RequireAuthorizationLayer::bearer(&authkey));
 
     let app = Router::new()
@@ -50,6 +58,28 @@ pub async fn run_webserver(
     Ok(())
 }
 
+/// Report connectivity of the Lightning node and the connected federations,
+/// responds with `503 Service Unavailable` if any of them is unhealthy
+#[debug_handler]
+#[instrument(skip_all, err)]
+async fn health(
+    Extension(rpc): Extension<GatewayRpcSender>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let health = rpc.send(HealthPayload).await?;
+    let status = if health.is_healthy() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok((status, Json(json!(health))))
+}
+
+/// Prometheus metrics in the text exposition format
+#[debug_handler]
+async fn metrics() -> impl IntoResponse {
+    fedimint_metrics::encode_metrics()
+}
+
 /// Display gateway ecash note balance
 #[debug_handler]
 #[instrument(skip_all, err)]
@@ -143,3 +173,33 @@ async fn restore(
     rpc.send(payload).await?;
     Ok(())
 }
+
+/// Move ecash between connected federations according to the rebalance config
+#[instrument(skip_all, err)]
+async fn rebalance(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<RebalancePayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    let result = rpc.send(payload).await?;
+    Ok(Json(json!(result)))
+}
+
+/// Replace the rebalance config
+#[instrument(skip_all, err)]
+async fn set_rebalance_config(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<SetRebalanceConfigPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    rpc.send(payload).await?;
+    Ok(())
+}
+
+/// Replace the balance policy of a federation
+#[instrument(skip_all, err)]
+async fn set_balance_policy(
+    Extension(rpc): Extension<GatewayRpcSender>,
+    Json(payload): Json<SetBalancePolicyPayload>,
+) -> Result<impl IntoResponse, GatewayError> {
+    rpc.send(payload).await?;
+    Ok(())
+}
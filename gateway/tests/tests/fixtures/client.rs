@@ -11,8 +11,11 @@ use fedimint_core::core::LEGACY_HARDCODED_INSTANCE_ID_LN;
 use fedimint_core::module::registry::ModuleDecoderRegistry;
 use fedimint_core::PeerId;
 use ln_gateway::client::{DynDbFactory, IGatewayClientBuilder};
+use ln_gateway::rebalance::RebalanceConfig;
 use ln_gateway::GatewayError;
-use mint_client::{module_decode_stubs, Client, GatewayClient, GatewayClientConfig};
+use mint_client::{
+    module_decode_stubs, Client, GatewayBalancePolicy, GatewayClient, GatewayClientConfig,
+};
 use secp256k1::{PublicKey, Secp256k1};
 use url::Url;
 
@@ -97,6 +100,7 @@ impl IGatewayClientBuilder for TestGatewayClientBuilder {
             timelock_delta: 10,
             node_pub_key: node_pubkey,
             api: self.gateway_api.clone(),
+            balance_policy: Default::default(),
         })
     }
 
@@ -109,4 +113,23 @@ impl IGatewayClientBuilder for TestGatewayClientBuilder {
         // noop: return empty config list
         Ok([].into())
     }
+
+    fn save_balance_policy(
+        &self,
+        _federation_id: FederationId,
+        _policy: &GatewayBalancePolicy,
+    ) -> Result<(), GatewayError> {
+        // noop: don't save configs
+        Ok(())
+    }
+
+    fn save_rebalance_config(&self, _config: &RebalanceConfig) -> Result<(), GatewayError> {
+        // noop: don't save configs
+        Ok(())
+    }
+
+    fn load_rebalance_config(&self) -> Result<Option<RebalanceConfig>, GatewayError> {
+        // noop: nothing was saved
+        Ok(None)
+    }
 }
@@ -36,6 +36,7 @@ use fedimint_mint_client::MintClientGen;
 use fedimint_mint_server::common::db::NonceKeyPrefix;
 use fedimint_mint_server::common::MintOutput;
 use fedimint_mint_server::MintGen;
+use fedimint_server::config::session::DkgSession;
 use fedimint_server::config::{ServerConfig, ServerConfigParams};
 use fedimint_server::consensus::{
     ConsensusProposal, FedimintConsensus, HbbftConsensusOutcome, TransactionSubmissionError,
@@ -43,6 +44,7 @@ use fedimint_server::consensus::{
 use fedimint_server::db::GLOBAL_DATABASE_VERSION;
 use fedimint_server::net::connect::mock::MockNetwork;
 use fedimint_server::net::connect::{Connector, TlsTcpConnector};
+use fedimint_server::net::limits::ApiLimiter;
 use fedimint_server::net::peers::PeerConnector;
 use fedimint_server::{consensus, EpochMessage, FedimintServer};
 use fedimint_testing::btc::fixtures::FakeBitcoinTest;
@@ -57,7 +59,6 @@ use fedimint_wallet_server::{Wallet, WalletGen};
 use futures::executor::block_on;
 use futures::future::{join_all, select_all};
 use futures::{FutureExt, StreamExt};
-use hbbft::honey_badger::Batch;
 use itertools::Itertools;
 use ln_gateway::actor::GatewayActor;
 use ln_gateway::client::{DynGatewayClientBuilder, MemDbFactory, StandardGatewayClientBuilder};
@@ -423,8 +424,11 @@ async fn distributed_config(
         async move {
             let our_params = params[peer].clone();
 
-            let cfg = ServerConfig::distributed_gen(&our_params, registry, &mut task_group);
-            (*peer, cfg.await.expect("generation failed"))
+            let session = DkgSession::new(our_params.our_id, &our_params.peer_ids);
+            let cfg =
+                ServerConfig::distributed_gen(&our_params, registry, session, &mut task_group);
+            let (cfg, _transcript) = cfg.await.expect("generation failed");
+            (*peer, cfg)
         }
     }))
     .await
@@ -495,6 +499,7 @@ impl GatewayTest {
             timelock_delta: 10,
             api: announce_addr.clone(),
             node_pub_key,
+            balance_policy: Default::default(),
         };
 
         // Create federation client builder for the gateway
@@ -665,6 +670,17 @@ impl FederationTest {
             })
     }
 
+    /// Announces new API and p2p URLs for the first server
+    pub async fn update_endpoint(&self, api_url: Url, p2p_url: Url) {
+        self.servers[0]
+            .lock()
+            .await
+            .fedimint
+            .consensus
+            .update_endpoint(api_url, p2p_url)
+            .await;
+    }
+
     /// Sends a custom proposal, ignoring whatever is in FedimintConsensus
     /// Useful for simulating malicious federation nodes
     pub async fn override_proposal(&self, items: Vec<ConsensusItem>) {
@@ -1148,7 +1164,9 @@ impl FederationTest {
 
         let audit = block_on(current_consensus.audit());
 
-        if last_consensus.is_empty() || last_consensus.epoch < new_consensus.epoch {
+        if last_consensus.contributions.values().all(Vec::is_empty)
+            || last_consensus.epoch < new_consensus.epoch
+        {
             info!("{}", consensus::debug::epoch_message(&new_consensus));
             info!("\n{}", audit);
             let bs = std::cmp::max(
@@ -1175,6 +1193,12 @@ impl FederationTest {
         task_group: &mut TaskGroup,
     ) -> Self {
         let servers = join_all(server_config.values().map(|cfg| async {
+            // Ban misbehaving peers right away so tests don't need to wait for
+            // repeated faults
+            let mut cfg = cfg.clone();
+            cfg.local.peer_bans.ban_score = 1;
+            let cfg = &cfg;
+
             let btc_rpc = bitcoin_gen();
             let decoders = module_inits.decoders(cfg.iter_module_instances()).unwrap();
             let db = database_gen(decoders.clone());
@@ -1247,9 +1271,18 @@ impl FederationTest {
 
             let cfg = cfg.clone();
             let consensus = fedimint.consensus.clone();
+            let limiter = Arc::new(ApiLimiter::new(&cfg.local.api_limits));
+            let api_task_group = task_group.clone();
             task_group
                 .spawn("rpc server", move |handle| async {
-                    fedimint_server::net::api::run_server(cfg, consensus, handle).await
+                    fedimint_server::net::api::run_server(
+                        cfg,
+                        consensus,
+                        limiter,
+                        api_task_group,
+                        handle,
+                    )
+                    .await
                 })
                 .await;
 
@@ -1269,9 +1302,10 @@ impl FederationTest {
         let wallet = cfg
             .get_module_config_typed(cfg.get_module_id_by_kind("wallet").unwrap())
             .unwrap();
-        let last_consensus = Arc::new(Mutex::new(Batch {
+        let last_consensus = Arc::new(Mutex::new(HbbftConsensusOutcome {
             epoch: 0,
             contributions: BTreeMap::new(),
+            faults: vec![],
         }));
         let max_balance_sheet = Arc::new(AtomicI64::new(0));
 
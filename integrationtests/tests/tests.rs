@@ -27,6 +27,7 @@ use mint_client::{ClientError, ConfigVerifyError};
 use threshold_crypto::{SecretKey, SecretKeyShare};
 use tracing::log::warn;
 use tracing::{debug, info, instrument};
+use url::Url;
 
 use crate::fixtures::{peers, test, unwrap_item, FederationTest};
 
@@ -628,6 +629,68 @@ async fn lightning_gateway_pays_internal_invoice() -> Result<()> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn user_pays_internal_invoice_without_gateway() -> Result<()> {
+    test(2, |fed, user, bitcoin, gateway, lightning| async move {
+        fed.mine_and_mint(&user, &*bitcoin, sats(2000)).await;
+
+        let receiving_user = user.new_user_with_peers(peers(&[0])).await;
+
+        let confirmed_invoice = {
+            let (txid, invoice, payment_keypair) = receiving_user
+                .client
+                .generate_unconfirmed_invoice_and_submit(sats(1000), "".into(), &mut rng(), None)
+                .await
+                .unwrap();
+            fed.run_consensus_epochs(1).await;
+
+            receiving_user
+                .client
+                .await_invoice_confirmation(txid, invoice, payment_keypair)
+                .await
+                .unwrap()
+        };
+        let incoming_contract_id = confirmed_invoice.contract_id();
+
+        let (contract_id, funding_outpoint) = user
+            .client
+            .fund_internal_payment(&confirmed_invoice.invoice, rng())
+            .await
+            .unwrap()
+            .expect("Invoice was issued in our federation");
+        assert_eq!(contract_id, incoming_contract_id);
+        fed.run_consensus_epochs(2).await; // fund incoming contract, decrypt preimage
+
+        user.client
+            .await_internal_payment(contract_id, funding_outpoint, rng())
+            .await
+            .unwrap();
+
+        let receiving_outpoint = receiving_user
+            .client
+            .claim_incoming_contract(incoming_contract_id, rng())
+            .await
+            .unwrap();
+        fed.run_consensus_epochs(2).await; // claim incoming contract and mint the notes
+
+        receiving_user
+            .client
+            .fetch_notes(receiving_outpoint)
+            .await
+            .unwrap();
+
+        user.assert_total_notes(sats(1000)).await; // no gateway fee was paid
+        receiving_user.assert_total_notes(sats(1000)).await;
+        gateway.user.assert_total_notes(sats(0)).await; // gateway was not involved
+
+        if !lightning.is_shared() {
+            assert_eq!(lightning.amount_sent().await, sats(0));
+        }
+        assert_eq!(fed.max_balance_sheet(), 0);
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn lightning_gateway_pays_outgoing_invoice() -> Result<()> {
     test(2, |fed, user, bitcoin, gateway, lightning| async move {
@@ -1255,6 +1318,26 @@ async fn verifies_client_configs() -> Result<()> {
     .await
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn clients_learn_endpoint_updates() -> Result<()> {
+    test(2, |fed, user, _bitcoin, _, _| async move {
+        fed.run_consensus_epochs(1).await;
+        let id = user.client.config().0.federation_id.clone();
+        let api_url: Url = "ws://moved.example.com:5000".parse().unwrap();
+        let p2p_url: Url = "ws://moved.example.com:5001".parse().unwrap();
+
+        fed.update_endpoint(api_url.clone(), p2p_url).await;
+        fed.run_consensus_epochs(1).await;
+        // the federation signs the client config with the new endpoints
+        fed.run_empty_epochs(1).await;
+
+        user.client.verify_config(&id).await.unwrap();
+        let endpoints = user.client.api_endpoints().await;
+        assert!(endpoints.iter().any(|node| node.url == api_url));
+    })
+    .await
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn cannot_replay_transactions() -> Result<()> {
     test(4, |fed, user, bitcoin, _, _| async move {
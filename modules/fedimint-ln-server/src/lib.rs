@@ -7,10 +7,11 @@ use fedimint_core::config::{
     ConfigGenParams, DkgResult, ModuleConfigResponse, ServerModuleConfig, TypedServerModuleConfig,
     TypedServerModuleConsensusConfig,
 };
-use fedimint_core::core::{ModuleInstanceId, LEGACY_HARDCODED_INSTANCE_ID_WALLET};
+use fedimint_core::core::{DynOutput, ModuleInstanceId, LEGACY_HARDCODED_INSTANCE_ID_WALLET};
 use fedimint_core::db::{Database, DatabaseVersion, ModuleDatabaseTransaction};
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::audit::Audit;
+use fedimint_core::module::check::DatabaseCheckReport;
 use fedimint_core::module::interconnect::ModuleInterconect;
 use fedimint_core::module::{
     api_endpoint, ApiEndpoint, ApiError, ApiRequestErased, ApiVersion, ConsensusProposal,
@@ -224,6 +225,39 @@ impl ServerModuleGen for LightningGen {
 
         Box::new(lightning.into_iter())
     }
+
+    async fn check_database(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        outputs: &[(OutPoint, DynOutput)],
+        audit: &mut Audit,
+        report: &mut DatabaseCheckReport,
+    ) {
+        Lightning::audit_database(dbtx, audit).await;
+
+        for (out_point, output) in outputs {
+            let output = output
+                .as_any()
+                .downcast_ref::<LightningOutput>()
+                .expect("incorrect output type passed to module plugin");
+            let LightningOutput::Contract(contract) = output else {
+                continue;
+            };
+
+            let outcome = dbtx.get_value(&ContractUpdateKey(*out_point)).await;
+            report
+                .invariant("accepted contract outputs have an outcome")
+                .verify(outcome.is_some(), || out_point.to_string());
+
+            let contract_id = contract.contract.contract_id();
+            let account = dbtx.get_value(&ContractKey(contract_id)).await;
+            report
+                .invariant("accepted contract outputs have a contract account")
+                .verify(account.is_some(), || {
+                    format!("{contract_id} in {out_point}")
+                });
+        }
+    }
 }
 /// The lightning module implements an account system. It does not have the
 /// privacy guarantees of the e-cash mint module but instead allows for smart
@@ -744,9 +778,7 @@ impl ServerModule for Lightning {
         dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
         audit: &mut Audit,
     ) {
-        audit
-            .add_items(dbtx, &ContractKeyPrefix, |_, v| -(v.amount.msats as i64))
-            .await;
+        Lightning::audit_database(dbtx, audit).await;
     }
 
     fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
@@ -793,6 +825,16 @@ impl Lightning {
         Lightning { cfg }
     }
 
+    /// Adds the funds locked in contracts to `audit`
+    async fn audit_database(
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        audit: &mut Audit,
+    ) {
+        audit
+            .add_items(dbtx, &ContractKeyPrefix, |_, v| -(v.amount.msats as i64))
+            .await;
+    }
+
     fn validate_decryption_share(
         &self,
         peer: PeerId,
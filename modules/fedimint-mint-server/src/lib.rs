@@ -7,11 +7,12 @@ use fedimint_core::config::{
     ConfigGenParams, DkgResult, ModuleConfigResponse, ModuleGenParams, ServerModuleConfig,
     TypedServerModuleConfig, TypedServerModuleConsensusConfig,
 };
-use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::core::{DynOutput, ModuleInstanceId};
 use fedimint_core::db::{Database, DatabaseVersion, ModuleDatabaseTransaction};
 use fedimint_core::encoding::Encodable;
 use fedimint_core::module::__reexports::serde_json;
 use fedimint_core::module::audit::Audit;
+use fedimint_core::module::check::DatabaseCheckReport;
 use fedimint_core::module::interconnect::ModuleInterconect;
 use fedimint_core::module::{
     api_endpoint, ApiEndpoint, ApiError, ApiVersion, ConsensusProposal, CoreConsensusVersion,
@@ -276,6 +277,95 @@ impl ServerModuleGen for MintGen {
 
         Box::new(mint.into_iter())
     }
+
+    async fn check_database(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        outputs: &[(OutPoint, DynOutput)],
+        audit: &mut Audit,
+        report: &mut DatabaseCheckReport,
+    ) {
+        Mint::audit_database(dbtx, audit).await;
+
+        let outputs = outputs
+            .iter()
+            .map(|(out_point, output)| {
+                let output = output
+                    .as_any()
+                    .downcast_ref::<MintOutput>()
+                    .expect("incorrect output type passed to module plugin");
+                (*out_point, output)
+            })
+            .collect::<HashMap<_, _>>();
+        let received_shares = dbtx
+            .find_by_prefix(&ReceivedPartialSignaturesKeyPrefix)
+            .await
+            .map(|(key, share)| (key.request_id, (key.peer_id, share)))
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .into_group_map();
+
+        for (out_point, output) in &outputs {
+            let outcome = dbtx.get_value(&OutputOutcomeKey(*out_point)).await;
+            let proposal = dbtx
+                .get_value(&ProposedPartialSignatureKey {
+                    out_point: *out_point,
+                })
+                .await;
+
+            report
+                .invariant("accepted outputs are signed or have pending signature shares")
+                .verify(outcome.is_some() || proposal.is_some(), || {
+                    out_point.to_string()
+                });
+
+            if let Some(outcome) = outcome {
+                report
+                    .invariant("blind signatures match the notes of their output")
+                    .verify(outcome.0.structural_eq(&output.0), || out_point.to_string());
+                report
+                    .invariant("signed outputs have no pending signature shares")
+                    .verify(
+                        proposal.is_none() && !received_shares.contains_key(out_point),
+                        || out_point.to_string(),
+                    );
+            }
+
+            if let Some(proposal) = proposal {
+                report
+                    .invariant("proposed signature shares match the notes of their output")
+                    .verify(shares_match_output(&proposal, output), || {
+                        out_point.to_string()
+                    });
+            }
+        }
+
+        for (out_point, shares) in &received_shares {
+            for (peer, share) in shares {
+                report
+                    .invariant("received signature shares match the notes of their output")
+                    .verify(
+                        outputs
+                            .get(out_point)
+                            .map_or(false, |output| shares_match_output(share, output)),
+                        || format!("{out_point} from {peer}"),
+                    );
+            }
+        }
+    }
+}
+
+/// Checks that `shares` sign exactly the blind nonces of `output`
+fn shares_match_output(shares: &MintOutputSignatureShare, output: &MintOutput) -> bool {
+    shares
+        .0
+        .iter_items()
+        .map(|(amount, (msg, _))| (amount, *msg))
+        .eq(output
+            .0
+            .iter_items()
+            .map(|(amount, nonce)| (amount, nonce.0)))
 }
 /// Federated mint member mint
 #[derive(Debug)]
@@ -629,14 +719,7 @@ impl ServerModule for Mint {
         dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
         audit: &mut Audit,
     ) {
-        audit
-            .add_items(dbtx, &MintAuditItemKeyPrefix, |k, v| match k {
-                MintAuditItemKey::Issuance(_) => -(v.msats as i64),
-                MintAuditItemKey::IssuanceTotal => -(v.msats as i64),
-                MintAuditItemKey::Redemption(_) => v.msats as i64,
-                MintAuditItemKey::RedemptionTotal => v.msats as i64,
-            })
-            .await;
+        Mint::audit_database(dbtx, audit).await;
     }
 
     fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
@@ -914,6 +997,21 @@ impl Mint {
         )
     }
 
+    /// Adds the issued and redeemed ecash to `audit`
+    async fn audit_database(
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        audit: &mut Audit,
+    ) {
+        audit
+            .add_items(dbtx, &MintAuditItemKeyPrefix, |k, v| match k {
+                MintAuditItemKey::Issuance(_) => -(v.msats as i64),
+                MintAuditItemKey::IssuanceTotal => -(v.msats as i64),
+                MintAuditItemKey::Redemption(_) => v.msats as i64,
+                MintAuditItemKey::RedemptionTotal => v.msats as i64,
+            })
+            .await;
+    }
+
     async fn process_partial_signature<'a>(
         &self,
         dbtx: &mut ModuleDatabaseTransaction<'a, ModuleInstanceId>,
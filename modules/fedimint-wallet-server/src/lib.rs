@@ -32,13 +32,14 @@ use fedimint_core::config::{
     ConfigGenParams, DkgResult, ModuleConfigResponse, ModuleGenParams, ServerModuleConfig,
     TypedServerModuleConfig, TypedServerModuleConsensusConfig,
 };
-use fedimint_core::core::ModuleInstanceId;
+use fedimint_core::core::{DynOutput, ModuleInstanceId};
 use fedimint_core::db::{
     Database, DatabaseTransaction, DatabaseVersion, ModuleDatabaseTransaction,
 };
 use fedimint_core::encoding::{Decodable, Encodable};
 use fedimint_core::module::__reexports::serde_json;
 use fedimint_core::module::audit::Audit;
+use fedimint_core::module::check::DatabaseCheckReport;
 use fedimint_core::module::interconnect::ModuleInterconect;
 use fedimint_core::module::{
     api_endpoint, ApiEndpoint, ApiVersion, ConsensusProposal, CoreConsensusVersion,
@@ -161,7 +162,7 @@ impl ServerModuleGen for WalletGen {
             .expect("Invalid wallet params");
 
         let secp = secp256k1::Secp256k1::new();
-        let (sk, pk) = secp.generate_keypair(&mut OsRng);
+        let (sk, pk) = secp.generate_keypair(&mut peers.rng());
         let our_key = CompressedPublicKey { key: pk };
         let peer_peg_in_keys: BTreeMap<PeerId, CompressedPublicKey> = peers
             .exchange_pubkeys(our_key.key)
@@ -271,6 +272,54 @@ impl ServerModuleGen for WalletGen {
 
         Box::new(wallet.into_iter())
     }
+
+    async fn check_database(
+        &self,
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        outputs: &[(OutPoint, DynOutput)],
+        audit: &mut Audit,
+        report: &mut DatabaseCheckReport,
+    ) {
+        Wallet::audit_database(dbtx, audit).await;
+
+        for (out_point, _) in outputs {
+            let outcome = dbtx.get_value(&PegOutBitcoinTransaction(*out_point)).await;
+            report
+                .invariant("accepted peg-outs have a bitcoin transaction")
+                .verify(outcome.is_some(), || out_point.to_string());
+        }
+
+        let pending_txs = dbtx
+            .find_by_prefix(&PendingTransactionPrefixKey)
+            .await
+            .map(|(_, pending_tx)| pending_tx)
+            .collect::<Vec<_>>()
+            .await;
+        for pending_tx in pending_txs {
+            let txid = pending_tx.tx.txid();
+            let inputs = pending_tx
+                .tx
+                .input
+                .iter()
+                .map(|input| input.previous_output)
+                .collect::<HashSet<_>>();
+
+            // Spent UTXOs are removed from the spendable set when the peg-out is
+            // signed, so the pending transaction is the only record of them
+            for (utxo_key, _) in &pending_tx.selected_utxos {
+                report
+                    .invariant("UTXOs of pending transactions are spent by them")
+                    .verify(inputs.contains(&utxo_key.0), || {
+                        format!("{} in {txid}", utxo_key.0)
+                    });
+
+                let spendable = dbtx.get_value(utxo_key).await;
+                report
+                    .invariant("UTXOs of pending transactions are not spendable")
+                    .verify(spendable.is_none(), || format!("{} in {txid}", utxo_key.0));
+            }
+        }
+    }
 }
 
 #[apply(async_trait_maybe_send!)]
@@ -617,21 +666,7 @@ impl ServerModule for Wallet {
         dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
         audit: &mut Audit,
     ) {
-        audit
-            .add_items(dbtx, &UTXOPrefixKey, |_, v| v.amount.to_sat() as i64 * 1000)
-            .await;
-        audit
-            .add_items(dbtx, &UnsignedTransactionPrefixKey, |_, v| match v.rbf {
-                None => v.change.to_sat() as i64 * 1000,
-                Some(rbf) => rbf.fees.amount().to_sat() as i64 * -1000,
-            })
-            .await;
-        audit
-            .add_items(dbtx, &PendingTransactionPrefixKey, |_, v| match v.rbf {
-                None => v.change.to_sat() as i64 * 1000,
-                Some(rbf) => rbf.fees.amount().to_sat() as i64 * -1000,
-            })
-            .await;
+        Wallet::audit_database(dbtx, audit).await;
     }
 
     fn api_endpoints(&self) -> Vec<ApiEndpoint<Self>> {
@@ -1165,6 +1200,29 @@ impl Wallet {
         }
     }
 
+    /// Adds the federation's UTXOs and the change of peg-out transactions to
+    /// `audit`
+    async fn audit_database(
+        dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
+        audit: &mut Audit,
+    ) {
+        audit
+            .add_items(dbtx, &UTXOPrefixKey, |_, v| v.amount.to_sat() as i64 * 1000)
+            .await;
+        audit
+            .add_items(dbtx, &UnsignedTransactionPrefixKey, |_, v| match v.rbf {
+                None => v.change.to_sat() as i64 * 1000,
+                Some(rbf) => rbf.fees.amount().to_sat() as i64 * -1000,
+            })
+            .await;
+        audit
+            .add_items(dbtx, &PendingTransactionPrefixKey, |_, v| match v.rbf {
+                None => v.change.to_sat() as i64 * 1000,
+                Some(rbf) => rbf.fees.amount().to_sat() as i64 * -1000,
+            })
+            .await;
+    }
+
     async fn available_utxos(
         &self,
         dbtx: &mut ModuleDatabaseTransaction<'_, ModuleInstanceId>,
@@ -180,6 +180,8 @@ async fn main() -> anyhow::Result<()> {
             let db = Database::new(RocksDb::open(db).expect("Error opening DB"), decoders);
             let mut dbtx = db.begin_transaction().await;
 
+            // Epochs pruned by `--epoch-retention` only keep their hashes, their tweaks
+            // can't be recovered from the epoch history
             let tweaks = dbtx.find_by_prefix(&EpochHistoryKeyPrefix).await.flat_map(
                 |(_, SignedEpochOutcome { outcome, .. })| {
                     let UnzipConsensusItem {